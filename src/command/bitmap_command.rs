@@ -0,0 +1,545 @@
+use crate::database::lib::DatabaseHolder;
+use crate::database::notify::{NOTIFY_GENERIC, NOTIFY_STRING};
+use crate::parser::response::Response;
+use crate::util::common_utils::normalize_range;
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::value::{BitOperation, Value, ValueString};
+use anyhow::{anyhow, ensure};
+
+/// Strings are limited to 512MB, so are the bit offsets.
+const MAX_BIT_OFFSET: i64 = 512 * 1024 * 1024 * 8 - 1;
+
+pub fn setbit(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let offset = parse_bit_offset(&parser, 2)?;
+    let on = match parser.get_slice(3)? {
+        b"1" => true,
+        b"0" => false,
+        _ => return Err(anyhow!("bit is not an integer or out of range")),
+    };
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let old =
+        db.update_or_create_string(
+            db_index,
+            key.clone(),
+            |string| Ok(string.setbit(offset, on)),
+        )?;
+    db.notify(NOTIFY_STRING, "setbit", db_index, &key);
+    Ok(Response::Integer(old as i64))
+}
+pub fn getbit(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 3, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let offset = parse_bit_offset(&parser, 2)?;
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let bit = match db.get_string(db_index, &key)? {
+        Some(value) => value.getbit(offset),
+        None => 0,
+    };
+    Ok(Response::Integer(bit as i64))
+}
+pub fn bitcount(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 2 || parser.argv.len() == 4 || parser.argv.len() == 5,
+        "syntax error"
+    );
+    let key = parser.get_vec(1)?;
+    let range = if parser.argv.len() >= 4 {
+        Some((
+            parser.get_i64(2)?,
+            parser.get_i64(3)?,
+            parse_bit_unit(&parser, 4)?,
+        ))
+    } else {
+        None
+    };
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let Some(value) = db.get_string(db_index, &key)? else {
+        return Ok(Response::Integer(0));
+    };
+    let bit_range = match range {
+        Some((start, end, bit_unit)) => to_bit_range(value, start, end, bit_unit),
+        None => to_bit_range(value, 0, -1, false),
+    };
+    let count = match bit_range {
+        Some((start, end)) => value.bitcount(start, end),
+        None => 0,
+    };
+    Ok(Response::Integer(count as i64))
+}
+pub fn bitpos(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() >= 3 && parser.argv.len() <= 6,
+        "InvalidArgument"
+    );
+    let key = parser.get_vec(1)?;
+    let bit = match parser.get_slice(2)? {
+        b"1" => true,
+        b"0" => false,
+        _ => return Err(anyhow!("The bit argument must be 1 or 0.")),
+    };
+    let start = if parser.argv.len() >= 4 {
+        parser.get_i64(3)?
+    } else {
+        0
+    };
+    let end_given = parser.argv.len() >= 5;
+    let end = if end_given { parser.get_i64(4)? } else { -1 };
+    let bit_unit = parse_bit_unit(&parser, 5)?;
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let Some(value) = db.get_string(db_index, &key)? else {
+        return Ok(Response::Integer(if bit { -1 } else { 0 }));
+    };
+    let Some((start, end)) = to_bit_range(value, start, end, bit_unit) else {
+        return Ok(Response::Integer(-1));
+    };
+    let position = match value.bitpos(bit, start, end) {
+        Some(position) => position as i64,
+        // Looking for a clear bit past the end of the string finds one,
+        // unless the range was given explicitly.
+        None if !bit && !end_given => end as i64 + 1,
+        None => -1,
+    };
+    Ok(Response::Integer(position))
+}
+pub fn bitop(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
+    let operation = match parser.get_vec(1)?.to_ascii_uppercase().as_slice() {
+        b"AND" => BitOperation::And,
+        b"OR" => BitOperation::Or,
+        b"XOR" => BitOperation::Xor,
+        b"NOT" => BitOperation::Not,
+        b"DIFF" => BitOperation::Diff,
+        _ => return Err(anyhow!("syntax error")),
+    };
+    let key_count = parser.argv.len() - 3;
+    ensure!(
+        operation != BitOperation::Not || key_count == 1,
+        "BITOP NOT must be called with a single source key."
+    );
+    ensure!(
+        operation != BitOperation::Diff || key_count >= 2,
+        "BITOP DIFF must be called with at least two source keys."
+    );
+    let destination = parser.get_vec(2)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let mut inputs = vec![];
+    for i in 3..parser.argv.len() {
+        let input = match db.get_string(db_index, parser.get_slice(i)?)? {
+            Some(value) => value.data.clone(),
+            None => vec![],
+        };
+        inputs.push(input);
+    }
+    let result = ValueString::bitop(operation, &inputs);
+    let len = result.strlen();
+    let removed = db.remove(db_index, &destination)?.is_some();
+    if len > 0 {
+        db.insert(db_index, destination.clone(), Value::String(result))?;
+        db.notify(NOTIFY_STRING, "set", db_index, &destination);
+    } else if removed {
+        db.notify(NOTIFY_GENERIC, "del", db_index, &destination);
+    }
+    Ok(Response::Integer(len as i64))
+}
+pub fn bitfield(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_bitfield(parser, database_lock, db_index, false)
+}
+pub fn bitfield_ro(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_bitfield(parser, database_lock, db_index, true)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Overflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+#[derive(Debug, Clone, Copy)]
+struct BitfieldType {
+    signed: bool,
+    bits: u32,
+}
+impl BitfieldType {
+    fn min(&self) -> i128 {
+        if self.signed {
+            -(1i128 << (self.bits - 1))
+        } else {
+            0
+        }
+    }
+    fn max(&self) -> i128 {
+        if self.signed {
+            (1i128 << (self.bits - 1)) - 1
+        } else {
+            (1i128 << self.bits) - 1
+        }
+    }
+    fn read(&self, value: &ValueString, offset: usize) -> i128 {
+        let raw = value.get_bits(offset, self.bits);
+        if self.signed && self.bits < 64 && raw >> (self.bits - 1) & 1 == 1 {
+            raw as i128 - (1i128 << self.bits)
+        } else if self.signed {
+            raw as i64 as i128
+        } else {
+            raw as i128
+        }
+    }
+    fn write(&self, value: &mut ValueString, offset: usize, number: i128) {
+        value.set_bits(offset, self.bits, number as i64 as u64);
+    }
+    /// Applies the overflow policy, `None` means the operation fails.
+    fn fit(&self, number: i128, overflow: Overflow) -> Option<i128> {
+        if number >= self.min() && number <= self.max() {
+            return Some(number);
+        }
+        match overflow {
+            Overflow::Wrap => {
+                let modulo = 1i128 << self.bits;
+                let wrapped = number.rem_euclid(modulo);
+                if wrapped > self.max() {
+                    Some(wrapped - modulo)
+                } else {
+                    Some(wrapped)
+                }
+            }
+            Overflow::Sat => Some(number.clamp(self.min(), self.max())),
+            Overflow::Fail => None,
+        }
+    }
+}
+enum BitfieldOperation {
+    Get,
+    Set(i64),
+    Incrby(i64),
+}
+struct BitfieldCommand {
+    operation: BitfieldOperation,
+    field_type: BitfieldType,
+    offset: usize,
+    overflow: Overflow,
+}
+fn generic_bitfield(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+    read_only: bool,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let mut commands = vec![];
+    let mut overflow = Overflow::Wrap;
+    let mut i = 2;
+    while i < parser.argv.len() {
+        let name = parser.get_vec(i)?.to_ascii_uppercase();
+        if name == b"OVERFLOW" {
+            overflow = match parser.get_vec(i + 1)?.to_ascii_uppercase().as_slice() {
+                b"WRAP" => Overflow::Wrap,
+                b"SAT" => Overflow::Sat,
+                b"FAIL" => Overflow::Fail,
+                _ => return Err(anyhow!("Invalid OVERFLOW type specified")),
+            };
+            i += 2;
+            continue;
+        }
+        let field_type = parse_bitfield_type(&parser, i + 1)?;
+        let offset = parse_bitfield_offset(&parser, i + 2, field_type)?;
+        let operation = match name.as_slice() {
+            b"GET" => {
+                i += 3;
+                BitfieldOperation::Get
+            }
+            b"SET" if !read_only => {
+                i += 4;
+                BitfieldOperation::Set(parser.get_i64(i - 1)?)
+            }
+            b"INCRBY" if !read_only => {
+                i += 4;
+                BitfieldOperation::Incrby(parser.get_i64(i - 1)?)
+            }
+            b"SET" | b"INCRBY" => {
+                return Err(anyhow!("BITFIELD_RO only supports the GET subcommand"))
+            }
+            _ => return Err(anyhow!("syntax error")),
+        };
+        commands.push(BitfieldCommand {
+            operation,
+            field_type,
+            offset,
+            overflow,
+        });
+    }
+    let writes = commands
+        .iter()
+        .any(|command| !matches!(command.operation, BitfieldOperation::Get));
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    if !writes {
+        let empty = ValueString { data: vec![] };
+        let value = db.get_string(db_index, &key)?.unwrap_or(&empty);
+        let responses = commands
+            .iter()
+            .map(|command| Response::Integer(command.field_type.read(value, command.offset) as i64))
+            .collect();
+        return Ok(Response::Array(responses));
+    }
+    let (responses, wrote) = if db.get_string(db_index, &key)?.is_some() {
+        db.update_or_create_string(db_index, key.clone(), |value| {
+            Ok(run_bitfield(value, commands))
+        })?
+    } else {
+        // The key is only created once a SET or INCRBY did not fail
+        let mut value = ValueString { data: vec![] };
+        let (responses, wrote) = run_bitfield(&mut value, commands);
+        if wrote {
+            db.insert(db_index, key.clone(), Value::String(value))?;
+        }
+        (responses, wrote)
+    };
+    if wrote {
+        db.notify(NOTIFY_STRING, "setbit", db_index, &key);
+    }
+    Ok(Response::Array(responses))
+}
+/// Runs the subcommands of BITFIELD, the bool tells whether one of them wrote.
+fn run_bitfield(value: &mut ValueString, commands: Vec<BitfieldCommand>) -> (Vec<Response>, bool) {
+    let mut responses = vec![];
+    let mut wrote = false;
+    for command in commands {
+        let field_type = command.field_type;
+        let old = field_type.read(value, command.offset);
+        let response = match command.operation {
+            BitfieldOperation::Get => Response::Integer(old as i64),
+            BitfieldOperation::Set(number) => {
+                match field_type.fit(number as i128, command.overflow) {
+                    Some(number) => {
+                        field_type.write(value, command.offset, number);
+                        wrote = true;
+                        Response::Integer(old as i64)
+                    }
+                    None => Response::Nil,
+                }
+            }
+            BitfieldOperation::Incrby(increment) => {
+                match field_type.fit(old + increment as i128, command.overflow) {
+                    Some(number) => {
+                        field_type.write(value, command.offset, number);
+                        wrote = true;
+                        Response::Integer(number as i64)
+                    }
+                    None => Response::Nil,
+                }
+            }
+        };
+        responses.push(response);
+    }
+    (responses, wrote)
+}
+fn parse_bitfield_type(parser: &ParsedCommand, pos: usize) -> Result<BitfieldType, anyhow::Error> {
+    let error = || {
+        anyhow!("Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.")
+    };
+    let data = parser.get_str(pos)?;
+    let signed = match data.chars().next() {
+        Some('i') | Some('I') => true,
+        Some('u') | Some('U') => false,
+        _ => return Err(error()),
+    };
+    let bits = data[1..].parse::<u32>().map_err(|_| error())?;
+    let max_bits = if signed { 64 } else { 63 };
+    ensure!(bits >= 1 && bits <= max_bits, error());
+    Ok(BitfieldType { signed, bits })
+}
+/// Parses an offset in bits, or in multiples of the type width when prefixed with `#`.
+fn parse_bitfield_offset(
+    parser: &ParsedCommand,
+    pos: usize,
+    field_type: BitfieldType,
+) -> Result<usize, anyhow::Error> {
+    let data = parser.get_str(pos)?;
+    let error = || anyhow!("bit offset is not an integer or out of range");
+    let offset = match data.strip_prefix('#') {
+        Some(index) => index
+            .parse::<i64>()
+            .map_err(|_| error())?
+            .checked_mul(field_type.bits as i64)
+            .ok_or_else(error)?,
+        None => data.parse::<i64>().map_err(|_| error())?,
+    };
+    ensure!(
+        offset >= 0 && offset + field_type.bits as i64 - 1 <= MAX_BIT_OFFSET,
+        error()
+    );
+    Ok(offset as usize)
+}
+fn parse_bit_offset(parser: &ParsedCommand, pos: usize) -> Result<usize, anyhow::Error> {
+    let offset = parser
+        .get_i64(pos)
+        .map_err(|_| anyhow!("bit offset is not an integer or out of range"))?;
+    ensure!(
+        (0..=MAX_BIT_OFFSET).contains(&offset),
+        "bit offset is not an integer or out of range"
+    );
+    Ok(offset as usize)
+}
+/// Parses the optional `BYTE|BIT` unit of BITCOUNT and BITPOS, returns whether it is `BIT`.
+fn parse_bit_unit(parser: &ParsedCommand, pos: usize) -> Result<bool, anyhow::Error> {
+    if pos >= parser.argv.len() {
+        return Ok(false);
+    }
+    match parser.get_vec(pos)?.to_ascii_uppercase().as_slice() {
+        b"BYTE" => Ok(false),
+        b"BIT" => Ok(true),
+        _ => Err(anyhow!("syntax error")),
+    }
+}
+/// Turns a `start`/`end` range in bytes or bits into included bit offsets.
+fn to_bit_range(
+    value: &ValueString,
+    start: i64,
+    end: i64,
+    bit_unit: bool,
+) -> Option<(usize, usize)> {
+    if bit_unit {
+        normalize_range(start, end, value.strlen() * 8)
+    } else {
+        normalize_range(start, end, value.strlen()).map(|(start, end)| (start * 8, end * 8 + 7))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const U8: BitfieldType = BitfieldType {
+        signed: false,
+        bits: 8,
+    };
+    const I8: BitfieldType = BitfieldType {
+        signed: true,
+        bits: 8,
+    };
+    const I64: BitfieldType = BitfieldType {
+        signed: true,
+        bits: 64,
+    };
+
+    #[test]
+    fn overflow_modes() {
+        assert_eq!(U8.fit(255, Overflow::Fail), Some(255));
+        assert_eq!(U8.fit(256, Overflow::Wrap), Some(0));
+        assert_eq!(U8.fit(300, Overflow::Wrap), Some(44));
+        assert_eq!(U8.fit(-1, Overflow::Wrap), Some(255));
+        assert_eq!(U8.fit(300, Overflow::Sat), Some(255));
+        assert_eq!(U8.fit(-5, Overflow::Sat), Some(0));
+        assert_eq!(U8.fit(256, Overflow::Fail), None);
+        assert_eq!(U8.fit(-1, Overflow::Fail), None);
+
+        assert_eq!(I8.fit(128, Overflow::Wrap), Some(-128));
+        assert_eq!(I8.fit(-129, Overflow::Wrap), Some(127));
+        assert_eq!(I8.fit(200, Overflow::Sat), Some(127));
+        assert_eq!(I8.fit(-200, Overflow::Sat), Some(-128));
+        assert_eq!(I8.fit(-128, Overflow::Fail), Some(-128));
+        assert_eq!(I8.fit(128, Overflow::Fail), None);
+
+        let above = i64::MAX as i128 + 1;
+        assert_eq!(I64.fit(above, Overflow::Wrap), Some(i64::MIN as i128));
+        assert_eq!(I64.fit(above, Overflow::Sat), Some(i64::MAX as i128));
+        assert_eq!(I64.fit(above, Overflow::Fail), None);
+    }
+
+    #[test]
+    fn read_and_write_signed_fields() {
+        let mut value = ValueString { data: vec![] };
+        I8.write(&mut value, 3, -2);
+        assert_eq!(I8.read(&value, 3), -2);
+        assert_eq!(U8.read(&value, 3), 254);
+        I64.write(&mut value, 16, i64::MIN as i128);
+        assert_eq!(I64.read(&value, 16), i64::MIN as i128);
+    }
+
+    #[test]
+    fn a_failed_write_leaves_the_value_alone() {
+        let command = |operation, overflow| BitfieldCommand {
+            operation,
+            field_type: U8,
+            offset: 0,
+            overflow,
+        };
+        let mut value = ValueString { data: vec![] };
+        let (responses, wrote) = run_bitfield(
+            &mut value,
+            vec![
+                command(BitfieldOperation::Incrby(300), Overflow::Fail),
+                command(BitfieldOperation::Get, Overflow::Fail),
+            ],
+        );
+        assert_eq!(responses, vec![Response::Nil, Response::Integer(0)]);
+        assert!(!wrote);
+        assert!(value.data.iter().all(|byte| *byte == 0));
+
+        let (responses, wrote) = run_bitfield(
+            &mut value,
+            vec![
+                command(BitfieldOperation::Set(250), Overflow::Fail),
+                command(BitfieldOperation::Incrby(10), Overflow::Sat),
+                command(BitfieldOperation::Incrby(10), Overflow::Wrap),
+            ],
+        );
+        assert_eq!(
+            responses,
+            vec![
+                Response::Integer(0),
+                Response::Integer(255),
+                Response::Integer(9)
+            ]
+        );
+        assert!(wrote);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_MODULE;
+use crate::parser::response::Response;
+use crate::vojo::bloom::{ValueBloomFilter, BLOOM_DEFAULT_EXPANSION};
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::value::Value;
+use anyhow::{anyhow, ensure};
+
+pub fn bf_reserve(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let error_rate = parser.get_f64(2).map_err(|_| anyhow!("Bad error rate"))?;
+    ensure!(error_rate > 0.0 && error_rate < 1.0, "Bad error rate");
+    let capacity = parser.get_i64(3).map_err(|_| anyhow!("Bad capacity"))?;
+    ensure!(capacity > 0, "Bad capacity");
+    let mut expansion = BLOOM_DEFAULT_EXPANSION;
+    let mut nonscaling = false;
+    let mut i = 4;
+    while i < parser.argv.len() {
+        match parser.get_str(i)?.to_uppercase().as_str() {
+            "EXPANSION" => {
+                let value = parser
+                    .get_i64(i + 1)
+                    .map_err(|_| anyhow!("Bad expansion"))?;
+                ensure!(value >= 1 && value <= u32::MAX as i64, "Bad expansion");
+                expansion = value as u32;
+                i += 2;
+            }
+            "NONSCALING" => {
+                nonscaling = true;
+                i += 1;
+            }
+            _ => return Err(anyhow!("syntax error")),
+        }
+    }
+    if nonscaling {
+        expansion = 0;
+    }
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    ensure!(db.get(db_index, key.clone())?.is_none(), "item exists");
+    let filter = ValueBloomFilter::new(error_rate, capacity as u64, expansion)?;
+    db.insert(db_index, key.clone(), Value::BloomFilter(filter))?;
+    db.notify(NOTIFY_MODULE, "bf.reserve", db_index, &key);
+    Ok(Response::Status("OK".to_owned()))
+}
+pub fn bf_add(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 3, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let added = db.update_or_create_bloom_filter(db_index, key.clone(), |filter| {
+        filter.add(parser.get_slice(2)?)
+    })?;
+    db.notify(NOTIFY_MODULE, "bf.add", db_index, &key);
+    Ok(Response::Integer(added as i64))
+}
+/// Adds several items, a full non scaling filter gives an error for the
+/// remaining ones instead of failing the whole command.
+pub fn bf_madd(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let result = db.update_or_create_bloom_filter(db_index, key.clone(), |filter| {
+        let mut result = vec![];
+        for i in 2..parser.argv.len() {
+            result.push(match filter.add(parser.get_slice(i)?) {
+                Ok(added) => Response::Integer(added as i64),
+                Err(e) => Response::Error(e.to_string()),
+            });
+        }
+        Ok(result)
+    })?;
+    db.notify(NOTIFY_MODULE, "bf.add", db_index, &key);
+    Ok(Response::Array(result))
+}
+pub fn bf_exists(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 3, "InvalidArgument");
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let exists = match db.get_bloom_filter(db_index, parser.get_slice(1)?)? {
+        Some(filter) => filter.exists(parser.get_slice(2)?),
+        None => false,
+    };
+    Ok(Response::Integer(exists as i64))
+}
+pub fn bf_mexists(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let filter = db.get_bloom_filter(db_index, parser.get_slice(1)?)?;
+    let mut result = vec![];
+    for i in 2..parser.argv.len() {
+        let item = parser.get_slice(i)?;
+        let exists = filter.is_some_and(|filter| filter.exists(item));
+        result.push(Response::Integer(exists as i64));
+    }
+    Ok(Response::Array(result))
+}
@@ -0,0 +1,140 @@
+use crate::database::lib::DatabaseHolder;
+use crate::database::tracking::TrackingOptions;
+use crate::parser::response::Response;
+use crate::vojo::parsered_command::ParsedCommand;
+use anyhow::{anyhow, ensure};
+
+/// CLIENT ID, TRACKING, CACHING and GETREDIR, which act on the connection `client_id`.
+pub fn client(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    client_id: u64,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let tracking = &database_lock.tracking;
+    let subcommand = parser.get_str(1)?.to_uppercase();
+    match subcommand.as_str() {
+        "ID" => {
+            ensure!(parser.argv.len() == 2, "InvalidArgument");
+            Ok(Response::Integer(client_id as i64))
+        }
+        "TRACKING" => {
+            ensure!(parser.argv.len() >= 3, "InvalidArgument");
+            let on = match parser.get_str(2)?.to_uppercase().as_str() {
+                "ON" => true,
+                "OFF" => false,
+                _ => return Err(anyhow!("syntax error")),
+            };
+            let options = parse_tracking_options(&parser, 3)?;
+            if on {
+                tracking.enable(client_id, options)?;
+            } else {
+                tracking.disable(client_id);
+            }
+            Ok(Response::Status(String::from("OK")))
+        }
+        "CACHING" => {
+            ensure!(parser.argv.len() == 3, "InvalidArgument");
+            let yes = match parser.get_str(2)?.to_uppercase().as_str() {
+                "YES" => true,
+                "NO" => false,
+                _ => return Err(anyhow!("syntax error")),
+            };
+            tracking.set_caching(client_id, yes)?;
+            Ok(Response::Status(String::from("OK")))
+        }
+        "GETREDIR" => {
+            ensure!(parser.argv.len() == 2, "InvalidArgument");
+            Ok(Response::Integer(tracking.redirect(client_id)))
+        }
+        _ => Err(anyhow!(
+            "unknown subcommand '{}'. Try CLIENT HELP.",
+            parser.get_str(1)?
+        )),
+    }
+}
+/// HELLO [protover], switches the connection to RESP2 or RESP3 and describes the server.
+pub fn hello(
+    parser: &ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    client_id: u64,
+    protocol: &mut u8,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() <= 2, "syntax error");
+    if parser.argv.len() == 2 {
+        let version = parser
+            .get_i64(1)
+            .map_err(|_| anyhow!("Protocol version is not an integer or out of range"))?;
+        ensure!(
+            version == 2 || version == 3,
+            "NOPROTO unsupported protocol version"
+        );
+        *protocol = version as u8;
+        database_lock.tracking.set_protocol(client_id, *protocol);
+    }
+    let fields = vec![
+        ("server", Response::Data(b"redis".to_vec())),
+        (
+            "version",
+            Response::Data(env!("CARGO_PKG_VERSION").as_bytes().to_vec()),
+        ),
+        ("proto", Response::Integer(*protocol as i64)),
+        ("id", Response::Integer(client_id as i64)),
+        ("mode", Response::Data(b"standalone".to_vec())),
+        ("role", Response::Data(b"master".to_vec())),
+        ("modules", Response::Array(vec![])),
+    ];
+    let fields = fields
+        .into_iter()
+        .map(|(name, value)| (Response::Data(name.as_bytes().to_vec()), value));
+    match *protocol {
+        3 => Ok(Response::Map(fields.collect())),
+        _ => Ok(Response::Array(
+            fields.flat_map(|(name, value)| [name, value]).collect(),
+        )),
+    }
+}
+fn parse_tracking_options(
+    parser: &ParsedCommand,
+    start: usize,
+) -> Result<TrackingOptions, anyhow::Error> {
+    let mut options = TrackingOptions::default();
+    let mut i = start;
+    while i < parser.argv.len() {
+        match parser.get_str(i)?.to_uppercase().as_str() {
+            "REDIRECT" => {
+                ensure!(i + 1 < parser.argv.len(), "syntax error");
+                let id = parser
+                    .get_i64(i + 1)
+                    .map_err(|_| anyhow!("value is not an integer or out of range"))?;
+                ensure!(id > 0, "The client ID you want redirect to does not exist");
+                options.redirect = Some(id as u64);
+                i += 1;
+            }
+            "PREFIX" => {
+                ensure!(i + 1 < parser.argv.len(), "syntax error");
+                options.prefixes.push(parser.get_vec(i + 1)?);
+                i += 1;
+            }
+            "BCAST" => options.bcast = true,
+            "OPTIN" => options.optin = true,
+            "OPTOUT" => options.optout = true,
+            "NOLOOP" => options.noloop = true,
+            _ => return Err(anyhow!("syntax error")),
+        }
+        i += 1;
+    }
+    ensure!(
+        options.bcast || options.prefixes.is_empty(),
+        "PREFIX option requires BCAST mode to be enabled"
+    );
+    ensure!(
+        !(options.optin && options.optout),
+        "You can't use both OPTIN and OPTOUT"
+    );
+    ensure!(
+        !(options.bcast && (options.optin || options.optout)),
+        "OPTIN and OPTOUT are not compatible with BCAST"
+    );
+    Ok(options)
+}
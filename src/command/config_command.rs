@@ -0,0 +1,120 @@
+use crate::database::lib::{Database, DatabaseHolder};
+use crate::database::notify::{notify_flags_to_string, parse_notify_flags};
+use crate::database::persistence::{parse_dbfilename, parse_dir};
+use crate::parser::response::Response;
+use crate::util::glob::glob_match;
+use crate::vojo::parsered_command::ParsedCommand;
+use anyhow::{anyhow, ensure};
+
+/// The parameters CONFIG GET and CONFIG SET know about.
+const PARAMETERS: [&str; 5] = [
+    "notify-keyspace-events",
+    "tracking-table-max-keys",
+    "lua-time-limit",
+    "dir",
+    "dbfilename",
+];
+
+pub fn config(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    _db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let subcommand = parser.get_str(1)?.to_uppercase();
+    match subcommand.as_str() {
+        "GET" => {
+            ensure!(parser.argv.len() >= 3, "InvalidArgument");
+            let db = database_lock
+                .database_lock
+                .lock()
+                .map_err(|e| anyhow!("{}", e))?;
+            let mut response = vec![];
+            for name in PARAMETERS {
+                let requested = (2..parser.argv.len()).any(|i| {
+                    parser.get_str(i).is_ok_and(|pattern| {
+                        glob_match(pattern.to_lowercase().as_bytes(), name.as_bytes())
+                    })
+                });
+                if requested {
+                    response.push(Response::Data(name.as_bytes().to_vec()));
+                    let value = get_parameter(&db, database_lock, name);
+                    response.push(Response::Data(value.into_bytes()));
+                }
+            }
+            Ok(Response::Array(response))
+        }
+        "SET" => {
+            ensure!(
+                parser.argv.len() >= 4 && parser.argv.len().is_multiple_of(2),
+                "InvalidArgument"
+            );
+            let mut db = database_lock
+                .database_lock
+                .lock()
+                .map_err(|e| anyhow!("{}", e))?;
+            // Every value is checked before anything is changed
+            let mut updates = vec![];
+            for i in (2..parser.argv.len()).step_by(2) {
+                let name = parser.get_str(i)?.to_lowercase();
+                ensure!(
+                    PARAMETERS.contains(&name.as_str()),
+                    "Unknown option or number of arguments for CONFIG SET - '{}'",
+                    name
+                );
+                updates.push((name, parser.get_str(i + 1)?));
+            }
+            let mut flags = db.notifier.flags;
+            let mut max_keys = database_lock.tracking.max_keys();
+            let mut time_limit = database_lock.scripts.time_limit();
+            let mut dir = database_lock.rdb_config.dir();
+            let mut dbfilename = database_lock.rdb_config.dbfilename();
+            for (name, value) in updates {
+                let failed = |e: anyhow::Error| {
+                    anyhow!(
+                        "CONFIG SET failed (possibly related to argument '{}') - {}",
+                        name,
+                        e
+                    )
+                };
+                match name.as_str() {
+                    "notify-keyspace-events" => {
+                        flags = parse_notify_flags(value).map_err(failed)?
+                    }
+                    "tracking-table-max-keys" => {
+                        max_keys = value.parse::<usize>().map_err(|_| {
+                            failed(anyhow!("argument couldn't be parsed into an integer"))
+                        })?
+                    }
+                    "lua-time-limit" => {
+                        time_limit = value.parse::<u64>().map_err(|_| {
+                            failed(anyhow!("argument couldn't be parsed into an integer"))
+                        })?
+                    }
+                    "dir" => dir = parse_dir(value).map_err(failed)?,
+                    _ => dbfilename = parse_dbfilename(value).map_err(failed)?,
+                }
+            }
+            db.notifier.flags = flags;
+            database_lock.tracking.set_max_keys(max_keys);
+            database_lock.scripts.set_time_limit(time_limit);
+            database_lock.rdb_config.set_dir(dir);
+            database_lock.rdb_config.set_dbfilename(dbfilename);
+            Ok(Response::Status(String::from("OK")))
+        }
+        _ => Err(anyhow!(
+            "unknown subcommand '{}'. Try CONFIG HELP.",
+            parser.get_str(1)?
+        )),
+    }
+}
+fn get_parameter(db: &Database, database_lock: &DatabaseHolder, name: &str) -> String {
+    match name {
+        "notify-keyspace-events" => notify_flags_to_string(db.notifier.flags),
+        "tracking-table-max-keys" => database_lock.tracking.max_keys().to_string(),
+        "lua-time-limit" => database_lock.scripts.time_limit().to_string(),
+        "dir" => database_lock.rdb_config.dir().display().to_string(),
+        "dbfilename" => database_lock.rdb_config.dbfilename(),
+        _ => String::new(),
+    }
+}
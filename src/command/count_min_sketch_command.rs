@@ -0,0 +1,193 @@
+use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_MODULE;
+use crate::parser::response::Response;
+use crate::vojo::count_min_sketch::ValueCountMinSketch;
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::value::Value;
+use anyhow::{anyhow, ensure};
+
+pub fn cms_initbydim(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 4, "InvalidArgument");
+    let width = parser
+        .get_i64(2)
+        .map_err(|_| anyhow!("CMS: invalid width"))?;
+    ensure!(width > 0 && width <= u32::MAX as i64, "CMS: invalid width");
+    let depth = parser
+        .get_i64(3)
+        .map_err(|_| anyhow!("CMS: invalid depth"))?;
+    ensure!(depth > 0 && depth <= u32::MAX as i64, "CMS: invalid depth");
+    create(
+        parser,
+        database_lock,
+        db_index,
+        ValueCountMinSketch::new(width as u32, depth as u32)?,
+    )
+}
+pub fn cms_initbyprob(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 4, "InvalidArgument");
+    let error = parser
+        .get_f64(2)
+        .map_err(|_| anyhow!("CMS: invalid overestimation value"))?;
+    ensure!(
+        error > 0.0 && error < 1.0,
+        "CMS: invalid overestimation value"
+    );
+    let probability = parser
+        .get_f64(3)
+        .map_err(|_| anyhow!("CMS: invalid prob value"))?;
+    ensure!(
+        probability > 0.0 && probability < 1.0,
+        "CMS: invalid prob value"
+    );
+    let (width, depth) = ValueCountMinSketch::dimensions_for(error, probability);
+    create(
+        parser,
+        database_lock,
+        db_index,
+        ValueCountMinSketch::new(width, depth)?,
+    )
+}
+fn create(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+    sketch: ValueCountMinSketch,
+) -> Result<Response, anyhow::Error> {
+    let key = parser.get_vec(1)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    ensure!(
+        db.get(db_index, key.clone())?.is_none(),
+        "CMS: key already exists"
+    );
+    db.insert(db_index, key.clone(), Value::CountMinSketch(sketch))?;
+    // cms.initbydim or cms.initbyprob
+    let event = parser.get_str(0)?.to_lowercase();
+    db.notify(NOTIFY_MODULE, &event, db_index, &key);
+    Ok(Response::Status("OK".to_owned()))
+}
+pub fn cms_incrby(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() >= 4 && parser.argv.len().is_multiple_of(2),
+        "InvalidArgument"
+    );
+    let mut increments = vec![];
+    for i in (2..parser.argv.len()).step_by(2) {
+        let increment = parser
+            .get_i64(i + 1)
+            .ok()
+            .filter(|value| *value >= 0 && *value <= u32::MAX as i64)
+            .ok_or(anyhow!("CMS: Cannot parse number"))?;
+        increments.push((parser.get_slice(i)?, increment as u32));
+    }
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let key = parser.get_slice(1)?;
+    let sketch = db
+        .get_count_min_sketch_mut(db_index, key)?
+        .ok_or(anyhow!("CMS: key does not exist"))?;
+    let mut result = vec![];
+    let mut overflow = None;
+    for (item, increment) in increments {
+        match sketch.incr_by(item, increment) {
+            Ok(count) => result.push(Response::Integer(count as i64)),
+            Err(e) => {
+                overflow = Some(e);
+                break;
+            }
+        }
+    }
+    // The items before an overflow were counted
+    if !result.is_empty() {
+        db.modified(db_index, key);
+        db.notify(NOTIFY_MODULE, "cms.incrby", db_index, key);
+    }
+    match overflow {
+        Some(e) => Err(e),
+        None => Ok(Response::Array(result)),
+    }
+}
+pub fn cms_query(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let sketch = db
+        .get_count_min_sketch(db_index, parser.get_slice(1)?)?
+        .ok_or(anyhow!("CMS: key does not exist"))?;
+    let mut result = vec![];
+    for i in 2..parser.argv.len() {
+        result.push(Response::Integer(sketch.query(parser.get_slice(i)?) as i64));
+    }
+    Ok(Response::Array(result))
+}
+/// `CMS.MERGE destination numKeys source [source ...] [WEIGHTS weight [weight ...]]`,
+/// the destination must exist and have the same dimensions as the sources.
+pub fn cms_merge(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
+    let key_count = parser
+        .get_i64(2)
+        .ok()
+        .filter(|value| *value > 0)
+        .ok_or(anyhow!("CMS: invalid numkeys"))? as usize;
+    ensure!(parser.argv.len() >= 3 + key_count, "InvalidArgument");
+    let mut weights = vec![1; key_count];
+    let weights_pos = 3 + key_count;
+    if parser.argv.len() > weights_pos {
+        ensure!(
+            parser.get_str(weights_pos)?.eq_ignore_ascii_case("WEIGHTS")
+                && parser.argv.len() == weights_pos + 1 + key_count,
+            "InvalidArgument"
+        );
+        for (i, weight) in weights.iter_mut().enumerate() {
+            *weight = parser
+                .get_i64(weights_pos + 1 + i)
+                .map_err(|_| anyhow!("CMS: invalid weight value"))?;
+        }
+    }
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let mut sources = vec![];
+    for i in 0..key_count {
+        let source = db
+            .get_count_min_sketch(db_index, parser.get_slice(3 + i)?)?
+            .ok_or(anyhow!("CMS: key does not exist"))?;
+        sources.push(source.clone());
+    }
+    let key = parser.get_slice(1)?;
+    let destination = db
+        .get_count_min_sketch_mut(db_index, key)?
+        .ok_or(anyhow!("CMS: key does not exist"))?;
+    let sources: Vec<(&ValueCountMinSketch, i64)> = sources.iter().zip(weights).collect();
+    destination.merge(&sources)?;
+    db.modified(db_index, key);
+    db.notify(NOTIFY_MODULE, "cms.merge", db_index, key);
+    Ok(Response::Status("OK".to_owned()))
+}
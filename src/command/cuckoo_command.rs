@@ -0,0 +1,114 @@
+use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_MODULE;
+use crate::parser::response::Response;
+use crate::vojo::cuckoo::{
+    ValueCuckooFilter, CUCKOO_DEFAULT_BUCKET_SIZE, CUCKOO_DEFAULT_EXPANSION,
+    CUCKOO_DEFAULT_MAX_ITERATIONS,
+};
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::value::Value;
+use anyhow::{anyhow, ensure};
+
+pub fn cf_reserve(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let capacity = parser.get_i64(2).map_err(|_| anyhow!("Bad capacity"))?;
+    ensure!(capacity > 0, "Bad capacity");
+    let mut bucket_size = CUCKOO_DEFAULT_BUCKET_SIZE;
+    let mut max_iterations = CUCKOO_DEFAULT_MAX_ITERATIONS;
+    let mut expansion = CUCKOO_DEFAULT_EXPANSION;
+    let mut i = 3;
+    while i < parser.argv.len() {
+        let option = parser.get_str(i)?.to_uppercase();
+        let value = parser.get_i64(i + 1);
+        match option.as_str() {
+            "BUCKETSIZE" => {
+                bucket_size = value
+                    .ok()
+                    .filter(|value| (1..=255).contains(value))
+                    .ok_or(anyhow!("Bad bucket size"))? as u32;
+            }
+            "MAXITERATIONS" => {
+                max_iterations = value
+                    .ok()
+                    .filter(|value| (1..=65535).contains(value))
+                    .ok_or(anyhow!("Bad max iterations"))? as u32;
+            }
+            "EXPANSION" => {
+                expansion = value
+                    .ok()
+                    .filter(|value| (0..=32768).contains(value))
+                    .ok_or(anyhow!("Bad expansion"))? as u32;
+            }
+            _ => return Err(anyhow!("syntax error")),
+        }
+        i += 2;
+    }
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    ensure!(db.get(db_index, key.clone())?.is_none(), "item exists");
+    let filter = ValueCuckooFilter::new(capacity as u64, bucket_size, max_iterations, expansion)?;
+    db.insert(db_index, key.clone(), Value::CuckooFilter(filter))?;
+    db.notify(NOTIFY_MODULE, "cf.reserve", db_index, &key);
+    Ok(Response::Status("OK".to_owned()))
+}
+pub fn cf_add(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 3, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    db.update_or_create_cuckoo_filter(db_index, key.clone(), |filter| {
+        filter.add(parser.get_slice(2)?)
+    })?;
+    db.notify(NOTIFY_MODULE, "cf.add", db_index, &key);
+    Ok(Response::Integer(1))
+}
+pub fn cf_exists(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 3, "InvalidArgument");
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let exists = match db.get_cuckoo_filter(db_index, parser.get_slice(1)?)? {
+        Some(filter) => filter.exists(parser.get_slice(2)?),
+        None => false,
+    };
+    Ok(Response::Integer(exists as i64))
+}
+pub fn cf_del(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 3, "InvalidArgument");
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let key = parser.get_slice(1)?;
+    let filter = db
+        .get_cuckoo_filter_mut(db_index, key)?
+        .ok_or(anyhow!("Not found"))?;
+    let deleted = filter.delete(parser.get_slice(2)?);
+    if deleted {
+        db.modified(db_index, key);
+        db.notify(NOTIFY_MODULE, "cf.del", db_index, key);
+    }
+    Ok(Response::Integer(deleted as i64))
+}
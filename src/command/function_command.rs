@@ -0,0 +1,178 @@
+use crate::command::script_command::load_library;
+use crate::database::function::{FunctionLibraries, Library};
+use crate::database::lib::DatabaseHolder;
+use crate::parser::response::Response;
+use crate::util::glob::glob_match;
+use crate::vojo::parsered_command::ParsedCommand;
+use anyhow::{anyhow, ensure};
+use bincode::config;
+
+/// The first byte of a FUNCTION DUMP payload, for the payloads of later versions
+/// to be refused.
+const DUMP_VERSION: u8 = 1;
+
+/// FUNCTION LOAD, LIST, DELETE, FLUSH, DUMP and RESTORE. LOAD runs with the
+/// database claimed, like a script.
+pub fn function(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    _db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let subcommand = parser.get_str(1)?.to_uppercase();
+    if subcommand == "LOAD" {
+        ensure!(
+            parser.argv.len() == 3 || parser.argv.len() == 4,
+            "InvalidArgument"
+        );
+        let replace = parser.argv.len() == 4;
+        if replace {
+            ensure!(
+                parser.get_slice(2)?.eq_ignore_ascii_case(b"REPLACE"),
+                "Unknown option given: {}",
+                parser.get_str(2)?
+            );
+        }
+        // The lock is only taken once the code of the library ran
+        let library = load_library(
+            &database_lock.scripts,
+            parser.get_str(parser.argv.len() - 1)?,
+        )?;
+        let name = library.name.clone();
+        let mut db = database_lock
+            .database_lock
+            .lock()
+            .map_err(|e| anyhow!("{}", e))?;
+        db.functions.load(library, replace)?;
+        return Ok(Response::Data(name.into_bytes()));
+    }
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let functions = &mut db.functions;
+    match subcommand.as_str() {
+        "LIST" => {
+            let mut with_code = false;
+            let mut pattern = None;
+            let mut i = 2;
+            while i < parser.argv.len() {
+                match parser.get_str(i)?.to_uppercase().as_str() {
+                    "WITHCODE" => with_code = true,
+                    "LIBRARYNAME" => {
+                        ensure!(
+                            i + 1 < parser.argv.len(),
+                            "library name argument was not given"
+                        );
+                        pattern = Some(parser.get_slice(i + 1)?);
+                        i += 1;
+                    }
+                    _ => return Err(anyhow!("Unknown argument {}", parser.get_str(i)?)),
+                }
+                i += 1;
+            }
+            let libraries = functions
+                .libraries
+                .values()
+                .filter(|library| {
+                    pattern.is_none_or(|pattern| glob_match(pattern, library.name.as_bytes()))
+                })
+                .map(|library| describe_library(library, with_code))
+                .collect();
+            Ok(Response::Array(libraries))
+        }
+        "DELETE" => {
+            ensure!(parser.argv.len() == 3, "InvalidArgument");
+            functions.delete(parser.get_str(2)?)?;
+            Ok(Response::Status(String::from("OK")))
+        }
+        "FLUSH" => {
+            ensure!(parser.argv.len() <= 3, "InvalidArgument");
+            if parser.argv.len() == 3 {
+                ensure!(
+                    parser.get_slice(2)?.eq_ignore_ascii_case(b"ASYNC")
+                        || parser.get_slice(2)?.eq_ignore_ascii_case(b"SYNC"),
+                    "FUNCTION FLUSH only supports SYNC|ASYNC option"
+                );
+            }
+            functions.flush();
+            Ok(Response::Status(String::from("OK")))
+        }
+        "DUMP" => {
+            ensure!(parser.argv.len() == 2, "InvalidArgument");
+            let libraries: Vec<&Library> = functions.libraries.values().collect();
+            let mut payload = vec![DUMP_VERSION];
+            payload.extend(bincode::encode_to_vec(libraries, config::standard())?);
+            Ok(Response::Data(payload))
+        }
+        "RESTORE" => {
+            ensure!(
+                parser.argv.len() == 3 || parser.argv.len() == 4,
+                "InvalidArgument"
+            );
+            let payload = parser.get_slice(2)?;
+            let libraries: Vec<Library> = match payload.split_first() {
+                Some((&DUMP_VERSION, encoded)) => {
+                    bincode::decode_from_slice(encoded, config::standard())
+                        .map(|(libraries, _)| libraries)
+                        .map_err(|_| anyhow!("payload version or checksum are wrong"))?
+                }
+                _ => return Err(anyhow!("payload version or checksum are wrong")),
+            };
+            let policy = match parser.argv.len() {
+                4 => parser.get_str(3)?.to_uppercase(),
+                _ => String::from("APPEND"),
+            };
+            // Nothing is restored when one of the libraries can not be
+            let mut restored = match policy.as_str() {
+                "FLUSH" => FunctionLibraries::default(),
+                "APPEND" | "REPLACE" => functions.clone(),
+                _ => {
+                    return Err(anyhow!(
+                    "Wrong restore policy given, value should be either FLUSH, APPEND or REPLACE."
+                ))
+                }
+            };
+            for library in libraries {
+                restored.load(library, policy == "REPLACE")?;
+            }
+            *functions = restored;
+            Ok(Response::Status(String::from("OK")))
+        }
+        _ => Err(anyhow!(
+            "unknown subcommand '{}'. Try FUNCTION HELP.",
+            parser.get_str(1)?
+        )),
+    }
+}
+/// A library as FUNCTION LIST shows it, the name of each field followed by its value.
+fn describe_library(library: &Library, with_code: bool) -> Response {
+    let data = |text: &str| Response::Data(text.as_bytes().to_vec());
+    let functions = library
+        .functions
+        .iter()
+        .map(|function| {
+            Response::Array(vec![
+                data("name"),
+                data(&function.name),
+                data("description"),
+                function.description.as_deref().map_or(Response::Nil, data),
+                data("flags"),
+                Response::Array(function.flags.iter().map(|flag| data(flag)).collect()),
+            ])
+        })
+        .collect();
+    let mut fields = vec![
+        data("library_name"),
+        data(&library.name),
+        data("engine"),
+        data(&library.engine),
+        data("functions"),
+        Response::Array(functions),
+    ];
+    if with_code {
+        fields.push(data("library_code"));
+        fields.push(data(&library.code));
+    }
+    Response::Array(fields)
+}
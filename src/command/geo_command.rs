@@ -0,0 +1,445 @@
+use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_ZSET;
+use crate::parser::response::Response;
+use crate::util::common_utils::f64_to_bytes;
+use crate::util::geohash::{
+    covering_score_ranges, decode_score, distance, distance_if_in_shape, encode, to_geohash_string,
+    validate_coordinates, GeoShape, GEO_STEP_MAX,
+};
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::value::{ValueSortedSet, ZaddOptions, ZaddOutcome};
+use anyhow::{anyhow, ensure};
+use std::collections::HashMap;
+
+pub fn geoadd(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 5, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let mut options = ZaddOptions::default();
+    let mut ch = false;
+    let mut i = 2;
+    while i < parser.argv.len() {
+        match parser.get_vec(i)?.to_ascii_uppercase().as_slice() {
+            b"NX" => options.nx = true,
+            b"XX" => options.xx = true,
+            b"CH" => ch = true,
+            _ => break,
+        }
+        i += 1;
+    }
+    ensure!(
+        !(options.nx && options.xx),
+        "XX and NX options at the same time are not compatible"
+    );
+    let triplet_count = parser.argv.len() - i;
+    ensure!(
+        triplet_count > 0 && triplet_count.is_multiple_of(3),
+        "syntax error. Try GEOADD key [x1] [y1] [name1] [x2] [y2] [name2] ... "
+    );
+    let mut points = vec![];
+    while i < parser.argv.len() {
+        let longitude = parser.get_f64(i)?;
+        let latitude = parser.get_f64(i + 1)?;
+        ensure!(
+            validate_coordinates(longitude, latitude),
+            "invalid longitude,latitude pair {:.6},{:.6}",
+            longitude,
+            latitude
+        );
+        let score = encode(longitude, latitude, GEO_STEP_MAX).bits as f64;
+        points.push((score, parser.get_vec(i + 2)?));
+        i += 3;
+    }
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let mut added = 0;
+    let mut changed = 0;
+    for (score, member) in points {
+        match db.zadd(db_index, key.clone(), score, member, &options)? {
+            ZaddOutcome::Added(_) => added += 1,
+            ZaddOutcome::Updated(_) => changed += 1,
+            _ => {}
+        }
+    }
+    if added > 0 {
+        database_lock.blocking_keys.signal(db_index, &key);
+    }
+    if ch {
+        Ok(Response::Integer(added + changed))
+    } else {
+        Ok(Response::Integer(added))
+    }
+}
+pub fn geopos(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let sorted_set = db.get_sorted_set(db_index, &key)?;
+    let mut responses = vec![];
+    for i in 2..parser.argv.len() {
+        let score = sorted_set.and_then(|value| value.score(parser.get_slice(i).ok()?));
+        responses.push(match score {
+            Some(score) => coordinates_response(decode_score(score)),
+            None => Response::Nil,
+        });
+    }
+    Ok(Response::Array(responses))
+}
+pub fn geodist(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 4 || parser.argv.len() == 5,
+        "InvalidArgument"
+    );
+    let key = parser.get_vec(1)?;
+    let conversion = if parser.argv.len() == 5 {
+        parse_unit(&parser, 4)?
+    } else {
+        1.0
+    };
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let Some(sorted_set) = db.get_sorted_set(db_index, &key)? else {
+        return Ok(Response::Nil);
+    };
+    let (Some(first), Some(second)) = (
+        sorted_set.score(parser.get_slice(2)?),
+        sorted_set.score(parser.get_slice(3)?),
+    ) else {
+        return Ok(Response::Nil);
+    };
+    let (long1, lat1) = decode_score(first);
+    let (long2, lat2) = decode_score(second);
+    let meters = distance(long1, lat1, long2, lat2);
+    Ok(Response::Data(format_distance(meters / conversion)))
+}
+pub fn geohash(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let sorted_set = db.get_sorted_set(db_index, &key)?;
+    let mut responses = vec![];
+    for i in 2..parser.argv.len() {
+        let score = sorted_set.and_then(|value| value.score(parser.get_slice(i).ok()?));
+        responses.push(match score {
+            Some(score) => Response::Data(to_geohash_string(score)),
+            None => Response::Nil,
+        });
+    }
+    Ok(Response::Array(responses))
+}
+pub fn geosearch(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 6, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let args = parse_geosearch_args(&parser, 2, false)?;
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let Some(sorted_set) = db.get_sorted_set(db_index, &key)? else {
+        return Ok(Response::Array(vec![]));
+    };
+    let points = geo_search(sorted_set, &args)?;
+    let with_any = args.with_coord || args.with_dist || args.with_hash;
+    let responses = points
+        .into_iter()
+        .map(|point| {
+            if !with_any {
+                return Response::Data(point.member);
+            }
+            let mut item = vec![Response::Data(point.member)];
+            if args.with_dist {
+                item.push(Response::Data(format_distance(
+                    point.distance / args.conversion,
+                )));
+            }
+            if args.with_hash {
+                item.push(Response::Integer(point.score as i64));
+            }
+            if args.with_coord {
+                item.push(coordinates_response(point.coordinates));
+            }
+            Response::Array(item)
+        })
+        .collect();
+    Ok(Response::Array(responses))
+}
+pub fn geosearchstore(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 7, "InvalidArgument");
+    let destination = parser.get_vec(1)?;
+    let source = parser.get_vec(2)?;
+    let args = parse_geosearch_args(&parser, 3, true)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let scores: HashMap<Vec<u8>, f64> = match db.get_sorted_set(db_index, &source)? {
+        Some(sorted_set) => geo_search(sorted_set, &args)?
+            .into_iter()
+            .map(|point| {
+                let score = if args.store_dist {
+                    point.distance / args.conversion
+                } else {
+                    point.score
+                };
+                (point.member, score)
+            })
+            .collect(),
+        None => HashMap::new(),
+    };
+    let len = db.zstore(
+        db_index,
+        destination.clone(),
+        ValueSortedSet::from_scores(scores),
+    )?;
+    if len > 0 {
+        db.notify(NOTIFY_ZSET, "geosearchstore", db_index, &destination);
+        database_lock.blocking_keys.signal(db_index, &destination);
+    }
+    Ok(Response::Integer(len as i64))
+}
+
+enum GeoFrom {
+    Member(Vec<u8>),
+    Coordinates(f64, f64),
+}
+struct GeoSearchArgs {
+    from: GeoFrom,
+    shape: GeoShape,
+    /// The number of meters in the unit of the shape
+    conversion: f64,
+    descending: Option<bool>,
+    count: Option<usize>,
+    any: bool,
+    with_coord: bool,
+    with_dist: bool,
+    with_hash: bool,
+    store_dist: bool,
+}
+struct GeoPoint {
+    member: Vec<u8>,
+    score: f64,
+    coordinates: (f64, f64),
+    /// The distance to the center in meters
+    distance: f64,
+}
+fn geo_search(
+    sorted_set: &ValueSortedSet,
+    args: &GeoSearchArgs,
+) -> Result<Vec<GeoPoint>, anyhow::Error> {
+    let center = match &args.from {
+        GeoFrom::Member(member) => decode_score(
+            sorted_set
+                .score(member)
+                .ok_or(anyhow!("could not decode requested zset member"))?,
+        ),
+        GeoFrom::Coordinates(longitude, latitude) => (*longitude, *latitude),
+    };
+    let limit = if args.any { args.count } else { None };
+    let mut points = vec![];
+    'ranges: for (min, max) in covering_score_ranges(&args.shape, center.0, center.1) {
+        for item in sorted_set.range_by_score(min, max) {
+            let coordinates = decode_score(item.score);
+            if let Some(distance) = distance_if_in_shape(&args.shape, center, coordinates) {
+                points.push(GeoPoint {
+                    member: item.member.clone(),
+                    score: item.score,
+                    coordinates,
+                    distance,
+                });
+                if limit == Some(points.len()) {
+                    break 'ranges;
+                }
+            }
+        }
+    }
+    // COUNT without ANY returns the closest points
+    let descending = match (args.descending, args.count, args.any) {
+        (None, Some(_), false) => Some(false),
+        (descending, _, _) => descending,
+    };
+    if let Some(descending) = descending {
+        points.sort_by(|a, b| {
+            let ordering = a.distance.total_cmp(&b.distance);
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+    if let Some(count) = args.count {
+        points.truncate(count);
+    }
+    Ok(points)
+}
+/// Parses the arguments shared by GEOSEARCH and GEOSEARCHSTORE starting at `start`.
+fn parse_geosearch_args(
+    parser: &ParsedCommand,
+    start: usize,
+    store: bool,
+) -> Result<GeoSearchArgs, anyhow::Error> {
+    let mut from = None;
+    let mut by = None;
+    let mut args = GeoSearchArgs {
+        from: GeoFrom::Coordinates(0.0, 0.0),
+        shape: GeoShape::Radius(0.0),
+        conversion: 1.0,
+        descending: None,
+        count: None,
+        any: false,
+        with_coord: false,
+        with_dist: false,
+        with_hash: false,
+        store_dist: false,
+    };
+    let mut i = start;
+    while i < parser.argv.len() {
+        match parser.get_vec(i)?.to_ascii_uppercase().as_slice() {
+            b"FROMMEMBER" if from.is_none() => {
+                from = Some(GeoFrom::Member(parser.get_vec(i + 1)?));
+                i += 2;
+            }
+            b"FROMLONLAT" if from.is_none() => {
+                let longitude = parser.get_f64(i + 1)?;
+                let latitude = parser.get_f64(i + 2)?;
+                ensure!(
+                    validate_coordinates(longitude, latitude),
+                    "invalid longitude,latitude pair {:.6},{:.6}",
+                    longitude,
+                    latitude
+                );
+                from = Some(GeoFrom::Coordinates(longitude, latitude));
+                i += 3;
+            }
+            b"FROMMEMBER" | b"FROMLONLAT" => {
+                return Err(anyhow!(
+                    "exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"
+                ))
+            }
+            b"BYRADIUS" if by.is_none() => {
+                let radius = parse_size(parser, i + 1, "radius")?;
+                args.conversion = parse_unit(parser, i + 2)?;
+                by = Some(GeoShape::Radius(radius * args.conversion));
+                i += 3;
+            }
+            b"BYBOX" if by.is_none() => {
+                let width = parse_size(parser, i + 1, "width")?;
+                let height = parse_size(parser, i + 2, "height")?;
+                args.conversion = parse_unit(parser, i + 3)?;
+                by = Some(GeoShape::Box {
+                    width: width * args.conversion,
+                    height: height * args.conversion,
+                });
+                i += 4;
+            }
+            b"BYRADIUS" | b"BYBOX" => {
+                return Err(anyhow!(
+                    "exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH"
+                ))
+            }
+            b"ASC" => {
+                args.descending = Some(false);
+                i += 1;
+            }
+            b"DESC" => {
+                args.descending = Some(true);
+                i += 1;
+            }
+            b"COUNT" => {
+                let count = parser.get_i64(i + 1)?;
+                ensure!(count > 0, "COUNT must be > 0");
+                args.count = Some(count as usize);
+                i += 2;
+                if i < parser.argv.len() && parser.get_vec(i)?.eq_ignore_ascii_case(b"ANY") {
+                    args.any = true;
+                    i += 1;
+                }
+            }
+            b"WITHCOORD" if !store => {
+                args.with_coord = true;
+                i += 1;
+            }
+            b"WITHDIST" if !store => {
+                args.with_dist = true;
+                i += 1;
+            }
+            b"WITHHASH" if !store => {
+                args.with_hash = true;
+                i += 1;
+            }
+            b"STOREDIST" if store => {
+                args.store_dist = true;
+                i += 1;
+            }
+            _ => return Err(anyhow!("syntax error")),
+        }
+    }
+    args.from = from.ok_or(anyhow!(
+        "exactly one of FROMMEMBER or FROMLONLAT can be specified for GEOSEARCH"
+    ))?;
+    args.shape = by.ok_or(anyhow!(
+        "exactly one of BYRADIUS and BYBOX can be specified for GEOSEARCH"
+    ))?;
+    Ok(args)
+}
+fn parse_size(parser: &ParsedCommand, pos: usize, name: &str) -> Result<f64, anyhow::Error> {
+    let size = parser
+        .get_f64(pos)
+        .map_err(|_| anyhow!("need numeric {}", name))?;
+    ensure!(size >= 0.0, "{} cannot be negative", name);
+    Ok(size)
+}
+/// Returns the number of meters in the unit.
+fn parse_unit(parser: &ParsedCommand, pos: usize) -> Result<f64, anyhow::Error> {
+    match parser.get_vec(pos)?.to_ascii_lowercase().as_slice() {
+        b"m" => Ok(1.0),
+        b"km" => Ok(1000.0),
+        b"ft" => Ok(0.3048),
+        b"mi" => Ok(1609.34),
+        _ => Err(anyhow!(
+            "unsupported unit provided. please use M, KM, FT, MI"
+        )),
+    }
+}
+fn format_distance(distance: f64) -> Vec<u8> {
+    format!("{distance:.4}").into_bytes()
+}
+fn coordinates_response((longitude, latitude): (f64, f64)) -> Response {
+    Response::Array(vec![
+        Response::Data(f64_to_bytes(longitude)),
+        Response::Data(f64_to_bytes(latitude)),
+    ])
+}
@@ -1,6 +1,7 @@
 use anyhow::{anyhow, ensure};
 
 use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_HASH;
 use crate::parser::response::Response;
 use crate::vojo::parsered_command::ParsedCommand;
 
@@ -20,6 +21,7 @@ pub  fn hset(
             len += 1;
         }
     }
+    db.notify(NOTIFY_HASH, "hset", db_index, &key);
 
     Ok(Response::Integer(len as i64))
 }
@@ -0,0 +1,80 @@
+use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_STRING;
+use crate::parser::response::Response;
+use crate::vojo::hyperloglog::ValueHyperLogLog;
+use crate::vojo::parsered_command::ParsedCommand;
+use anyhow::{anyhow, ensure};
+
+pub fn pfadd(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let changed =
+        db.update_or_create_hyperloglog(db_index, key.clone(), |hyperloglog, created| {
+            let mut changed = created;
+            for i in 2..parser.argv.len() {
+                changed |= hyperloglog.add(parser.get_slice(i)?);
+            }
+            Ok(changed)
+        })?;
+    if changed {
+        db.notify(NOTIFY_STRING, "pfadd", db_index, &key);
+    }
+    Ok(Response::Integer(changed as i64))
+}
+pub fn pfcount(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    if parser.argv.len() == 2 {
+        let count = match db.get_hyperloglog(db_index, parser.get_slice(1)?)? {
+            Some(hyperloglog) => hyperloglog.count(),
+            None => 0,
+        };
+        return Ok(Response::Integer(count as i64));
+    }
+    let mut union = ValueHyperLogLog::new();
+    for i in 1..parser.argv.len() {
+        if let Some(hyperloglog) = db.get_hyperloglog(db_index, parser.get_slice(i)?)? {
+            union.merge(hyperloglog);
+        }
+    }
+    Ok(Response::Integer(union.count() as i64))
+}
+pub fn pfmerge(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let destination = parser.get_vec(1)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let mut union = ValueHyperLogLog::new();
+    for i in 2..parser.argv.len() {
+        if let Some(hyperloglog) = db.get_hyperloglog(db_index, parser.get_slice(i)?)? {
+            union.merge(hyperloglog);
+        }
+    }
+    db.update_or_create_hyperloglog(db_index, destination.clone(), |hyperloglog, _| {
+        hyperloglog.merge(&union);
+        Ok(())
+    })?;
+    db.notify(NOTIFY_STRING, "pfadd", db_index, &destination);
+    Ok(Response::Status("OK".to_owned()))
+}
@@ -0,0 +1,455 @@
+use crate::database::lib::Database;
+use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_MODULE;
+use crate::parser::response::Response;
+use crate::util::json_path::JsonPath;
+use crate::vojo::json::{format_json, parse_json, parse_number, JsonFormat, ValueJson};
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::value::Value;
+use anyhow::{anyhow, ensure};
+
+pub fn json_set(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 4 || parser.argv.len() == 5,
+        "InvalidArgument"
+    );
+    let key = parser.get_vec(1)?;
+    let path = JsonPath::parse(parser.get_str(2)?)?;
+    let value = parse_json(parser.get_slice(3)?)?;
+    let (nx, xx) = if parser.argv.len() == 5 {
+        match parser.get_str(4)?.to_uppercase().as_str() {
+            "NX" => (true, false),
+            "XX" => (false, true),
+            _ => return Err(anyhow!("syntax error")),
+        }
+    } else {
+        (false, false)
+    };
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let set = match db.get_json_mut(db_index, &key)? {
+        Some(json) => {
+            let set = json.set(&path, value, nx, xx);
+            if set {
+                db.modified(db_index, &key);
+                db.reindex(db_index, &key);
+                db.notify(NOTIFY_MODULE, "json.set", db_index, &key);
+            }
+            set
+        }
+        None => {
+            ensure!(path.is_root(), "new objects must be created at the root");
+            if !xx {
+                db.insert(db_index, key.clone(), Value::Json(ValueJson::new(value)))?;
+                db.notify(NOTIFY_MODULE, "json.set", db_index, &key);
+            }
+            !xx
+        }
+    };
+    if set {
+        Ok(Response::Status(String::from("OK")))
+    } else {
+        Ok(Response::Nil)
+    }
+}
+pub fn json_get(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let mut format = JsonFormat::default();
+    let mut paths = vec![];
+    let mut i = 2;
+    while i < parser.argv.len() {
+        let option = parser.get_str(i)?.to_uppercase();
+        let target = match option.as_str() {
+            "INDENT" => &mut format.indent,
+            "NEWLINE" => &mut format.newline,
+            "SPACE" => &mut format.space,
+            _ => {
+                paths.push((parser.get_str(i)?, JsonPath::parse(parser.get_str(i)?)?));
+                i += 1;
+                continue;
+            }
+        };
+        *target = parser.get_str(i + 1)?.to_string();
+        i += 2;
+    }
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let Some(json) = db.get_json(db_index, &key)? else {
+        return Ok(Response::Nil);
+    };
+    if paths.is_empty() {
+        paths.push((".", JsonPath::root()));
+    }
+    if let [(text, path)] = paths.as_slice() {
+        let result = path_result(json, text, path)?;
+        return Ok(Response::Data(format_json(&result, &format)));
+    }
+    // Several paths reply with an object keyed by path, where every value is an array
+    // unless all the paths are legacy ones.
+    let all_legacy = paths.iter().all(|(_, path)| path.legacy);
+    let mut object = serde_json::Map::new();
+    for (text, path) in paths.iter() {
+        let result = if all_legacy {
+            path_result(json, text, path)?
+        } else {
+            serde_json::Value::Array(json.get(path).into_iter().cloned().collect())
+        };
+        object.insert(text.to_string(), result);
+    }
+    Ok(Response::Data(format_json(
+        &serde_json::Value::Object(object),
+        &format,
+    )))
+}
+pub fn json_mget(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let text = parser.get_str(parser.argv.len() - 1)?;
+    let path = JsonPath::parse(text)?;
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let mut responses = vec![];
+    for i in 1..parser.argv.len() - 1 {
+        // Keys holding another type are replied as missing ones
+        let json = db.get_json(db_index, parser.get_slice(i)?).ok().flatten();
+        let response = match json.map(|json| path_result(json, text, &path)) {
+            Some(Ok(result)) => Response::Data(format_json(&result, &JsonFormat::default())),
+            _ => Response::Nil,
+        };
+        responses.push(response);
+    }
+    Ok(Response::Array(responses))
+}
+pub fn json_del(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 2 || parser.argv.len() == 3,
+        "InvalidArgument"
+    );
+    let key = parser.get_vec(1)?;
+    let path = parse_path(&parser, 2)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    if path.is_root() {
+        let exists = db.get_json(db_index, &key)?.is_some();
+        if exists {
+            db.remove(db_index, &key)?;
+            db.notify(NOTIFY_MODULE, "json.del", db_index, &key);
+        }
+        return Ok(Response::Integer(exists as i64));
+    }
+    let deleted = match db.get_json_mut(db_index, &key)? {
+        Some(json) => {
+            let deleted = json.delete(&path);
+            if deleted > 0 {
+                db.modified(db_index, &key);
+                db.reindex(db_index, &key);
+                db.notify(NOTIFY_MODULE, "json.del", db_index, &key);
+            }
+            deleted
+        }
+        None => 0,
+    };
+    Ok(Response::Integer(deleted as i64))
+}
+pub fn json_numincrby(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let text = parser.get_str(2)?;
+    let path = JsonPath::parse(text)?;
+    let by = parse_number(parser.get_slice(3)?)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let json = get_existing_json(&mut db, db_index, &key)?;
+    let results = json.num_incr_by(&path, &by)?;
+    if results.iter().any(Option::is_some) {
+        db.modified(db_index, &key);
+        db.reindex(db_index, &key);
+        db.notify(NOTIFY_MODULE, "json.numincrby", db_index, &key);
+    }
+    let result = if path.legacy {
+        legacy_result(text, results, "a number")?
+    } else {
+        serde_json::Value::Array(
+            results
+                .into_iter()
+                .map(|result| result.unwrap_or(serde_json::Value::Null))
+                .collect(),
+        )
+    };
+    Ok(Response::Data(format_json(&result, &JsonFormat::default())))
+}
+pub fn json_strappend(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 3 || parser.argv.len() == 4,
+        "InvalidArgument"
+    );
+    let key = parser.get_vec(1)?;
+    let (text, path) = if parser.argv.len() == 4 {
+        (parser.get_str(2)?, JsonPath::parse(parser.get_str(2)?)?)
+    } else {
+        (".", JsonPath::root())
+    };
+    let suffix = match parse_json(parser.get_slice(parser.argv.len() - 1)?)? {
+        serde_json::Value::String(suffix) => suffix,
+        _ => return Err(anyhow!("expected a JSON string")),
+    };
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let json = get_existing_json(&mut db, db_index, &key)?;
+    let results = json.str_append(&path, &suffix);
+    if results.iter().any(Option::is_some) {
+        db.modified(db_index, &key);
+        db.reindex(db_index, &key);
+        db.notify(NOTIFY_MODULE, "json.strappend", db_index, &key);
+    }
+    integer_results(&path, text, results, "a string")
+}
+pub fn json_arrappend(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let text = parser.get_str(2)?;
+    let path = JsonPath::parse(text)?;
+    let mut values = vec![];
+    for i in 3..parser.argv.len() {
+        values.push(parse_json(parser.get_slice(i)?)?);
+    }
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let json = get_existing_json(&mut db, db_index, &key)?;
+    let results = json.arr_append(&path, &values);
+    if results.iter().any(Option::is_some) {
+        db.modified(db_index, &key);
+        db.reindex(db_index, &key);
+        db.notify(NOTIFY_MODULE, "json.arrappend", db_index, &key);
+    }
+    integer_results(&path, text, results, "an array")
+}
+pub fn json_arrpop(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!((2..=4).contains(&parser.argv.len()), "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let text = if parser.argv.len() > 2 {
+        parser.get_str(2)?
+    } else {
+        "."
+    };
+    let path = JsonPath::parse(text)?;
+    let index = if parser.argv.len() == 4 {
+        parser.get_i64(3)?
+    } else {
+        -1
+    };
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let json = get_existing_json(&mut db, db_index, &key)?;
+    let results = json.arr_pop(&path, index);
+    if results.iter().any(|result| matches!(result, Some(Some(_)))) {
+        db.modified(db_index, &key);
+        db.reindex(db_index, &key);
+        db.notify(NOTIFY_MODULE, "json.arrpop", db_index, &key);
+    }
+    let popped_response = |popped: Option<serde_json::Value>| match popped {
+        Some(value) => Response::Data(format_json(&value, &JsonFormat::default())),
+        None => Response::Nil,
+    };
+    if path.legacy {
+        let popped = legacy_result(text, results, "an array")?;
+        return Ok(popped_response(popped));
+    }
+    Ok(Response::Array(
+        results
+            .into_iter()
+            .map(|result| popped_response(result.flatten()))
+            .collect(),
+    ))
+}
+pub fn json_objkeys(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 2 || parser.argv.len() == 3,
+        "InvalidArgument"
+    );
+    let key = parser.get_vec(1)?;
+    let text = if parser.argv.len() == 3 {
+        parser.get_str(2)?
+    } else {
+        "."
+    };
+    let path = JsonPath::parse(text)?;
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let Some(json) = db.get_json(db_index, &key)? else {
+        return Ok(Response::Nil);
+    };
+    let keys_response = |keys: Vec<String>| {
+        Response::Array(
+            keys.into_iter()
+                .map(|key| Response::Data(key.into_bytes()))
+                .collect(),
+        )
+    };
+    let results = json.obj_keys(&path);
+    if path.legacy {
+        return Ok(keys_response(legacy_result(text, results, "an object")?));
+    }
+    Ok(Response::Array(
+        results
+            .into_iter()
+            .map(|result| result.map(keys_response).unwrap_or(Response::Nil))
+            .collect(),
+    ))
+}
+pub fn json_type(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 2 || parser.argv.len() == 3,
+        "InvalidArgument"
+    );
+    let key = parser.get_vec(1)?;
+    let path = parse_path(&parser, 2)?;
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let Some(json) = db.get_json(db_index, &key)? else {
+        return Ok(Response::Nil);
+    };
+    let types = json.types(&path);
+    if path.legacy {
+        return Ok(match types.first() {
+            Some(name) => Response::Status(name.to_string()),
+            None => Response::Nil,
+        });
+    }
+    Ok(Response::Array(
+        types
+            .into_iter()
+            .map(|name| Response::Data(name.as_bytes().to_vec()))
+            .collect(),
+    ))
+}
+
+/// The optional path at `pos`, the root when it is missing.
+fn parse_path(parser: &ParsedCommand, pos: usize) -> Result<JsonPath, anyhow::Error> {
+    if pos < parser.argv.len() {
+        JsonPath::parse(parser.get_str(pos)?)
+    } else {
+        Ok(JsonPath::root())
+    }
+}
+fn get_existing_json<'a>(
+    db: &'a mut Database,
+    db_index: usize,
+    key: &[u8],
+) -> Result<&'a mut ValueJson, anyhow::Error> {
+    db.get_json_mut(db_index, key)?.ok_or(anyhow!(
+        "could not perform this operation on a key that doesn't exist"
+    ))
+}
+/// What JSON.GET replies for one path: the first match of a legacy path,
+/// which must match, or the array of matches of a JSONPath.
+fn path_result(
+    json: &ValueJson,
+    text: &str,
+    path: &JsonPath,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let matches = json.get(path);
+    if path.legacy {
+        matches
+            .first()
+            .map(|value| (*value).clone())
+            .ok_or(anyhow!("Path '{}' does not exist", text))
+    } else {
+        Ok(serde_json::Value::Array(
+            matches.into_iter().cloned().collect(),
+        ))
+    }
+}
+/// A legacy path replies with its first match, which must be of the expected type.
+fn legacy_result<T>(
+    text: &str,
+    results: Vec<Option<T>>,
+    expected: &str,
+) -> Result<T, anyhow::Error> {
+    results
+        .into_iter()
+        .next()
+        .ok_or(anyhow!("Path '{}' does not exist", text))?
+        .ok_or(anyhow!("wrong type of path value - expected {}", expected))
+}
+fn integer_results(
+    path: &JsonPath,
+    text: &str,
+    results: Vec<Option<usize>>,
+    expected: &str,
+) -> Result<Response, anyhow::Error> {
+    if path.legacy {
+        return Ok(Response::Integer(
+            legacy_result(text, results, expected)? as i64
+        ));
+    }
+    Ok(Response::Array(
+        results
+            .into_iter()
+            .map(|result| match result {
+                Some(len) => Response::Integer(len as i64),
+                None => Response::Nil,
+            })
+            .collect(),
+    ))
+}
@@ -1,5 +1,6 @@
 
 use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_LIST;
 use crate::parser::response::Response;
 use crate::vojo::parsered_command::ParsedCommand;
 use anyhow::{anyhow, ensure};
@@ -17,6 +18,7 @@ pub  fn lpush(
         let val = parser.get_vec(i)?;
         len = db.lpush(db_index, key.clone(), val)?;
     }
+    db.notify(NOTIFY_LIST, "lpush", db_index, &key);
 
     Ok(Response::Integer(len as i64))
 }
@@ -34,6 +36,7 @@ pub  fn rpush(
         let val = parser.get_vec(i)?;
         len = db.rpush(db_index, key.clone(), val)?;
     }
+    db.notify(NOTIFY_LIST, "rpush", db_index, &key);
 
     Ok(Response::Integer(len as i64))
 }
@@ -50,7 +53,10 @@ pub  fn lpop(
     } else {
         None
     };
-    let res = db.lpop(db_index, key, count_option)?;
+    let res = db.lpop(db_index, key.clone(), count_option)?;
+    if !matches!(res, Response::Nil) {
+        db.notify(NOTIFY_LIST, "lpop", db_index, &key);
+    }
     Ok(res)
 }
 pub  fn rpop(
@@ -66,7 +72,10 @@ pub  fn rpop(
     } else {
         None
     };
-    let res = db.rpop(db_index, key, count_option)?;
+    let res = db.rpop(db_index, key.clone(), count_option)?;
+    if !matches!(res, Response::Nil) {
+        db.notify(NOTIFY_LIST, "rpop", db_index, &key);
+    }
     Ok(res)
 }
 pub  fn lrange(
@@ -1,5 +1,25 @@
+pub mod bitmap_command;
+pub mod bloom_command;
+pub mod client_command;
+pub mod config_command;
+pub mod count_min_sketch_command;
+pub mod cuckoo_command;
+pub mod function_command;
+pub mod geo_command;
 pub mod hash_command;
+pub mod hyperloglog_command;
+pub mod json_command;
 pub mod list_command;
+pub mod module_command;
+pub mod pubsub_command;
+pub mod script_command;
+pub mod search_command;
+pub mod server_command;
 pub mod set_command;
 pub mod sorted_set_command;
+pub mod stream_command;
 pub mod string_command;
+pub mod throttle_command;
+pub mod timeseries_command;
+pub mod topk_command;
+pub mod vector_set_command;
@@ -0,0 +1,80 @@
+use crate::database::lib::DatabaseHolder;
+#[cfg(feature = "native-modules")]
+use crate::module::native::native_command;
+use crate::module::wasm::{WasmModule, DEFAULT_FUEL, DEFAULT_MAX_MEMORY};
+use crate::parser::response::Response;
+#[cfg(feature = "native-modules")]
+use crate::util::command_table::check_arity;
+use crate::vojo::parsered_command::ParsedCommand;
+use anyhow::{anyhow, ensure};
+
+/// MODULE LOADWASM path [FUEL fuel] [MAXMEMORY bytes], UNLOAD and LIST.
+pub fn module(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    _db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    match parser.get_str(1)?.to_uppercase().as_str() {
+        "LOADWASM" => {
+            ensure!(parser.argv.len() >= 3, "InvalidArgument");
+            let mut fuel = DEFAULT_FUEL;
+            let mut max_memory = DEFAULT_MAX_MEMORY;
+            let mut i = 3;
+            while i < parser.argv.len() {
+                ensure!(i + 1 < parser.argv.len(), "syntax error");
+                let value = parser.get_i64(i + 1)?;
+                ensure!(value > 0, "{} must be positive", parser.get_str(i)?);
+                match parser.get_str(i)?.to_uppercase().as_str() {
+                    "FUEL" => fuel = value as u64,
+                    "MAXMEMORY" => max_memory = value as usize,
+                    _ => return Err(anyhow!("syntax error")),
+                }
+                i += 2;
+            }
+            let module = WasmModule::load(parser.get_str(2)?, fuel, max_memory)?;
+            let name = database_lock.modules.load(module)?;
+            info!("Module '{}' loaded from {}", name, parser.get_str(2)?);
+            Ok(Response::Status(String::from("OK")))
+        }
+        "UNLOAD" => {
+            ensure!(parser.argv.len() == 3, "InvalidArgument");
+            database_lock.modules.unload(parser.get_str(2)?)?;
+            Ok(Response::Status(String::from("OK")))
+        }
+        "LIST" => {
+            ensure!(parser.argv.len() == 2, "InvalidArgument");
+            Ok(database_lock.modules.list())
+        }
+        _ => Err(anyhow!(
+            "unknown subcommand '{}'. Try MODULE HELP.",
+            parser.get_str(1)?
+        )),
+    }
+}
+/// Runs a command registered by a module, `None` when no module registered it.
+pub fn module_command(
+    command_name: &str,
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Option<Result<Response, anyhow::Error>> {
+    #[cfg(feature = "native-modules")]
+    if let Some(handler) = native_command(command_name) {
+        return Some(
+            check_arity(command_name, &parser)
+                .and_then(|_| handler(parser, database_lock, db_index)),
+        );
+    }
+    // Looked up first, an unknown command does not wait for the database lock
+    if !database_lock.modules.handles(command_name) {
+        return None;
+    }
+    let mut db = match database_lock.database_lock.lock() {
+        Ok(db) => db,
+        Err(e) => return Some(Err(anyhow!("{}", e))),
+    };
+    database_lock
+        .modules
+        .call(command_name, &mut db, db_index, &parser)
+}
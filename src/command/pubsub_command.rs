@@ -0,0 +1,189 @@
+use crate::database::lib::DatabaseHolder;
+use crate::database::pubsub::{Subscription, SubscriptionKind};
+use crate::parser::response::Response;
+use crate::util::hash::key_hash_slot;
+use crate::vojo::parsered_command::ParsedCommand;
+use anyhow::{anyhow, ensure};
+use std::collections::HashSet;
+
+/// The commands a connection in subscriber mode accepts.
+pub const SUBSCRIBER_COMMANDS: [&str; 7] = [
+    "SUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "SSUBSCRIBE",
+    "SUNSUBSCRIBE",
+    "PING",
+];
+
+pub fn publish(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    _db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 3, "InvalidArgument");
+    let receivers = database_lock
+        .pubsub
+        .publish(parser.get_slice(1)?, parser.get_slice(2)?);
+    Ok(Response::Integer(receivers as i64))
+}
+pub fn spublish(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    _db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 3, "InvalidArgument");
+    let receivers = database_lock
+        .pubsub
+        .spublish(parser.get_slice(1)?, parser.get_slice(2)?);
+    Ok(Response::Integer(receivers as i64))
+}
+pub fn pubsub(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    _db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let pubsub = &database_lock.pubsub;
+    let subcommand = parser.get_str(1)?.to_uppercase();
+    match subcommand.as_str() {
+        "CHANNELS" | "SHARDCHANNELS" => {
+            ensure!(parser.argv.len() <= 3, "InvalidArgument");
+            let kind = match subcommand.as_str() {
+                "CHANNELS" => SubscriptionKind::Channel,
+                _ => SubscriptionKind::Shard,
+            };
+            let pattern = match parser.argv.len() {
+                3 => Some(parser.get_slice(2)?),
+                _ => None,
+            };
+            Ok(Response::Array(
+                pubsub
+                    .channels(kind, pattern)
+                    .into_iter()
+                    .map(Response::Data)
+                    .collect(),
+            ))
+        }
+        "NUMSUB" | "SHARDNUMSUB" => {
+            let kind = match subcommand.as_str() {
+                "NUMSUB" => SubscriptionKind::Channel,
+                _ => SubscriptionKind::Shard,
+            };
+            let mut response = vec![];
+            for i in 2..parser.argv.len() {
+                let channel = parser.get_vec(i)?;
+                let subscribers = pubsub.number_of_subscribers(kind, &channel);
+                response.push(Response::Data(channel));
+                response.push(Response::Integer(subscribers as i64));
+            }
+            Ok(Response::Array(response))
+        }
+        "NUMPAT" => {
+            ensure!(parser.argv.len() == 2, "InvalidArgument");
+            Ok(Response::Integer(pubsub.number_of_patterns() as i64))
+        }
+        _ => Err(anyhow!(
+            "unknown subcommand '{}'. Try PUBSUB HELP.",
+            parser.get_str(1)?
+        )),
+    }
+}
+/// SUBSCRIBE, PSUBSCRIBE and SSUBSCRIBE, one reply per channel with the number of subscriptions.
+pub fn subscribe(
+    parser: &ParsedCommand,
+    subscription: &mut Subscription,
+    kind: SubscriptionKind,
+) -> Result<Vec<Response>, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let names: Vec<Vec<u8>> = (1..parser.argv.len())
+        .map(|i| parser.get_vec(i))
+        .collect::<Result<_, _>>()?;
+    if kind == SubscriptionKind::Shard {
+        ensure_same_slot(&names)?;
+    }
+    let mut replies = vec![];
+    for name in names {
+        subscription.subscribe(kind, &name);
+        replies.push(subscription_reply(
+            subscription,
+            kind,
+            ["subscribe", "psubscribe", "ssubscribe"],
+            Some(name),
+        ));
+    }
+    Ok(replies)
+}
+/// UNSUBSCRIBE, PUNSUBSCRIBE and SUNSUBSCRIBE, from all the channels when none is given.
+pub fn unsubscribe(
+    parser: &ParsedCommand,
+    subscription: &mut Subscription,
+    kind: SubscriptionKind,
+) -> Result<Vec<Response>, anyhow::Error> {
+    let names: Vec<Vec<u8>> = if parser.argv.len() > 1 {
+        let names: Vec<Vec<u8>> = (1..parser.argv.len())
+            .map(|i| parser.get_vec(i))
+            .collect::<Result<_, _>>()?;
+        if kind == SubscriptionKind::Shard {
+            ensure_same_slot(&names)?;
+        }
+        names
+    } else {
+        subscription.names(kind).iter().cloned().collect()
+    };
+    let kinds = ["unsubscribe", "punsubscribe", "sunsubscribe"];
+    if names.is_empty() {
+        return Ok(vec![subscription_reply(subscription, kind, kinds, None)]);
+    }
+    let mut replies = vec![];
+    for name in names {
+        subscription.unsubscribe(kind, &name);
+        replies.push(subscription_reply(subscription, kind, kinds, Some(name)));
+    }
+    Ok(replies)
+}
+/// PING replies with an array in subscriber mode.
+pub fn subscribed_ping(parser: ParsedCommand) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() <= 2, "InvalidArgument");
+    let message = match parser.argv.len() {
+        2 => parser.get_vec(1)?,
+        _ => vec![],
+    };
+    Ok(Response::Array(vec![
+        Response::Data(b"pong".to_vec()),
+        Response::Data(message),
+    ]))
+}
+/// Sharded channels are owned by the node of their slot, so they can only be
+/// subscribed to together when they are in the same slot.
+fn ensure_same_slot(channels: &[Vec<u8>]) -> Result<(), anyhow::Error> {
+    let slots: HashSet<u16> = channels
+        .iter()
+        .map(|channel| key_hash_slot(channel))
+        .collect();
+    ensure!(
+        slots.len() <= 1,
+        "CROSSSLOT Keys in request don't hash to the same slot"
+    );
+    Ok(())
+}
+/// The subscribe reply for the kind of subscription. Sharded channels are counted
+/// apart from the other subscriptions.
+fn subscription_reply(
+    subscription: &Subscription,
+    kind: SubscriptionKind,
+    names: [&str; 3],
+    channel: Option<Vec<u8>>,
+) -> Response {
+    let (name, count) = match kind {
+        SubscriptionKind::Channel => (names[0], subscription.count_unsharded()),
+        SubscriptionKind::Pattern => (names[1], subscription.count_unsharded()),
+        SubscriptionKind::Shard => (names[2], subscription.shard_channels.len()),
+    };
+    Response::Array(vec![
+        Response::Data(name.as_bytes().to_vec()),
+        channel.map(Response::Data).unwrap_or(Response::Nil),
+        Response::Integer(count as i64),
+    ])
+}
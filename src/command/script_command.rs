@@ -0,0 +1,682 @@
+use crate::database::function::{FunctionInfo, FunctionLibraries, Library, FUNCTION_FLAGS};
+use crate::database::lib::DatabaseHolder;
+use crate::database::script::{sha1_hex, Scripts};
+use crate::parser::response::Response;
+use crate::util::command_table::{check_arity, is_noscript_command, is_write_command};
+use crate::vojo::parsered_command::ParsedCommand;
+use anyhow::{anyhow, ensure};
+use mlua::{HookTriggers, Lua, LuaOptions, StdLib, Value as LuaValue, Variadic};
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, info, warn};
+
+/// The interpreter checks for SCRIPT KILL and the time limit every that many instructions.
+const HOOK_INSTRUCTIONS: u32 = 100_000;
+/// The largest value of the random generator, like `REDIS_LRAND48_MAX`.
+const LRAND48_MAX: u64 = i32::MAX as u64;
+
+/// Runs after the script helpers are registered: `redis.call` raises the errors
+/// `redis.pcall` returns, the files can not be read and no global can be created.
+/// FUNCTION LOAD runs it too, where `redis.pcall` does not exist.
+const PRELUDE: &str = r#"
+redis.call = function(...)
+    local reply = redis.pcall(...)
+    if type(reply) == 'table' and reply.err then
+        error(reply.err, 2)
+    end
+    return reply
+end
+redis.status_reply = function(status)
+    return { ok = status }
+end
+redis.error_reply = function(error)
+    return { err = error }
+end
+redis.LOG_DEBUG = 0
+redis.LOG_VERBOSE = 1
+redis.LOG_NOTICE = 2
+redis.LOG_WARNING = 3
+dofile = nil
+loadfile = nil
+setmetatable(_G, {
+    __newindex = function(_, name)
+        error("Script attempted to create global variable '" .. tostring(name) .. "'", 2)
+    end,
+    __index = function(_, name)
+        error("Script attempted to access nonexistent global variable '" .. tostring(name) .. "'", 2)
+    end,
+})
+"#;
+
+type KeysAndArgs = (Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+/// A script to run with the keys and arguments given to EVAL or FCALL.
+pub struct ScriptCall {
+    /// How the errors name the script, `f_<sha1>` or the function name.
+    pub name: String,
+    pub body: String,
+    /// FCALL runs the library in `body` and then calls this function.
+    pub function: Option<String>,
+    pub keys: Vec<Vec<u8>>,
+    pub args: Vec<Vec<u8>>,
+    /// EVAL_RO, EVALSHA_RO and the `no-writes` functions, the script can not
+    /// call write commands.
+    pub read_only: bool,
+}
+
+/// SCRIPT LOAD, EXISTS, FLUSH and KILL.
+pub fn script(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    _db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let scripts = &database_lock.scripts;
+    let subcommand = parser.get_str(1)?.to_uppercase();
+    match subcommand.as_str() {
+        "LOAD" => {
+            ensure!(parser.argv.len() == 3, "InvalidArgument");
+            let sha = scripts.load(parser.get_str(2)?);
+            Ok(Response::Data(sha.into_bytes()))
+        }
+        "EXISTS" => {
+            ensure!(parser.argv.len() >= 3, "InvalidArgument");
+            let exists = (2..parser.argv.len())
+                .map(|i| Ok(Response::Integer(scripts.exists(parser.get_str(i)?) as i64)))
+                .collect::<Result<_, anyhow::Error>>()?;
+            Ok(Response::Array(exists))
+        }
+        "FLUSH" => {
+            ensure!(parser.argv.len() <= 3, "InvalidArgument");
+            if parser.argv.len() == 3 {
+                ensure!(
+                    parser.get_slice(2)?.eq_ignore_ascii_case(b"ASYNC")
+                        || parser.get_slice(2)?.eq_ignore_ascii_case(b"SYNC"),
+                    "SCRIPT FLUSH only support SYNC|ASYNC option"
+                );
+            }
+            scripts.flush();
+            Ok(Response::Status(String::from("OK")))
+        }
+        "KILL" => {
+            ensure!(parser.argv.len() == 2, "InvalidArgument");
+            scripts.kill()?;
+            Ok(Response::Status(String::from("OK")))
+        }
+        _ => Err(anyhow!(
+            "unknown subcommand '{}'. Try SCRIPT HELP.",
+            parser.get_str(1)?
+        )),
+    }
+}
+/// Reads `EVAL script numkeys [key ...] [arg ...]` and the EVALSHA and read-only
+/// variants. EVAL caches the script for the next EVALSHA.
+pub fn parse_eval(
+    command_name: &str,
+    parser: &ParsedCommand,
+    scripts: &Scripts,
+) -> Result<ScriptCall, anyhow::Error> {
+    check_arity(command_name, parser)?;
+    let (keys, args) = keys_and_args(parser)?;
+    let (sha, body) = match command_name {
+        "EVAL" | "EVAL_RO" => {
+            let body = parser.get_str(1)?.to_string();
+            (scripts.load(&body), body)
+        }
+        _ => {
+            let sha = parser.get_str(1)?.to_lowercase();
+            let body = scripts
+                .get(&sha)
+                .ok_or(anyhow!("NOSCRIPT No matching script. Please use EVAL."))?;
+            (sha, body)
+        }
+    };
+    Ok(ScriptCall {
+        name: format!("f_{}", sha),
+        body,
+        function: None,
+        keys,
+        args,
+        read_only: command_name.ends_with("_RO"),
+    })
+}
+/// Reads `FCALL function numkeys [key ...] [arg ...]` and FCALL_RO, which can
+/// only call the functions with the `no-writes` flag.
+pub fn parse_fcall(
+    command_name: &str,
+    parser: &ParsedCommand,
+    functions: &FunctionLibraries,
+) -> Result<ScriptCall, anyhow::Error> {
+    check_arity(command_name, parser)?;
+    let (keys, args) = keys_and_args(parser)?;
+    let name = parser.get_str(1)?;
+    let (library, function) = functions
+        .get_function(name)
+        .ok_or(anyhow!("Function not found"))?;
+    ensure!(
+        command_name != "FCALL_RO" || function.is_read_only(),
+        "Can not execute a script with write flag using *_ro command."
+    );
+    Ok(ScriptCall {
+        name: function.name.clone(),
+        body: library_body(&library.code),
+        function: Some(function.name.clone()),
+        keys,
+        args,
+        read_only: function.is_read_only(),
+    })
+}
+/// The keys and the arguments following `numkeys`, the third argument.
+fn keys_and_args(parser: &ParsedCommand) -> Result<KeysAndArgs, anyhow::Error> {
+    let numkeys = parser
+        .get_i64(2)
+        .map_err(|_| anyhow!("value is not an integer or out of range"))?;
+    ensure!(numkeys >= 0, "Number of keys can't be negative");
+    let numkeys = numkeys as usize;
+    ensure!(
+        numkeys <= parser.argv.len() - 3,
+        "Number of keys can't be greater than number of args"
+    );
+    let arguments = |range: std::ops::Range<usize>| {
+        range
+            .map(|i| parser.get_vec(i))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    Ok((
+        arguments(3..3 + numkeys)?,
+        arguments(3 + numkeys..parser.argv.len())?,
+    ))
+}
+/// Runs the code given to FUNCTION LOAD to find the functions it registers.
+/// The first line names the engine and the library, e.g. `#!lua name=mylib`.
+pub fn load_library(scripts: &Arc<Scripts>, code: &str) -> Result<Library, anyhow::Error> {
+    let shebang = code
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("#!"))
+        .ok_or(anyhow!("Missing library metadata"))?;
+    let mut metadata = shebang.split_whitespace();
+    let engine = metadata.next().unwrap_or_default();
+    ensure!(
+        engine.eq_ignore_ascii_case("lua"),
+        "Engine '{}' not found",
+        engine
+    );
+    let mut name = None;
+    for item in metadata {
+        match item.split_once('=') {
+            Some(("name", value)) => name = Some(value),
+            _ => return Err(anyhow!("Invalid metadata value given: {}", item)),
+        }
+    }
+    let name = name.ok_or(anyhow!("Library name was not given"))?;
+    ensure!(
+        is_valid_name(name),
+        "Library names can only contain letters, numbers, or underscores(_) and must be at least one character long"
+    );
+
+    let lua = sandbox().map_err(|e| anyhow!("{}", e))?;
+    let _running = scripts.start();
+    set_time_limit(&lua, scripts);
+    let functions = RefCell::new(Vec::<FunctionInfo>::new());
+    let result = lua.scope(|scope| {
+        let register = scope.create_function(|_, args: Variadic<LuaValue>| {
+            let (function, _) = registration(args)?;
+            let mut functions = functions.borrow_mut();
+            if functions.iter().any(|other| other.name == function.name) {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "Function {} already exists",
+                    function.name
+                )));
+            }
+            functions.push(function);
+            Ok(())
+        })?;
+        let redis: mlua::Table = lua.globals().raw_get("redis")?;
+        redis.raw_set("register_function", register)?;
+        lua.load(PRELUDE).set_name("=prelude").exec()?;
+        // No command can run while the library loads
+        redis.raw_set("call", LuaValue::Nil)?;
+        lua.load(library_body(code))
+            .set_name("=user_function")
+            .exec()
+    });
+    result.map_err(|e| anyhow!("Error registering functions: {}", lua_error_message(&e)))?;
+    let functions = functions.into_inner();
+    ensure!(!functions.is_empty(), "No functions registered");
+    Ok(Library {
+        name: name.to_string(),
+        engine: String::from("LUA"),
+        code: code.to_string(),
+        functions,
+    })
+}
+/// The code of a library without its metadata line, which Lua can not read.
+/// The line is kept empty so that the errors report the right line numbers.
+fn library_body(code: &str) -> String {
+    match code.split_once('\n') {
+        Some((_, body)) => format!("\n{}", body),
+        None => String::new(),
+    }
+}
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+/// The arguments of `redis.register_function`: a name and a callback, or a table
+/// with `function_name`, `callback` and the optional `flags` and `description`.
+fn registration(
+    args: Variadic<LuaValue<'_>>,
+) -> Result<(FunctionInfo, mlua::Function<'_>), mlua::Error> {
+    let error = |message: &str| mlua::Error::RuntimeError(message.to_string());
+    let (name, callback, flags, description) = match args.as_slice() {
+        [name, callback] => (name.clone(), callback.clone(), LuaValue::Nil, LuaValue::Nil),
+        [LuaValue::Table(table)] => {
+            for pair in table.clone().pairs::<String, LuaValue>() {
+                let (key, _) = pair?;
+                if !["function_name", "callback", "flags", "description"].contains(&key.as_str()) {
+                    return Err(error("unknown argument given to redis.register_function"));
+                }
+            }
+            (
+                table.raw_get("function_name")?,
+                table.raw_get("callback")?,
+                table.raw_get("flags")?,
+                table.raw_get("description")?,
+            )
+        }
+        _ => {
+            return Err(error(
+                "wrong number of arguments to redis.register_function",
+            ))
+        }
+    };
+    let LuaValue::String(name) = name else {
+        return Err(error(
+            "function_name argument given to redis.register_function must be a string",
+        ));
+    };
+    let name = name.to_str()?.to_string();
+    if !is_valid_name(&name) {
+        return Err(error("Function names can only contain letters, numbers, or underscores(_) and must be at least one character long"));
+    }
+    let LuaValue::Function(callback) = callback else {
+        return Err(error(
+            "callback argument given to redis.register_function must be a function",
+        ));
+    };
+    let flags = match flags {
+        LuaValue::Nil => vec![],
+        LuaValue::Table(flags) => flags
+            .sequence_values::<String>()
+            .map(|flag| {
+                let flag = flag?;
+                if FUNCTION_FLAGS.contains(&flag.as_str()) {
+                    Ok(flag)
+                } else {
+                    Err(error("unknown flag given"))
+                }
+            })
+            .collect::<Result<_, _>>()?,
+        _ => return Err(error(
+            "flags argument to redis.register_function must be a table representing function flags",
+        )),
+    };
+    let description = match description {
+        LuaValue::Nil => None,
+        LuaValue::String(description) => Some(description.to_str()?.to_string()),
+        _ => {
+            return Err(error(
+                "description argument given to redis.register_function must be a string",
+            ))
+        }
+    };
+    Ok((
+        FunctionInfo {
+            name,
+            description,
+            flags,
+        },
+        callback,
+    ))
+}
+/// Stops the script on SCRIPT KILL, or when it runs longer than `lua-time-limit`
+/// before writing.
+fn set_time_limit(lua: &Lua, scripts: &Arc<Scripts>) {
+    let scripts = scripts.clone();
+    let started = Instant::now();
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS),
+        move |_, _| {
+            scripts
+                .check(started)
+                .map_err(|e| mlua::Error::RuntimeError(e.to_string()))
+        },
+    );
+}
+/// A Lua interpreter with the libraries and the helpers every script gets.
+fn sandbox() -> Result<Lua, mlua::Error> {
+    let lua = Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )?;
+    let redis = lua.create_table()?;
+    let sha1hex = lua.create_function(|_, data: mlua::String| Ok(sha1_hex(data.as_bytes())))?;
+    redis.raw_set("sha1hex", sha1hex)?;
+    let log = lua.create_function(|_, (level, message): (i64, Variadic<String>)| {
+        let message = message.join(" ");
+        match level {
+            0 | 1 => debug!("{}", message),
+            2 => info!("{}", message),
+            _ => warn!("{}", message),
+        }
+        Ok(())
+    })?;
+    redis.raw_set("log", log)?;
+    lua.globals().raw_set("redis", redis)?;
+    Ok(lua)
+}
+/// Runs a script in an interpreter of its own. `call` runs the commands of
+/// `redis.call` and `redis.pcall`, the caller holds the database lock meanwhile.
+///
+/// The script only gets the base, table, string and math libraries, and
+/// `math.random` restarts from the same seed every time, so that a script does
+/// the same thing whenever it runs with the same dataset and arguments.
+pub fn run_script<F>(
+    scripts: &Arc<Scripts>,
+    script: ScriptCall,
+    mut call: F,
+) -> Result<Response, anyhow::Error>
+where
+    F: FnMut(&str, ParsedCommand) -> Response,
+{
+    let lua = sandbox().map_err(|e| anyhow!("{}", e))?;
+    let running = scripts.start();
+    set_time_limit(&lua, scripts);
+    let read_only = script.read_only;
+    // The lrand48 generator Redis gives its scripts, seeded with 0 for each run
+    let state = Cell::new(0x330E_u64);
+    let result = lua.scope(|scope| {
+        let globals = lua.globals();
+        let strings = |items: Vec<Vec<u8>>| {
+            let table = lua.create_table()?;
+            for (i, item) in items.into_iter().enumerate() {
+                table.raw_set(i + 1, lua.create_string(item)?)?;
+            }
+            Ok::<_, mlua::Error>(table)
+        };
+        let redis: mlua::Table = globals.raw_get("redis")?;
+        let pcall = scope.create_function_mut(|lua, args: Variadic<LuaValue>| {
+            let args = args
+                .iter()
+                .map(command_argument)
+                .collect::<Result<Vec<_>, _>>()?;
+            let Some(name) = args.first() else {
+                return Err(mlua::Error::RuntimeError(String::from(
+                    "Please specify at least one argument for this redis lib call",
+                )));
+            };
+            let command_name = String::from_utf8_lossy(name).to_uppercase();
+            let parsed_command = ParsedCommand::from_args(args);
+            let response = match check_script_command(&command_name, &parsed_command, read_only) {
+                Ok(()) => {
+                    if is_write_command(&command_name) {
+                        running.wrote();
+                    }
+                    call(&command_name, parsed_command)
+                }
+                Err(e) => Response::Error(e.to_string()),
+            };
+            response_to_lua(lua, response)
+        })?;
+        redis.raw_set("pcall", pcall)?;
+        let random = scope.create_function(|_, bounds: Variadic<i64>| {
+            let next = (0x5DEECE66D_u64.wrapping_mul(state.get()) + 0xB) & ((1 << 48) - 1);
+            state.set(next);
+            let r = ((next >> 17) % LRAND48_MAX) as f64 / LRAND48_MAX as f64;
+            match bounds.as_slice() {
+                [] => Ok(LuaValue::Number(r)),
+                [upper] if *upper >= 1 => Ok(LuaValue::Number((r * *upper as f64).floor() + 1.0)),
+                [lower, upper] if lower <= upper => Ok(LuaValue::Number(
+                    (r * (upper - lower + 1) as f64).floor() + *lower as f64,
+                )),
+                [_] | [_, _] => Err(mlua::Error::RuntimeError(String::from(
+                    "bad argument to 'random' (interval is empty)",
+                ))),
+                _ => Err(mlua::Error::RuntimeError(String::from(
+                    "wrong number of arguments",
+                ))),
+            }
+        })?;
+        let randomseed = scope.create_function(|_, seed: i64| {
+            state.set(((seed as u32 as u64) << 16) | 0x330E);
+            Ok(())
+        })?;
+        let math: mlua::Table = globals.raw_get("math")?;
+        math.raw_set("random", random)?;
+        math.raw_set("randomseed", randomseed)?;
+
+        let Some(function) = &script.function else {
+            globals.raw_set("KEYS", strings(script.keys)?)?;
+            globals.raw_set("ARGV", strings(script.args)?)?;
+            lua.load(PRELUDE).set_name("=prelude").exec()?;
+            let value: LuaValue = lua.load(&script.body).set_name("=user_script").eval()?;
+            return Ok(lua_to_response(value));
+        };
+        // The library registers its functions again, then the one called runs
+        // with the keys and the arguments as its parameters
+        let callbacks = lua.create_table()?;
+        let registered = callbacks.clone();
+        let register = scope.create_function(move |_, args: Variadic<LuaValue>| {
+            let (function, callback) = registration(args)?;
+            registered.raw_set(function.name, callback)
+        })?;
+        redis.raw_set("register_function", register)?;
+        lua.load(PRELUDE).set_name("=prelude").exec()?;
+        lua.load(&script.body).set_name("=user_function").exec()?;
+        redis.raw_set("register_function", LuaValue::Nil)?;
+        let callback: mlua::Function = callbacks.raw_get(function.as_str())?;
+        let value: LuaValue = callback.call((strings(script.keys)?, strings(script.args)?))?;
+        Ok(lua_to_response(value))
+    });
+    result.map_err(|e| {
+        anyhow!(
+            "Error running script (call to {}): {}",
+            script.name,
+            lua_error_message(&e)
+        )
+    })
+}
+/// Whether a script can call the command, e.g. a read-only script can only read.
+fn check_script_command(
+    command_name: &str,
+    parsed_command: &ParsedCommand,
+    read_only: bool,
+) -> Result<(), anyhow::Error> {
+    check_arity(command_name, parsed_command)?;
+    ensure!(
+        !is_noscript_command(command_name),
+        "This Redis command is not allowed from script"
+    );
+    ensure!(
+        !read_only || !is_write_command(command_name),
+        "Write commands are not allowed from read-only scripts."
+    );
+    Ok(())
+}
+/// The arguments of `redis.call` are strings or numbers, printed like Lua does.
+fn command_argument(value: &LuaValue) -> Result<Vec<u8>, mlua::Error> {
+    match value {
+        LuaValue::String(s) => Ok(s.as_bytes().to_vec()),
+        LuaValue::Integer(i) => Ok(i.to_string().into_bytes()),
+        LuaValue::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => {
+            Ok((*n as i64).to_string().into_bytes())
+        }
+        LuaValue::Number(n) => Ok(n.to_string().into_bytes()),
+        _ => Err(mlua::Error::RuntimeError(String::from(
+            "Lua redis lib command arguments must be strings or integers",
+        ))),
+    }
+}
+/// Converts a command reply like Redis does for the RESP2 scripts: a nil reply
+/// becomes false, a status `{ok = status}` and an error `{err = message}`.
+fn response_to_lua(lua: &Lua, response: Response) -> Result<LuaValue<'_>, mlua::Error> {
+    let value = match response {
+        Response::Nil => LuaValue::Boolean(false),
+        Response::Integer(i) => LuaValue::Integer(i),
+        Response::Data(data) => LuaValue::String(lua.create_string(data)?),
+        Response::Status(status) => {
+            let table = lua.create_table()?;
+            table.raw_set("ok", status)?;
+            LuaValue::Table(table)
+        }
+        Response::Error(error) => {
+            let table = lua.create_table()?;
+            table.raw_set("err", error)?;
+            LuaValue::Table(table)
+        }
+        Response::Array(items) | Response::Push(items) => {
+            let table = lua.create_table()?;
+            for (i, item) in items.into_iter().enumerate() {
+                table.raw_set(i + 1, response_to_lua(lua, item)?)?;
+            }
+            LuaValue::Table(table)
+        }
+        Response::Map(entries) => {
+            let table = lua.create_table()?;
+            for (i, item) in entries
+                .into_iter()
+                .flat_map(|(key, value)| [key, value])
+                .enumerate()
+            {
+                table.raw_set(i + 1, response_to_lua(lua, item)?)?;
+            }
+            LuaValue::Table(table)
+        }
+    };
+    Ok(value)
+}
+/// Converts the value returned by a script: numbers are truncated to integers,
+/// a table is an array up to its first nil unless it has an `ok` or `err` field.
+fn lua_to_response(value: LuaValue) -> Response {
+    match value {
+        LuaValue::Boolean(true) => Response::Integer(1),
+        LuaValue::Integer(i) => Response::Integer(i),
+        LuaValue::Number(n) => Response::Integer(n as i64),
+        LuaValue::String(s) => Response::Data(s.as_bytes().to_vec()),
+        LuaValue::Table(table) => {
+            if let Ok(LuaValue::String(error)) = table.raw_get("err") {
+                return Response::Error(error.to_string_lossy().into_owned());
+            }
+            if let Ok(LuaValue::String(status)) = table.raw_get("ok") {
+                return Response::Status(status.to_string_lossy().into_owned());
+            }
+            Response::Array(
+                table
+                    .sequence_values::<LuaValue>()
+                    .map_while(Result::ok)
+                    .map(lua_to_response)
+                    .collect(),
+            )
+        }
+        _ => Response::Nil,
+    }
+}
+/// The message of a Lua error without the traceback.
+fn lua_error_message(error: &mlua::Error) -> String {
+    match error {
+        mlua::Error::CallbackError { cause, .. } => lua_error_message(cause),
+        mlua::Error::RuntimeError(message) | mlua::Error::SyntaxError { message, .. } => message
+            .split("\nstack traceback:")
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        e => e.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(code: &str) -> Result<Library, anyhow::Error> {
+        load_library(&Arc::new(Scripts::new()), code)
+    }
+
+    #[test]
+    fn libraries_register_their_functions() {
+        let library = load(
+            "#!lua name=mylib\n\
+             redis.register_function('f1', function(keys, args) return 1 end)\n\
+             redis.register_function{function_name='f2', callback=function() return 2 end,\n\
+                 flags={'no-writes'}, description='the second one'}",
+        )
+        .unwrap();
+        assert_eq!(library.name, "mylib");
+        assert_eq!(library.engine, "LUA");
+        let functions: Vec<_> = library
+            .functions
+            .iter()
+            .map(|f| (f.name.as_str(), f.description.as_deref(), f.is_read_only()))
+            .collect();
+        assert_eq!(
+            functions,
+            vec![("f1", None, false), ("f2", Some("the second one"), true)]
+        );
+    }
+
+    #[test]
+    fn library_metadata() {
+        let error = |code: &str| load(code).unwrap_err().to_string();
+        let register = "\nredis.register_function('f', function() end)";
+        assert_eq!(error("return 1"), "Missing library metadata");
+        assert_eq!(
+            error(&format!("#!js name=lib{}", register)),
+            "Engine 'js' not found"
+        );
+        assert_eq!(
+            error(&format!("#!lua{}", register)),
+            "Library name was not given"
+        );
+        assert_eq!(
+            error(&format!("#!lua name=lib version=1{}", register)),
+            "Invalid metadata value given: version=1"
+        );
+        assert!(error(&format!("#!lua name=my-lib{}", register))
+            .starts_with("Library names can only contain"));
+        assert!(load(&format!("#!LUA name=lib{}", register)).is_ok());
+    }
+
+    #[test]
+    fn registration_errors() {
+        let error = |body: &str| {
+            load(&format!("#!lua name=lib\n{}", body))
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(error("return 1"), "No functions registered");
+        assert!(error(
+            "redis.register_function('f', function() end)\n\
+             redis.register_function('f', function() end)"
+        )
+        .contains("Function f already exists"));
+        assert!(error("redis.register_function('f', 1)")
+            .contains("callback argument given to redis.register_function must be a function"));
+        assert!(error("redis.register_function{function_name='f', callback=function() end, flags={'no-reads'}}")
+            .contains("unknown flag given"));
+        assert!(error(
+            "redis.register_function{function_name='f', callback=function() end, foo=1}"
+        )
+        .contains("unknown argument given to redis.register_function"));
+        assert!(
+            error("redis.register_function('f', function() end)\nredis.call('SET', 'k', 'v')")
+                .starts_with("Error registering functions: ")
+        );
+    }
+
+    #[test]
+    fn the_metadata_line_is_kept_empty() {
+        assert_eq!(library_body("#!lua name=lib\nreturn 1"), "\nreturn 1");
+        assert_eq!(library_body("#!lua name=lib"), "");
+    }
+}
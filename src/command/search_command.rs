@@ -0,0 +1,657 @@
+use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_GENERIC;
+use crate::database::search::{
+    FieldType, IndexDefinition, IndexSource, Matches, SchemaField, SearchIndex,
+};
+use crate::parser::response::Response;
+use crate::util::common_utils::f64_to_bytes;
+use crate::util::json_path::JsonPath;
+use crate::util::search_query::parse_query;
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::value::Value;
+use anyhow::{anyhow, ensure};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+/// The default `LIMIT` of FT.SEARCH and FT.AGGREGATE.
+const DEFAULT_LIMIT: usize = 10;
+
+pub fn ft_create(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 5, "InvalidArgument");
+    let name = parser.get_str(1)?.to_string();
+    let mut source = IndexSource::Hash;
+    let mut prefixes = vec![];
+    let mut i = 2;
+    loop {
+        ensure!(i < parser.argv.len(), "Fields arguments are missing");
+        match parser.get_str(i)?.to_uppercase().as_str() {
+            "ON" => {
+                source = match parser.get_str(i + 1)?.to_uppercase().as_str() {
+                    "HASH" => IndexSource::Hash,
+                    "JSON" => IndexSource::Json,
+                    other => return Err(anyhow!("Unknown index type `{}`", other)),
+                };
+                i += 2;
+            }
+            "PREFIX" => {
+                let count = parser
+                    .get_i64(i + 1)
+                    .ok()
+                    .filter(|count| *count > 0)
+                    .ok_or(anyhow!("Bad arguments for PREFIX"))?
+                    as usize;
+                for j in i + 2..i + 2 + count {
+                    prefixes.push(parser.get_vec(j)?);
+                }
+                i += 2 + count;
+            }
+            "SCHEMA" => {
+                i += 1;
+                break;
+            }
+            other => return Err(anyhow!("Unknown argument `{}`", other)),
+        }
+    }
+    let mut fields: Vec<SchemaField> = vec![];
+    while i < parser.argv.len() {
+        let path = parser.get_str(i)?.to_string();
+        i += 1;
+        let mut name = path.clone();
+        if i + 1 < parser.argv.len() && parser.get_str(i)?.eq_ignore_ascii_case("AS") {
+            name = parser.get_str(i + 1)?.to_string();
+            i += 2;
+        }
+        ensure!(
+            !fields.iter().any(|field| field.name == name),
+            "Duplicate field in schema - {}",
+            name
+        );
+        let type_name = parser
+            .get_str(i)
+            .map_err(|_| anyhow!("Field `{}` has no type", name))?
+            .to_uppercase();
+        i += 1;
+        let mut field_type = match type_name.as_str() {
+            "TEXT" => FieldType::Text { weight: 1.0 },
+            "TAG" => FieldType::Tag {
+                separator: ',',
+                case_sensitive: false,
+            },
+            "NUMERIC" => FieldType::Numeric,
+            _ => return Err(anyhow!("Invalid field type for field `{}`", name)),
+        };
+        let mut sortable = false;
+        while i < parser.argv.len() {
+            match (parser.get_str(i)?.to_uppercase().as_str(), &mut field_type) {
+                ("SORTABLE", _) => sortable = true,
+                ("NOSTEM", FieldType::Text { .. }) => {}
+                ("WEIGHT", FieldType::Text { weight }) => {
+                    *weight = parser
+                        .get_f64(i + 1)
+                        .ok()
+                        .filter(|weight| *weight >= 0.0)
+                        .ok_or(anyhow!("Bad arguments for WEIGHT"))?;
+                    i += 1;
+                }
+                ("SEPARATOR", FieldType::Tag { separator, .. }) => {
+                    let mut chars = parser.get_str(i + 1)?.chars();
+                    *separator = match (chars.next(), chars.next()) {
+                        (Some(c), None) => c,
+                        _ => return Err(anyhow!("Tag separator must be a single character")),
+                    };
+                    i += 1;
+                }
+                ("CASESENSITIVE", FieldType::Tag { case_sensitive, .. }) => *case_sensitive = true,
+                _ => break,
+            }
+            i += 1;
+        }
+        fields.push(SchemaField {
+            path,
+            name,
+            field_type,
+            sortable,
+        });
+    }
+    ensure!(!fields.is_empty(), "Fields arguments are missing");
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let definition = IndexDefinition {
+        name,
+        source,
+        prefixes,
+        db_index,
+        fields,
+    };
+    let db = &mut *db;
+    db.search.create(definition, &db.data)?;
+    Ok(Response::Status(String::from("OK")))
+}
+pub fn ft_dropindex(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    _db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 2 || parser.argv.len() == 3,
+        "InvalidArgument"
+    );
+    let delete_documents = if parser.argv.len() == 3 {
+        ensure!(
+            parser.get_str(2)?.eq_ignore_ascii_case("DD"),
+            "Unknown argument `{}`",
+            parser.get_str(2)?
+        );
+        true
+    } else {
+        false
+    };
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let index = db.search.drop_index(parser.get_str(1)?)?;
+    if delete_documents {
+        let db_index = index.definition.db_index;
+        for key in index.documents.keys() {
+            if db.remove(db_index, key)?.is_some() {
+                db.notify(NOTIFY_GENERIC, "del", db_index, key);
+            }
+        }
+    }
+    Ok(Response::Status(String::from("OK")))
+}
+pub fn ft_search(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    _db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let query = parse_query(parser.get_str(2)?)?;
+    let mut no_content = false;
+    let mut with_scores = false;
+    let mut return_fields: Option<Vec<String>> = None;
+    let mut sort_by: Option<(String, bool)> = None;
+    let (mut offset, mut count) = (0, DEFAULT_LIMIT);
+    let mut i = 3;
+    while i < parser.argv.len() {
+        match parser.get_str(i)?.to_uppercase().as_str() {
+            "NOCONTENT" => no_content = true,
+            "WITHSCORES" => with_scores = true,
+            "RETURN" => {
+                let fields = parse_count(&parser, i + 1, "RETURN")?;
+                return_fields = Some(
+                    (i + 2..i + 2 + fields)
+                        .map(|j| parser.get_str(j).map(|field| field.to_string()))
+                        .collect::<Result<_, _>>()?,
+                );
+                i += 1 + fields;
+            }
+            "SORTBY" => {
+                let field = parser.get_str(i + 1)?.trim_start_matches('@').to_string();
+                let descending = match parser.get_str(i + 2).map(|order| order.to_uppercase()) {
+                    Ok(order) if order == "ASC" || order == "DESC" => {
+                        i += 1;
+                        order == "DESC"
+                    }
+                    _ => false,
+                };
+                sort_by = Some((field, descending));
+                i += 1;
+            }
+            "LIMIT" => {
+                (offset, count) = parse_limit(&parser, i)?;
+                i += 2;
+            }
+            other => return Err(anyhow!("Unknown argument `{}`", other)),
+        }
+        i += 1;
+    }
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let index = db.search.get(parser.get_str(1)?)?;
+    let matches = index.execute(&query)?;
+    let total = matches.len();
+    let mut results: Vec<(Vec<u8>, f64)> = matches.into_iter().collect();
+    match sort_by {
+        Some((field, descending)) => {
+            let position = index
+                .field_position(&field)
+                .ok_or(anyhow!("Property `{}` not loaded nor in schema", field))?;
+            let sort_value = |key: &Vec<u8>| {
+                index.documents[key].values[position]
+                    .as_ref()
+                    .map(|value| value.to_bytes())
+            };
+            results.sort_by(|(key, _), (other, _)| {
+                compare_values(
+                    sort_value(key).as_deref(),
+                    sort_value(other).as_deref(),
+                    descending,
+                )
+                .then_with(|| key.cmp(other))
+            });
+        }
+        None => results.sort_by(|(key, score), (other, other_score)| {
+            other_score
+                .partial_cmp(score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| key.cmp(other))
+        }),
+    }
+    let mut response = vec![Response::Integer(total as i64)];
+    for (key, score) in results.into_iter().skip(offset).take(count) {
+        response.push(Response::Data(key.clone()));
+        if with_scores {
+            response.push(Response::Data(f64_to_bytes(score)));
+        }
+        if no_content {
+            continue;
+        }
+        let Some(value) = db.get(index.definition.db_index, key)? else {
+            response.push(Response::Array(vec![]));
+            continue;
+        };
+        let fields = match &return_fields {
+            Some(names) => names
+                .iter()
+                .filter_map(|name| {
+                    load_field(index, value, name).map(|loaded| (name.clone(), loaded))
+                })
+                .collect(),
+            None => load_all(value),
+        };
+        response.push(Response::Array(
+            fields
+                .into_iter()
+                .flat_map(|(name, value)| {
+                    [Response::Data(name.into_bytes()), Response::Data(value)]
+                })
+                .collect(),
+        ));
+    }
+    Ok(Response::Array(response))
+}
+
+enum Reducer {
+    Count,
+    CountDistinct(String),
+    Sum(String),
+    Avg(String),
+    Min(String),
+    Max(String),
+}
+impl Reducer {
+    fn parse(name: &str, args: &[String]) -> Result<Reducer, anyhow::Error> {
+        let property = || -> Result<String, anyhow::Error> {
+            match args {
+                [property] => Ok(property.trim_start_matches('@').to_string()),
+                _ => Err(anyhow!("Bad arguments for reducer {}", name)),
+            }
+        };
+        Ok(match name.to_uppercase().as_str() {
+            "COUNT" => {
+                ensure!(args.is_empty(), "Bad arguments for reducer COUNT");
+                Reducer::Count
+            }
+            "COUNT_DISTINCT" => Reducer::CountDistinct(property()?),
+            "SUM" => Reducer::Sum(property()?),
+            "AVG" => Reducer::Avg(property()?),
+            "MIN" => Reducer::Min(property()?),
+            "MAX" => Reducer::Max(property()?),
+            _ => return Err(anyhow!("No such reducer `{}`", name)),
+        })
+    }
+    fn default_alias(&self) -> String {
+        let (name, property) = match self {
+            Reducer::Count => ("count", ""),
+            Reducer::CountDistinct(property) => ("count_distinct", property.as_str()),
+            Reducer::Sum(property) => ("sum", property.as_str()),
+            Reducer::Avg(property) => ("avg", property.as_str()),
+            Reducer::Min(property) => ("min", property.as_str()),
+            Reducer::Max(property) => ("max", property.as_str()),
+        };
+        format!("__generated_alias{}{}", name, property.to_lowercase())
+    }
+    fn reduce(
+        &self,
+        rows: &[AggregateRow],
+        lookup: impl Fn(&AggregateRow, &str) -> Option<Vec<u8>>,
+    ) -> Option<Vec<u8>> {
+        let numbers = |property: &str| -> Vec<f64> {
+            rows.iter()
+                .filter_map(|row| lookup(row, property))
+                .filter_map(|value| String::from_utf8_lossy(&value).parse::<f64>().ok())
+                .collect()
+        };
+        match self {
+            Reducer::Count => Some(rows.len().to_string().into_bytes()),
+            Reducer::CountDistinct(property) => {
+                let distinct: HashSet<Vec<u8>> = rows
+                    .iter()
+                    .filter_map(|row| lookup(row, property))
+                    .collect();
+                Some(distinct.len().to_string().into_bytes())
+            }
+            Reducer::Sum(property) => Some(f64_to_bytes(numbers(property).iter().sum())),
+            Reducer::Avg(property) => {
+                let numbers = numbers(property);
+                (!numbers.is_empty())
+                    .then(|| f64_to_bytes(numbers.iter().sum::<f64>() / numbers.len() as f64))
+            }
+            Reducer::Min(property) => numbers(property)
+                .into_iter()
+                .reduce(f64::min)
+                .map(f64_to_bytes),
+            Reducer::Max(property) => numbers(property)
+                .into_iter()
+                .reduce(f64::max)
+                .map(f64_to_bytes),
+        }
+    }
+}
+/// A row of FT.AGGREGATE, the key is known until the rows are grouped.
+struct AggregateRow {
+    key: Option<Vec<u8>>,
+    values: Vec<(String, Option<Vec<u8>>)>,
+}
+/// The values of the GROUPBY properties which identify a group.
+type GroupValues = Vec<Option<Vec<u8>>>;
+enum AggregateStep {
+    Load(Option<Vec<String>>),
+    GroupBy(Vec<String>, Vec<(Reducer, String)>),
+    SortBy(Vec<(String, bool)>, Option<usize>),
+    Limit(usize, usize),
+}
+pub fn ft_aggregate(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    _db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let query = parse_query(parser.get_str(2)?)?;
+    let mut steps = vec![];
+    let mut i = 3;
+    while i < parser.argv.len() {
+        match parser.get_str(i)?.to_uppercase().as_str() {
+            "LOAD" => {
+                if parser.get_str(i + 1)? == "*" {
+                    steps.push(AggregateStep::Load(None));
+                    i += 2;
+                } else {
+                    let count = parse_count(&parser, i + 1, "LOAD")?;
+                    steps.push(AggregateStep::Load(Some(properties(
+                        &parser,
+                        i + 2,
+                        count,
+                    )?)));
+                    i += 2 + count;
+                }
+            }
+            "GROUPBY" => {
+                let count = parse_count(&parser, i + 1, "GROUPBY")?;
+                let group = properties(&parser, i + 2, count)?;
+                i += 2 + count;
+                let mut reducers = vec![];
+                while i < parser.argv.len() && parser.get_str(i)?.eq_ignore_ascii_case("REDUCE") {
+                    let name = parser.get_str(i + 1)?;
+                    let nargs = parse_count(&parser, i + 2, "REDUCE")?;
+                    let args: Vec<String> = (i + 3..i + 3 + nargs)
+                        .map(|j| parser.get_str(j).map(|arg| arg.to_string()))
+                        .collect::<Result<_, _>>()?;
+                    let reducer = Reducer::parse(name, &args)?;
+                    i += 3 + nargs;
+                    let alias = if i + 1 < parser.argv.len()
+                        && parser.get_str(i)?.eq_ignore_ascii_case("AS")
+                    {
+                        i += 2;
+                        parser.get_str(i - 1)?.to_string()
+                    } else {
+                        reducer.default_alias()
+                    };
+                    reducers.push((reducer, alias));
+                }
+                steps.push(AggregateStep::GroupBy(group, reducers));
+            }
+            "SORTBY" => {
+                let count = parse_count(&parser, i + 1, "SORTBY")?;
+                let mut keys: Vec<(String, bool)> = vec![];
+                for j in i + 2..i + 2 + count {
+                    let arg = parser.get_str(j)?;
+                    match (arg.to_uppercase().as_str(), keys.last_mut()) {
+                        ("ASC", Some(last)) => last.1 = false,
+                        ("DESC", Some(last)) => last.1 = true,
+                        _ => keys.push((arg.trim_start_matches('@').to_string(), false)),
+                    }
+                }
+                i += 2 + count;
+                let mut max = None;
+                if i + 1 < parser.argv.len() && parser.get_str(i)?.eq_ignore_ascii_case("MAX") {
+                    max = Some(
+                        parser
+                            .get_i64(i + 1)
+                            .ok()
+                            .filter(|max| *max >= 0)
+                            .ok_or(anyhow!("Bad arguments for MAX"))?
+                            as usize,
+                    );
+                    i += 2;
+                }
+                steps.push(AggregateStep::SortBy(keys, max));
+            }
+            "LIMIT" => {
+                let (offset, count) = parse_limit(&parser, i)?;
+                steps.push(AggregateStep::Limit(offset, count));
+                i += 3;
+            }
+            other => return Err(anyhow!("Unknown argument `{}`", other)),
+        }
+    }
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let index = db.search.get(parser.get_str(1)?)?;
+    let matches: Matches = index.execute(&query)?;
+    let mut keys: Vec<Vec<u8>> = matches.into_keys().collect();
+    keys.sort();
+    let mut rows: Vec<AggregateRow> = keys
+        .into_iter()
+        .map(|key| AggregateRow {
+            key: Some(key),
+            values: vec![],
+        })
+        .collect();
+    // Properties not in a row yet are read from the indexed document
+    let lookup = |row: &AggregateRow, property: &str| -> Option<Vec<u8>> {
+        if let Some((_, value)) = row.values.iter().find(|(name, _)| name == property) {
+            return value.clone();
+        }
+        let document = index.documents.get(row.key.as_ref()?)?;
+        document.values[index.field_position(property)?]
+            .as_ref()
+            .map(|value| value.to_bytes())
+    };
+    for step in steps {
+        match step {
+            AggregateStep::Load(properties) => {
+                for row in rows.iter_mut() {
+                    let Some(key) = &row.key else {
+                        continue;
+                    };
+                    let Some(value) = db.get(index.definition.db_index, key.clone())? else {
+                        continue;
+                    };
+                    let loaded = match &properties {
+                        None => load_all(value),
+                        Some(properties) => properties
+                            .iter()
+                            .filter_map(|property| {
+                                load_field(index, value, property)
+                                    .map(|loaded| (property.clone(), loaded))
+                            })
+                            .collect(),
+                    };
+                    for (name, value) in loaded {
+                        row.values.retain(|(other, _)| *other != name);
+                        row.values.push((name, Some(value)));
+                    }
+                }
+            }
+            AggregateStep::GroupBy(properties, reducers) => {
+                let mut groups: Vec<(GroupValues, Vec<AggregateRow>)> = vec![];
+                let mut positions: HashMap<GroupValues, usize> = HashMap::new();
+                for row in rows {
+                    let group: GroupValues = properties
+                        .iter()
+                        .map(|property| lookup(&row, property))
+                        .collect();
+                    match positions.get(&group) {
+                        Some(position) => groups[*position].1.push(row),
+                        None => {
+                            positions.insert(group.clone(), groups.len());
+                            groups.push((group, vec![row]));
+                        }
+                    }
+                }
+                rows = groups
+                    .into_iter()
+                    .map(|(group, members)| {
+                        let mut values: Vec<(String, Option<Vec<u8>>)> =
+                            properties.iter().cloned().zip(group).collect();
+                        for (reducer, alias) in &reducers {
+                            values.push((alias.clone(), reducer.reduce(&members, lookup)));
+                        }
+                        AggregateRow { key: None, values }
+                    })
+                    .collect();
+            }
+            AggregateStep::SortBy(keys, max) => {
+                rows.sort_by(|row, other| {
+                    keys.iter()
+                        .map(|(property, descending)| {
+                            compare_values(
+                                lookup(row, property).as_deref(),
+                                lookup(other, property).as_deref(),
+                                *descending,
+                            )
+                        })
+                        .find(|ordering| ordering.is_ne())
+                        .unwrap_or(Ordering::Equal)
+                });
+                if let Some(max) = max {
+                    rows.truncate(max);
+                }
+            }
+            AggregateStep::Limit(offset, count) => {
+                rows = rows.into_iter().skip(offset).take(count).collect();
+            }
+        }
+    }
+    let mut response = vec![Response::Integer(rows.len() as i64)];
+    for row in rows {
+        response.push(Response::Array(
+            row.values
+                .into_iter()
+                .flat_map(|(name, value)| {
+                    [
+                        Response::Data(name.into_bytes()),
+                        value.map(Response::Data).unwrap_or(Response::Nil),
+                    ]
+                })
+                .collect(),
+        ));
+    }
+    Ok(Response::Array(response))
+}
+
+fn parse_count(parser: &ParsedCommand, pos: usize, option: &str) -> Result<usize, anyhow::Error> {
+    parser
+        .get_i64(pos)
+        .ok()
+        .filter(|count| *count >= 0 && pos + (*count as usize) < parser.argv.len())
+        .map(|count| count as usize)
+        .ok_or(anyhow!("Bad arguments for {}", option))
+}
+fn parse_limit(parser: &ParsedCommand, pos: usize) -> Result<(usize, usize), anyhow::Error> {
+    match (parser.get_i64(pos + 1), parser.get_i64(pos + 2)) {
+        (Ok(offset), Ok(count)) if offset >= 0 && count >= 0 => {
+            Ok((offset as usize, count as usize))
+        }
+        _ => Err(anyhow!("Bad arguments for LIMIT")),
+    }
+}
+fn properties(
+    parser: &ParsedCommand,
+    start: usize,
+    count: usize,
+) -> Result<Vec<String>, anyhow::Error> {
+    (start..start + count)
+        .map(|j| {
+            parser
+                .get_str(j)
+                .map(|property| property.trim_start_matches('@').to_string())
+        })
+        .collect()
+}
+/// Numbers compare as numbers, missing values are last whatever the order.
+fn compare_values(value: Option<&[u8]>, other: Option<&[u8]>, descending: bool) -> Ordering {
+    let (value, other) = match (value, other) {
+        (Some(value), Some(other)) => (value, other),
+        (Some(_), None) => return Ordering::Less,
+        (None, Some(_)) => return Ordering::Greater,
+        (None, None) => return Ordering::Equal,
+    };
+    let number = |value: &[u8]| String::from_utf8_lossy(value).parse::<f64>().ok();
+    let ordering = match (number(value), number(other)) {
+        (Some(value), Some(other)) => value.partial_cmp(&other).unwrap_or(Ordering::Equal),
+        _ => value.cmp(other),
+    };
+    if descending {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
+/// All the fields of a hash, or the whole JSON document as `$`.
+fn load_all(value: &Value) -> Vec<(String, Vec<u8>)> {
+    match value {
+        Value::Hash(hash) => {
+            let mut fields: Vec<(String, Vec<u8>)> = hash
+                .data
+                .iter()
+                .map(|(field, value)| (String::from_utf8_lossy(field).to_string(), value.clone()))
+                .collect();
+            fields.sort();
+            fields
+        }
+        Value::Json(json) => vec![(String::from("$"), json.data.to_string().into_bytes())],
+        _ => vec![],
+    }
+}
+/// A schema field by its name, or else a hash field or a JSONPath of the document.
+fn load_field(index: &SearchIndex, value: &Value, identifier: &str) -> Option<Vec<u8>> {
+    let path = index
+        .field_position(identifier)
+        .map(|position| index.definition.fields[position].path.as_str())
+        .unwrap_or(identifier);
+    match value {
+        Value::Hash(hash) => hash.data.get(path.as_bytes()).cloned(),
+        Value::Json(json) => {
+            let path = JsonPath::parse(path).ok()?;
+            json.get(&path).first().map(|value| match value {
+                serde_json::Value::String(text) => text.clone().into_bytes(),
+                other => other.to_string().into_bytes(),
+            })
+        }
+        _ => None,
+    }
+}
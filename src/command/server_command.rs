@@ -0,0 +1,111 @@
+use crate::database::lib::DatabaseHolder;
+use crate::parser::response::Response;
+use crate::vojo::parsered_command::ParsedCommand;
+use anyhow::{anyhow, ensure};
+
+pub fn flushdb(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    check_flush_mode(&parser)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    db.flush(db_index)?;
+    Ok(Response::Status(String::from("OK")))
+}
+pub fn flushall(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    _db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    check_flush_mode(&parser)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    db.flush_all()?;
+    Ok(Response::Status(String::from("OK")))
+}
+/// INFO [section ...], the Server, Persistence and Keyspace sections.
+pub fn info(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    _db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    let mut wanted = vec![];
+    for i in 1..parser.argv.len() {
+        wanted.push(parser.get_str(i)?.to_lowercase());
+    }
+    let everything = wanted.is_empty()
+        || wanted
+            .iter()
+            .any(|section| matches!(section.as_str(), "all" | "default" | "everything"));
+    let keyspace = {
+        let db = database_lock
+            .database_lock
+            .lock()
+            .map_err(|e| anyhow!("{}", e))?;
+        db.data
+            .iter()
+            .zip(db.expire_map.iter())
+            .enumerate()
+            .filter(|(_, (keys, _))| !keys.is_empty())
+            .map(|(index, (keys, expires))| {
+                (
+                    format!("db{}", index),
+                    format!("keys={},expires={}", keys.len(), expires.len()),
+                )
+            })
+            .collect()
+    };
+    let sections: Vec<(&str, Vec<(String, String)>)> = vec![
+        (
+            "Server",
+            vec![
+                (
+                    String::from("rcache_version"),
+                    env!("CARGO_PKG_VERSION").to_string(),
+                ),
+                (String::from("process_id"), std::process::id().to_string()),
+            ],
+        ),
+        (
+            "Persistence",
+            database_lock
+                .save_status
+                .info()
+                .into_iter()
+                .map(|(field, value)| (field.to_string(), value))
+                .collect(),
+        ),
+        ("Keyspace", keyspace),
+    ];
+    let text = sections
+        .into_iter()
+        .filter(|(name, _)| everything || wanted.contains(&name.to_lowercase()))
+        .map(|(name, fields)| {
+            let mut section = format!("# {}\r\n", name);
+            for (field, value) in fields {
+                section.push_str(&format!("{}:{}\r\n", field, value));
+            }
+            section
+        })
+        .collect::<Vec<String>>()
+        .join("\r\n");
+    Ok(Response::Data(text.into_bytes()))
+}
+/// ASYNC and SYNC are accepted, the keys are always freed right away.
+fn check_flush_mode(parser: &ParsedCommand) -> Result<(), anyhow::Error> {
+    ensure!(parser.argv.len() <= 2, "syntax error");
+    if parser.argv.len() == 2 {
+        ensure!(
+            parser.get_slice(1)?.eq_ignore_ascii_case(b"ASYNC")
+                || parser.get_slice(1)?.eq_ignore_ascii_case(b"SYNC"),
+            "syntax error"
+        );
+    }
+    Ok(())
+}
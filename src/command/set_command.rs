@@ -5,6 +5,7 @@ use crate::parser::response::Response;
 use crate::vojo::parsered_command::ParsedCommand;
 
 use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_SET;
 
 pub  fn sadd(
     parser: ParsedCommand,
@@ -21,6 +22,9 @@ pub  fn sadd(
             count += 1;
         }
     }
+    if count > 0 {
+        db.notify(NOTIFY_SET, "sadd", db_index, &key);
+    }
 
     Ok(Response::Integer(count))
 }
@@ -2,28 +2,640 @@ use crate::parser::response::Response;
 
 use crate::vojo::parsered_command::ParsedCommand;
 
-use crate::database::lib::DatabaseHolder;
+use crate::database::lib::{Database, DatabaseHolder};
+use crate::database::notify::NOTIFY_ZSET;
+use crate::util::common_utils::f64_to_bytes;
+use crate::vojo::value::{Aggregate, SortedSetData, ValueSortedSet, ZaddOptions, ZaddOutcome};
 use anyhow::{anyhow, ensure};
-pub  fn zadd(
+use std::time::Duration;
+
+/// A negative ZRANDMEMBER count may repeat members, the reply is built in memory
+/// so the count is bounded.
+const ZRANDMEMBER_MAX_REPEATED: u64 = 1 << 24;
+pub fn zadd(
     parser: ParsedCommand,
     database_lock: &mut DatabaseHolder,
     db_index: usize,
 ) -> Result<Response, anyhow::Error> {
-    ensure!(parser.argv.len() > 2, "InvalidArgument");
-    let mut db = database_lock.database_lock.lock().map_err(|e|anyhow!("{}",e))?;
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
     let key = parser.get_vec(1)?;
+    let mut options = ZaddOptions::default();
+    let mut ch = false;
     let mut i = 2;
-    let mut count = 0;
-    loop {
+    while i < parser.argv.len() {
+        match parser.get_vec(i)?.to_ascii_uppercase().as_slice() {
+            b"NX" => options.nx = true,
+            b"XX" => options.xx = true,
+            b"GT" => options.gt = true,
+            b"LT" => options.lt = true,
+            b"CH" => ch = true,
+            b"INCR" => options.incr = true,
+            _ => break,
+        }
+        i += 1;
+    }
+    ensure!(
+        !(options.nx && options.xx),
+        "XX and NX options at the same time are not compatible"
+    );
+    ensure!(
+        !((options.gt && options.lt) || ((options.gt || options.lt) && options.nx)),
+        "GT, LT, and/or NX options at the same time are not compatible"
+    );
+    let pair_count = parser.argv.len() - i;
+    ensure!(
+        pair_count > 0 && pair_count.is_multiple_of(2),
+        "syntax error"
+    );
+    ensure!(
+        !options.incr || pair_count == 2,
+        "INCR option supports a single increment-element pair"
+    );
+    let mut pairs = vec![];
+    while i < parser.argv.len() {
         let score = parser.get_f64(i)?;
         let member = parser.get_vec(i + 1)?;
+        pairs.push((score, member));
         i += 2;
-        if db.zadd(db_index, key.clone(), score, member)? {
-            count += 1;
+    }
+
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let mut added = 0;
+    let mut changed = 0;
+    let mut last_outcome = ZaddOutcome::Skipped;
+    for (score, member) in pairs {
+        last_outcome = db.zadd(db_index, key.clone(), score, member, &options)?;
+        match last_outcome {
+            ZaddOutcome::Added(_) => added += 1,
+            ZaddOutcome::Updated(_) => changed += 1,
+            _ => {}
+        }
+    }
+    if added > 0 {
+        database_lock.blocking_keys.signal(db_index, &key);
+    }
+    if options.incr {
+        return match last_outcome {
+            ZaddOutcome::Added(score)
+            | ZaddOutcome::Updated(score)
+            | ZaddOutcome::Unchanged(score) => Ok(Response::Data(f64_to_bytes(score))),
+            ZaddOutcome::Skipped => Ok(Response::Nil),
+        };
+    }
+    if ch {
+        Ok(Response::Integer(added + changed))
+    } else {
+        Ok(Response::Integer(added))
+    }
+}
+pub fn zincrby(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let increment = parser.get_f64(2)?;
+    let member = parser.get_vec(3)?;
+    let options = ZaddOptions {
+        incr: true,
+        ..Default::default()
+    };
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    match db.zadd(db_index, key.clone(), increment, member, &options)? {
+        ZaddOutcome::Added(score) => {
+            database_lock.blocking_keys.signal(db_index, &key);
+            Ok(Response::Data(f64_to_bytes(score)))
+        }
+        ZaddOutcome::Updated(score) | ZaddOutcome::Unchanged(score) => {
+            Ok(Response::Data(f64_to_bytes(score)))
+        }
+        ZaddOutcome::Skipped => Ok(Response::Nil),
+    }
+}
+pub fn zrem(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let mut members = vec![];
+    for i in 2..parser.argv.len() {
+        members.push(parser.get_vec(i)?);
+    }
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let removed = db.zrem(db_index, key, members)?;
+    Ok(Response::Integer(removed as i64))
+}
+pub fn zremrangebyscore(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let min = parser
+        .get_score_bound(2)
+        .map_err(|_| anyhow!("min or max is not a float"))?;
+    let max = parser
+        .get_score_bound(3)
+        .map_err(|_| anyhow!("min or max is not a float"))?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let removed = db.zremrangebyscore(db_index, key, min, max)?;
+    Ok(Response::Integer(removed as i64))
+}
+pub fn zremrangebylex(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let min = parser.get_lex_bound(2)?;
+    let max = parser.get_lex_bound(3)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let removed = db.zremrangebylex(db_index, key, min, max)?;
+    Ok(Response::Integer(removed as i64))
+}
+pub fn zpopmin(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_zpop(parser, database_lock, db_index, false)
+}
+pub fn zpopmax(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_zpop(parser, database_lock, db_index, true)
+}
+fn generic_zpop(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+    max: bool,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 2 || parser.argv.len() == 3,
+        "InvalidArgument"
+    );
+    let key = parser.get_vec(1)?;
+    let count = if parser.argv.len() == 3 {
+        parse_count(&parser, 2)?
+    } else {
+        1
+    };
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let popped = db.zpop(db_index, key, count, max)?;
+    Ok(flat_member_scores(popped))
+}
+pub fn zmpop(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    let (keys, max, count) = parse_zmpop_args(&parser, 1)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    for key in keys {
+        let popped = db.zpop(db_index, key.clone(), count, max)?;
+        if !popped.is_empty() {
+            return Ok(key_with_member_scores(key, popped));
+        }
+    }
+    Ok(Response::Nil)
+}
+pub async fn bzpopmin(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_bzpop(parser, database_lock, db_index, false).await
+}
+pub async fn bzpopmax(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_bzpop(parser, database_lock, db_index, true).await
+}
+async fn generic_bzpop(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+    max: bool,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let mut keys = vec![];
+    for i in 1..parser.argv.len() - 1 {
+        keys.push(parser.get_vec(i)?);
+    }
+    let timeout = parse_timeout(&parser, parser.argv.len() - 1)?;
+    let res = database_lock
+        .block_on_keys(db_index, &keys, timeout, |db| {
+            for key in keys.iter() {
+                if let Some(item) = db.zpop(db_index, key.clone(), 1, max)?.pop() {
+                    return Ok(Some(Response::Array(vec![
+                        Response::Data(key.clone()),
+                        Response::Data(item.member),
+                        Response::Data(f64_to_bytes(item.score)),
+                    ])));
+                }
+            }
+            Ok(None)
+        })
+        .await?;
+    Ok(res.unwrap_or(Response::Nil))
+}
+pub async fn bzmpop(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 5, "InvalidArgument");
+    let timeout = parse_timeout(&parser, 1)?;
+    let (keys, max, count) = parse_zmpop_args(&parser, 2)?;
+    let res = database_lock
+        .block_on_keys(db_index, &keys, timeout, |db| {
+            for key in keys.iter() {
+                let popped = db.zpop(db_index, key.clone(), count, max)?;
+                if !popped.is_empty() {
+                    return Ok(Some(key_with_member_scores(key.clone(), popped)));
+                }
+            }
+            Ok(None)
+        })
+        .await?;
+    Ok(res.unwrap_or(Response::Nil))
+}
+pub fn zrandmember(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() >= 2 && parser.argv.len() <= 4,
+        "InvalidArgument"
+    );
+    let key = parser.get_vec(1)?;
+    let count_option = if parser.argv.len() >= 3 {
+        let count = parser.get_i64(2)?;
+        ensure!(
+            count >= 0 || count.unsigned_abs() <= ZRANDMEMBER_MAX_REPEATED,
+            "value is out of range"
+        );
+        Some(count)
+    } else {
+        None
+    };
+    let with_scores = if parser.argv.len() == 4 {
+        ensure!(
+            parser.get_vec(3)?.eq_ignore_ascii_case(b"WITHSCORES"),
+            "syntax error"
+        );
+        true
+    } else {
+        false
+    };
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    match count_option {
+        Some(count) => {
+            let members = db.zrandmember(db_index, key, count)?;
+            if with_scores {
+                Ok(flat_member_scores(members))
+            } else {
+                Ok(Response::Array(
+                    members
+                        .into_iter()
+                        .map(|item| Response::Data(item.member))
+                        .collect(),
+                ))
+            }
         }
-        if i >= parser.argv.len() {
-            break;
+        None => {
+            let mut members = db.zrandmember(db_index, key, 1)?;
+            match members.pop() {
+                Some(item) => Ok(Response::Data(item.member)),
+                None => Ok(Response::Nil),
+            }
         }
     }
-    Ok(Response::Integer(count as i64))
+}
+pub fn zcard(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 2, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let len = db.zcard(db_index, key)?;
+    Ok(Response::Integer(len as i64))
+}
+pub fn zunion(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_zset_operation(parser, database_lock, db_index, ZsetOperation::Union)
+}
+pub fn zinter(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_zset_operation(parser, database_lock, db_index, ZsetOperation::Inter)
+}
+pub fn zdiff(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_zset_operation(parser, database_lock, db_index, ZsetOperation::Diff)
+}
+pub fn zunionstore(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_zset_operation_store(parser, database_lock, db_index, ZsetOperation::Union)
+}
+pub fn zinterstore(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_zset_operation_store(parser, database_lock, db_index, ZsetOperation::Inter)
+}
+pub fn zdiffstore(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_zset_operation_store(parser, database_lock, db_index, ZsetOperation::Diff)
+}
+pub fn zintercard(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let keys = parse_numkeys(&parser, 1)?;
+    let mut i = 2 + keys.len();
+    let mut limit = 0;
+    while i < parser.argv.len() {
+        ensure!(
+            parser.get_vec(i)?.eq_ignore_ascii_case(b"LIMIT"),
+            "syntax error"
+        );
+        let value = parser.get_i64(i + 1)?;
+        ensure!(value >= 0, "LIMIT can't be negative");
+        limit = value as usize;
+        i += 2;
+    }
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let args = ZsetOperationArgs {
+        weights: vec![1.0; keys.len()],
+        keys,
+        aggregate: Aggregate::Sum,
+        with_scores: false,
+    };
+    let sorted_set = zset_operation(&db, db_index, &ZsetOperation::Inter, &args)?;
+    let len = if limit > 0 {
+        sorted_set.len().min(limit)
+    } else {
+        sorted_set.len()
+    };
+    Ok(Response::Integer(len as i64))
+}
+fn generic_zset_operation(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+    operation: ZsetOperation,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let args = parse_zset_operation_args(&parser, 1, &operation, true)?;
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let sorted_set = zset_operation(&db, db_index, &operation, &args)?;
+    let items = sorted_set.data.into_iter().collect::<Vec<_>>();
+    if args.with_scores {
+        Ok(flat_member_scores(items))
+    } else {
+        Ok(Response::Array(
+            items
+                .into_iter()
+                .map(|item| Response::Data(item.member))
+                .collect(),
+        ))
+    }
+}
+fn generic_zset_operation_store(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+    operation: ZsetOperation,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
+    let destination = parser.get_vec(1)?;
+    let args = parse_zset_operation_args(&parser, 2, &operation, false)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let sorted_set = zset_operation(&db, db_index, &operation, &args)?;
+    let len = db.zstore(db_index, destination.clone(), sorted_set)?;
+    if len > 0 {
+        let event = match operation {
+            ZsetOperation::Union => "zunionstore",
+            ZsetOperation::Inter => "zinterstore",
+            ZsetOperation::Diff => "zdiffstore",
+        };
+        db.notify(NOTIFY_ZSET, event, db_index, &destination);
+        database_lock.blocking_keys.signal(db_index, &destination);
+    }
+    Ok(Response::Integer(len as i64))
+}
+
+enum ZsetOperation {
+    Union,
+    Inter,
+    Diff,
+}
+struct ZsetOperationArgs {
+    keys: Vec<Vec<u8>>,
+    weights: Vec<f64>,
+    aggregate: Aggregate,
+    with_scores: bool,
+}
+fn zset_operation(
+    db: &Database,
+    db_index: usize,
+    operation: &ZsetOperation,
+    args: &ZsetOperationArgs,
+) -> Result<ValueSortedSet, anyhow::Error> {
+    let mut inputs = vec![];
+    for key in args.keys.iter() {
+        inputs.push(db.zscore_map(db_index, key.clone())?);
+    }
+    let sorted_set = match operation {
+        ZsetOperation::Union => ValueSortedSet::union(&inputs, &args.weights, args.aggregate),
+        ZsetOperation::Inter => ValueSortedSet::inter(&inputs, &args.weights, args.aggregate),
+        ZsetOperation::Diff => ValueSortedSet::diff(&inputs),
+    };
+    Ok(sorted_set)
+}
+/// Parses `numkeys key [key ...] [WEIGHTS weight ...] [AGGREGATE SUM|MIN|MAX] [WITHSCORES]`
+/// starting at `start`. ZDIFF only accepts WITHSCORES.
+fn parse_zset_operation_args(
+    parser: &ParsedCommand,
+    start: usize,
+    operation: &ZsetOperation,
+    allow_with_scores: bool,
+) -> Result<ZsetOperationArgs, anyhow::Error> {
+    let keys = parse_numkeys(parser, start)?;
+    let mut args = ZsetOperationArgs {
+        weights: vec![1.0; keys.len()],
+        keys,
+        aggregate: Aggregate::Sum,
+        with_scores: false,
+    };
+    let weighted = !matches!(operation, ZsetOperation::Diff);
+    let mut i = start + 1 + args.keys.len();
+    while i < parser.argv.len() {
+        match parser.get_vec(i)?.to_ascii_uppercase().as_slice() {
+            b"WEIGHTS" if weighted => {
+                ensure!(i + args.keys.len() < parser.argv.len(), "syntax error");
+                for weight_index in 0..args.keys.len() {
+                    args.weights[weight_index] = parser
+                        .get_f64(i + 1 + weight_index)
+                        .map_err(|_| anyhow!("weight value is not a float"))?;
+                }
+                i += 1 + args.keys.len();
+            }
+            b"AGGREGATE" if weighted => {
+                args.aggregate = match parser.get_vec(i + 1)?.to_ascii_uppercase().as_slice() {
+                    b"SUM" => Aggregate::Sum,
+                    b"MIN" => Aggregate::Min,
+                    b"MAX" => Aggregate::Max,
+                    _ => return Err(anyhow!("syntax error")),
+                };
+                i += 2;
+            }
+            b"WITHSCORES" if allow_with_scores => {
+                args.with_scores = true;
+                i += 1;
+            }
+            _ => return Err(anyhow!("syntax error")),
+        }
+    }
+    Ok(args)
+}
+/// Parses `numkeys key [key ...]` starting at `start`.
+fn parse_numkeys(parser: &ParsedCommand, start: usize) -> Result<Vec<Vec<u8>>, anyhow::Error> {
+    let numkeys = parser.get_i64(start)?;
+    ensure!(numkeys > 0, "numkeys should be greater than 0");
+    let numkeys = numkeys as usize;
+    ensure!(
+        start + numkeys < parser.argv.len(),
+        "Number of keys can't be greater than number of args"
+    );
+    let mut keys = vec![];
+    for i in start + 1..start + 1 + numkeys {
+        keys.push(parser.get_vec(i)?);
+    }
+    Ok(keys)
+}
+
+/// Parses `numkeys key [key ...] MIN|MAX [COUNT count]` starting at `start`.
+fn parse_zmpop_args(
+    parser: &ParsedCommand,
+    start: usize,
+) -> Result<(Vec<Vec<u8>>, bool, usize), anyhow::Error> {
+    let keys = parse_numkeys(parser, start)?;
+    let mut i = start + 1 + keys.len();
+    let max = match parser.get_vec(i)?.to_ascii_uppercase().as_slice() {
+        b"MIN" => false,
+        b"MAX" => true,
+        _ => return Err(anyhow!("syntax error")),
+    };
+    i += 1;
+    let mut count = 1;
+    if i < parser.argv.len() {
+        ensure!(
+            parser.get_vec(i)?.eq_ignore_ascii_case(b"COUNT") && i + 2 == parser.argv.len(),
+            "syntax error"
+        );
+        count = parse_count(parser, i + 1)?;
+        ensure!(count > 0, "count should be greater than 0");
+    }
+    Ok((keys, max, count))
+}
+/// Parses a blocking timeout in seconds, zero means blocking forever.
+fn parse_timeout(parser: &ParsedCommand, pos: usize) -> Result<Duration, anyhow::Error> {
+    let timeout = parser
+        .get_f64(pos)
+        .map_err(|_| anyhow!("timeout is not a float or out of range"))?;
+    ensure!(timeout >= 0.0, "timeout is negative");
+    Duration::try_from_secs_f64(timeout).map_err(|_| anyhow!("timeout is out of range"))
+}
+fn parse_count(parser: &ParsedCommand, pos: usize) -> Result<usize, anyhow::Error> {
+    let count = parser.get_i64(pos)?;
+    ensure!(count >= 0, "value is out of range, must be positive");
+    Ok(count as usize)
+}
+fn flat_member_scores(items: Vec<SortedSetData>) -> Response {
+    let mut responses = vec![];
+    for item in items {
+        responses.push(Response::Data(item.member));
+        responses.push(Response::Data(f64_to_bytes(item.score)));
+    }
+    Response::Array(responses)
+}
+fn key_with_member_scores(key: Vec<u8>, items: Vec<SortedSetData>) -> Response {
+    let pairs = items
+        .into_iter()
+        .map(|item| {
+            Response::Array(vec![
+                Response::Data(item.member),
+                Response::Data(f64_to_bytes(item.score)),
+            ])
+        })
+        .collect();
+    Response::Array(vec![Response::Data(key), Response::Array(pairs)])
 }
@@ -0,0 +1,1051 @@
+use crate::database::lib::Database;
+use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_STREAM;
+use crate::parser::response::Response;
+use crate::util::common_utils::mstime;
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::stream::{
+    ClaimOptions, ConsumerGroup, StreamFields, StreamId, StreamIdSpec, TrimOptions, TrimStrategy,
+    ValueStream,
+};
+use anyhow::{anyhow, ensure};
+use std::time::Duration;
+
+pub fn xadd(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 5, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let mut no_mkstream = false;
+    let mut trim = None;
+    let mut i = 2;
+    loop {
+        match parser.get_vec(i)?.to_ascii_uppercase().as_slice() {
+            b"NOMKSTREAM" => {
+                no_mkstream = true;
+                i += 1;
+            }
+            b"MAXLEN" | b"MINID" => {
+                let (options, next) = parse_trim_options(&parser, i)?;
+                trim = Some(options);
+                i = next;
+            }
+            _ => break,
+        }
+    }
+    let spec = StreamIdSpec::parse(parser.get_slice(i)?)?;
+    let field_count = parser.argv.len() - i - 1;
+    ensure!(
+        field_count > 0 && field_count.is_multiple_of(2),
+        "InvalidArgument"
+    );
+    let mut fields = vec![];
+    for pos in i + 1..parser.argv.len() {
+        fields.push(parser.get_vec(pos)?);
+    }
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    if no_mkstream && db.get_stream(db_index, &key)?.is_none() {
+        return Ok(Response::Nil);
+    }
+    let (id, trimmed) = db.update_or_create_stream(db_index, key.clone(), |stream| {
+        let id = stream.add(spec, fields, mstime() as u64)?;
+        Ok((id, trim.is_some_and(|trim| stream.trim(&trim) > 0)))
+    })?;
+    db.notify(NOTIFY_STREAM, "xadd", db_index, &key);
+    if trimmed {
+        db.notify(NOTIFY_STREAM, "xtrim", db_index, &key);
+    }
+    database_lock.blocking_keys.signal(db_index, &key);
+    Ok(Response::Data(id.to_bytes()))
+}
+pub fn xrange(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_xrange(parser, database_lock, db_index, false)
+}
+pub fn xrevrange(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    generic_xrange(parser, database_lock, db_index, true)
+}
+fn generic_xrange(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+    rev: bool,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 4 || parser.argv.len() == 6,
+        "InvalidArgument"
+    );
+    let key = parser.get_vec(1)?;
+    let (start_pos, end_pos) = if rev { (3, 2) } else { (2, 3) };
+    let start = parse_range_start(parser.get_slice(start_pos)?)?;
+    let end = parse_range_end(parser.get_slice(end_pos)?)?;
+    let count = if parser.argv.len() == 6 {
+        ensure!(
+            parser.get_str(4)?.eq_ignore_ascii_case("COUNT"),
+            "syntax error"
+        );
+        Some(parser.get_i64(5)?.max(0) as usize)
+    } else {
+        None
+    };
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let entries = match db.get_stream(db_index, &key)? {
+        Some(stream) if count != Some(0) => stream.range(start, end, count, rev),
+        _ => vec![],
+    };
+    Ok(entries_response(entries))
+}
+pub fn xlen(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 2, "InvalidArgument");
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let len = match db.get_stream(db_index, parser.get_slice(1)?)? {
+        Some(stream) => stream.len(),
+        None => 0,
+    };
+    Ok(Response::Integer(len as i64))
+}
+pub fn xdel(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let ids = parse_ids(&parser, 2, parser.argv.len())?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let deleted = match db.get_stream_mut(db_index, &key)? {
+        Some(stream) => stream.delete(&ids),
+        None => 0,
+    };
+    if deleted > 0 {
+        db.modified(db_index, &key);
+        db.notify(NOTIFY_STREAM, "xdel", db_index, &key);
+    }
+    Ok(Response::Integer(deleted as i64))
+}
+pub fn xtrim(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let (options, next) = parse_trim_options(&parser, 2)?;
+    ensure!(next == parser.argv.len(), "syntax error");
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let removed = match db.get_stream_mut(db_index, &key)? {
+        Some(stream) => stream.trim(&options),
+        None => 0,
+    };
+    if removed > 0 {
+        db.modified(db_index, &key);
+        db.notify(NOTIFY_STREAM, "xtrim", db_index, &key);
+    }
+    Ok(Response::Integer(removed as i64))
+}
+pub async fn xread(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
+    let mut count = 0;
+    let mut block = None;
+    let mut i = 1;
+    loop {
+        match parser.get_vec(i)?.to_ascii_uppercase().as_slice() {
+            b"COUNT" => count = parse_count(&parser, i + 1)?,
+            b"BLOCK" => block = Some(parse_block(&parser, i + 1)?),
+            b"STREAMS" => break,
+            _ => return Err(anyhow!("syntax error")),
+        }
+        i += 2;
+    }
+    let (keys, id_args) = parse_streams(&parser, i + 1, "xread")?;
+    let mut ids = vec![];
+    {
+        let db = database_lock
+            .database_lock
+            .lock()
+            .map_err(|e| anyhow!("{}", e))?;
+        for (key, id_arg) in keys.iter().zip(id_args.iter()) {
+            let id = match id_arg.as_slice() {
+                b"$" => match db.get_stream(db_index, key)? {
+                    Some(stream) => stream.last_id,
+                    None => StreamId::MIN,
+                },
+                b">" => {
+                    return Err(anyhow!("The > ID can be specified only when calling XREADGROUP using the GROUP <group> <consumer> option."));
+                }
+                _ => StreamId::parse(id_arg, 0)?,
+            };
+            ids.push(id);
+        }
+    }
+    let count = if count == 0 { None } else { Some(count) };
+    let read = |db: &mut Database| -> Result<Option<Response>, anyhow::Error> {
+        let mut responses = vec![];
+        for (key, id) in keys.iter().zip(ids.iter()) {
+            let Some(stream) = db.get_stream(db_index, key)? else {
+                continue;
+            };
+            let Some(start) = id.next() else {
+                continue;
+            };
+            let entries = stream.range(start, StreamId::MAX, count, false);
+            if !entries.is_empty() {
+                responses.push(Response::Array(vec![
+                    Response::Data(key.clone()),
+                    entries_response(entries),
+                ]));
+            }
+        }
+        Ok((!responses.is_empty()).then_some(Response::Array(responses)))
+    };
+    let res = match block {
+        Some(timeout) => {
+            database_lock
+                .block_on_keys(db_index, &keys, timeout, read)
+                .await?
+        }
+        None => {
+            let mut db = database_lock
+                .database_lock
+                .lock()
+                .map_err(|e| anyhow!("{}", e))?;
+            read(&mut db)?
+        }
+    };
+    Ok(res.unwrap_or(Response::Nil))
+}
+pub async fn xreadgroup(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 7, "InvalidArgument");
+    ensure!(
+        parser.get_str(1)?.eq_ignore_ascii_case("GROUP"),
+        "syntax error"
+    );
+    let group = parser.get_vec(2)?;
+    let consumer = parser.get_vec(3)?;
+    let mut count = 0;
+    let mut block = None;
+    let mut no_ack = false;
+    let mut i = 4;
+    loop {
+        match parser.get_vec(i)?.to_ascii_uppercase().as_slice() {
+            b"COUNT" => {
+                count = parse_count(&parser, i + 1)?;
+                i += 2;
+            }
+            b"BLOCK" => {
+                block = Some(parse_block(&parser, i + 1)?);
+                i += 2;
+            }
+            b"NOACK" => {
+                no_ack = true;
+                i += 1;
+            }
+            b"STREAMS" => break,
+            _ => return Err(anyhow!("syntax error")),
+        }
+    }
+    let (keys, id_args) = parse_streams(&parser, i + 1, "xreadgroup")?;
+    let mut ids = vec![];
+    for id_arg in id_args.iter() {
+        let id = match id_arg.as_slice() {
+            b">" => None,
+            b"$" => {
+                return Err(anyhow!("The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set."));
+            }
+            _ => Some(StreamId::parse(id_arg, 0)?),
+        };
+        ids.push(id);
+    }
+    let read = |db: &mut Database| -> Result<Option<Response>, anyhow::Error> {
+        let mut responses = vec![];
+        for (key, id) in keys.iter().zip(ids.iter()) {
+            let no_group = || no_group_error(key, &group, " in XREADGROUP with GROUP option");
+            let stream = db.get_stream_mut(db_index, key)?.ok_or_else(no_group)?;
+            let entries = stream
+                .read_group(&group, &consumer, *id, count, no_ack, now())
+                .ok_or_else(no_group)?;
+            // New entries were delivered to the consumer
+            if id.is_none() && !entries.is_empty() {
+                db.modified(db_index, key);
+            }
+            // The history of the consumer is always returned, even when empty
+            if id.is_some() || !entries.is_empty() {
+                let entries = entries
+                    .into_iter()
+                    .map(|(id, fields)| match fields {
+                        Some(fields) => entry_response(id, fields),
+                        None => Response::Array(vec![Response::Data(id.to_bytes()), Response::Nil]),
+                    })
+                    .collect();
+                responses.push(Response::Array(vec![
+                    Response::Data(key.clone()),
+                    Response::Array(entries),
+                ]));
+            }
+        }
+        Ok((!responses.is_empty()).then_some(Response::Array(responses)))
+    };
+    let res = match block {
+        Some(timeout) => {
+            database_lock
+                .block_on_keys(db_index, &keys, timeout, read)
+                .await?
+        }
+        None => {
+            let mut db = database_lock
+                .database_lock
+                .lock()
+                .map_err(|e| anyhow!("{}", e))?;
+            read(&mut db)?
+        }
+    };
+    Ok(res.unwrap_or(Response::Nil))
+}
+pub fn xgroup(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let subcommand = parser.get_str(1)?.to_uppercase();
+    let arity_ok = match subcommand.as_str() {
+        "CREATE" => (5..=8).contains(&parser.argv.len()),
+        "SETID" => parser.argv.len() == 5 || parser.argv.len() == 7,
+        "DESTROY" => parser.argv.len() == 4,
+        "CREATECONSUMER" | "DELCONSUMER" => parser.argv.len() == 5,
+        _ => {
+            return Err(anyhow!(
+                "unknown subcommand '{}'. Try XGROUP HELP.",
+                parser.get_str(1)?
+            ))
+        }
+    };
+    ensure!(arity_ok, "InvalidArgument");
+    let key = parser.get_vec(2)?;
+    let group = parser.get_vec(3)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    match subcommand.as_str() {
+        "CREATE" | "SETID" => {
+            let mut mkstream = false;
+            let mut entries_read = None;
+            let mut i = 5;
+            while i < parser.argv.len() {
+                match parser.get_vec(i)?.to_ascii_uppercase().as_slice() {
+                    b"MKSTREAM" if subcommand == "CREATE" => mkstream = true,
+                    b"ENTRIESREAD" => {
+                        let value = parser.get_i64(i + 1)?;
+                        ensure!(value >= -1, "value for ENTRIESREAD must be positive or -1");
+                        entries_read = (value >= 0).then_some(value as u64);
+                        i += 1;
+                    }
+                    _ => return Err(anyhow!("syntax error")),
+                }
+                i += 1;
+            }
+            if db.get_stream(db_index, &key)?.is_none() {
+                ensure!(
+                    subcommand == "CREATE" && mkstream,
+                    "The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically."
+                );
+            }
+            db.update_or_create_stream(db_index, key.clone(), |stream| {
+                let id = match parser.get_slice(4)? {
+                    b"$" => stream.last_id,
+                    id => StreamId::parse(id, 0)?,
+                };
+                if subcommand == "CREATE" {
+                    ensure!(
+                        stream.create_group(group, id, entries_read),
+                        "BUSYGROUP Consumer Group name already exists"
+                    );
+                } else if !stream.set_group_id(&group, id, entries_read) {
+                    return Err(no_group_error(&key, &group, ""));
+                }
+                Ok(())
+            })?;
+            let event = if subcommand == "CREATE" {
+                "xgroup-create"
+            } else {
+                "xgroup-setid"
+            };
+            db.notify(NOTIFY_STREAM, event, db_index, &key);
+            Ok(Response::Status(String::from("OK")))
+        }
+        "DESTROY" => {
+            let destroyed = match db.get_stream_mut(db_index, &key)? {
+                Some(stream) => stream.groups.remove(&group).is_some(),
+                None => return Err(no_group_error(&key, &group, "")),
+            };
+            if destroyed {
+                db.modified(db_index, &key);
+                db.notify(NOTIFY_STREAM, "xgroup-destroy", db_index, &key);
+            }
+            // The clients blocked on the group get an error
+            database_lock.blocking_keys.signal(db_index, &key);
+            Ok(Response::Integer(destroyed as i64))
+        }
+        "CREATECONSUMER" => {
+            let consumer = parser.get_vec(4)?;
+            let created = db
+                .get_stream_mut(db_index, &key)?
+                .and_then(|stream| stream.create_consumer(&group, &consumer, now()))
+                .ok_or_else(|| no_group_error(&key, &group, ""))?;
+            if created {
+                db.modified(db_index, &key);
+                db.notify(NOTIFY_STREAM, "xgroup-createconsumer", db_index, &key);
+            }
+            Ok(Response::Integer(created as i64))
+        }
+        _ => {
+            let consumer = parser.get_vec(4)?;
+            let pending = db
+                .get_stream_mut(db_index, &key)?
+                .and_then(|stream| stream.delete_consumer(&group, &consumer))
+                .ok_or_else(|| no_group_error(&key, &group, ""))?;
+            db.modified(db_index, &key);
+            db.notify(NOTIFY_STREAM, "xgroup-delconsumer", db_index, &key);
+            Ok(Response::Integer(pending as i64))
+        }
+    }
+}
+pub fn xack(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let group = parser.get_vec(2)?;
+    let ids = parse_ids(&parser, 3, parser.argv.len())?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let acked = db
+        .get_stream_mut(db_index, &key)?
+        .and_then(|stream| stream.ack(&group, &ids))
+        .unwrap_or(0);
+    if acked > 0 {
+        db.modified(db_index, &key);
+    }
+    Ok(Response::Integer(acked as i64))
+}
+pub fn xpending(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let group_name = parser.get_vec(2)?;
+    let mut min_idle = 0;
+    let mut i = 3;
+    if parser.argv.len() > 3 && parser.get_str(3)?.eq_ignore_ascii_case("IDLE") {
+        min_idle = parser.get_i64(4)?;
+        i = 5;
+    }
+    let extended = parser.argv.len() > 3;
+    if extended {
+        ensure!(
+            parser.argv.len() == i + 3 || parser.argv.len() == i + 4,
+            "syntax error"
+        );
+    }
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let group = db
+        .get_stream(db_index, &key)?
+        .and_then(|stream| stream.group(&group_name))
+        .ok_or_else(|| no_group_error(&key, &group_name, ""))?;
+    if !extended {
+        return Ok(pending_summary(group));
+    }
+    let start = parse_range_start(parser.get_slice(i)?)?;
+    let end = parse_range_end(parser.get_slice(i + 1)?)?;
+    let count = parser.get_i64(i + 2)?.max(0) as usize;
+    let consumer = if parser.argv.len() == i + 4 {
+        Some(parser.get_slice(i + 3)?)
+    } else {
+        None
+    };
+    if start > end {
+        return Ok(Response::Array(vec![]));
+    }
+    let now = now();
+    let responses = group
+        .pending
+        .range(start..=end)
+        .filter(|(_, pending)| {
+            consumer.is_none_or(|consumer| pending.consumer == consumer)
+                && now - pending.delivery_time >= min_idle
+        })
+        .take(count)
+        .map(|(id, pending)| {
+            Response::Array(vec![
+                Response::Data(id.to_bytes()),
+                Response::Data(pending.consumer.clone()),
+                Response::Integer(now - pending.delivery_time),
+                Response::Integer(pending.delivery_count as i64),
+            ])
+        })
+        .collect();
+    Ok(Response::Array(responses))
+}
+pub fn xclaim(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 6, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let group = parser.get_vec(2)?;
+    let consumer = parser.get_vec(3)?;
+    let min_idle = parse_min_idle(&parser, 4)?;
+    let mut ids = vec![];
+    let mut i = 5;
+    while i < parser.argv.len() {
+        match StreamId::parse(parser.get_slice(i)?, 0) {
+            Ok(id) => ids.push(id),
+            Err(e) if ids.is_empty() => return Err(e),
+            Err(_) => break,
+        }
+        i += 1;
+    }
+    let now = now();
+    let mut options = ClaimOptions::default();
+    while i < parser.argv.len() {
+        match parser.get_vec(i)?.to_ascii_uppercase().as_slice() {
+            b"IDLE" => {
+                options.delivery_time = Some(now - parser.get_i64(i + 1)?.max(0));
+                i += 1;
+            }
+            b"TIME" => {
+                options.delivery_time = Some(parser.get_i64(i + 1)?);
+                i += 1;
+            }
+            b"RETRYCOUNT" => {
+                let retry_count = parser.get_i64(i + 1)?;
+                ensure!(
+                    retry_count >= 0,
+                    "Invalid RETRYCOUNT option argument for XCLAIM"
+                );
+                options.retry_count = Some(retry_count as u64);
+                i += 1;
+            }
+            b"LASTID" => {
+                options.last_id = Some(StreamId::parse(parser.get_slice(i + 1)?, 0)?);
+                i += 1;
+            }
+            b"FORCE" => options.force = true,
+            b"JUSTID" => options.just_id = true,
+            _ => {
+                return Err(anyhow!(
+                    "Unrecognized XCLAIM option '{}'",
+                    parser.get_str(i)?
+                ))
+            }
+        }
+        i += 1;
+    }
+    if let Some(delivery_time) = options.delivery_time {
+        // A delivery time in the future would make the entry claimable later than asked
+        options.delivery_time = Some(delivery_time.min(now));
+    }
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let claimed = db
+        .get_stream_mut(db_index, &key)?
+        .and_then(|stream| stream.claim(&group, &consumer, min_idle, &ids, &options, now))
+        .ok_or_else(|| no_group_error(&key, &group, ""))?;
+    if !claimed.is_empty() {
+        db.modified(db_index, &key);
+    }
+    if options.just_id {
+        Ok(ids_response(claimed.into_iter().map(|(id, _)| id)))
+    } else {
+        Ok(entries_response(claimed))
+    }
+}
+pub fn xautoclaim(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 6, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let group = parser.get_vec(2)?;
+    let consumer = parser.get_vec(3)?;
+    let min_idle = parse_min_idle(&parser, 4)?;
+    let start = parse_range_start(parser.get_slice(5)?)?;
+    let mut count = 100;
+    let mut just_id = false;
+    let mut i = 6;
+    while i < parser.argv.len() {
+        match parser.get_vec(i)?.to_ascii_uppercase().as_slice() {
+            b"COUNT" => {
+                let value = parser.get_i64(i + 1)?;
+                ensure!(value > 0, "COUNT must be > 0");
+                count = value as usize;
+                i += 1;
+            }
+            b"JUSTID" => just_id = true,
+            _ => return Err(anyhow!("syntax error")),
+        }
+        i += 1;
+    }
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let result = db
+        .get_stream_mut(db_index, &key)?
+        .and_then(|stream| {
+            stream.auto_claim(&group, &consumer, min_idle, start, count, just_id, now())
+        })
+        .ok_or_else(|| no_group_error(&key, &group, ""))?;
+    if !result.claimed.is_empty() || !result.deleted.is_empty() {
+        db.modified(db_index, &key);
+    }
+    let claimed = if just_id {
+        ids_response(result.claimed.into_iter().map(|(id, _)| id))
+    } else {
+        entries_response(result.claimed)
+    };
+    Ok(Response::Array(vec![
+        Response::Data(result.next.to_bytes()),
+        claimed,
+        ids_response(result.deleted.into_iter()),
+    ]))
+}
+pub fn xinfo(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let subcommand = parser.get_str(1)?.to_uppercase();
+    let key = parser.get_vec(2)?;
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let stream = db
+        .get_stream(db_index, &key)?
+        .ok_or_else(|| anyhow!("no such key"))?;
+    let now = now();
+    match subcommand.as_str() {
+        "STREAM" => {
+            let mut full = false;
+            let mut count = 10;
+            let mut i = 3;
+            while i < parser.argv.len() {
+                match parser.get_vec(i)?.to_ascii_uppercase().as_slice() {
+                    b"FULL" => full = true,
+                    b"COUNT" if full => {
+                        count = parser.get_i64(i + 1)?.max(0) as usize;
+                        i += 1;
+                    }
+                    _ => return Err(anyhow!("syntax error")),
+                }
+                i += 1;
+            }
+            if full {
+                Ok(stream_info_full(stream, count))
+            } else {
+                Ok(stream_info(stream))
+            }
+        }
+        "GROUPS" => {
+            ensure!(parser.argv.len() == 3, "InvalidArgument");
+            let responses = stream
+                .groups
+                .iter()
+                .map(|(name, group)| {
+                    info_reply(vec![
+                        ("name", Response::Data(name.clone())),
+                        ("consumers", Response::Integer(group.consumers.len() as i64)),
+                        ("pending", Response::Integer(group.pending.len() as i64)),
+                        (
+                            "last-delivered-id",
+                            Response::Data(group.last_delivered_id.to_bytes()),
+                        ),
+                        ("entries-read", optional_integer(group.entries_read)),
+                        ("lag", optional_integer(stream.lag(group))),
+                    ])
+                })
+                .collect();
+            Ok(Response::Array(responses))
+        }
+        "CONSUMERS" => {
+            ensure!(parser.argv.len() == 4, "InvalidArgument");
+            let group_name = parser.get_vec(3)?;
+            let group = stream
+                .group(&group_name)
+                .ok_or_else(|| no_group_error(&key, &group_name, ""))?;
+            let responses = group
+                .consumers
+                .iter()
+                .map(|(name, consumer)| {
+                    let inactive = if consumer.active_time < 0 {
+                        -1
+                    } else {
+                        now - consumer.active_time
+                    };
+                    info_reply(vec![
+                        ("name", Response::Data(name.clone())),
+                        ("pending", Response::Integer(consumer.pending.len() as i64)),
+                        ("idle", Response::Integer(now - consumer.seen_time)),
+                        ("inactive", Response::Integer(inactive)),
+                    ])
+                })
+                .collect();
+            Ok(Response::Array(responses))
+        }
+        _ => Err(anyhow!(
+            "unknown subcommand '{}'. Try XINFO HELP.",
+            parser.get_str(1)?
+        )),
+    }
+}
+
+/// The keys following STREAMS and their IDs, as given.
+type StreamsArgs = (Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+fn now() -> i64 {
+    mstime() as i64
+}
+/// Parses `MAXLEN|MINID [=|~] threshold [LIMIT count]` at `pos`, returns the
+/// options and the position after them.
+fn parse_trim_options(
+    parser: &ParsedCommand,
+    pos: usize,
+) -> Result<(TrimOptions, usize), anyhow::Error> {
+    let max_len = parser.get_str(pos)?.eq_ignore_ascii_case("MAXLEN");
+    let mut i = pos + 1;
+    let mut approx = false;
+    match parser.get_slice(i)? {
+        b"~" => {
+            approx = true;
+            i += 1;
+        }
+        b"=" => i += 1,
+        _ => {}
+    }
+    let strategy = if max_len {
+        let max_len = parser.get_i64(i)?;
+        ensure!(max_len >= 0, "The MAXLEN argument must be >= 0.");
+        TrimStrategy::MaxLen(max_len as usize)
+    } else {
+        TrimStrategy::MinId(StreamId::parse(parser.get_slice(i)?, 0)?)
+    };
+    i += 1;
+    let mut limit = 0;
+    if i < parser.argv.len() && parser.get_str(i)?.eq_ignore_ascii_case("LIMIT") {
+        ensure!(
+            approx,
+            "syntax error, LIMIT cannot be used without the special ~ option"
+        );
+        let value = parser.get_i64(i + 1)?;
+        ensure!(value >= 0, "The LIMIT argument must be >= 0.");
+        limit = value as usize;
+        i += 2;
+    }
+    Ok((TrimOptions { strategy, limit }, i))
+}
+/// Parses the start of an interval: `-`, an ID with an optional sequence,
+/// or an exclusive ID prefixed with `(`.
+fn parse_range_start(data: &[u8]) -> Result<StreamId, anyhow::Error> {
+    match data {
+        b"-" => Ok(StreamId::MIN),
+        b"+" => Ok(StreamId::MAX),
+        _ => match data.strip_prefix(b"(") {
+            Some(id) => StreamId::parse(id, 0)?
+                .next()
+                .ok_or(anyhow!("invalid start ID for the interval")),
+            None => StreamId::parse(data, 0),
+        },
+    }
+}
+fn parse_range_end(data: &[u8]) -> Result<StreamId, anyhow::Error> {
+    match data {
+        b"-" => Ok(StreamId::MIN),
+        b"+" => Ok(StreamId::MAX),
+        _ => match data.strip_prefix(b"(") {
+            Some(id) => StreamId::parse(id, u64::MAX)?
+                .prev()
+                .ok_or(anyhow!("invalid end ID for the interval")),
+            None => StreamId::parse(data, u64::MAX),
+        },
+    }
+}
+fn parse_ids(
+    parser: &ParsedCommand,
+    start: usize,
+    end: usize,
+) -> Result<Vec<StreamId>, anyhow::Error> {
+    let mut ids = vec![];
+    for i in start..end {
+        ids.push(StreamId::parse(parser.get_slice(i)?, 0)?);
+    }
+    Ok(ids)
+}
+/// Splits the arguments after STREAMS into the keys and their IDs.
+fn parse_streams(
+    parser: &ParsedCommand,
+    pos: usize,
+    command: &str,
+) -> Result<StreamsArgs, anyhow::Error> {
+    let remaining = parser.argv.len().saturating_sub(pos);
+    ensure!(
+        remaining > 0 && remaining.is_multiple_of(2),
+        "Unbalanced '{}' list of streams: for each stream key an ID or '$' must be specified.",
+        command
+    );
+    let stream_count = remaining / 2;
+    let mut keys = vec![];
+    let mut ids = vec![];
+    for i in 0..stream_count {
+        keys.push(parser.get_vec(pos + i)?);
+        ids.push(parser.get_vec(pos + stream_count + i)?);
+    }
+    Ok((keys, ids))
+}
+fn parse_count(parser: &ParsedCommand, pos: usize) -> Result<usize, anyhow::Error> {
+    Ok(parser.get_i64(pos)?.max(0) as usize)
+}
+fn parse_block(parser: &ParsedCommand, pos: usize) -> Result<Duration, anyhow::Error> {
+    let timeout = parser
+        .get_i64(pos)
+        .map_err(|_| anyhow!("timeout is not an integer or out of range"))?;
+    ensure!(timeout >= 0, "timeout is negative");
+    Ok(Duration::from_millis(timeout as u64))
+}
+fn parse_min_idle(parser: &ParsedCommand, pos: usize) -> Result<i64, anyhow::Error> {
+    let min_idle = parser
+        .get_i64(pos)
+        .map_err(|_| anyhow!("Invalid min-idle-time argument"))?;
+    Ok(min_idle.max(0))
+}
+fn no_group_error(key: &[u8], group: &[u8], context: &str) -> anyhow::Error {
+    anyhow!(
+        "NOGROUP No such key '{}' or consumer group '{}'{}",
+        String::from_utf8_lossy(key),
+        String::from_utf8_lossy(group),
+        context
+    )
+}
+fn entry_response(id: StreamId, fields: StreamFields) -> Response {
+    Response::Array(vec![
+        Response::Data(id.to_bytes()),
+        Response::Array(fields.into_iter().map(Response::Data).collect()),
+    ])
+}
+fn entries_response(entries: Vec<(StreamId, StreamFields)>) -> Response {
+    Response::Array(
+        entries
+            .into_iter()
+            .map(|(id, fields)| entry_response(id, fields))
+            .collect(),
+    )
+}
+fn ids_response(ids: impl Iterator<Item = StreamId>) -> Response {
+    Response::Array(ids.map(|id| Response::Data(id.to_bytes())).collect())
+}
+fn optional_entry(entry: Option<(&StreamId, &StreamFields)>) -> Response {
+    match entry {
+        Some((id, fields)) => entry_response(*id, fields.clone()),
+        None => Response::Nil,
+    }
+}
+fn optional_integer(value: Option<u64>) -> Response {
+    match value {
+        Some(value) => Response::Integer(value as i64),
+        None => Response::Nil,
+    }
+}
+/// Flattens name/value pairs the way XINFO replies.
+fn info_reply(fields: Vec<(&str, Response)>) -> Response {
+    let mut responses = vec![];
+    for (name, value) in fields {
+        responses.push(Response::Data(name.as_bytes().to_vec()));
+        responses.push(value);
+    }
+    Response::Array(responses)
+}
+fn pending_summary(group: &ConsumerGroup) -> Response {
+    let (Some(first), Some(last)) = (group.pending.keys().next(), group.pending.keys().last())
+    else {
+        return Response::Array(vec![
+            Response::Integer(0),
+            Response::Nil,
+            Response::Nil,
+            Response::Nil,
+        ]);
+    };
+    let consumers = group
+        .consumers
+        .iter()
+        .filter(|(_, consumer)| !consumer.pending.is_empty())
+        .map(|(name, consumer)| {
+            Response::Array(vec![
+                Response::Data(name.clone()),
+                Response::Data(consumer.pending.len().to_string().into_bytes()),
+            ])
+        })
+        .collect();
+    Response::Array(vec![
+        Response::Integer(group.pending.len() as i64),
+        Response::Data(first.to_bytes()),
+        Response::Data(last.to_bytes()),
+        Response::Array(consumers),
+    ])
+}
+fn stream_info_header(stream: &ValueStream) -> Vec<(&'static str, Response)> {
+    let first_id = stream
+        .first_entry()
+        .map(|(id, _)| *id)
+        .unwrap_or(StreamId::MIN);
+    vec![
+        ("length", Response::Integer(stream.len() as i64)),
+        (
+            "last-generated-id",
+            Response::Data(stream.last_id.to_bytes()),
+        ),
+        (
+            "max-deleted-entry-id",
+            Response::Data(stream.max_deleted_id.to_bytes()),
+        ),
+        (
+            "entries-added",
+            Response::Integer(stream.entries_added as i64),
+        ),
+        (
+            "recorded-first-entry-id",
+            Response::Data(first_id.to_bytes()),
+        ),
+    ]
+}
+fn stream_info(stream: &ValueStream) -> Response {
+    let mut fields = stream_info_header(stream);
+    fields.push(("groups", Response::Integer(stream.groups.len() as i64)));
+    fields.push(("first-entry", optional_entry(stream.first_entry())));
+    fields.push(("last-entry", optional_entry(stream.last_entry())));
+    info_reply(fields)
+}
+/// XINFO STREAM FULL, `count` limits the entries and the pending entries, 0 means all.
+fn stream_info_full(stream: &ValueStream, count: usize) -> Response {
+    let count = if count == 0 { usize::MAX } else { count };
+    let mut fields = stream_info_header(stream);
+    let entries = stream
+        .entries
+        .iter()
+        .take(count)
+        .map(|(id, fields)| entry_response(*id, fields.clone()))
+        .collect();
+    fields.push(("entries", Response::Array(entries)));
+    let groups = stream
+        .groups
+        .iter()
+        .map(|(name, group)| {
+            let pending = group
+                .pending
+                .iter()
+                .take(count)
+                .map(|(id, pending)| {
+                    Response::Array(vec![
+                        Response::Data(id.to_bytes()),
+                        Response::Data(pending.consumer.clone()),
+                        Response::Integer(pending.delivery_time),
+                        Response::Integer(pending.delivery_count as i64),
+                    ])
+                })
+                .collect();
+            let consumers = group
+                .consumers
+                .iter()
+                .map(|(name, consumer)| {
+                    let pending = consumer
+                        .pending
+                        .iter()
+                        .take(count)
+                        .filter_map(|id| {
+                            let pending = group.pending.get(id)?;
+                            Some(Response::Array(vec![
+                                Response::Data(id.to_bytes()),
+                                Response::Integer(pending.delivery_time),
+                                Response::Integer(pending.delivery_count as i64),
+                            ]))
+                        })
+                        .collect();
+                    info_reply(vec![
+                        ("name", Response::Data(name.clone())),
+                        ("seen-time", Response::Integer(consumer.seen_time)),
+                        ("active-time", Response::Integer(consumer.active_time)),
+                        (
+                            "pel-count",
+                            Response::Integer(consumer.pending.len() as i64),
+                        ),
+                        ("pending", Response::Array(pending)),
+                    ])
+                })
+                .collect();
+            info_reply(vec![
+                ("name", Response::Data(name.clone())),
+                (
+                    "last-delivered-id",
+                    Response::Data(group.last_delivered_id.to_bytes()),
+                ),
+                ("entries-read", optional_integer(group.entries_read)),
+                ("lag", optional_integer(stream.lag(group))),
+                ("pel-count", Response::Integer(group.pending.len() as i64)),
+                ("pending", Response::Array(pending)),
+                ("consumers", Response::Array(consumers)),
+            ])
+        })
+        .collect();
+    fields.push(("groups", Response::Array(groups)));
+    info_reply(fields)
+}
@@ -1,6 +1,7 @@
 use anyhow::{anyhow, ensure};
 
 use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_STRING;
 use crate::parser::response::Response;
 
 use crate::vojo::parsered_command::ParsedCommand;
@@ -24,7 +25,8 @@ pub  fn set(
 
     let value = ValueString { data: value };
     let wrapped_value = Value::String(value);
-    database.insert(db_index, key, wrapped_value)?;
+    database.insert(db_index, key.clone(), wrapped_value)?;
+    database.notify(NOTIFY_STRING, "set", db_index, &key);
     Ok(Response::Status("OK".to_owned()))
 }
 
@@ -75,13 +77,62 @@ pub  fn incr(
     };
     let value_integer = value + increment;
 
-    db.insert(
+    db.update(
         dbindex,
-        key,
+        key.clone(),
         Value::String(ValueString {
             data: value_integer.to_string().as_bytes().to_vec(),
         }),
     )?;
+    db.notify(NOTIFY_STRING, "incrby", dbindex, &key);
 
     Ok(Response::Status("OK".to_owned()))
 }
+pub fn setrange(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    dbindex: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let offset = parser
+        .get_i64(2)
+        .map_err(|_| anyhow!("offset is out of range"))?;
+    ensure!(offset >= 0, "offset is out of range");
+    let value = parser.get_vec(3)?;
+    ensure!(
+        offset as usize + value.len() <= 512 * 1024 * 1024,
+        "string exceeds maximum allowed size (proto-max-bulk-len)"
+    );
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    if value.is_empty() {
+        let len = db.get_string(dbindex, &key)?.map_or(0, |v| v.strlen());
+        return Ok(Response::Integer(len as i64));
+    }
+    let len = db.update_or_create_string(dbindex, key.clone(), |string| {
+        Ok(string.setrange(offset as usize, &value))
+    })?;
+    db.notify(NOTIFY_STRING, "setrange", dbindex, &key);
+    Ok(Response::Integer(len as i64))
+}
+pub fn getrange(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    dbindex: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let start = parser.get_i64(2)?;
+    let end = parser.get_i64(3)?;
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    match db.get_string(dbindex, &key)? {
+        Some(value) => Ok(Response::Data(value.getrange(start, end))),
+        None => Ok(Response::Data(vec![])),
+    }
+}
@@ -0,0 +1,231 @@
+use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_MODULE;
+use crate::parser::response::Response;
+use crate::util::common_utils::ustime;
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::value::{Value, ValueString};
+use anyhow::{anyhow, ensure};
+
+/// `CL.THROTTLE key max_burst count period [quantity]`, a rate limit of `count`
+/// actions per `period` seconds, with bursts of up to `max_burst + 1` actions.
+///
+/// The generic cell rate algorithm only keeps the theoretical arrival time of the
+/// next action, stored in microseconds as a string which expires when the limit
+/// is fully replenished. Replies with whether the action was limited, the limit,
+/// the remaining actions, and the seconds until a retry and until the reset.
+pub fn cl_throttle(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 5 || parser.argv.len() == 6,
+        "InvalidArgument"
+    );
+    let key = parser.get_vec(1)?;
+    let argument = |pos: usize, name: &str, min: i64| {
+        parser
+            .get_i64(pos)
+            .ok()
+            .filter(|value| *value >= min)
+            .ok_or(anyhow!("invalid {}", name))
+    };
+    let max_burst = argument(2, "max_burst", 0)?;
+    let count = argument(3, "count", 1)?;
+    let period = argument(4, "period", 1)?;
+    let quantity = if parser.argv.len() == 6 {
+        argument(5, "quantity", 0)?
+    } else {
+        1
+    };
+    let throttle = Throttle::new(max_burst, count, period, quantity)?;
+
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let stored = match db.get_string(db_index, &key)? {
+        Some(value) => Some(
+            std::str::from_utf8(&value.data)
+                .ok()
+                .and_then(|text| text.parse::<i128>().ok())
+                .ok_or(anyhow!("the throttle state is not an integer"))?,
+        ),
+        None => None,
+    };
+    let outcome = throttle.check(stored, ustime())?;
+    if let Some(new_tat) = outcome.new_tat {
+        let expire_at = i64::try_from((new_tat as u128).div_ceil(1000))
+            .map_err(|_| anyhow!("value is out of range"))?;
+        db.insert(
+            db_index,
+            key.clone(),
+            Value::String(ValueString {
+                data: new_tat.to_string().into_bytes(),
+            }),
+        )?;
+        db.expire_at(db_index, key.clone(), expire_at)?;
+        db.notify(NOTIFY_MODULE, "cl.throttle", db_index, &key);
+    }
+    Ok(Response::Array(vec![
+        Response::Integer(outcome.new_tat.is_none() as i64),
+        Response::Integer(throttle.limit),
+        Response::Integer(outcome.remaining),
+        Response::Integer(outcome.retry_after),
+        Response::Integer(outcome.reset_after),
+    ]))
+}
+/// The parameters of a rate limit, in microseconds.
+struct Throttle {
+    limit: i64,
+    emission_interval: i128,
+    tolerance: i128,
+    increment: i128,
+}
+/// The result of an action, `new_tat` is `None` when it was limited.
+struct ThrottleOutcome {
+    new_tat: Option<i128>,
+    remaining: i64,
+    retry_after: i64,
+    reset_after: i64,
+}
+impl Throttle {
+    fn new(max_burst: i64, count: i64, period: i64, quantity: i64) -> Result<Self, anyhow::Error> {
+        let out_of_range = || anyhow!("value is out of range");
+        let limit = max_burst.checked_add(1).ok_or_else(out_of_range)?;
+        // At most one action per microsecond, the unit of the stored time
+        let emission_interval = (period as i128 * 1_000_000 / count as i128).max(1);
+        let tolerance = emission_interval
+            .checked_mul(limit as i128)
+            .ok_or_else(out_of_range)?;
+        let increment = emission_interval
+            .checked_mul(quantity as i128)
+            .ok_or_else(out_of_range)?;
+        Ok(Throttle {
+            limit,
+            emission_interval,
+            tolerance,
+            increment,
+        })
+    }
+    /// Checks an action at `now` against the stored theoretical arrival time.
+    fn check(&self, stored: Option<i128>, now: i128) -> Result<ThrottleOutcome, anyhow::Error> {
+        let tat = stored.unwrap_or(now).max(now);
+        let new_tat = tat
+            .checked_add(self.increment)
+            .ok_or(anyhow!("value is out of range"))?;
+        let allow_at = new_tat - self.tolerance;
+        let limited = now < allow_at;
+        let ttl = if limited { tat - now } else { new_tat - now };
+        let next = self.tolerance - ttl;
+        let remaining = if next > -self.emission_interval {
+            (next / self.emission_interval).max(0)
+        } else {
+            0
+        };
+        let retry_after = if limited && self.increment <= self.tolerance {
+            seconds(allow_at - now)
+        } else {
+            -1
+        };
+        Ok(ThrottleOutcome {
+            new_tat: (!limited).then_some(new_tat),
+            remaining: remaining as i64,
+            retry_after,
+            reset_after: seconds(ttl),
+        })
+    }
+}
+/// Microseconds as whole seconds, rounded up so a client never retries too early.
+fn seconds(microseconds: i128) -> i64 {
+    (microseconds.max(0) as u128).div_ceil(1_000_000) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i128 = 1_700_000_000_000_000;
+    const SECOND: i128 = 1_000_000;
+
+    /// Runs actions at `now`, keeping the stored time like the command does.
+    fn act(throttle: &Throttle, stored: &mut Option<i128>, now: i128) -> ThrottleOutcome {
+        let outcome = throttle.check(*stored, now).unwrap();
+        if outcome.new_tat.is_some() {
+            *stored = outcome.new_tat;
+        }
+        outcome
+    }
+
+    #[test]
+    fn a_burst_is_allowed_then_limited() {
+        // 30 actions per minute, bursts of 16
+        let throttle = Throttle::new(15, 30, 60, 1).unwrap();
+        assert_eq!(throttle.limit, 16);
+        let mut stored = None;
+        let first = act(&throttle, &mut stored, NOW);
+        assert!(first.new_tat.is_some());
+        assert_eq!(
+            (first.remaining, first.retry_after, first.reset_after),
+            (15, -1, 2)
+        );
+        for expected in (0..15).rev() {
+            let outcome = act(&throttle, &mut stored, NOW);
+            assert!(outcome.new_tat.is_some());
+            assert_eq!(outcome.remaining, expected);
+        }
+        let limited = act(&throttle, &mut stored, NOW);
+        assert!(limited.new_tat.is_none());
+        assert_eq!(
+            (limited.remaining, limited.retry_after, limited.reset_after),
+            (0, 2, 32)
+        );
+    }
+
+    #[test]
+    fn actions_are_replenished_over_time() {
+        let throttle = Throttle::new(0, 1, 10, 1).unwrap();
+        let mut stored = None;
+        assert!(act(&throttle, &mut stored, NOW).new_tat.is_some());
+        let limited = act(&throttle, &mut stored, NOW + 4 * SECOND);
+        assert!(limited.new_tat.is_none());
+        assert_eq!(limited.retry_after, 6);
+        let allowed = act(&throttle, &mut stored, NOW + 10 * SECOND);
+        assert!(allowed.new_tat.is_some());
+        // A stored time in the past counts from now
+        let later = throttle.check(stored, NOW + 1000 * SECOND).unwrap();
+        assert_eq!(later.new_tat, Some(NOW + 1010 * SECOND));
+    }
+
+    #[test]
+    fn quantities() {
+        let throttle = Throttle::new(4, 1, 1, 3).unwrap();
+        let mut stored = None;
+        assert_eq!(act(&throttle, &mut stored, NOW).remaining, 2);
+        let limited = act(&throttle, &mut stored, NOW);
+        assert!(limited.new_tat.is_none());
+        // More than the burst can never be allowed, so there is no retry time
+        let too_many = Throttle::new(1, 1, 1, 3).unwrap();
+        let outcome = too_many.check(None, NOW).unwrap();
+        assert!(outcome.new_tat.is_none());
+        assert_eq!(outcome.retry_after, -1);
+        // A quantity of 0 only looks at the state
+        let peek = Throttle::new(4, 1, 1, 0).unwrap();
+        assert_eq!(peek.check(stored, NOW).unwrap().remaining, 2);
+    }
+
+    #[test]
+    fn fast_rates_are_capped_at_one_action_per_microsecond() {
+        let throttle = Throttle::new(0, i64::MAX, 1, 1).unwrap();
+        assert_eq!(throttle.emission_interval, 1);
+    }
+
+    #[test]
+    fn overflows_are_errors() {
+        assert!(Throttle::new(i64::MAX, 1, 1, 1).is_err());
+        assert!(Throttle::new(i64::MAX - 1, 1, i64::MAX, 1).is_err());
+        assert!(Throttle::new(0, 1, i64::MAX, i64::MAX).is_err());
+        let throttle = Throttle::new(0, 1, i64::MAX / 1_000_000, 1).unwrap();
+        assert!(throttle.check(Some(i128::MAX - 1), NOW).is_err());
+    }
+}
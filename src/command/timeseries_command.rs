@@ -0,0 +1,511 @@
+use crate::database::lib::{Database, DatabaseHolder};
+use crate::database::notify::NOTIFY_MODULE;
+use crate::parser::response::Response;
+use crate::util::common_utils::{f64_to_bytes, mstime};
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::timeseries::{
+    aggregate, AggregationType, CompactionRule, DuplicatePolicy, ValueTimeSeries,
+};
+use crate::vojo::value::Value;
+use anyhow::{anyhow, ensure};
+
+struct CreateOptions {
+    retention: i64,
+    labels: Vec<(String, String)>,
+    duplicate_policy: DuplicatePolicy,
+    on_duplicate: Option<DuplicatePolicy>,
+}
+/// Parses `[RETENTION ms] [DUPLICATE_POLICY policy] [ON_DUPLICATE policy] [LABELS label value ...]`,
+/// `LABELS` takes all the remaining arguments.
+fn parse_create_options(
+    parser: &ParsedCommand,
+    start: usize,
+) -> Result<CreateOptions, anyhow::Error> {
+    let mut options = CreateOptions {
+        retention: 0,
+        labels: vec![],
+        duplicate_policy: DuplicatePolicy::Block,
+        on_duplicate: None,
+    };
+    let mut i = start;
+    while i < parser.argv.len() {
+        match parser.get_str(i)?.to_uppercase().as_str() {
+            "RETENTION" => {
+                options.retention = parser
+                    .get_i64(i + 1)
+                    .ok()
+                    .filter(|value| *value >= 0)
+                    .ok_or(anyhow!("TSDB: invalid RETENTION value"))?;
+                i += 2;
+            }
+            "DUPLICATE_POLICY" => {
+                options.duplicate_policy = DuplicatePolicy::parse(parser.get_str(i + 1)?)?;
+                i += 2;
+            }
+            "ON_DUPLICATE" => {
+                options.on_duplicate = Some(DuplicatePolicy::parse(parser.get_str(i + 1)?)?);
+                i += 2;
+            }
+            "LABELS" => {
+                ensure!(
+                    (parser.argv.len() - i - 1).is_multiple_of(2),
+                    "TSDB: wrong number of arguments for LABELS"
+                );
+                for j in (i + 1..parser.argv.len()).step_by(2) {
+                    options.labels.push((
+                        parser.get_str(j)?.to_string(),
+                        parser.get_str(j + 1)?.to_string(),
+                    ));
+                }
+                i = parser.argv.len();
+            }
+            _ => return Err(anyhow!("syntax error")),
+        }
+    }
+    Ok(options)
+}
+fn parse_timestamp(parser: &ParsedCommand, pos: usize) -> Result<i64, anyhow::Error> {
+    if parser.get_str(pos)? == "*" {
+        return Ok(mstime() as i64);
+    }
+    parser
+        .get_i64(pos)
+        .ok()
+        .filter(|value| *value >= 0)
+        .ok_or(anyhow!("TSDB: invalid timestamp"))
+}
+fn parse_value(parser: &ParsedCommand, pos: usize) -> Result<f64, anyhow::Error> {
+    parser
+        .get_f64(pos)
+        .map_err(|_| anyhow!("TSDB: invalid value"))
+}
+/// Adds a sample to the series at `key`, then the samples it completed through
+/// the compaction rules to their destination series.
+fn add_sample(
+    db: &mut Database,
+    db_index: usize,
+    key: &[u8],
+    timestamp: i64,
+    value: f64,
+    policy: Option<DuplicatePolicy>,
+) -> Result<(), anyhow::Error> {
+    let series = db
+        .get_timeseries_mut(db_index, key)?
+        .ok_or(anyhow!("TSDB: the key does not exist"))?;
+    let mut pending = series.add(timestamp, value, policy)?;
+    db.modified(db_index, key);
+    db.notify(NOTIFY_MODULE, "ts.add", db_index, key);
+    while let Some(sample) = pending.pop() {
+        // A destination deleted since the rule was created is skipped
+        if let Ok(Some(dest)) = db.get_timeseries_mut(db_index, &sample.key) {
+            if let Ok(compacted) =
+                dest.add(sample.timestamp, sample.value, Some(DuplicatePolicy::Last))
+            {
+                pending.extend(compacted);
+                db.modified(db_index, &sample.key);
+                db.notify(NOTIFY_MODULE, "ts.add", db_index, &sample.key);
+            }
+        }
+    }
+    Ok(())
+}
+pub fn ts_create(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 2, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let options = parse_create_options(&parser, 2)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    ensure!(
+        db.get(db_index, key.clone())?.is_none(),
+        "TSDB: key already exists"
+    );
+    let series = ValueTimeSeries::new(options.retention, options.labels, options.duplicate_policy);
+    db.insert(db_index, key.clone(), Value::TimeSeries(series))?;
+    db.notify(NOTIFY_MODULE, "ts.create", db_index, &key);
+    Ok(Response::Status("OK".to_owned()))
+}
+/// `TS.ADD key timestamp value [options]`, the series is created with the options
+/// when it does not exist.
+pub fn ts_add(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    let timestamp = parse_timestamp(&parser, 2)?;
+    let value = parse_value(&parser, 3)?;
+    let options = parse_create_options(&parser, 4)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    if db.get_timeseries(db_index, &key)?.is_none() {
+        let series =
+            ValueTimeSeries::new(options.retention, options.labels, options.duplicate_policy);
+        db.insert(db_index, key.clone(), Value::TimeSeries(series))?;
+    }
+    add_sample(
+        &mut db,
+        db_index,
+        &key,
+        timestamp,
+        value,
+        options.on_duplicate,
+    )?;
+    Ok(Response::Integer(timestamp))
+}
+/// `TS.MADD key timestamp value [key timestamp value ...]`, replies with the
+/// timestamp or the error of each sample.
+pub fn ts_madd(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() >= 4 && (parser.argv.len() - 1).is_multiple_of(3),
+        "InvalidArgument"
+    );
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let mut result = vec![];
+    for i in (1..parser.argv.len()).step_by(3) {
+        let added = parse_timestamp(&parser, i + 1).and_then(|timestamp| {
+            let value = parse_value(&parser, i + 2)?;
+            add_sample(
+                &mut db,
+                db_index,
+                parser.get_slice(i)?,
+                timestamp,
+                value,
+                None,
+            )?;
+            Ok(timestamp)
+        });
+        result.push(match added {
+            Ok(timestamp) => Response::Integer(timestamp),
+            Err(e) => Response::Error(e.to_string()),
+        });
+    }
+    Ok(Response::Array(result))
+}
+
+struct RangeOptions {
+    from: i64,
+    to: i64,
+    count: Option<usize>,
+    align: i64,
+    aggregation: Option<(AggregationType, i64)>,
+}
+fn parse_range_timestamp(parser: &ParsedCommand, pos: usize) -> Result<i64, anyhow::Error> {
+    match parser.get_str(pos)? {
+        "-" => Ok(0),
+        "+" => Ok(i64::MAX),
+        _ => parse_timestamp(parser, pos),
+    }
+}
+/// Parses `from to [COUNT count] [ALIGN align] [AGGREGATION aggregator bucketDuration]`
+/// starting at `start`, stopping at the first unknown argument. Returns the
+/// position of that argument. `WITHLABELS` is accepted among the options when
+/// `with_labels` is given.
+fn parse_range_options(
+    parser: &ParsedCommand,
+    start: usize,
+    mut with_labels: Option<&mut bool>,
+) -> Result<(RangeOptions, usize), anyhow::Error> {
+    ensure!(parser.argv.len() >= start + 2, "InvalidArgument");
+    let mut options = RangeOptions {
+        from: parse_range_timestamp(parser, start)?,
+        to: parse_range_timestamp(parser, start + 1)?,
+        count: None,
+        align: 0,
+        aggregation: None,
+    };
+    let mut align = None;
+    let mut i = start + 2;
+    while i < parser.argv.len() {
+        match parser.get_str(i)?.to_uppercase().as_str() {
+            "WITHLABELS" if with_labels.is_some() => {
+                if let Some(with_labels) = with_labels.as_deref_mut() {
+                    *with_labels = true;
+                }
+                i += 1;
+            }
+            "COUNT" => {
+                let count = parser
+                    .get_i64(i + 1)
+                    .ok()
+                    .filter(|value| *value > 0)
+                    .ok_or(anyhow!("TSDB: Invalid COUNT value"))?;
+                options.count = Some(count as usize);
+                i += 2;
+            }
+            "ALIGN" => {
+                align = Some(match parser.get_str(i + 1)? {
+                    "-" | "start" => options.from,
+                    "+" | "end" => options.to,
+                    _ => parser
+                        .get_i64(i + 1)
+                        .map_err(|_| anyhow!("TSDB: unknown ALIGN parameter"))?,
+                });
+                i += 2;
+            }
+            "AGGREGATION" => {
+                let aggregation = AggregationType::parse(parser.get_str(i + 1)?)?;
+                let bucket_duration = parser
+                    .get_i64(i + 2)
+                    .ok()
+                    .filter(|value| *value > 0)
+                    .ok_or(anyhow!("TSDB: bucketDuration must be greater than zero"))?;
+                options.aggregation = Some((aggregation, bucket_duration));
+                i += 3;
+            }
+            _ => break,
+        }
+    }
+    if let Some(align) = align {
+        ensure!(
+            options.aggregation.is_some(),
+            "TSDB: ALIGN parameter can only be used with AGGREGATION"
+        );
+        options.align = align;
+    }
+    Ok((options, i))
+}
+fn range_samples(series: &ValueTimeSeries, options: &RangeOptions, reverse: bool) -> Response {
+    let mut samples = series.range(options.from, options.to);
+    if let Some((aggregation, bucket_duration)) = options.aggregation {
+        samples = aggregate(&samples, aggregation, bucket_duration, options.align);
+    }
+    if reverse {
+        samples.reverse();
+    }
+    if let Some(count) = options.count {
+        samples.truncate(count);
+    }
+    Response::Array(
+        samples
+            .into_iter()
+            .map(|(timestamp, value)| {
+                Response::Array(vec![
+                    Response::Integer(timestamp),
+                    Response::Data(f64_to_bytes(value)),
+                ])
+            })
+            .collect(),
+    )
+}
+fn range(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+    reverse: bool,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
+    let (options, end) = parse_range_options(&parser, 2, None)?;
+    ensure!(end == parser.argv.len(), "syntax error");
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let series = db
+        .get_timeseries(db_index, parser.get_slice(1)?)?
+        .ok_or(anyhow!("TSDB: the key does not exist"))?;
+    Ok(range_samples(series, &options, reverse))
+}
+pub fn ts_range(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    range(parser, database_lock, db_index, false)
+}
+pub fn ts_revrange(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    range(parser, database_lock, db_index, true)
+}
+
+/// A `TS.MRANGE` filter: `label=value`, `label!=value`, `label=` (no such label),
+/// `label!=` (has the label), and the `(value1,value2)` lists.
+struct LabelFilter {
+    label: String,
+    equal: bool,
+    values: Vec<String>,
+}
+impl LabelFilter {
+    fn parse(text: &str) -> Result<LabelFilter, anyhow::Error> {
+        let (label, equal, value) = match text.split_once("!=") {
+            Some((label, value)) => (label, false, value),
+            None => match text.split_once('=') {
+                Some((label, value)) => (label, true, value),
+                None => return Err(anyhow!("TSDB: failed parsing labels")),
+            },
+        };
+        ensure!(!label.is_empty(), "TSDB: failed parsing labels");
+        let values = match value.strip_prefix('(').and_then(|v| v.strip_suffix(')')) {
+            Some(list) => list.split(',').map(|v| v.trim().to_string()).collect(),
+            None if value.is_empty() => vec![],
+            None => vec![value.to_string()],
+        };
+        Ok(LabelFilter {
+            label: label.to_string(),
+            equal,
+            values,
+        })
+    }
+    /// Whether the filter selects series, `label!=` and `label=` alone match too much
+    fn is_matcher(&self) -> bool {
+        self.equal && !self.values.is_empty()
+    }
+    fn matches(&self, series: &ValueTimeSeries) -> bool {
+        let value = series.label(&self.label);
+        let in_values = value.is_some_and(|value| self.values.iter().any(|v| v == value));
+        match (self.equal, self.values.is_empty()) {
+            (true, false) => in_values,
+            (true, true) => value.is_none(),
+            (false, false) => !in_values,
+            (false, true) => value.is_some(),
+        }
+    }
+}
+/// `TS.MRANGE from to [WITHLABELS] [COUNT count] [ALIGN align] [AGGREGATION aggregator bucketDuration] FILTER filter...`
+pub fn ts_mrange(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 5, "InvalidArgument");
+    let mut with_labels = false;
+    let (options, i) = parse_range_options(&parser, 1, Some(&mut with_labels))?;
+    ensure!(
+        i < parser.argv.len() && parser.get_str(i)?.eq_ignore_ascii_case("FILTER"),
+        "TSDB: missing FILTER argument"
+    );
+    let mut filters = vec![];
+    for j in i + 1..parser.argv.len() {
+        filters.push(LabelFilter::parse(parser.get_str(j)?)?);
+    }
+    ensure!(
+        filters.iter().any(LabelFilter::is_matcher),
+        "TSDB: please provide at least one matcher"
+    );
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let mut series: Vec<(&Vec<u8>, &ValueTimeSeries)> = db
+        .data
+        .get(db_index)
+        .ok_or(anyhow!("can not find db index-{}", db_index))?
+        .iter()
+        .filter_map(|(key, value)| match value {
+            Value::TimeSeries(series) if filters.iter().all(|f| f.matches(series)) => {
+                Some((key, series))
+            }
+            _ => None,
+        })
+        .collect();
+    series.sort_by(|a, b| a.0.cmp(b.0));
+    let result = series
+        .into_iter()
+        .map(|(key, series)| {
+            let labels = if with_labels {
+                series
+                    .labels
+                    .iter()
+                    .map(|(label, value)| {
+                        Response::Array(vec![
+                            Response::Data(label.clone().into_bytes()),
+                            Response::Data(value.clone().into_bytes()),
+                        ])
+                    })
+                    .collect()
+            } else {
+                vec![]
+            };
+            Response::Array(vec![
+                Response::Data(key.clone()),
+                Response::Array(labels),
+                range_samples(series, &options, false),
+            ])
+        })
+        .collect();
+    Ok(Response::Array(result))
+}
+/// `TS.CREATERULE sourceKey destKey AGGREGATION aggregator bucketDuration [alignTimestamp]`
+pub fn ts_createrule(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 6 || parser.argv.len() == 7,
+        "InvalidArgument"
+    );
+    let source_key = parser.get_vec(1)?;
+    let dest_key = parser.get_vec(2)?;
+    ensure!(
+        parser.get_str(3)?.eq_ignore_ascii_case("AGGREGATION"),
+        "syntax error"
+    );
+    let aggregation = AggregationType::parse(parser.get_str(4)?)?;
+    let bucket_duration = parser
+        .get_i64(5)
+        .ok()
+        .filter(|value| *value > 0)
+        .ok_or(anyhow!("TSDB: bucketDuration must be greater than zero"))?;
+    let align = match parser.argv.len() {
+        7 => parser
+            .get_i64(6)
+            .map_err(|_| anyhow!("TSDB: invalid alignTimestamp"))?,
+        _ => 0,
+    };
+    ensure!(
+        source_key != dest_key,
+        "TSDB: the source key and destination key should be different"
+    );
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let source = db
+        .get_timeseries(db_index, &source_key)?
+        .ok_or(anyhow!("TSDB: the key does not exist"))?;
+    ensure!(
+        source.source_key.as_ref() != Some(&dest_key),
+        "TSDB: the destination key is the source of the source key"
+    );
+    let dest = db
+        .get_timeseries_mut(db_index, &dest_key)?
+        .ok_or(anyhow!("TSDB: the key does not exist"))?;
+    ensure!(
+        dest.source_key.is_none(),
+        "TSDB: the destination key already has a src rule"
+    );
+    dest.source_key = Some(source_key.clone());
+    db.modified(db_index, &dest_key);
+    db.notify(NOTIFY_MODULE, "ts.createrule", db_index, &dest_key);
+    if let Some(source) = db.get_timeseries_mut(db_index, &source_key)? {
+        source.rules.push(CompactionRule {
+            dest_key,
+            bucket_duration,
+            align,
+            current: None,
+            aggregation,
+        });
+        db.modified(db_index, &source_key);
+        db.notify(NOTIFY_MODULE, "ts.createrule", db_index, &source_key);
+    }
+    Ok(Response::Status("OK".to_owned()))
+}
@@ -0,0 +1,163 @@
+use crate::database::lib::DatabaseHolder;
+use crate::database::notify::NOTIFY_MODULE;
+use crate::parser::response::Response;
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::topk::{ValueTopK, TOPK_DEFAULT_DECAY, TOPK_DEFAULT_DEPTH, TOPK_DEFAULT_WIDTH};
+use crate::vojo::value::Value;
+use anyhow::{anyhow, ensure};
+
+/// `TOPK.RESERVE key topk [width depth decay]`
+pub fn topk_reserve(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 3 || parser.argv.len() == 6,
+        "InvalidArgument"
+    );
+    let key = parser.get_vec(1)?;
+    let dimension = |pos: usize, name: &str| -> Result<u32, anyhow::Error> {
+        parser
+            .get_i64(pos)
+            .ok()
+            .filter(|value| *value > 0 && *value <= u32::MAX as i64)
+            .map(|value| value as u32)
+            .ok_or(anyhow!("TopK: invalid {}", name))
+    };
+    let k = dimension(2, "k")?;
+    let (mut width, mut depth, mut decay) =
+        (TOPK_DEFAULT_WIDTH, TOPK_DEFAULT_DEPTH, TOPK_DEFAULT_DECAY);
+    if parser.argv.len() == 6 {
+        width = dimension(3, "width")?;
+        depth = dimension(4, "depth")?;
+        decay = parser
+            .get_f64(5)
+            .ok()
+            .filter(|value| *value > 0.0 && *value <= 1.0)
+            .ok_or(anyhow!("TopK: invalid decay value. must be '<= 1' & '> 0'"))?;
+    }
+    let topk = ValueTopK::new(k, width, depth, decay)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    ensure!(
+        db.get(db_index, key.clone())?.is_none(),
+        "TopK: key already exists"
+    );
+    db.insert(db_index, key.clone(), Value::TopK(topk))?;
+    db.notify(NOTIFY_MODULE, "topk.reserve", db_index, &key);
+    Ok(Response::Status("OK".to_owned()))
+}
+pub fn topk_add(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let mut increments = vec![];
+    for i in 2..parser.argv.len() {
+        increments.push((parser.get_slice(i)?, 1));
+    }
+    let key = parser.get_slice(1)?;
+    incr_by(key, increments, "topk.add", database_lock, db_index)
+}
+pub fn topk_incrby(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() >= 4 && parser.argv.len().is_multiple_of(2),
+        "InvalidArgument"
+    );
+    let mut increments = vec![];
+    for i in (2..parser.argv.len()).step_by(2) {
+        let increment = parser
+            .get_i64(i + 1)
+            .ok()
+            .filter(|value| *value >= 1 && *value <= 100_000)
+            .ok_or(anyhow!(
+                "TopK: increment must be an integer between 1 and 100,000"
+            ))?;
+        increments.push((parser.get_slice(i)?, increment as u32));
+    }
+    let key = parser.get_slice(1)?;
+    incr_by(key, increments, "topk.incrby", database_lock, db_index)
+}
+/// Replies with the item expelled from the top list by each increment, or nil.
+fn incr_by(
+    key: &[u8],
+    increments: Vec<(&[u8], u32)>,
+    event: &str,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let topk = db
+        .get_topk_mut(db_index, key)?
+        .ok_or(anyhow!("TopK: key does not exist"))?;
+    let result = increments
+        .into_iter()
+        .map(|(item, increment)| match topk.incr_by(item, increment) {
+            Some(expelled) => Response::Data(expelled),
+            None => Response::Nil,
+        })
+        .collect();
+    db.modified(db_index, key);
+    db.notify(NOTIFY_MODULE, event, db_index, key);
+    Ok(Response::Array(result))
+}
+pub fn topk_query(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 3, "InvalidArgument");
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let topk = db
+        .get_topk(db_index, parser.get_slice(1)?)?
+        .ok_or(anyhow!("TopK: key does not exist"))?;
+    let mut result = vec![];
+    for i in 2..parser.argv.len() {
+        result.push(Response::Integer(topk.contains(parser.get_slice(i)?) as i64));
+    }
+    Ok(Response::Array(result))
+}
+pub fn topk_list(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 2 || parser.argv.len() == 3,
+        "InvalidArgument"
+    );
+    let with_count = parser.argv.len() == 3;
+    ensure!(
+        !with_count || parser.get_str(2)?.eq_ignore_ascii_case("WITHCOUNT"),
+        "syntax error"
+    );
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let topk = db
+        .get_topk(db_index, parser.get_slice(1)?)?
+        .ok_or(anyhow!("TopK: key does not exist"))?;
+    let mut result = vec![];
+    for entry in topk.list() {
+        result.push(Response::Data(entry.item.clone()));
+        if with_count {
+            result.push(Response::Integer(entry.count as i64));
+        }
+    }
+    Ok(Response::Array(result))
+}
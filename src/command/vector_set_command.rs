@@ -0,0 +1,428 @@
+use crate::database::lib::DatabaseHolder;
+use crate::database::notify::{NOTIFY_GENERIC, NOTIFY_MODULE};
+use crate::parser::response::Response;
+use crate::util::vector_filter::FilterExpression;
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::value::Value;
+use crate::vojo::vector_set::{
+    Quantization, QuantizedVector, ValueVectorSet, VectorNode, VSET_DEFAULT_EF_CONSTRUCTION,
+    VSET_DEFAULT_EF_SEARCH, VSET_DEFAULT_M,
+};
+use anyhow::{anyhow, ensure};
+
+/// Parses `FP32 blob` or `VALUES num value...` at `pos`, returns the vector and
+/// the position after it.
+fn parse_vector(parser: &ParsedCommand, pos: usize) -> Result<(Vec<f32>, usize), anyhow::Error> {
+    match parser.get_str(pos)?.to_uppercase().as_str() {
+        "FP32" => {
+            let blob = parser.get_slice(pos + 1)?;
+            ensure!(
+                !blob.is_empty() && blob.len().is_multiple_of(4),
+                "Invalid FP32 blob size"
+            );
+            let vector = blob
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect();
+            Ok((vector, pos + 2))
+        }
+        "VALUES" => {
+            let count = parser
+                .get_i64(pos + 1)
+                .ok()
+                .filter(|value| *value > 0)
+                .ok_or(anyhow!("invalid vector dimension"))? as usize;
+            ensure!(parser.argv.len() >= pos + 2 + count, "InvalidArgument");
+            let mut vector = Vec::with_capacity(count);
+            for i in 0..count {
+                let value = parser
+                    .get_f64(pos + 2 + i)
+                    .map_err(|_| anyhow!("invalid vector specification"))?;
+                vector.push(value as f32);
+            }
+            Ok((vector, pos + 2 + count))
+        }
+        _ => Err(anyhow!("syntax error")),
+    }
+}
+/// Vectors are stored as f32, formatting them as f64 would show the rounding noise.
+fn f32_to_bytes(value: f32) -> Vec<u8> {
+    value.to_string().into_bytes()
+}
+fn parse_positive(parser: &ParsedCommand, pos: usize, error: &str) -> Result<usize, anyhow::Error> {
+    parser
+        .get_i64(pos)
+        .ok()
+        .filter(|value| *value > 0)
+        .map(|value| value as usize)
+        .ok_or(anyhow!("{}", error))
+}
+fn parse_attributes(text: &str) -> Result<Option<String>, anyhow::Error> {
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let value: serde_json::Value =
+        serde_json::from_str(text).map_err(|_| anyhow!("Invalid JSON in attributes"))?;
+    ensure!(value.is_object(), "Invalid JSON in attributes");
+    Ok(Some(text.to_string()))
+}
+/// `VADD key (FP32 blob | VALUES num value...) element [CAS] [NOQUANT | Q8 | BIN] [EF ef] [SETATTR json] [M links]`,
+/// the quantization and the links per node are chosen when the set is created.
+pub fn vadd(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
+    let key = parser.get_vec(1)?;
+    ensure!(
+        !parser.get_str(2)?.eq_ignore_ascii_case("REDUCE"),
+        "REDUCE is not supported"
+    );
+    let (vector, pos) = parse_vector(&parser, 2)?;
+    let element = parser.get_vec(pos)?;
+    let mut quantization = None;
+    let mut ef = VSET_DEFAULT_EF_CONSTRUCTION;
+    let mut attributes = None;
+    let mut m = VSET_DEFAULT_M;
+    let mut i = pos + 1;
+    while i < parser.argv.len() {
+        match parser.get_str(i)?.to_uppercase().as_str() {
+            // Adds run under the database lock, there is nothing to check and set
+            "CAS" => i += 1,
+            "NOQUANT" => {
+                quantization = Some(Quantization::NoQuant);
+                i += 1;
+            }
+            "Q8" => {
+                quantization = Some(Quantization::Q8);
+                i += 1;
+            }
+            "BIN" => {
+                quantization = Some(Quantization::Bin);
+                i += 1;
+            }
+            "EF" => {
+                ef = parse_positive(&parser, i + 1, "invalid EF")?;
+                i += 2;
+            }
+            "SETATTR" => {
+                attributes = Some(parse_attributes(parser.get_str(i + 1)?)?);
+                i += 2;
+            }
+            "M" => {
+                m = parse_positive(&parser, i + 1, "invalid M")?.min(4096) as u32;
+                i += 2;
+            }
+            _ => return Err(anyhow!("syntax error")),
+        }
+    }
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    if db.get_vector_set(db_index, &key)?.is_none() {
+        let set = ValueVectorSet::new(
+            vector.len() as u32,
+            quantization.unwrap_or(Quantization::Q8),
+            m,
+        );
+        db.insert(db_index, key.clone(), Value::VectorSet(set))?;
+    }
+    let set = db
+        .get_vector_set_mut(db_index, &key)?
+        .ok_or(anyhow!("can not find the vector set"))?;
+    ensure!(
+        vector.len() == set.dim as usize,
+        "Vector dimension mismatch - got {} but set has {}",
+        vector.len(),
+        set.dim
+    );
+    ensure!(
+        quantization.is_none_or(|quantization| quantization == set.quantization),
+        "asked quantization mismatch with existing vector set"
+    );
+    // Without SETATTR the attributes of an existing element are kept
+    let added = set.add(element.clone(), &vector, attributes.clone().flatten(), ef);
+    if let (Some(attributes), Some(node)) = (attributes, set.get_mut(&element)) {
+        node.attributes = attributes;
+    }
+    db.modified(db_index, &key);
+    db.notify(NOTIFY_MODULE, "vadd", db_index, &key);
+    Ok(Response::Integer(added as i64))
+}
+/// `VSIM key (ELE element | FP32 blob | VALUES num value...) [WITHSCORES] [WITHATTRIBS] [COUNT count]
+/// [EF ef] [FILTER expression] [FILTER-EF effort] [TRUTH] [NOTHREAD]`
+pub fn vsim(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() >= 4, "InvalidArgument");
+    let (query, pos) = if parser.get_str(2)?.eq_ignore_ascii_case("ELE") {
+        (None, 4)
+    } else {
+        let (vector, pos) = parse_vector(&parser, 2)?;
+        (Some(vector), pos)
+    };
+    let mut with_scores = false;
+    let mut with_attributes = false;
+    let mut count = 10;
+    let mut ef = VSET_DEFAULT_EF_SEARCH;
+    let mut filter = None;
+    let mut filter_ef = None;
+    let mut truth = false;
+    let mut i = pos;
+    while i < parser.argv.len() {
+        match parser.get_str(i)?.to_uppercase().as_str() {
+            "WITHSCORES" => with_scores = true,
+            "WITHATTRIBS" => with_attributes = true,
+            "TRUTH" => truth = true,
+            // The search always runs on the calling thread
+            "NOTHREAD" => {}
+            "COUNT" => {
+                count = parse_positive(&parser, i + 1, "invalid COUNT")?;
+                i += 1;
+            }
+            "EF" => {
+                ef = parse_positive(&parser, i + 1, "invalid EF")?;
+                i += 1;
+            }
+            "FILTER" => {
+                filter = Some(FilterExpression::parse(parser.get_str(i + 1)?)?);
+                i += 1;
+            }
+            "FILTER-EF" => {
+                filter_ef = Some(parse_positive(&parser, i + 1, "invalid FILTER-EF")?);
+                i += 1;
+            }
+            _ => return Err(anyhow!("syntax error")),
+        }
+        i += 1;
+    }
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let Some(set) = db.get_vector_set(db_index, parser.get_slice(1)?)? else {
+        return Ok(Response::Array(vec![]));
+    };
+    let query = match query {
+        Some(vector) => vector,
+        None => set
+            .query_vector(parser.get_slice(3)?)
+            .ok_or(anyhow!("element not found in set"))?,
+    };
+    ensure!(
+        query.len() == set.dim as usize,
+        "Vector dimension mismatch - got {} but set has {}",
+        query.len(),
+        set.dim
+    );
+    let matches = |node: &VectorNode| {
+        node.attributes
+            .as_ref()
+            .and_then(|attributes| serde_json::from_str(attributes).ok())
+            .is_some_and(|attributes| {
+                filter
+                    .as_ref()
+                    .is_some_and(|filter| filter.matches(&attributes))
+            })
+    };
+    let predicate: Option<&dyn Fn(&VectorNode) -> bool> = match filter {
+        Some(_) => Some(&matches),
+        None => None,
+    };
+    let found = if truth {
+        set.search_exact(&query, count, predicate)
+    } else {
+        set.search(
+            &query,
+            count,
+            ef,
+            predicate,
+            filter_ef.unwrap_or(count * 100),
+        )
+    };
+    let mut result = vec![];
+    for (node, score) in found {
+        result.push(Response::Data(node.element.clone()));
+        if with_scores {
+            result.push(Response::Data(f32_to_bytes(score)));
+        }
+        if with_attributes {
+            result.push(match node.attributes.as_ref() {
+                Some(attributes) => Response::Data(attributes.clone().into_bytes()),
+                None => Response::Nil,
+            });
+        }
+    }
+    Ok(Response::Array(result))
+}
+pub fn vrem(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 3, "InvalidArgument");
+    let key = parser.get_slice(1)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let Some(set) = db.get_vector_set_mut(db_index, key)? else {
+        return Ok(Response::Integer(0));
+    };
+    let removed = set.remove(parser.get_slice(2)?);
+    let emptied = set.len() == 0;
+    if removed {
+        db.modified(db_index, key);
+        db.notify(NOTIFY_MODULE, "vrem", db_index, key);
+    }
+    if emptied {
+        db.remove(db_index, key)?;
+        db.notify(NOTIFY_GENERIC, "del", db_index, key);
+    }
+    Ok(Response::Integer(removed as i64))
+}
+pub fn vcard(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 2, "InvalidArgument");
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let len = db
+        .get_vector_set(db_index, parser.get_slice(1)?)?
+        .map_or(0, |set| set.len());
+    Ok(Response::Integer(len as i64))
+}
+pub fn vdim(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 2, "InvalidArgument");
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let set = db
+        .get_vector_set(db_index, parser.get_slice(1)?)?
+        .ok_or(anyhow!("key does not exist"))?;
+    Ok(Response::Integer(set.dim as i64))
+}
+/// `VEMB key element [RAW]`, with `RAW` the quantization, the stored vector,
+/// its norm and for Q8 the largest component are returned.
+pub fn vemb(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(
+        parser.argv.len() == 3 || parser.argv.len() == 4,
+        "InvalidArgument"
+    );
+    let raw = parser.argv.len() == 4;
+    ensure!(
+        !raw || parser.get_str(3)?.eq_ignore_ascii_case("RAW"),
+        "syntax error"
+    );
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let Some(set) = db.get_vector_set(db_index, parser.get_slice(1)?)? else {
+        return Ok(Response::Nil);
+    };
+    let element = parser.get_slice(2)?;
+    if !raw {
+        return Ok(match set.embedding(element) {
+            Some(vector) => Response::Array(
+                vector
+                    .into_iter()
+                    .map(|value| Response::Data(f32_to_bytes(value)))
+                    .collect(),
+            ),
+            None => Response::Nil,
+        });
+    }
+    let Some(node) = set.get(element) else {
+        return Ok(Response::Nil);
+    };
+    let mut result = vec![];
+    match &node.vector {
+        QuantizedVector::Float(values) => {
+            result.push(Response::Status("f32".to_owned()));
+            result.push(Response::Data(
+                values
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes())
+                    .collect(),
+            ));
+            result.push(Response::Data(f32_to_bytes(node.norm)));
+        }
+        QuantizedVector::Int8 { values, scale } => {
+            result.push(Response::Status("int8".to_owned()));
+            result.push(Response::Data(
+                values.iter().map(|value| *value as u8).collect(),
+            ));
+            result.push(Response::Data(f32_to_bytes(node.norm)));
+            result.push(Response::Data(f32_to_bytes(scale * 127.0)));
+        }
+        QuantizedVector::Binary(bits) => {
+            result.push(Response::Status("bin".to_owned()));
+            result.push(Response::Data(
+                bits.iter().flat_map(|value| value.to_le_bytes()).collect(),
+            ));
+            result.push(Response::Data(f32_to_bytes(node.norm)));
+        }
+    }
+    Ok(Response::Array(result))
+}
+/// `VSETATTR key element json`, an empty string removes the attributes.
+pub fn vsetattr(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 4, "InvalidArgument");
+    let attributes = parse_attributes(parser.get_str(3)?)?;
+    let mut db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let key = parser.get_slice(1)?;
+    let node = db
+        .get_vector_set_mut(db_index, key)?
+        .and_then(|set| set.get_mut(parser.get_slice(2).ok()?));
+    let Some(node) = node else {
+        return Ok(Response::Integer(0));
+    };
+    node.attributes = attributes;
+    db.modified(db_index, key);
+    db.notify(NOTIFY_MODULE, "vsetattr", db_index, key);
+    Ok(Response::Integer(1))
+}
+pub fn vgetattr(
+    parser: ParsedCommand,
+    database_lock: &mut DatabaseHolder,
+    db_index: usize,
+) -> Result<Response, anyhow::Error> {
+    ensure!(parser.argv.len() == 3, "InvalidArgument");
+    let db = database_lock
+        .database_lock
+        .lock()
+        .map_err(|e| anyhow!("{}", e))?;
+    let attributes = db
+        .get_vector_set(db_index, parser.get_slice(1)?)?
+        .and_then(|set| set.get(parser.get_slice(2).ok()?))
+        .and_then(|node| node.attributes.clone());
+    Ok(match attributes {
+        Some(attributes) => Response::Data(attributes.into_bytes()),
+        None => Response::Nil,
+    })
+}
@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+type Waiters = HashMap<(usize, Vec<u8>), Vec<Arc<Notify>>>;
+
+/// The clients blocked on keys, e.g. BZPOPMIN waiting for a ZADD.
+///
+/// Every blocked client owns one `Notify` registered under all the keys it waits for.
+/// `Notify::notify_one` keeps a permit when the client is not awaiting yet,
+/// so a key signaled between the client's last check and its next wait is not missed.
+#[derive(Default)]
+pub struct BlockingKeys {
+    waiters: Mutex<Waiters>,
+}
+impl BlockingKeys {
+    pub fn new() -> Self {
+        BlockingKeys {
+            waiters: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Registers a client on `keys`, the client is removed when the guard is dropped.
+    pub fn register(self: &Arc<Self>, db_index: usize, keys: &[Vec<u8>]) -> BlockingGuard {
+        let notify = Arc::new(Notify::new());
+        if let Ok(mut waiters) = self.waiters.lock() {
+            for key in keys {
+                waiters
+                    .entry((db_index, key.clone()))
+                    .or_default()
+                    .push(notify.clone());
+            }
+        }
+        BlockingGuard {
+            blocking_keys: self.clone(),
+            db_index,
+            keys: keys.to_vec(),
+            notify,
+        }
+    }
+    /// Wakes up the clients blocked on the key.
+    pub fn signal(&self, db_index: usize, key: &[u8]) {
+        if let Ok(waiters) = self.waiters.lock() {
+            if let Some(notifies) = waiters.get(&(db_index, key.to_vec())) {
+                for notify in notifies {
+                    notify.notify_one();
+                }
+            }
+        }
+    }
+    fn unregister(&self, db_index: usize, keys: &[Vec<u8>], notify: &Arc<Notify>) {
+        if let Ok(mut waiters) = self.waiters.lock() {
+            for key in keys {
+                let waiter_key = (db_index, key.clone());
+                if let Some(notifies) = waiters.get_mut(&waiter_key) {
+                    notifies.retain(|item| !Arc::ptr_eq(item, notify));
+                    if notifies.is_empty() {
+                        waiters.remove(&waiter_key);
+                    }
+                }
+            }
+        }
+    }
+}
+pub struct BlockingGuard {
+    blocking_keys: Arc<BlockingKeys>,
+    db_index: usize,
+    keys: Vec<Vec<u8>>,
+    pub notify: Arc<Notify>,
+}
+impl Drop for BlockingGuard {
+    fn drop(&mut self) {
+        self.blocking_keys
+            .unregister(self.db_index, &self.keys, &self.notify);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn a_signal_sent_before_waiting_is_kept() {
+        let blocking_keys = Arc::new(BlockingKeys::new());
+        let guard = blocking_keys.register(0, &[b"a".to_vec(), b"b".to_vec()]);
+        blocking_keys.signal(0, b"b");
+        let woken = timeout(Duration::from_millis(100), guard.notify.notified()).await;
+        assert!(woken.is_ok());
+    }
+
+    #[tokio::test]
+    async fn only_the_signaled_key_and_database_wake_up() {
+        let blocking_keys = Arc::new(BlockingKeys::new());
+        let guard = blocking_keys.register(0, &[b"a".to_vec()]);
+        blocking_keys.signal(1, b"a");
+        blocking_keys.signal(0, b"other");
+        let woken = timeout(Duration::from_millis(50), guard.notify.notified()).await;
+        assert!(woken.is_err());
+    }
+
+    #[test]
+    fn dropping_the_guard_unregisters_the_client() {
+        let blocking_keys = Arc::new(BlockingKeys::new());
+        let first = blocking_keys.register(0, &[b"a".to_vec()]);
+        let second = blocking_keys.register(0, &[b"a".to_vec()]);
+        drop(first);
+        assert_eq!(
+            blocking_keys.waiters.lock().unwrap()[&(0, b"a".to_vec())].len(),
+            1
+        );
+        drop(second);
+        assert!(blocking_keys.waiters.lock().unwrap().is_empty());
+    }
+}
@@ -1,13 +1,15 @@
+use super::fs_writer::MyWriter;
 use super::{fs_writer::MyReader, lib::Database};
 use bincode::config;
-use std::fs::OpenOptions;
+use std::fs::{self, File, OpenOptions};
+use std::path::Path;
 use tokio::time::Instant;
-pub async fn load_rdb(file_path: String) -> Result<Database, anyhow::Error> {
-    info!("Rdb file is loading ,file path is: {}", file_path);
+pub async fn load_rdb(file_path: &Path) -> Result<Database, anyhow::Error> {
+    info!("Rdb file is loading ,file path is: {}", file_path.display());
     let now = Instant::now();
-    let file = OpenOptions::new().read(true).open(file_path.clone())?;
+    let file = OpenOptions::new().read(true).open(file_path)?;
     let config = config::standard();
-    let my_reader = MyReader(file);
+    let my_reader = MyReader::new(file);
     let database: Database = bincode::decode_from_reader(my_reader, config)?;
     let key_len = database.data[0].len();
     info!(
@@ -17,3 +19,270 @@ pub async fn load_rdb(file_path: String) -> Result<Database, anyhow::Error> {
     );
     Ok(database)
 }
+/// Loads the snapshot at `file_path`. A server without a snapshot starts empty,
+/// unless the snapshot was asked for.
+pub async fn load_or_new(file_path: &Path, required: bool) -> Result<Database, anyhow::Error> {
+    if required || file_path.exists() {
+        load_rdb(file_path).await
+    } else {
+        Ok(Database::new())
+    }
+}
+/// Writes the snapshot to a temporary file next to `path`, then renames it
+/// over `path` once it is on disk. The previous snapshot is left as it was
+/// when the save fails.
+pub fn save_rdb(database: &Database, path: &Path) -> Result<(), anyhow::Error> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let temp_path = directory.join(format!("temp-{}.rdb", std::process::id()));
+    let saved = write_rdb(database, &temp_path).and_then(|_| Ok(fs::rename(&temp_path, path)?));
+    if saved.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    saved?;
+    // The rename only survives a crash once the directory is synced
+    #[cfg(not(target_os = "windows"))]
+    File::open(directory)?.sync_all()?;
+    Ok(())
+}
+fn write_rdb(database: &Database, temp_path: &Path) -> Result<(), anyhow::Error> {
+    let file = File::create(temp_path)?;
+    let mywriter = MyWriter::new(file.try_clone()?);
+    bincode::encode_into_writer(database, mywriter, config::standard())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::function::{FunctionInfo, Library};
+    use crate::database::search::{FieldType, IndexDefinition, IndexSource, SchemaField};
+    use crate::vojo::value::{Value, ValueHash, ValueString};
+    use bincode::Encode;
+    use std::collections::HashMap;
+
+    /// An empty directory for the snapshots of one test.
+    fn directory(name: &str) -> std::path::PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("rcache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+    fn database() -> Database {
+        let mut database = Database::new();
+        database
+            .insert(
+                0,
+                b"name".to_vec(),
+                Value::String(ValueString {
+                    data: b"rcache".to_vec(),
+                }),
+            )
+            .unwrap();
+        let hash = ValueHash {
+            data: HashMap::from([(b"title".to_vec(), b"red shoes".to_vec())]),
+        };
+        database
+            .insert(0, b"doc:1".to_vec(), Value::Hash(hash))
+            .unwrap();
+        database.expire_map[0].insert(b"name".to_vec(), i64::MAX);
+        database
+            .search
+            .create(
+                IndexDefinition {
+                    name: "idx".to_owned(),
+                    source: IndexSource::Hash,
+                    prefixes: vec![b"doc:".to_vec()],
+                    db_index: 0,
+                    fields: vec![SchemaField {
+                        path: "title".to_owned(),
+                        name: "title".to_owned(),
+                        field_type: FieldType::Text { weight: 1.0 },
+                        sortable: false,
+                    }],
+                },
+                &database.data,
+            )
+            .unwrap();
+        database
+            .functions
+            .load(
+                Library {
+                    name: "lib".to_owned(),
+                    engine: "LUA".to_owned(),
+                    code: "#!lua name=lib".to_owned(),
+                    functions: vec![FunctionInfo {
+                        name: "f".to_owned(),
+                        description: None,
+                        flags: vec![],
+                    }],
+                },
+                false,
+            )
+            .unwrap();
+        database
+    }
+    fn encoded(fields: impl Encode) -> Vec<u8> {
+        bincode::encode_to_vec(fields, config::standard()).unwrap()
+    }
+    /// Writes the first fields of the database the way an older version did.
+    fn write_old_snapshot(path: &Path, fields: impl Encode) {
+        fs::write(path, encoded(fields)).unwrap();
+    }
+
+    #[tokio::test]
+    async fn save_and_load_round_trip() {
+        let directory = directory("round-trip");
+        let path = directory.join("dump.rdb");
+        let database = database();
+        save_rdb(&database, &path).unwrap();
+        let loaded = load_rdb(&path).await.unwrap();
+        assert_eq!(loaded, database);
+        // The documents are indexed again
+        assert!(loaded
+            .search
+            .get("idx")
+            .unwrap()
+            .documents
+            .contains_key(b"doc:1".as_slice()));
+        // Only the snapshot is left in the directory
+        let files: Vec<_> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, vec!["dump.rdb"]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_missing_snapshot_is_only_loaded_when_required() {
+        let directory = directory("missing");
+        let path = directory.join("dump.rdb");
+        assert_eq!(load_or_new(&path, false).await.unwrap(), Database::new());
+        assert!(load_or_new(&path, true).await.is_err());
+        let database = database();
+        save_rdb(&database, &path).unwrap();
+        assert_eq!(load_or_new(&path, false).await.unwrap(), database);
+        assert_eq!(load_or_new(&path, true).await.unwrap(), database);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn old_snapshots_are_loaded() {
+        let directory = directory("old-snapshots");
+        let path = directory.join("dump.rdb");
+        let database = database();
+
+        // Before the search indexes and the function libraries
+        write_old_snapshot(
+            &path,
+            (&database.data, &database.expire_map, &database.node_info),
+        );
+        let loaded = load_rdb(&path).await.unwrap();
+        assert_eq!(loaded.data, database.data);
+        assert_eq!(loaded.expire_map, database.expire_map);
+        assert!(loaded.search.indexes.is_empty());
+        assert!(loaded.functions.libraries.is_empty());
+
+        // Before the function libraries
+        write_old_snapshot(
+            &path,
+            (
+                &database.data,
+                &database.expire_map,
+                &database.node_info,
+                &database.search,
+            ),
+        );
+        let loaded = load_rdb(&path).await.unwrap();
+        assert_eq!(loaded.search, database.search);
+        assert!(loaded
+            .search
+            .get("idx")
+            .unwrap()
+            .documents
+            .contains_key(b"doc:1".as_slice()));
+        assert!(loaded.functions.libraries.is_empty());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_truncated_snapshot_is_an_error() {
+        let directory = directory("truncated");
+        let path = directory.join("dump.rdb");
+        save_rdb(&database(), &path).unwrap();
+        let saved = fs::read(&path).unwrap();
+        // Even where a field of an older snapshot would end
+        for len in 0..saved.len() {
+            fs::write(&path, &saved[..len]).unwrap();
+            assert!(load_rdb(&path).await.is_err(), "cut at {}", len);
+        }
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_truncated_old_snapshot_is_an_error() {
+        let directory = directory("truncated-old");
+        let path = directory.join("dump.rdb");
+        let database = database();
+        let keys = (&database.data, &database.expire_map, &database.node_info);
+        // The snapshots written before the search indexes, and before the function libraries
+        let old_lens = [encoded(keys).len(), encoded((keys, &database.search)).len()];
+        let fields = (keys, &database.search, &database.functions);
+        let encoded = encoded(fields);
+        for len in 0..encoded.len() {
+            fs::write(&path, &encoded[..len]).unwrap();
+            let loaded = load_rdb(&path).await;
+            assert_eq!(loaded.is_ok(), old_lens.contains(&len), "cut at {}", len);
+        }
+        fs::write(&path, &encoded).unwrap();
+        let loaded = load_rdb(&path).await.unwrap();
+        assert_eq!(loaded, database);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_newer_snapshot_format_is_an_error() {
+        let directory = directory("newer-format");
+        let path = directory.join("dump.rdb");
+        let database = database();
+        write_old_snapshot(
+            &path,
+            (
+                u64::from_be_bytes(*b"RCACHEDB"),
+                2u32,
+                &database.data,
+                &database.expire_map,
+                &database.node_info,
+            ),
+        );
+        let error = load_rdb(&path).await.unwrap_err();
+        assert!(error.to_string().contains("format version 2"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn a_failed_save_keeps_the_previous_snapshot() {
+        let directory = directory("failed-save");
+        let path = directory.join("dump.rdb");
+        fs::write(&path, b"previous").unwrap();
+        // The snapshot can not be renamed over a directory which is not empty
+        let blocked = directory.join("blocked");
+        fs::create_dir_all(blocked.join("inside")).unwrap();
+        assert!(save_rdb(&database(), &blocked).is_err());
+        assert!(save_rdb(&database(), &directory.join("missing").join("dump.rdb")).is_err());
+
+        assert_eq!(fs::read(&path).unwrap(), b"previous");
+        let mut files: Vec<_> = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["blocked", "dump.rdb"]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,24 +1,88 @@
 use bincode::error::DecodeError;
 use bincode::error::EncodeError;
 use bincode::{de::read::Reader, enc::write::Writer};
-use std::io::Read;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
 
 use std::{fs::File, io::Write};
-pub struct MyWriter(pub File);
+pub struct MyWriter {
+    file: File,
+    written: usize,
+}
+impl MyWriter {
+    pub fn new(file: File) -> Self {
+        MyWriter { file, written: 0 }
+    }
+}
 impl Writer for MyWriter {
     fn write(&mut self, bytes: &[u8]) -> Result<(), EncodeError> {
-        let _ = self.0.write_all(bytes);
-
+        self.file
+            .write_all(bytes)
+            .map_err(|inner| EncodeError::Io {
+                inner,
+                index: self.written,
+            })?;
+        self.written += bytes.len();
         Ok(())
     }
 }
-pub struct MyReader(pub File);
+/// Reads the snapshot through a buffer, which lets the decoder peek whether it ended.
+pub struct MyReader(BufReader<File>);
+impl MyReader {
+    pub fn new(file: File) -> Self {
+        MyReader(BufReader::new(file))
+    }
+}
 impl Reader for MyReader {
     /// Fill the given `bytes` argument with values. Exactly the length of the given slice must be filled, or else an error must be returned.
     fn read(&mut self, bytes: &mut [u8]) -> Result<(), DecodeError> {
-        self.0
-            .read(bytes)
-            .map_err(|e| DecodeError::OtherString(e.to_string()))?;
-        Ok(())
+        self.0.read_exact(bytes).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => DecodeError::UnexpectedEnd {
+                additional: bytes.len(),
+            },
+            _ => DecodeError::OtherString(e.to_string()),
+        })
+    }
+    /// The buffered bytes, `None` at the end of the file or when fewer are buffered.
+    fn peek_read(&mut self, n: usize) -> Option<&[u8]> {
+        self.0.fill_buf().ok().filter(|buffer| buffer.len() >= n)
+    }
+    fn consume(&mut self, n: usize) {
+        BufRead::consume(&mut self.0, n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_end_of_the_file_is_an_unexpected_end() {
+        let path = std::env::temp_dir().join(format!("rcache-reader-{}", std::process::id()));
+        std::fs::write(&path, [1, 2, 3]).unwrap();
+        let mut reader = MyReader::new(File::open(&path).unwrap());
+        let mut bytes = [0; 2];
+        reader.read(&mut bytes).unwrap();
+        assert_eq!(bytes, [1, 2]);
+        assert_eq!(reader.peek_read(1), Some([3].as_slice()));
+        assert_eq!(reader.peek_read(2), None);
+        assert!(matches!(
+            reader.read(&mut bytes),
+            Err(DecodeError::UnexpectedEnd { additional: 2 })
+        ));
+        assert_eq!(reader.peek_read(1), None);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn write_errors_are_returned() {
+        let path = std::env::temp_dir().join(format!("rcache-writer-{}", std::process::id()));
+        std::fs::write(&path, []).unwrap();
+        // A file opened for reading only can not be written
+        let mut writer = MyWriter::new(File::open(&path).unwrap());
+        assert!(matches!(
+            writer.write(b"data"),
+            Err(EncodeError::Io { index: 0, .. })
+        ));
+        std::fs::remove_file(path).unwrap();
     }
 }
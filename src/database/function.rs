@@ -0,0 +1,173 @@
+use anyhow::{anyhow, ensure};
+use bincode::{Decode, Encode};
+use std::collections::BTreeMap;
+
+/// The flags a function can be registered with.
+pub const FUNCTION_FLAGS: [&str; 5] = [
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+/// A function registered by a library with `redis.register_function`.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+}
+impl FunctionInfo {
+    /// Functions without the `no-writes` flag can not run with FCALL_RO.
+    pub fn is_read_only(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "no-writes")
+    }
+}
+/// A library loaded with FUNCTION LOAD. Its code runs again each time one of
+/// its functions is called.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct Library {
+    pub name: String,
+    pub engine: String,
+    pub code: String,
+    pub functions: Vec<FunctionInfo>,
+}
+
+/// The function libraries, they are written to the snapshot with the keys and
+/// are not removed by FLUSHALL.
+#[derive(PartialEq, Debug, Clone, Default, Encode, Decode)]
+pub struct FunctionLibraries {
+    pub libraries: BTreeMap<String, Library>,
+}
+impl FunctionLibraries {
+    /// Adds a library, or replaces the one with the same name when `replace` is set.
+    /// A function name can only be used by one library.
+    pub fn load(&mut self, library: Library, replace: bool) -> Result<(), anyhow::Error> {
+        ensure!(
+            replace || !self.libraries.contains_key(&library.name),
+            "Library '{}' already exists",
+            library.name
+        );
+        for function in &library.functions {
+            if let Some((other, _)) = self.get_function(&function.name) {
+                ensure!(
+                    other.name == library.name,
+                    "Function {} already exists",
+                    function.name
+                );
+            }
+        }
+        self.libraries.insert(library.name.clone(), library);
+        Ok(())
+    }
+    pub fn delete(&mut self, name: &str) -> Result<(), anyhow::Error> {
+        self.libraries
+            .remove(name)
+            .map(|_| ())
+            .ok_or(anyhow!("Library not found"))
+    }
+    pub fn flush(&mut self) {
+        self.libraries.clear();
+    }
+    /// The library registering the function and the function itself.
+    pub fn get_function(&self, name: &str) -> Option<(&Library, &FunctionInfo)> {
+        self.libraries.values().find_map(|library| {
+            library
+                .functions
+                .iter()
+                .find(|function| function.name == name)
+                .map(|function| (library, function))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn library(name: &str, functions: &[&str]) -> Library {
+        Library {
+            name: name.to_string(),
+            engine: String::from("LUA"),
+            code: format!("#!lua name={}", name),
+            functions: functions
+                .iter()
+                .map(|name| FunctionInfo {
+                    name: name.to_string(),
+                    description: None,
+                    flags: vec![],
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn load_and_replace() {
+        let mut functions = FunctionLibraries::default();
+        functions
+            .load(library("lib", &["f1", "f2"]), false)
+            .unwrap();
+        let error = functions.load(library("lib", &["f3"]), false).unwrap_err();
+        assert_eq!(error.to_string(), "Library 'lib' already exists");
+
+        functions.load(library("lib", &["f3"]), true).unwrap();
+        assert!(functions.get_function("f1").is_none());
+        let (library, function) = functions.get_function("f3").unwrap();
+        assert_eq!(
+            (library.name.as_str(), function.name.as_str()),
+            ("lib", "f3")
+        );
+    }
+
+    #[test]
+    fn a_function_name_belongs_to_one_library() {
+        let mut functions = FunctionLibraries::default();
+        functions.load(library("lib1", &["f1"]), false).unwrap();
+        let error = functions.load(library("lib2", &["f1"]), false).unwrap_err();
+        assert_eq!(error.to_string(), "Function f1 already exists");
+        let error = functions.load(library("lib2", &["f1"]), true).unwrap_err();
+        assert_eq!(error.to_string(), "Function f1 already exists");
+        assert!(!functions.libraries.contains_key("lib2"));
+    }
+
+    #[test]
+    fn delete_and_flush() {
+        let mut functions = FunctionLibraries::default();
+        functions.load(library("lib1", &["f1"]), false).unwrap();
+        functions.load(library("lib2", &["f2"]), false).unwrap();
+        functions.delete("lib1").unwrap();
+        assert_eq!(
+            functions.delete("lib1").unwrap_err().to_string(),
+            "Library not found"
+        );
+        assert!(functions.get_function("f1").is_none());
+        functions.flush();
+        assert!(functions.libraries.is_empty());
+    }
+
+    #[test]
+    fn read_only_functions() {
+        let mut function = library("lib", &["f"]).functions.remove(0);
+        assert!(!function.is_read_only());
+        function.flags = vec![String::from("allow-stale"), String::from("no-writes")];
+        assert!(function.is_read_only());
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut functions = FunctionLibraries::default();
+        let mut lib = library("lib", &["f1", "f2"]);
+        lib.functions[0].description = Some(String::from("the first one"));
+        lib.functions[1].flags = vec![String::from("no-writes")];
+        functions.load(lib, false).unwrap();
+        functions.load(library("other", &["f3"]), false).unwrap();
+
+        let config = bincode::config::standard();
+        let encoded = bincode::encode_to_vec(&functions, config).unwrap();
+        let (decoded, read): (FunctionLibraries, usize) =
+            bincode::decode_from_slice(&encoded, config).unwrap();
+        assert_eq!(read, encoded.len());
+        assert_eq!(decoded, functions);
+    }
+}
@@ -1,53 +1,170 @@
-use crate::database::fs_writer::MyWriter;
+use crate::database::common::save_rdb;
+use crate::module::registry::Modules;
 use crate::parser::response::Response;
+use crate::util::common_utils::mstime;
 
-use crate::vojo::value::BackgroundEvent;
+use crate::vojo::bloom::{
+    ValueBloomFilter, BLOOM_DEFAULT_CAPACITY, BLOOM_DEFAULT_ERROR_RATE, BLOOM_DEFAULT_EXPANSION,
+};
+use crate::vojo::count_min_sketch::ValueCountMinSketch;
+use crate::vojo::cuckoo::{
+    ValueCuckooFilter, CUCKOO_DEFAULT_BUCKET_SIZE, CUCKOO_DEFAULT_CAPACITY,
+    CUCKOO_DEFAULT_EXPANSION, CUCKOO_DEFAULT_MAX_ITERATIONS,
+};
+use crate::vojo::hyperloglog::ValueHyperLogLog;
+use crate::vojo::json::ValueJson;
+#[cfg(feature = "native-modules")]
+use crate::vojo::module_value::ModuleData;
+use crate::vojo::parsered_command::LexBound;
+use crate::vojo::stream::ValueStream;
+use crate::vojo::timeseries::ValueTimeSeries;
+use crate::vojo::topk::ValueTopK;
 use crate::vojo::value::Value;
-use crate::vojo::value::{ValueSet, ValueSortedSet};
+use crate::vojo::value::{SortedSetData, ValueSet, ValueSortedSet, ZaddOptions, ZaddOutcome};
+use crate::vojo::vector_set::ValueVectorSet;
 
-use std::collections::{BTreeSet, HashMap};
+use std::collections::Bound;
+use std::collections::HashMap;
 use std::collections::{HashSet, VecDeque};
 
+use super::blocking::BlockingKeys;
+use super::function::FunctionLibraries;
 use super::info::NodeInfo;
+use super::notify::{KeyspaceNotifier, NOTIFY_EXPIRED, NOTIFY_GENERIC, NOTIFY_NEW, NOTIFY_ZSET};
+use super::persistence::{RdbConfig, SaveStatus};
+use super::pubsub::PubSub;
+use super::script::Scripts;
+use super::search::{IndexDefinition, SearchIndexes};
+use super::tracking::{KeyTracker, Tracking};
+use super::watch::WatchedKeys;
 use crate::logger::default_logger::setup_logger;
 use crate::vojo::value::ValueHash;
 use crate::vojo::value::ValueList;
-use bincode::{config, Decode, Encode};
+use crate::vojo::value::ValueString;
+use bincode::de::read::Reader;
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{impl_borrow_decode, Decode, Encode};
 #[cfg(not(any(target_os = "windows")))]
 use fork::fork;
 #[cfg(not(any(target_os = "windows")))]
+use fork::waitpid;
+#[cfg(not(any(target_os = "windows")))]
 use fork::Fork;
-use std::fs::OpenOptions;
+#[cfg(not(any(target_os = "windows")))]
+use std::io::{Read, Write};
 #[cfg(not(any(target_os = "windows")))]
 use std::ops::Deref;
+#[cfg(not(any(target_os = "windows")))]
+use std::os::unix::net::UnixStream;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::sync::TryLockError;
 use std::time::Duration;
-use tokio::sync::mpsc;
 use tokio::time::interval;
+use tokio::time::timeout_at;
 use tokio::time::Instant;
 
 #[derive(Clone)]
 pub struct DatabaseHolder {
     pub database_lock: Arc<Mutex<Database>>,
+    pub blocking_keys: Arc<BlockingKeys>,
+    pub pubsub: Arc<PubSub>,
+    pub tracking: Arc<Tracking>,
+    pub watched_keys: Arc<WatchedKeys>,
+    pub scripts: Arc<Scripts>,
+    pub modules: Arc<Modules>,
+    pub save_status: Arc<SaveStatus>,
+    pub rdb_config: Arc<RdbConfig>,
+    /// Set while a transaction runs, the blocking commands then give up
+    /// instead of waiting.
+    pub non_blocking: bool,
 }
 impl DatabaseHolder {
+    pub fn new(mut database: Database, rdb_config: RdbConfig) -> Self {
+        let pubsub = Arc::new(PubSub::new());
+        let tracking = Arc::new(Tracking::new(pubsub.clone()));
+        let watched_keys = Arc::new(WatchedKeys::new());
+        database.notifier.attach(pubsub.clone());
+        database
+            .tracker
+            .attach(tracking.clone(), watched_keys.clone());
+        DatabaseHolder {
+            database_lock: Arc::new(Mutex::new(database)),
+            blocking_keys: Arc::new(BlockingKeys::new()),
+            pubsub,
+            tracking,
+            watched_keys,
+            scripts: Arc::new(Scripts::new()),
+            modules: Arc::new(Modules::new()),
+            save_status: Arc::new(SaveStatus::new()),
+            rdb_config: Arc::new(rdb_config),
+            non_blocking: false,
+        }
+    }
+    /// Runs `try_serve` until it returns a response, waiting for one of `keys`
+    /// to be signaled between the attempts. The database lock is only held
+    /// while `try_serve` runs. Returns `None` when the timeout is reached,
+    /// a zero timeout blocks forever.
+    pub async fn block_on_keys<F>(
+        &self,
+        db_index: usize,
+        keys: &[Vec<u8>],
+        timeout: Duration,
+        mut try_serve: F,
+    ) -> Result<Option<Response>, anyhow::Error>
+    where
+        F: FnMut(&mut Database) -> Result<Option<Response>, anyhow::Error>,
+    {
+        let guard = self.blocking_keys.register(db_index, keys);
+        let deadline = if timeout.is_zero() {
+            None
+        } else {
+            Some(Instant::now() + timeout)
+        };
+        loop {
+            {
+                // Entered for each attempt only, a blocked command does not keep
+                // a script waiting
+                let _entered = match self.non_blocking {
+                    true => None,
+                    false => Some(self.scripts.enter().await?),
+                };
+                let mut db = self.database_lock.lock().map_err(|e| anyhow!("{}", e))?;
+                if let Some(response) = try_serve(&mut db)? {
+                    return Ok(Some(response));
+                }
+            }
+            if self.non_blocking {
+                return Ok(None);
+            }
+            match deadline {
+                Some(deadline) => {
+                    if timeout_at(deadline, guard.notify.notified()).await.is_err() {
+                        return Ok(None);
+                    }
+                }
+                None => guard.notify.notified().await,
+            }
+        }
+    }
     pub async fn expire_loop(&self) -> Result<(), anyhow::Error> {
         let mut interval = interval(Duration::from_millis(200));
         loop {
             interval.tick().await;
 
-            let mut lock = self.database_lock.lock().map_err(|e| anyhow!("{}", e))?;
-            let current_time = Instant::now();
-
-            for (index, map) in &mut lock.expire_map.iter_mut().enumerate() {
-                let expired_keys: Vec<Vec<u8>> = map
+            let mut lock = match self.database_lock.try_lock() {
+                Ok(lock) => lock,
+                // Held by a script or a transaction, the keys expire at the next tick
+                Err(TryLockError::WouldBlock) => continue,
+                Err(e) => return Err(anyhow!("{}", e)),
+            };
+            let now = mstime() as i64;
+            for index in 0..lock.expire_map.len() {
+                let expired_keys: Vec<Vec<u8>> = lock.expire_map[index]
                     .iter()
-                    .filter(|(_, &time)| {
-                        let time_duration = Duration::from_secs(time as u64);
-                        let expiration_time = current_time.checked_sub(time_duration);
-                        expiration_time.is_none() // If the expiration time is None, it's expired
-                    })
+                    .filter(|(_, &time)| time <= now)
                     .map(|(key, _)| key.clone())
                     .collect();
 
@@ -57,64 +174,93 @@ impl DatabaseHolder {
                         key.clone(),
                         index
                     );
-                    map.remove(&key);
+                    lock.remove(index, &key)?;
+                    lock.notify(NOTIFY_EXPIRED, "expired", index, &key);
                 }
             }
         }
     }
+    /// Saves the snapshot every 10 seconds from a forked child, the parent
+    /// records whether it succeeded once the child exits.
     #[cfg(not(any(target_os = "windows")))]
     pub async fn rdb_save(&self) -> Result<(), anyhow::Error> {
         let mut interval = interval(Duration::from_millis(10000));
-        let file_path = "rcache.rdb";
-        let config = config::standard();
         loop {
             interval.tick().await;
-            let mut file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(file_path.clone())?;
+            // A script or a transaction holds the lock, the snapshot is saved at the next tick
+            let Some(_entered) = self.scripts.try_enter() else {
+                continue;
+            };
+            let file_path = self.rdb_config.path();
+            // The previous save is still running
+            if !self.save_status.start() {
+                continue;
+            }
+            let (mut parent_end, mut child_end) = match UnixStream::pair() {
+                Ok(pair) => pair,
+                Err(e) => {
+                    error!("Can't save in background: {}", e);
+                    self.save_status.finish(false);
+                    continue;
+                }
+            };
             let lock = self.database_lock.lock().map_err(|e| anyhow!("{}", e))?;
-            if let Ok(Fork::Child) = fork() {
-                let _worker_guard = setup_logger();
-                let database = lock.deref();
-                let key_len = lock.data[0].len();
-                let current_time = Instant::now();
-                let mywriter = MyWriter(file);
-                let res = bincode::encode_into_writer(database, mywriter, config.clone());
-                if let Err(e) = res {
-                    error!("{}", e);
+            match fork() {
+                Ok(Fork::Child) => {
+                    let _worker_guard = setup_logger();
+                    let database = lock.deref();
+                    let key_len = lock.data[0].len();
+                    let current_time = Instant::now();
+                    let saved = save_rdb(database, &file_path);
+                    match &saved {
+                        Ok(()) => {
+                            info!(
+                                "Rdb file has been saved,keys count is {},total time cost {}ms",
+                                key_len,
+                                current_time.elapsed().as_millis()
+                            );
+                            println!(
+                                "Rdb file has been saved,keys count is {},total time cost {}ms",
+                                key_len,
+                                current_time.elapsed().as_millis()
+                            );
+                        }
+                        Err(e) => error!("Rdb file could not be saved: {}", e),
+                    }
+                    // The parent reads an error when the child dies before writing this
+                    let _ = child_end.write_all(&[saved.is_ok() as u8]);
+                    std::process::exit(if saved.is_ok() { 0 } else { 1 });
+                }
+                Ok(Fork::Parent(pid)) => {
+                    drop(lock);
+                    drop(child_end);
+                    let save_status = self.save_status.clone();
+                    tokio::task::spawn_blocking(move || {
+                        let mut saved = [0];
+                        let saved = parent_end.read_exact(&mut saved).is_ok() && saved[0] == 1;
+                        let _ = waitpid(pid);
+                        save_status.finish(saved);
+                    });
+                }
+                Err(e) => {
+                    drop(lock);
+                    error!("Can't save in background: fork failed with {}", e);
+                    self.save_status.finish(false);
                 }
-                let first_cost = current_time.elapsed();
-                info!(
-                    "Rdb file has been saved,keys count is {},encode time cost {}ms,total time cost {}ms",
-                    key_len,
-                    first_cost.as_millis(),
-                    current_time.elapsed().as_millis()
-                );
-                println!(
-                    "Rdb file has been saved,keys count is {},encode time cost {}ms,total time cost {}ms",
-                    key_len,
-                    first_cost.as_millis(),
-                    current_time.elapsed().as_millis()
-                );
-                std::process::exit(0);
             }
-            drop(lock);
         }
     }
     #[cfg(target_os = "windows")]
     pub async fn rdb_save(&self) -> Result<(), anyhow::Error> {
         let mut interval = interval(Duration::from_millis(10000));
-        let file_path = "rcache.rdb";
-        let config = config::standard();
         loop {
             interval.tick().await;
-            let file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true) // Create the file if it does not exist
-                .open(file_path)?;
+            // A script or a transaction holds the lock, the snapshot is saved at the next tick
+            let Some(_entered) = self.scripts.try_enter() else {
+                continue;
+            };
+            let file_path = self.rdb_config.path();
+            self.save_status.start();
             let lock = self.database_lock.lock().map_err(|e| anyhow!("{}", e))?;
             let database = lock.clone();
             drop(lock);
@@ -122,35 +268,116 @@ impl DatabaseHolder {
 
             let key_len = database.data[0].len();
             let current_time = Instant::now();
-            let mywriter = MyWriter(file);
-
-            let res = bincode::encode_into_writer(database, mywriter, config);
-            if let Err(e) = res {
-                error!("{}", e);
+            let saved = save_rdb(&database, &file_path);
+            match &saved {
+                Ok(()) => {
+                    info!(
+                        "Rdb file has been saved,keys count is {},total time cost {}ms",
+                        key_len,
+                        current_time.elapsed().as_millis()
+                    );
+                    println!(
+                        "Rdb file has been saved,keys count is {},total time cost {}ms",
+                        key_len,
+                        current_time.elapsed().as_millis()
+                    );
+                }
+                Err(e) => error!("Rdb file could not be saved: {}", e),
             }
-            let first_cost = current_time.elapsed();
-            info!(
-                    "Rdb file has been saved,keys count is {},encode time cost {}ms,total time cost {}ms",
-                    key_len,
-                    first_cost.as_millis(),
-                    current_time.elapsed().as_millis()
-                );
-            println!(
-                    "Rdb file has been saved,keys count is {},encode time cost {}ms,total time cost {}ms",
-                    key_len,
-                    first_cost.as_millis(),
-                    current_time.elapsed().as_millis()
-                );
+            self.save_status.finish(saved.is_ok());
         }
     }
 }
-#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+/// Starts the snapshots, followed by their format version. The older snapshots
+/// start with the number of databases instead.
+const SNAPSHOT_MAGIC: u64 = u64::from_be_bytes(*b"RCACHEDB");
+/// 1: the keys, their expiry times, the node info, the search indexes and the
+/// function libraries.
+const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(PartialEq, Debug, Clone)]
 
 pub struct Database {
     pub data: Vec<HashMap<Vec<u8>, Value>>,
+    /// The unix time in milliseconds at which a key is removed.
     pub expire_map: Vec<HashMap<Vec<u8>, i64>>,
     pub node_info: NodeInfo,
+    pub search: SearchIndexes,
+    pub notifier: KeyspaceNotifier,
+    pub tracker: KeyTracker,
+    pub functions: FunctionLibraries,
+}
+impl Encode for Database {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        SNAPSHOT_MAGIC.encode(encoder)?;
+        SNAPSHOT_VERSION.encode(encoder)?;
+        self.data.encode(encoder)?;
+        self.expire_map.encode(encoder)?;
+        self.node_info.encode(encoder)?;
+        self.search.encode(encoder)?;
+        self.functions.encode(encoder)
+    }
+}
+impl<Context> Decode<Context> for Database {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let (data, expire_map, node_info, definitions, functions) = match u64::decode(decoder)? {
+            SNAPSHOT_MAGIC => {
+                let version = u32::decode(decoder)?;
+                if version > SNAPSHOT_VERSION {
+                    return Err(DecodeError::OtherString(format!(
+                        "the snapshot format version {} is newer than this server",
+                        version
+                    )));
+                }
+                (
+                    Decode::decode(decoder)?,
+                    Decode::decode(decoder)?,
+                    Decode::decode(decoder)?,
+                    Decode::decode(decoder)?,
+                    Decode::decode(decoder)?,
+                )
+            }
+            // A snapshot without a version ends after the last field its server knew,
+            // a field is only missing when the snapshot ends right before it
+            databases => {
+                let data = (0..databases)
+                    .map(|_| Decode::decode(decoder))
+                    .collect::<Result<Vec<HashMap<Vec<u8>, Value>>, _>>()?;
+                let expire_map = Decode::decode(decoder)?;
+                let node_info = Decode::decode(decoder)?;
+                let definitions: Vec<IndexDefinition> = match at_end(decoder) {
+                    true => vec![],
+                    false => Decode::decode(decoder)?,
+                };
+                let functions = match at_end(decoder) {
+                    true => FunctionLibraries::default(),
+                    false => Decode::decode(decoder)?,
+                };
+                (data, expire_map, node_info, definitions, functions)
+            }
+        };
+        let mut search = SearchIndexes::default();
+        for definition in definitions {
+            search
+                .create(definition, &data)
+                .map_err(|e| DecodeError::OtherString(e.to_string()))?;
+        }
+        Ok(Database {
+            data,
+            expire_map,
+            node_info,
+            search,
+            notifier: KeyspaceNotifier::default(),
+            tracker: KeyTracker::default(),
+            functions,
+        })
+    }
+}
+/// Whether nothing is left to decode, the reader has to implement `peek_read`.
+fn at_end<D: Decoder>(decoder: &mut D) -> bool {
+    decoder.reader().peek_read(1).is_none()
 }
+impl_borrow_decode!(Database);
 
 impl Default for Database {
     fn default() -> Self {
@@ -171,6 +398,10 @@ impl Database {
             data: data_vec,
             expire_map,
             node_info,
+            search: SearchIndexes::default(),
+            notifier: KeyspaceNotifier::default(),
+            tracker: KeyTracker::default(),
+            functions: FunctionLibraries::default(),
         }
     }
     pub fn get(&self, db_index: usize, key: Vec<u8>) -> Result<Option<&Value>, anyhow::Error> {
@@ -184,18 +415,429 @@ impl Database {
     pub fn get_self(self) -> Self {
         self
     }
+    /// Stores the value at `key`, the value it replaces and its expiry time are dropped.
     pub fn insert(
         &mut self,
         db_index: usize,
         key: Vec<u8>,
         value: Value,
     ) -> Result<(), anyhow::Error> {
-        self.data
+        if let Some(expire_map) = self.expire_map.get_mut(db_index) {
+            expire_map.remove(&key);
+        }
+        self.update(db_index, key, value)
+    }
+    /// Stores the value at `key` like [`Database::insert`], but the key keeps its
+    /// expiry time, for the commands which change a value rather than replace it.
+    pub fn update(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        value: Value,
+    ) -> Result<(), anyhow::Error> {
+        let replaced = self
+            .data
             .get_mut(db_index)
             .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))?
-            .insert(key, value);
+            .insert(key.clone(), value);
+        if replaced.is_none() {
+            self.notifier.notify(NOTIFY_NEW, "new", db_index, &key);
+        }
+        self.tracker.modified(db_index, &key);
+        self.reindex(db_index, &key);
         Ok(())
     }
+    /// Runs `f` on the value stored at `key`, the value made by `create` is stored
+    /// first when the key is missing. The key is reported new and modified once `f`
+    /// succeeded, a value created for `f` is removed again when it fails.
+    fn update_or_insert_with<T>(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        create: impl FnOnce() -> Value,
+        f: impl FnOnce(&mut Value) -> Result<T, anyhow::Error>,
+    ) -> Result<T, anyhow::Error> {
+        let keys = self
+            .data
+            .get_mut(db_index)
+            .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))?;
+        let created = !keys.contains_key(&key);
+        match f(keys.entry(key.clone()).or_insert_with(create)) {
+            Ok(result) => {
+                if created {
+                    self.notifier.notify(NOTIFY_NEW, "new", db_index, &key);
+                }
+                self.tracker.modified(db_index, &key);
+                Ok(result)
+            }
+            Err(e) => {
+                if created {
+                    keys.remove(&key);
+                }
+                Err(e)
+            }
+        }
+    }
+    /// Gets the value stored at `key` to modify it, the caller reports it with
+    /// [`Database::modified`] once it actually changed.
+    fn get_value_mut(
+        &mut self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&mut Value>, anyhow::Error> {
+        self.data
+            .get_mut(db_index)
+            .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))
+            .map(|keys| keys.get_mut(key))
+    }
+    /// Tells the clients tracking or watching the key that its value changed.
+    pub fn modified(&self, db_index: usize, key: &[u8]) {
+        self.tracker.modified(db_index, key);
+    }
+    /// Publishes a keyspace notification when its class is enabled.
+    pub fn notify(&self, class: u32, event: &str, db_index: usize, key: &[u8]) {
+        self.notifier.notify(class, event, db_index, key);
+    }
+    /// Brings the search indexes covering the key up to date with its value.
+    pub fn reindex(&mut self, db_index: usize, key: &[u8]) {
+        let value = self.data.get(db_index).and_then(|keys| keys.get(key));
+        self.search.update(db_index, key, value);
+    }
+    pub fn get_string(
+        &self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&ValueString>, anyhow::Error> {
+        match self.get(db_index, key.to_vec())? {
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    /// Runs `f` on the string stored at `key`, an empty string is created when the
+    /// key is missing.
+    pub fn update_or_create_string<T>(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        f: impl FnOnce(&mut ValueString) -> Result<T, anyhow::Error>,
+    ) -> Result<T, anyhow::Error> {
+        self.update_or_insert_with(
+            db_index,
+            key,
+            || Value::String(ValueString { data: vec![] }),
+            |value| f(value.to_value_string_mut()?),
+        )
+    }
+    pub fn get_hyperloglog(
+        &self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&ValueHyperLogLog>, anyhow::Error> {
+        match self.get(db_index, key.to_vec())? {
+            Some(Value::HyperLogLog(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    /// Runs `f` on the HyperLogLog stored at `key`, the bool tells whether it was
+    /// just created.
+    pub fn update_or_create_hyperloglog<T>(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        f: impl FnOnce(&mut ValueHyperLogLog, bool) -> Result<T, anyhow::Error>,
+    ) -> Result<T, anyhow::Error> {
+        let created = self.get(db_index, key.clone())?.is_none();
+        self.update_or_insert_with(
+            db_index,
+            key,
+            || Value::HyperLogLog(ValueHyperLogLog::new()),
+            |value| match value {
+                Value::HyperLogLog(value) => f(value, created),
+                _ => Err(anyhow!("WrongTypeError")),
+            },
+        )
+    }
+    pub fn get_stream(
+        &self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&ValueStream>, anyhow::Error> {
+        match self.get(db_index, key.to_vec())? {
+            Some(Value::Stream(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    pub fn get_stream_mut(
+        &mut self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&mut ValueStream>, anyhow::Error> {
+        let value_option = self.get_value_mut(db_index, key)?;
+        match value_option {
+            Some(Value::Stream(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    /// Runs `f` on the stream stored at `key`, an empty stream is created when the
+    /// key is missing.
+    pub fn update_or_create_stream<T>(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        f: impl FnOnce(&mut ValueStream) -> Result<T, anyhow::Error>,
+    ) -> Result<T, anyhow::Error> {
+        self.update_or_insert_with(
+            db_index,
+            key,
+            || Value::Stream(ValueStream::new()),
+            |value| match value {
+                Value::Stream(value) => f(value),
+                _ => Err(anyhow!("WrongTypeError")),
+            },
+        )
+    }
+    pub fn get_json(
+        &self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&ValueJson>, anyhow::Error> {
+        match self.get(db_index, key.to_vec())? {
+            Some(Value::Json(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    pub fn get_json_mut(
+        &mut self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&mut ValueJson>, anyhow::Error> {
+        let value_option = self.get_value_mut(db_index, key)?;
+        match value_option {
+            Some(Value::Json(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    pub fn get_bloom_filter(
+        &self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&ValueBloomFilter>, anyhow::Error> {
+        match self.get(db_index, key.to_vec())? {
+            Some(Value::BloomFilter(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    /// Runs `f` on the Bloom filter stored at `key`, a filter with the default
+    /// parameters is created when the key is missing.
+    pub fn update_or_create_bloom_filter<T>(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        f: impl FnOnce(&mut ValueBloomFilter) -> Result<T, anyhow::Error>,
+    ) -> Result<T, anyhow::Error> {
+        self.update_or_insert_with(
+            db_index,
+            key,
+            || {
+                Value::BloomFilter(
+                    ValueBloomFilter::new(
+                        BLOOM_DEFAULT_ERROR_RATE,
+                        BLOOM_DEFAULT_CAPACITY,
+                        BLOOM_DEFAULT_EXPANSION,
+                    )
+                    .expect("the default filter is valid"),
+                )
+            },
+            |value| match value {
+                Value::BloomFilter(value) => f(value),
+                _ => Err(anyhow!("WrongTypeError")),
+            },
+        )
+    }
+    pub fn get_cuckoo_filter(
+        &self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&ValueCuckooFilter>, anyhow::Error> {
+        match self.get(db_index, key.to_vec())? {
+            Some(Value::CuckooFilter(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    pub fn get_cuckoo_filter_mut(
+        &mut self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&mut ValueCuckooFilter>, anyhow::Error> {
+        let value_option = self.get_value_mut(db_index, key)?;
+        match value_option {
+            Some(Value::CuckooFilter(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    /// Runs `f` on the Cuckoo filter stored at `key`, a filter with the default
+    /// parameters is created when the key is missing.
+    pub fn update_or_create_cuckoo_filter<T>(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        f: impl FnOnce(&mut ValueCuckooFilter) -> Result<T, anyhow::Error>,
+    ) -> Result<T, anyhow::Error> {
+        self.update_or_insert_with(
+            db_index,
+            key,
+            || {
+                Value::CuckooFilter(
+                    ValueCuckooFilter::new(
+                        CUCKOO_DEFAULT_CAPACITY,
+                        CUCKOO_DEFAULT_BUCKET_SIZE,
+                        CUCKOO_DEFAULT_MAX_ITERATIONS,
+                        CUCKOO_DEFAULT_EXPANSION,
+                    )
+                    .expect("the default filter is valid"),
+                )
+            },
+            |value| match value {
+                Value::CuckooFilter(value) => f(value),
+                _ => Err(anyhow!("WrongTypeError")),
+            },
+        )
+    }
+    pub fn get_count_min_sketch(
+        &self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&ValueCountMinSketch>, anyhow::Error> {
+        match self.get(db_index, key.to_vec())? {
+            Some(Value::CountMinSketch(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    pub fn get_count_min_sketch_mut(
+        &mut self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&mut ValueCountMinSketch>, anyhow::Error> {
+        let value_option = self.get_value_mut(db_index, key)?;
+        match value_option {
+            Some(Value::CountMinSketch(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    pub fn get_topk(
+        &self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&ValueTopK>, anyhow::Error> {
+        match self.get(db_index, key.to_vec())? {
+            Some(Value::TopK(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    pub fn get_topk_mut(
+        &mut self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&mut ValueTopK>, anyhow::Error> {
+        let value_option = self.get_value_mut(db_index, key)?;
+        match value_option {
+            Some(Value::TopK(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    pub fn get_timeseries(
+        &self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&ValueTimeSeries>, anyhow::Error> {
+        match self.get(db_index, key.to_vec())? {
+            Some(Value::TimeSeries(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    pub fn get_timeseries_mut(
+        &mut self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&mut ValueTimeSeries>, anyhow::Error> {
+        let value_option = self.get_value_mut(db_index, key)?;
+        match value_option {
+            Some(Value::TimeSeries(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    pub fn get_vector_set(
+        &self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&ValueVectorSet>, anyhow::Error> {
+        match self.get(db_index, key.to_vec())? {
+            Some(Value::VectorSet(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    pub fn get_vector_set_mut(
+        &mut self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&mut ValueVectorSet>, anyhow::Error> {
+        let value_option = self.get_value_mut(db_index, key)?;
+        match value_option {
+            Some(Value::VectorSet(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    /// Gets the value of a native module type stored at `key`.
+    #[cfg(feature = "native-modules")]
+    pub fn get_module_value<T: ModuleData>(
+        &self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&T>, anyhow::Error> {
+        match self.get(db_index, key.to_vec())? {
+            Some(Value::Module(value)) => value
+                .downcast_ref()
+                .map(Some)
+                .ok_or(anyhow!("WrongTypeError")),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    /// Gets the value of a native module type stored at `key` to modify it, the
+    /// module reports the change with [`Database::modified`].
+    #[cfg(feature = "native-modules")]
+    pub fn get_module_value_mut<T: ModuleData>(
+        &mut self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&mut T>, anyhow::Error> {
+        let value_option = self.get_value_mut(db_index, key)?;
+        match value_option {
+            Some(Value::Module(value)) => value
+                .downcast_mut()
+                .map(Some)
+                .ok_or(anyhow!("WrongTypeError")),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
     pub fn lpush(
         &mut self,
         db_index: usize,
@@ -205,13 +847,7 @@ impl Database {
         let tt = Value::List(ValueList {
             data: VecDeque::new(),
         });
-        let value_list = self
-            .data
-            .get_mut(db_index)
-            .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))?
-            .entry(key.clone())
-            .or_insert_with(|| tt);
-        value_list.lpush(value)
+        self.update_or_insert_with(db_index, key, || tt, |value_list| value_list.lpush(value))
     }
     pub fn rpush(
         &mut self,
@@ -222,13 +858,7 @@ impl Database {
         let tt = Value::List(ValueList {
             data: VecDeque::new(),
         });
-        let value_list = self
-            .data
-            .get_mut(db_index)
-            .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))?
-            .entry(key.clone())
-            .or_insert_with(|| tt);
-        value_list.rpush(value)
+        self.update_or_insert_with(db_index, key, || tt, |value_list| value_list.rpush(value))
     }
     pub fn lpop(
         &mut self,
@@ -236,13 +866,10 @@ impl Database {
         key: Vec<u8>,
         count_option: Option<i64>,
     ) -> Result<Response, anyhow::Error> {
-        let value_option = self
-            .data
-            .get_mut(db_index)
-            .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))?
-            .get_mut(&key);
+        let value_option = self.get_value_mut(db_index, &key)?;
         if let Some(val) = value_option {
             let res = val.lpop(count_option)?;
+            self.modified(db_index, &key);
             Ok(res)
         } else {
             Ok(Response::Nil)
@@ -254,13 +881,10 @@ impl Database {
         key: Vec<u8>,
         count_option: Option<i64>,
     ) -> Result<Response, anyhow::Error> {
-        let value_option = self
-            .data
-            .get_mut(db_index)
-            .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))?
-            .get_mut(&key);
+        let value_option = self.get_value_mut(db_index, &key)?;
         if let Some(val) = value_option {
             let res = val.rpop(count_option)?;
+            self.modified(db_index, &key);
             Ok(res)
         } else {
             Ok(Response::Nil)
@@ -283,24 +907,239 @@ impl Database {
             None => Ok(Response::Array(vec![])),
         }
     }
+    pub fn remove(&mut self, db_index: usize, key: &[u8]) -> Result<Option<Value>, anyhow::Error> {
+        let value = self
+            .data
+            .get_mut(db_index)
+            .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))?
+            .remove(key);
+        if let Some(expire_map) = self.expire_map.get_mut(db_index) {
+            expire_map.remove(key);
+        }
+        if value.is_some() {
+            self.search.update(db_index, key, None);
+            self.tracker.modified(db_index, key);
+        }
+        Ok(value)
+    }
+    /// Deletes every key of the database.
+    pub fn flush(&mut self, db_index: usize) -> Result<(), anyhow::Error> {
+        self.clear(db_index)?;
+        self.tracker.flushed([db_index]);
+        Ok(())
+    }
+    /// Deletes every key of every database.
+    pub fn flush_all(&mut self) -> Result<(), anyhow::Error> {
+        for db_index in 0..self.data.len() {
+            self.clear(db_index)?;
+        }
+        self.tracker.flushed(0..self.data.len());
+        Ok(())
+    }
+    fn clear(&mut self, db_index: usize) -> Result<(), anyhow::Error> {
+        let keys = self
+            .data
+            .get_mut(db_index)
+            .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))?;
+        for key in std::mem::take(keys).into_keys() {
+            self.search.update(db_index, &key, None);
+        }
+        if let Some(expire_map) = self.expire_map.get_mut(db_index) {
+            expire_map.clear();
+        }
+        Ok(())
+    }
+    pub fn expire_at(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        time: i64,
+    ) -> Result<(), anyhow::Error> {
+        self.expire_map
+            .get_mut(db_index)
+            .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))?
+            .insert(key.clone(), time);
+        self.tracker.modified(db_index, &key);
+        Ok(())
+    }
     pub fn zadd(
         &mut self,
         db_index: usize,
         key: Vec<u8>,
         score: f64,
         member: Vec<u8>,
-    ) -> Result<bool, anyhow::Error> {
-        let value_sosrted_set = self
-            .data
-            .get_mut(db_index)
-            .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))?
-            .entry(key.clone())
-            .or_insert_with(|| {
-                Value::SortedSet(ValueSortedSet {
-                    data: BTreeSet::new(),
-                })
-            });
-        value_sosrted_set.zadd(member, score)
+        options: &ZaddOptions,
+    ) -> Result<ZaddOutcome, anyhow::Error> {
+        // XX never creates the key
+        if options.xx && self.get(db_index, key.clone())?.is_none() {
+            return Ok(ZaddOutcome::Skipped);
+        }
+        let res = self.update_or_insert_with(
+            db_index,
+            key.clone(),
+            || Value::SortedSet(ValueSortedSet::new()),
+            |value_sosrted_set| value_sosrted_set.zadd(member, score, options),
+        );
+        if let Ok(ZaddOutcome::Added(_) | ZaddOutcome::Updated(_)) = res {
+            let event = if options.incr { "zincr" } else { "zadd" };
+            self.notify(NOTIFY_ZSET, event, db_index, &key);
+        }
+        self.remove_if_empty_sorted_set(db_index, &key)?;
+        res
+    }
+    pub fn zrem(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        members: Vec<Vec<u8>>,
+    ) -> Result<usize, anyhow::Error> {
+        let Some(sorted_set) = self.get_sorted_set_mut(db_index, &key)? else {
+            return Ok(0);
+        };
+        let removed = members
+            .iter()
+            .filter(|member| sorted_set.remove(member).is_some())
+            .count();
+        if removed > 0 {
+            self.modified(db_index, &key);
+            self.notify(NOTIFY_ZSET, "zrem", db_index, &key);
+        }
+        self.remove_if_empty_sorted_set(db_index, &key)?;
+        Ok(removed)
+    }
+    pub fn zremrangebyscore(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        min: Bound<f64>,
+        max: Bound<f64>,
+    ) -> Result<usize, anyhow::Error> {
+        let Some(sorted_set) = self.get_sorted_set_mut(db_index, &key)? else {
+            return Ok(0);
+        };
+        let removed = sorted_set.remove_range_by_score(min, max);
+        if removed > 0 {
+            self.modified(db_index, &key);
+            self.notify(NOTIFY_ZSET, "zremrangebyscore", db_index, &key);
+        }
+        self.remove_if_empty_sorted_set(db_index, &key)?;
+        Ok(removed)
+    }
+    pub fn zremrangebylex(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        min: LexBound,
+        max: LexBound,
+    ) -> Result<usize, anyhow::Error> {
+        let Some(sorted_set) = self.get_sorted_set_mut(db_index, &key)? else {
+            return Ok(0);
+        };
+        let removed = sorted_set.remove_range_by_lex(&min, &max);
+        if removed > 0 {
+            self.modified(db_index, &key);
+            self.notify(NOTIFY_ZSET, "zremrangebylex", db_index, &key);
+        }
+        self.remove_if_empty_sorted_set(db_index, &key)?;
+        Ok(removed)
+    }
+    pub fn zpop(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        count: usize,
+        max: bool,
+    ) -> Result<Vec<SortedSetData>, anyhow::Error> {
+        let Some(sorted_set) = self.get_sorted_set_mut(db_index, &key)? else {
+            return Ok(vec![]);
+        };
+        let popped = sorted_set.pop(count, max);
+        if !popped.is_empty() {
+            self.modified(db_index, &key);
+            let event = if max { "zpopmax" } else { "zpopmin" };
+            self.notify(NOTIFY_ZSET, event, db_index, &key);
+        }
+        self.remove_if_empty_sorted_set(db_index, &key)?;
+        Ok(popped)
+    }
+    pub fn zrandmember(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        count: i64,
+    ) -> Result<Vec<SortedSetData>, anyhow::Error> {
+        match self.get_sorted_set(db_index, &key)? {
+            Some(sorted_set) => Ok(sorted_set.random_members(count)),
+            None => Ok(vec![]),
+        }
+    }
+    pub fn zcard(&mut self, db_index: usize, key: Vec<u8>) -> Result<usize, anyhow::Error> {
+        match self.get_sorted_set(db_index, &key)? {
+            Some(sorted_set) => Ok(sorted_set.len()),
+            None => Ok(0),
+        }
+    }
+    /// Reads the scores of a sorted set or a set, a missing key is an empty input.
+    pub fn zscore_map(
+        &self,
+        db_index: usize,
+        key: Vec<u8>,
+    ) -> Result<HashMap<Vec<u8>, f64>, anyhow::Error> {
+        match self.get(db_index, key)? {
+            Some(value) => value.to_score_map(),
+            None => Ok(HashMap::new()),
+        }
+    }
+    /// Replaces `key` with the sorted set, or deletes it when the sorted set is empty.
+    pub fn zstore(
+        &mut self,
+        db_index: usize,
+        key: Vec<u8>,
+        sorted_set: ValueSortedSet,
+    ) -> Result<usize, anyhow::Error> {
+        let removed = self.remove(db_index, &key)?.is_some();
+        let len = sorted_set.len();
+        if len > 0 {
+            self.insert(db_index, key, Value::SortedSet(sorted_set))?;
+        } else if removed {
+            self.notify(NOTIFY_GENERIC, "del", db_index, &key);
+        }
+        Ok(len)
+    }
+    pub fn get_sorted_set(
+        &self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&ValueSortedSet>, anyhow::Error> {
+        match self.get(db_index, key.to_vec())? {
+            Some(Value::SortedSet(value)) => Ok(Some(value)),
+            Some(_) => Err(anyhow!("WrongTypeError")),
+            None => Ok(None),
+        }
+    }
+    fn get_sorted_set_mut(
+        &mut self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<Option<&mut ValueSortedSet>, anyhow::Error> {
+        let value_option = self.get_value_mut(db_index, key)?;
+        match value_option {
+            Some(value) => Ok(Some(value.to_value_sorted_set_mut()?)),
+            None => Ok(None),
+        }
+    }
+    fn remove_if_empty_sorted_set(
+        &mut self,
+        db_index: usize,
+        key: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        if let Some(Value::SortedSet(sorted_set)) = self.get(db_index, key.to_vec())? {
+            if sorted_set.is_empty() {
+                self.remove(db_index, key)?;
+                self.notify(NOTIFY_GENERIC, "del", db_index, key);
+            }
+        }
+        Ok(())
     }
     pub fn sadd(
         &mut self,
@@ -308,17 +1147,16 @@ impl Database {
         key: Vec<u8>,
         value: Vec<u8>,
     ) -> Result<bool, anyhow::Error> {
-        let value_set = self
-            .data
-            .get_mut(db_index)
-            .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))?
-            .entry(key)
-            .or_insert_with(|| {
+        self.update_or_insert_with(
+            db_index,
+            key,
+            || {
                 Value::Set(ValueSet {
                     data: HashSet::new(),
                 })
-            });
-        value_set.sadd(value)
+            },
+            |value_set| value_set.sadd(value),
+        )
     }
     pub fn hset(
         &mut self,
@@ -328,24 +1166,17 @@ impl Database {
 
         value: Vec<u8>,
     ) -> Result<bool, anyhow::Error> {
-        let value_set = self
-            .data
-            .get_mut(db_index)
-            .ok_or(anyhow::anyhow!("can not find db index-{}", db_index))?
-            .entry(key.clone())
-            .or_insert_with(|| {
+        let added = self.update_or_insert_with(
+            db_index,
+            key.clone(),
+            || {
                 Value::Hash(ValueHash {
                     data: HashMap::new(),
                 })
-            });
-        value_set.hset(field, value)
-    }
-}
-
-async fn scan_expire(sender: mpsc::Sender<BackgroundEvent>) {
-    let mut tick_stream = interval(Duration::from_millis(1000));
-    loop {
-        let _ = sender.send(BackgroundEvent::Nil).await;
-        tick_stream.tick().await;
+            },
+            |value_set| value_set.hset(field, value),
+        )?;
+        self.reindex(db_index, &key);
+        Ok(added)
     }
 }
@@ -1,4 +1,13 @@
+pub mod blocking;
 pub mod common;
 pub mod fs_writer;
+pub mod function;
 pub mod info;
 pub mod lib;
+pub mod notify;
+pub mod persistence;
+pub mod pubsub;
+pub mod script;
+pub mod search;
+pub mod tracking;
+pub mod watch;
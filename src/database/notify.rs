@@ -0,0 +1,218 @@
+use super::pubsub::PubSub;
+use anyhow::anyhow;
+use std::sync::Arc;
+
+pub const NOTIFY_KEYSPACE: u32 = 1 << 0;
+pub const NOTIFY_KEYEVENT: u32 = 1 << 1;
+pub const NOTIFY_GENERIC: u32 = 1 << 2;
+pub const NOTIFY_STRING: u32 = 1 << 3;
+pub const NOTIFY_LIST: u32 = 1 << 4;
+pub const NOTIFY_SET: u32 = 1 << 5;
+pub const NOTIFY_HASH: u32 = 1 << 6;
+pub const NOTIFY_ZSET: u32 = 1 << 7;
+pub const NOTIFY_EXPIRED: u32 = 1 << 8;
+pub const NOTIFY_EVICTED: u32 = 1 << 9;
+pub const NOTIFY_STREAM: u32 = 1 << 10;
+pub const NOTIFY_NEW: u32 = 1 << 11;
+/// The events of the module types: JSON, time series, probabilistic and vector sets.
+pub const NOTIFY_MODULE: u32 = 1 << 12;
+/// The `A` alias, every class except new keys.
+pub const NOTIFY_ALL: u32 = NOTIFY_GENERIC
+    | NOTIFY_STRING
+    | NOTIFY_LIST
+    | NOTIFY_SET
+    | NOTIFY_HASH
+    | NOTIFY_ZSET
+    | NOTIFY_EXPIRED
+    | NOTIFY_EVICTED
+    | NOTIFY_STREAM
+    | NOTIFY_MODULE;
+
+const CLASS_FLAGS: [(char, u32); 11] = [
+    ('g', NOTIFY_GENERIC),
+    ('$', NOTIFY_STRING),
+    ('l', NOTIFY_LIST),
+    ('s', NOTIFY_SET),
+    ('h', NOTIFY_HASH),
+    ('z', NOTIFY_ZSET),
+    ('x', NOTIFY_EXPIRED),
+    ('e', NOTIFY_EVICTED),
+    ('t', NOTIFY_STREAM),
+    ('d', NOTIFY_MODULE),
+    ('n', NOTIFY_NEW),
+];
+
+/// Parses the `notify-keyspace-events` flags, e.g. `KEA` or `Kgx`.
+pub fn parse_notify_flags(text: &str) -> Result<u32, anyhow::Error> {
+    let mut flags = 0;
+    for c in text.chars() {
+        flags |= match c {
+            'A' => NOTIFY_ALL,
+            'K' => NOTIFY_KEYSPACE,
+            'E' => NOTIFY_KEYEVENT,
+            _ => CLASS_FLAGS
+                .iter()
+                .find(|(flag, _)| *flag == c)
+                .map(|(_, class)| *class)
+                .ok_or(anyhow!(
+                    "Invalid event class character. Use 'Ag$lshzxetdKEn'."
+                ))?,
+        };
+    }
+    Ok(flags)
+}
+pub fn notify_flags_to_string(flags: u32) -> String {
+    let mut text = String::new();
+    if flags & NOTIFY_ALL == NOTIFY_ALL {
+        text.push('A');
+    }
+    for (flag, class) in CLASS_FLAGS {
+        if flags & class & NOTIFY_ALL != 0 && flags & NOTIFY_ALL != NOTIFY_ALL {
+            text.push(flag);
+        }
+    }
+    if flags & NOTIFY_KEYSPACE != 0 {
+        text.push('K');
+    }
+    if flags & NOTIFY_KEYEVENT != 0 {
+        text.push('E');
+    }
+    if flags & NOTIFY_NEW != 0 {
+        text.push('n');
+    }
+    text
+}
+
+/// Publishes keyspace and keyevent notifications of the classes enabled by
+/// `notify-keyspace-events`. It is runtime state, nothing is written to the snapshot.
+#[derive(Clone, Default)]
+pub struct KeyspaceNotifier {
+    pub flags: u32,
+    pubsub: Option<Arc<PubSub>>,
+}
+impl std::fmt::Debug for KeyspaceNotifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyspaceNotifier")
+            .field("flags", &notify_flags_to_string(self.flags))
+            .finish()
+    }
+}
+impl PartialEq for KeyspaceNotifier {
+    fn eq(&self, other: &Self) -> bool {
+        self.flags == other.flags
+    }
+}
+impl KeyspaceNotifier {
+    pub fn attach(&mut self, pubsub: Arc<PubSub>) {
+        self.pubsub = Some(pubsub);
+    }
+    pub fn notify(&self, class: u32, event: &str, db_index: usize, key: &[u8]) {
+        let Some(pubsub) = &self.pubsub else {
+            return;
+        };
+        if self.flags & class == 0 {
+            return;
+        }
+        if self.flags & NOTIFY_KEYSPACE != 0 {
+            let channel = [format!("__keyspace@{}__:", db_index).as_bytes(), key].concat();
+            pubsub.publish(&channel, event.as_bytes());
+        }
+        if self.flags & NOTIFY_KEYEVENT != 0 {
+            let channel = format!("__keyevent@{}__:{}", db_index, event);
+            pubsub.publish(channel.as_bytes(), key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::pubsub::{Subscription, SubscriptionKind};
+    use crate::parser::response::Response;
+
+    fn message(channel: &str, payload: &str) -> Response {
+        Response::Array(vec![
+            Response::Data(b"message".to_vec()),
+            Response::Data(channel.as_bytes().to_vec()),
+            Response::Data(payload.as_bytes().to_vec()),
+        ])
+    }
+    fn received(subscription: &mut Subscription) -> Vec<Response> {
+        let mut messages = vec![];
+        while let Ok(message) = subscription.receiver.try_recv() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn every_class_has_a_flag() {
+        let classes = [
+            ('K', NOTIFY_KEYSPACE),
+            ('E', NOTIFY_KEYEVENT),
+            ('g', NOTIFY_GENERIC),
+            ('$', NOTIFY_STRING),
+            ('l', NOTIFY_LIST),
+            ('s', NOTIFY_SET),
+            ('h', NOTIFY_HASH),
+            ('z', NOTIFY_ZSET),
+            ('x', NOTIFY_EXPIRED),
+            ('e', NOTIFY_EVICTED),
+            ('t', NOTIFY_STREAM),
+            ('d', NOTIFY_MODULE),
+            ('n', NOTIFY_NEW),
+        ];
+        for (flag, class) in classes {
+            assert_eq!(parse_notify_flags(&flag.to_string()).unwrap(), class);
+        }
+        assert_eq!(parse_notify_flags("").unwrap(), 0);
+        assert!(parse_notify_flags("Kq").is_err());
+    }
+
+    #[test]
+    fn all_classes_but_new_keys() {
+        let flags = parse_notify_flags("KEA").unwrap();
+        assert_eq!(flags & NOTIFY_NEW, 0);
+        assert_ne!(flags & NOTIFY_MODULE, 0);
+        assert_eq!(notify_flags_to_string(flags), "AKE");
+        assert_eq!(notify_flags_to_string(flags | NOTIFY_NEW), "AKEn");
+        let flags = parse_notify_flags("Kgx").unwrap();
+        assert_eq!(flags, NOTIFY_KEYSPACE | NOTIFY_GENERIC | NOTIFY_EXPIRED);
+        assert_eq!(notify_flags_to_string(flags), "gxK");
+        assert_eq!(
+            parse_notify_flags(&notify_flags_to_string(flags)).unwrap(),
+            flags
+        );
+    }
+
+    #[test]
+    fn keyspace_and_keyevent_channels() {
+        let pubsub = Arc::new(PubSub::new());
+        let mut subscription = Subscription::new(pubsub.clone());
+        subscription.subscribe(SubscriptionKind::Channel, b"__keyspace@0__:k");
+        subscription.subscribe(SubscriptionKind::Channel, b"__keyevent@0__:set");
+        let mut notifier = KeyspaceNotifier::default();
+        notifier.attach(pubsub);
+
+        notifier.flags = parse_notify_flags("K$").unwrap();
+        notifier.notify(NOTIFY_STRING, "set", 0, b"k");
+        assert_eq!(
+            received(&mut subscription),
+            vec![message("__keyspace@0__:k", "set")]
+        );
+
+        notifier.flags = parse_notify_flags("E$").unwrap();
+        notifier.notify(NOTIFY_STRING, "set", 0, b"k");
+        assert_eq!(
+            received(&mut subscription),
+            vec![message("__keyevent@0__:set", "k")]
+        );
+
+        // A class which is not enabled, or another database, is not published
+        notifier.flags = parse_notify_flags("KEl").unwrap();
+        notifier.notify(NOTIFY_STRING, "set", 0, b"k");
+        notifier.flags = parse_notify_flags("KE$").unwrap();
+        notifier.notify(NOTIFY_STRING, "set", 1, b"k");
+        assert!(received(&mut subscription).is_empty());
+    }
+}
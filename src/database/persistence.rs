@@ -0,0 +1,214 @@
+use crate::util::common_utils::mstime;
+use anyhow::{anyhow, ensure};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::Mutex;
+
+/// The file the snapshot is saved to when `dbfilename` is not set.
+pub const DEFAULT_DBFILENAME: &str = "rcache.rdb";
+
+/// `dir` and `dbfilename`, the snapshot is loaded from `dir/dbfilename` at
+/// startup and saved there.
+pub struct RdbConfig {
+    dir: Mutex<PathBuf>,
+    dbfilename: Mutex<String>,
+}
+impl RdbConfig {
+    pub fn new(dir: impl AsRef<Path>, dbfilename: &str) -> Result<Self, anyhow::Error> {
+        Ok(RdbConfig {
+            dir: Mutex::new(parse_dir(dir)?),
+            dbfilename: Mutex::new(parse_dbfilename(dbfilename)?),
+        })
+    }
+    /// The directory and the file name of the snapshot at `rdb_path`.
+    pub fn from_rdb_path(rdb_path: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let rdb_path = rdb_path.as_ref();
+        let dir = match rdb_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let dbfilename = rdb_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or(anyhow!("Invalid rdb path {}", rdb_path.display()))?;
+        RdbConfig::new(dir, dbfilename)
+    }
+    pub fn dir(&self) -> PathBuf {
+        self.dir
+            .lock()
+            .map(|dir| dir.clone())
+            .unwrap_or_else(|e| e.into_inner().clone())
+    }
+    pub fn dbfilename(&self) -> String {
+        self.dbfilename
+            .lock()
+            .map(|dbfilename| dbfilename.clone())
+            .unwrap_or_else(|e| e.into_inner().clone())
+    }
+    pub fn path(&self) -> PathBuf {
+        self.dir().join(self.dbfilename())
+    }
+    pub fn set_dir(&self, dir: PathBuf) {
+        if let Ok(mut current) = self.dir.lock() {
+            *current = dir;
+        }
+    }
+    pub fn set_dbfilename(&self, dbfilename: String) {
+        if let Ok(mut current) = self.dbfilename.lock() {
+            *current = dbfilename;
+        }
+    }
+}
+/// The absolute path of an existing directory.
+pub fn parse_dir(dir: impl AsRef<Path>) -> Result<PathBuf, anyhow::Error> {
+    let dir = dir.as_ref().canonicalize().map_err(|e| anyhow!("{}", e))?;
+    ensure!(dir.is_dir(), "Not a directory");
+    Ok(dir)
+}
+pub fn parse_dbfilename(dbfilename: &str) -> Result<String, anyhow::Error> {
+    ensure!(
+        !dbfilename.is_empty() && Path::new(dbfilename).file_name() == Some(dbfilename.as_ref()),
+        "dbfilename can't be a path, just a filename"
+    );
+    Ok(dbfilename.to_string())
+}
+
+/// The state of the snapshots saved in the background, shown by INFO persistence.
+pub struct SaveStatus {
+    in_progress: AtomicBool,
+    last_ok: AtomicBool,
+    /// When the last snapshot was saved, or the server started, in unix seconds.
+    last_save_time: AtomicI64,
+    /// When the running save started, in milliseconds.
+    started: AtomicI64,
+    /// How long the last save took in seconds, -1 before the first one.
+    last_duration: AtomicI64,
+}
+impl Default for SaveStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl SaveStatus {
+    pub fn new() -> Self {
+        SaveStatus {
+            in_progress: AtomicBool::new(false),
+            last_ok: AtomicBool::new(true),
+            last_save_time: AtomicI64::new((mstime() / 1000) as i64),
+            started: AtomicI64::new(0),
+            last_duration: AtomicI64::new(-1),
+        }
+    }
+    /// Marks a save as running, false when one already is.
+    pub fn start(&self) -> bool {
+        if self.in_progress.swap(true, Ordering::Relaxed) {
+            return false;
+        }
+        self.started.store(mstime() as i64, Ordering::Relaxed);
+        true
+    }
+    pub fn finish(&self, saved: bool) {
+        let now = mstime() as i64;
+        let duration = (now - self.started.load(Ordering::Relaxed)) / 1000;
+        self.last_duration.store(duration, Ordering::Relaxed);
+        self.last_ok.store(saved, Ordering::Relaxed);
+        if saved {
+            self.last_save_time.store(now / 1000, Ordering::Relaxed);
+        }
+        self.in_progress.store(false, Ordering::Relaxed);
+    }
+    /// The fields of INFO persistence.
+    pub fn info(&self) -> Vec<(&'static str, String)> {
+        let in_progress = self.in_progress.load(Ordering::Relaxed);
+        let current_duration = match in_progress {
+            true => (mstime() as i64 - self.started.load(Ordering::Relaxed)) / 1000,
+            false => -1,
+        };
+        let status = match self.last_ok.load(Ordering::Relaxed) {
+            true => "ok",
+            false => "err",
+        };
+        vec![
+            ("rdb_bgsave_in_progress", (in_progress as u8).to_string()),
+            (
+                "rdb_last_save_time",
+                self.last_save_time.load(Ordering::Relaxed).to_string(),
+            ),
+            ("rdb_last_bgsave_status", status.to_string()),
+            (
+                "rdb_last_bgsave_time_sec",
+                self.last_duration.load(Ordering::Relaxed).to_string(),
+            ),
+            ("rdb_current_bgsave_time_sec", current_duration.to_string()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(status: &SaveStatus, name: &str) -> String {
+        status
+            .info()
+            .into_iter()
+            .find(|(field, _)| *field == name)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn the_dir_must_exist() {
+        let dir = parse_dir(".").unwrap();
+        assert!(dir.is_absolute());
+        assert_eq!(dir, std::env::current_dir().unwrap());
+        assert!(parse_dir("./no-such-directory").is_err());
+        // A file is not a directory
+        assert!(parse_dir(file!()).is_err());
+    }
+
+    #[test]
+    fn the_dbfilename_is_not_a_path() {
+        assert_eq!(parse_dbfilename("dump.rdb").unwrap(), "dump.rdb");
+        for dbfilename in ["", "dir/dump.rdb", "/dump.rdb", "..", "."] {
+            assert!(parse_dbfilename(dbfilename).is_err(), "{}", dbfilename);
+        }
+    }
+
+    #[test]
+    fn the_rdb_path_is_split_into_dir_and_dbfilename() {
+        let current_dir = std::env::current_dir().unwrap();
+        let rdb_config = RdbConfig::from_rdb_path("dump.rdb").unwrap();
+        assert_eq!(rdb_config.dir(), current_dir);
+        assert_eq!(rdb_config.dbfilename(), "dump.rdb");
+        assert_eq!(rdb_config.path(), current_dir.join("dump.rdb"));
+        let rdb_config = RdbConfig::from_rdb_path("src/dump.rdb").unwrap();
+        assert_eq!(rdb_config.dir(), current_dir.join("src"));
+        assert_eq!(rdb_config.dbfilename(), "dump.rdb");
+        assert!(RdbConfig::from_rdb_path("no-such-directory/dump.rdb").is_err());
+        assert!(RdbConfig::from_rdb_path("src/..").is_err());
+    }
+
+    #[test]
+    fn the_last_save_is_reported() {
+        let status = SaveStatus::new();
+        assert_eq!(field(&status, "rdb_last_bgsave_status"), "ok");
+        assert_eq!(field(&status, "rdb_last_bgsave_time_sec"), "-1");
+        assert_eq!(field(&status, "rdb_current_bgsave_time_sec"), "-1");
+
+        assert!(status.start());
+        assert!(!status.start());
+        assert_eq!(field(&status, "rdb_bgsave_in_progress"), "1");
+        assert_eq!(field(&status, "rdb_current_bgsave_time_sec"), "0");
+        status.finish(false);
+        assert_eq!(field(&status, "rdb_bgsave_in_progress"), "0");
+        assert_eq!(field(&status, "rdb_last_bgsave_status"), "err");
+        assert_eq!(field(&status, "rdb_last_bgsave_time_sec"), "0");
+
+        status.last_save_time.store(0, Ordering::Relaxed);
+        assert!(status.start());
+        status.finish(true);
+        assert_eq!(field(&status, "rdb_last_bgsave_status"), "ok");
+        assert_ne!(field(&status, "rdb_last_save_time"), "0");
+    }
+}
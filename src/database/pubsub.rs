@@ -0,0 +1,391 @@
+use crate::parser::response::Response;
+use crate::util::glob::glob_match;
+use crate::util::hash::key_hash_slot;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+type Subscribers = HashMap<Vec<u8>, HashMap<u64, UnboundedSender<Response>>>;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SubscriptionKind {
+    Channel,
+    Pattern,
+    /// A sharded channel, which belongs to the slot its name hashes to.
+    Shard,
+}
+
+#[derive(Default)]
+struct Registry {
+    channels: Subscribers,
+    patterns: Subscribers,
+    /// The sharded channels of every slot owned by this node.
+    shards: HashMap<u16, Subscribers>,
+}
+impl Registry {
+    fn subscribers(&mut self, kind: SubscriptionKind, name: &[u8]) -> &mut Subscribers {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => self.shards.entry(key_hash_slot(name)).or_default(),
+        }
+    }
+}
+/// The channels and patterns clients are subscribed to.
+///
+/// Every subscribed client owns the receiving end of a channel, messages are
+/// pushed by the publishing client's task and written by the subscriber's task.
+#[derive(Default)]
+pub struct PubSub {
+    registry: Mutex<Registry>,
+    next_id: AtomicU64,
+}
+impl PubSub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sends the message to the subscribers of the channel and of the patterns
+    /// matching it, returns how many clients received it.
+    pub fn publish(&self, channel: &[u8], message: &[u8]) -> usize {
+        let Ok(registry) = self.registry.lock() else {
+            return 0;
+        };
+        let mut receivers = 0;
+        if let Some(subscribers) = registry.channels.get(channel) {
+            for sender in subscribers.values() {
+                let sent = sender.send(Response::Array(vec![
+                    Response::Data(b"message".to_vec()),
+                    Response::Data(channel.to_vec()),
+                    Response::Data(message.to_vec()),
+                ]));
+                receivers += sent.is_ok() as usize;
+            }
+        }
+        for (pattern, subscribers) in &registry.patterns {
+            if !glob_match(pattern, channel) {
+                continue;
+            }
+            for sender in subscribers.values() {
+                let sent = sender.send(Response::Array(vec![
+                    Response::Data(b"pmessage".to_vec()),
+                    Response::Data(pattern.clone()),
+                    Response::Data(channel.to_vec()),
+                    Response::Data(message.to_vec()),
+                ]));
+                receivers += sent.is_ok() as usize;
+            }
+        }
+        receivers
+    }
+    /// Sends the message to the subscribers of the sharded channel, only the slot
+    /// of the channel is looked at and patterns never match sharded channels.
+    pub fn spublish(&self, channel: &[u8], message: &[u8]) -> usize {
+        let Ok(registry) = self.registry.lock() else {
+            return 0;
+        };
+        let Some(subscribers) = registry
+            .shards
+            .get(&key_hash_slot(channel))
+            .and_then(|channels| channels.get(channel))
+        else {
+            return 0;
+        };
+        subscribers
+            .values()
+            .filter(|sender| {
+                sender
+                    .send(Response::Array(vec![
+                        Response::Data(b"smessage".to_vec()),
+                        Response::Data(channel.to_vec()),
+                        Response::Data(message.to_vec()),
+                    ]))
+                    .is_ok()
+            })
+            .count()
+    }
+    /// Sends a message to one subscriber of the channel, whatever the type of the
+    /// payload. Returns false when the client is not subscribed to it.
+    pub fn send_to(&self, channel: &[u8], id: u64, payload: Response) -> bool {
+        let Ok(registry) = self.registry.lock() else {
+            return false;
+        };
+        let Some(sender) = registry
+            .channels
+            .get(channel)
+            .and_then(|subscribers| subscribers.get(&id))
+        else {
+            return false;
+        };
+        sender
+            .send(Response::Array(vec![
+                Response::Data(b"message".to_vec()),
+                Response::Data(channel.to_vec()),
+                payload,
+            ]))
+            .is_ok()
+    }
+    /// The channels with at least one subscriber, optionally matching a pattern.
+    pub fn channels(&self, kind: SubscriptionKind, pattern: Option<&[u8]>) -> Vec<Vec<u8>> {
+        let Ok(registry) = self.registry.lock() else {
+            return vec![];
+        };
+        let channels: Vec<&Vec<u8>> = match kind {
+            SubscriptionKind::Shard => registry
+                .shards
+                .values()
+                .flat_map(|channels| channels.keys())
+                .collect(),
+            _ => registry.channels.keys().collect(),
+        };
+        channels
+            .into_iter()
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect()
+    }
+    pub fn number_of_subscribers(&self, kind: SubscriptionKind, channel: &[u8]) -> usize {
+        self.registry
+            .lock()
+            .ok()
+            .and_then(|registry| {
+                let subscribers = match kind {
+                    SubscriptionKind::Channel => &registry.channels,
+                    SubscriptionKind::Pattern => &registry.patterns,
+                    SubscriptionKind::Shard => registry.shards.get(&key_hash_slot(channel))?,
+                };
+                subscribers.get(channel).map(|clients| clients.len())
+            })
+            .unwrap_or(0)
+    }
+    pub fn number_of_patterns(&self) -> usize {
+        self.registry
+            .lock()
+            .map(|registry| registry.patterns.len())
+            .unwrap_or(0)
+    }
+    fn add(
+        &self,
+        kind: SubscriptionKind,
+        name: &[u8],
+        id: u64,
+        sender: &UnboundedSender<Response>,
+    ) {
+        if let Ok(mut registry) = self.registry.lock() {
+            registry
+                .subscribers(kind, name)
+                .entry(name.to_vec())
+                .or_default()
+                .insert(id, sender.clone());
+        }
+    }
+    fn remove(&self, kind: SubscriptionKind, name: &[u8], id: u64) {
+        if let Ok(mut registry) = self.registry.lock() {
+            let subscribers = registry.subscribers(kind, name);
+            if let Some(clients) = subscribers.get_mut(name) {
+                clients.remove(&id);
+                if clients.is_empty() {
+                    subscribers.remove(name);
+                }
+            }
+            if kind == SubscriptionKind::Shard {
+                let slot = key_hash_slot(name);
+                if registry
+                    .shards
+                    .get(&slot)
+                    .is_some_and(|channels| channels.is_empty())
+                {
+                    registry.shards.remove(&slot);
+                }
+            }
+        }
+    }
+}
+
+/// The subscriptions of one connection, which is in subscriber mode while it has any.
+/// They are removed from the registry when the connection is dropped.
+pub struct Subscription {
+    pubsub: Arc<PubSub>,
+    pub id: u64,
+    pub channels: HashSet<Vec<u8>>,
+    pub patterns: HashSet<Vec<u8>>,
+    pub shard_channels: HashSet<Vec<u8>>,
+    sender: UnboundedSender<Response>,
+    pub receiver: UnboundedReceiver<Response>,
+}
+impl Subscription {
+    pub fn new(pubsub: Arc<PubSub>) -> Self {
+        let id = pubsub.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let (sender, receiver) = unbounded_channel();
+        Subscription {
+            pubsub,
+            id,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashSet::new(),
+            sender,
+            receiver,
+        }
+    }
+    /// The channel the messages of the connection are pushed to.
+    pub fn sender(&self) -> UnboundedSender<Response> {
+        self.sender.clone()
+    }
+    pub fn names(&self, kind: SubscriptionKind) -> &HashSet<Vec<u8>> {
+        match kind {
+            SubscriptionKind::Channel => &self.channels,
+            SubscriptionKind::Pattern => &self.patterns,
+            SubscriptionKind::Shard => &self.shard_channels,
+        }
+    }
+    fn names_mut(&mut self, kind: SubscriptionKind) -> &mut HashSet<Vec<u8>> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => &mut self.shard_channels,
+        }
+    }
+    pub fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len() + self.shard_channels.len()
+    }
+    pub fn count_unsharded(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+    pub fn is_active(&self) -> bool {
+        self.count() > 0
+    }
+    pub fn subscribe(&mut self, kind: SubscriptionKind, name: &[u8]) {
+        if self.names_mut(kind).insert(name.to_vec()) {
+            self.pubsub.add(kind, name, self.id, &self.sender);
+        }
+    }
+    pub fn unsubscribe(&mut self, kind: SubscriptionKind, name: &[u8]) {
+        if self.names_mut(kind).remove(name) {
+            self.pubsub.remove(kind, name, self.id);
+        }
+    }
+}
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        for kind in [
+            SubscriptionKind::Channel,
+            SubscriptionKind::Pattern,
+            SubscriptionKind::Shard,
+        ] {
+            for name in std::mem::take(self.names_mut(kind)) {
+                self.pubsub.remove(kind, &name, self.id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(kind: &str, parts: &[&str]) -> Response {
+        let mut items = vec![Response::Data(kind.as_bytes().to_vec())];
+        items.extend(
+            parts
+                .iter()
+                .map(|part| Response::Data(part.as_bytes().to_vec())),
+        );
+        Response::Array(items)
+    }
+
+    #[test]
+    fn channel_and_pattern_subscribers_receive_the_messages() {
+        let pubsub = Arc::new(PubSub::new());
+        let mut channel = Subscription::new(pubsub.clone());
+        let mut pattern = Subscription::new(pubsub.clone());
+        channel.subscribe(SubscriptionKind::Channel, b"news.tech");
+        pattern.subscribe(SubscriptionKind::Pattern, b"news.*");
+        pattern.subscribe(SubscriptionKind::Pattern, b"news.*");
+        assert_eq!(pattern.count(), 1);
+
+        assert_eq!(pubsub.publish(b"news.tech", b"hello"), 2);
+        assert_eq!(pubsub.publish(b"sport", b"goal"), 0);
+        let received = channel.receiver.try_recv().unwrap();
+        assert_eq!(received, message("message", &["news.tech", "hello"]));
+        let received = pattern.receiver.try_recv().unwrap();
+        assert_eq!(
+            received,
+            message("pmessage", &["news.*", "news.tech", "hello"])
+        );
+        assert!(channel.receiver.try_recv().is_err());
+        assert!(pattern.receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn subscriptions_are_counted_until_they_are_dropped() {
+        let pubsub = Arc::new(PubSub::new());
+        let mut first = Subscription::new(pubsub.clone());
+        let mut second = Subscription::new(pubsub.clone());
+        first.subscribe(SubscriptionKind::Channel, b"a");
+        first.subscribe(SubscriptionKind::Pattern, b"a*");
+        second.subscribe(SubscriptionKind::Channel, b"a");
+        second.subscribe(SubscriptionKind::Channel, b"b");
+        assert_ne!(first.id, second.id);
+        assert_eq!(
+            pubsub.number_of_subscribers(SubscriptionKind::Channel, b"a"),
+            2
+        );
+        assert_eq!(pubsub.number_of_patterns(), 1);
+        let mut channels = pubsub.channels(SubscriptionKind::Channel, None);
+        channels.sort();
+        assert_eq!(channels, vec![b"a".to_vec(), b"b".to_vec()]);
+        assert_eq!(
+            pubsub.channels(SubscriptionKind::Channel, Some(b"b*")),
+            vec![b"b".to_vec()]
+        );
+
+        second.unsubscribe(SubscriptionKind::Channel, b"b");
+        assert!(pubsub
+            .channels(SubscriptionKind::Channel, Some(b"b"))
+            .is_empty());
+        drop(first);
+        assert_eq!(
+            pubsub.number_of_subscribers(SubscriptionKind::Channel, b"a"),
+            1
+        );
+        assert_eq!(pubsub.number_of_patterns(), 0);
+        drop(second);
+        assert!(pubsub.channels(SubscriptionKind::Channel, None).is_empty());
+    }
+
+    #[test]
+    fn sharded_channels_are_apart_from_the_other_subscriptions() {
+        let pubsub = Arc::new(PubSub::new());
+        let mut sharded = Subscription::new(pubsub.clone());
+        let mut pattern = Subscription::new(pubsub.clone());
+        sharded.subscribe(SubscriptionKind::Shard, b"{user}.a");
+        sharded.subscribe(SubscriptionKind::Shard, b"{user}.b");
+        pattern.subscribe(SubscriptionKind::Pattern, b"*");
+        assert_eq!(sharded.count_unsharded(), 0);
+
+        // Patterns never match sharded channels, and PUBLISH never reaches them
+        assert_eq!(pubsub.spublish(b"{user}.a", b"hello"), 1);
+        assert_eq!(pubsub.spublish(b"{user}.c", b"hello"), 0);
+        assert_eq!(pubsub.publish(b"{user}.a", b"hello"), 1);
+        let received = sharded.receiver.try_recv().unwrap();
+        assert_eq!(received, message("smessage", &["{user}.a", "hello"]));
+        assert!(sharded.receiver.try_recv().is_err());
+        let received = pattern.receiver.try_recv().unwrap();
+        assert_eq!(received, message("pmessage", &["*", "{user}.a", "hello"]));
+
+        assert_eq!(
+            pubsub.number_of_subscribers(SubscriptionKind::Shard, b"{user}.a"),
+            1
+        );
+        assert_eq!(
+            pubsub.number_of_subscribers(SubscriptionKind::Channel, b"{user}.a"),
+            0
+        );
+        assert!(pubsub.channels(SubscriptionKind::Channel, None).is_empty());
+        sharded.unsubscribe(SubscriptionKind::Shard, b"{user}.a");
+        let channels = pubsub.channels(SubscriptionKind::Shard, None);
+        assert_eq!(channels, vec![b"{user}.b".to_vec()]);
+        drop(sharded);
+        assert!(pubsub.registry.lock().unwrap().shards.is_empty());
+    }
+}
@@ -0,0 +1,308 @@
+use crate::util::common_utils::mstime;
+use anyhow::ensure;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::time::timeout;
+
+const DEFAULT_TIME_LIMIT: u64 = 5000;
+/// How often a command waiting for a script checks whether it should reply BUSY.
+const BUSY_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The Lua scripts loaded with SCRIPT LOAD or EVAL, by their SHA1 digest, and
+/// the state of the script running now.
+///
+/// Scripts run one at a time while the database lock is held, SCRIPT KILL is
+/// served by another connection without the lock and only raises `killed`.
+///
+/// The other commands do not wait on the lock while a script holds it, that would
+/// block the worker thread which has to serve SCRIPT KILL. A script or a transaction
+/// claims the database first, and the commands enter only while it is not claimed.
+pub struct Scripts {
+    cache: Mutex<HashMap<String, String>>,
+    /// The database is claimed by a script or a transaction.
+    claimed: AtomicBool,
+    /// When the database was claimed, in unix milliseconds.
+    claimed_at: AtomicI64,
+    /// The commands which entered and may be holding the database lock.
+    entered: AtomicUsize,
+    /// Notified when the claim is released.
+    released: Notify,
+    running: AtomicBool,
+    /// The running script called a write command, it can not be killed anymore.
+    wrote: AtomicBool,
+    killed: AtomicBool,
+    /// `lua-time-limit`, in milliseconds, after which a script which did not write
+    /// is stopped. 0 disables it.
+    time_limit: AtomicU64,
+}
+impl Default for Scripts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Scripts {
+    pub fn new() -> Self {
+        Scripts {
+            cache: Mutex::new(HashMap::new()),
+            claimed: AtomicBool::new(false),
+            claimed_at: AtomicI64::new(0),
+            entered: AtomicUsize::new(0),
+            released: Notify::new(),
+            running: AtomicBool::new(false),
+            wrote: AtomicBool::new(false),
+            killed: AtomicBool::new(false),
+            time_limit: AtomicU64::new(DEFAULT_TIME_LIMIT),
+        }
+    }
+    /// Caches the script and returns its SHA1 digest.
+    pub fn load(&self, body: &str) -> String {
+        let sha = sha1_hex(body.as_bytes());
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.insert(sha.clone(), body.to_string());
+        }
+        sha
+    }
+    pub fn get(&self, sha: &str) -> Option<String> {
+        self.cache
+            .lock()
+            .ok()
+            .and_then(|cache| cache.get(&sha.to_lowercase()).cloned())
+    }
+    pub fn exists(&self, sha: &str) -> bool {
+        self.cache
+            .lock()
+            .is_ok_and(|cache| cache.contains_key(&sha.to_lowercase()))
+    }
+    pub fn flush(&self) {
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.clear();
+        }
+    }
+    pub fn time_limit(&self) -> u64 {
+        self.time_limit.load(Ordering::Relaxed)
+    }
+    pub fn set_time_limit(&self, time_limit: u64) {
+        self.time_limit.store(time_limit, Ordering::Relaxed);
+    }
+    /// Waits until the database is not claimed to let a command take the database
+    /// lock, until the returned guard is dropped.
+    pub async fn enter(&self) -> Result<Entered<'_>, anyhow::Error> {
+        self.wait_for(|| self.try_enter()).await
+    }
+    /// Enters without waiting, `None` while the database is claimed.
+    pub fn try_enter(&self) -> Option<Entered<'_>> {
+        self.entered.fetch_add(1, Ordering::SeqCst);
+        if self.claimed.load(Ordering::SeqCst) {
+            self.entered.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(Entered { scripts: self })
+    }
+    /// Waits until the database is not claimed to claim it for a script or a
+    /// transaction, until the returned guard is dropped.
+    pub async fn claim(&self) -> Result<Claimed<'_>, anyhow::Error> {
+        self.wait_for(|| {
+            self.claimed
+                .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+                .ok()?;
+            self.claimed_at.store(mstime() as i64, Ordering::Relaxed);
+            Some(Claimed { scripts: self })
+        })
+        .await
+    }
+    /// Retries `f` while the database is claimed. Replies BUSY once the claim
+    /// lasted longer than `lua-time-limit`.
+    async fn wait_for<T>(&self, f: impl Fn() -> Option<T>) -> Result<T, anyhow::Error> {
+        loop {
+            // Created first, so that a release after `f` failed is not missed
+            let released = self.released.notified();
+            if let Some(guard) = f() {
+                return Ok(guard);
+            }
+            let time_limit = self.time_limit() as i64;
+            let claimed_for = mstime() as i64 - self.claimed_at.load(Ordering::Relaxed);
+            ensure!(
+                time_limit == 0 || claimed_for < time_limit,
+                "BUSY Redis is busy running a script. You can only call SCRIPT KILL or SHUTDOWN NOSAVE."
+            );
+            let _ = timeout(BUSY_CHECK_INTERVAL, released).await;
+        }
+    }
+    /// Marks a script as running until the returned guard is dropped.
+    pub fn start(&self) -> RunningScript<'_> {
+        self.running.store(true, Ordering::Relaxed);
+        self.wrote.store(false, Ordering::Relaxed);
+        self.killed.store(false, Ordering::Relaxed);
+        RunningScript { scripts: self }
+    }
+    /// Checked periodically while a script runs, an error stops it. A script which
+    /// already wrote runs to its end, stopping it would leave its writes half done.
+    pub fn check(&self, started: Instant) -> Result<(), anyhow::Error> {
+        ensure!(
+            !self.killed.load(Ordering::Relaxed),
+            "Script killed by user with SCRIPT KILL..."
+        );
+        let time_limit = self.time_limit();
+        ensure!(
+            time_limit == 0
+                || self.wrote.load(Ordering::Relaxed)
+                || started.elapsed() < Duration::from_millis(time_limit),
+            "Script killed by timeout, it ran longer than lua-time-limit ({} ms)",
+            time_limit
+        );
+        Ok(())
+    }
+    /// SCRIPT KILL, a script which already modified the dataset is left running.
+    pub fn kill(&self) -> Result<(), anyhow::Error> {
+        ensure!(
+            self.running.load(Ordering::Relaxed),
+            "NOTBUSY No scripts in execution right now."
+        );
+        ensure!(
+            !self.wrote.load(Ordering::Relaxed),
+            "UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way using the SHUTDOWN NOSAVE command."
+        );
+        self.killed.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+}
+/// A command which may take the database lock.
+pub struct Entered<'a> {
+    scripts: &'a Scripts,
+}
+impl Drop for Entered<'_> {
+    fn drop(&mut self) {
+        self.scripts.entered.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+/// The database claimed by a script or a transaction.
+pub struct Claimed<'a> {
+    scripts: &'a Scripts,
+}
+impl Claimed<'_> {
+    /// Waits for the commands which entered before the claim to release the
+    /// database lock, they do not wait while holding it.
+    pub fn wait_for_entered(&self) {
+        while self.scripts.entered.load(Ordering::SeqCst) > 0 {
+            std::thread::yield_now();
+        }
+    }
+}
+impl Drop for Claimed<'_> {
+    fn drop(&mut self) {
+        self.scripts.claimed.store(false, Ordering::SeqCst);
+        self.scripts.released.notify_waiters();
+    }
+}
+pub struct RunningScript<'a> {
+    scripts: &'a Scripts,
+}
+impl RunningScript<'_> {
+    pub fn wrote(&self) {
+        self.scripts.wrote.store(true, Ordering::Relaxed);
+    }
+}
+impl Drop for RunningScript<'_> {
+    fn drop(&mut self) {
+        self.scripts.running.store(false, Ordering::Relaxed);
+    }
+}
+pub fn sha1_hex(data: &[u8]) -> String {
+    sha1_smol::Sha1::from(data).digest().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_caches_the_script_by_its_digest() {
+        let scripts = Scripts::new();
+        let sha = scripts.load("return 1");
+        assert_eq!(sha, "e0e1f9fabfc9d4800c877a703b823ac0578ff8db");
+        assert!(scripts.exists(&sha.to_uppercase()));
+        assert_eq!(scripts.get(&sha), Some("return 1".to_string()));
+        scripts.flush();
+        assert!(!scripts.exists(&sha));
+    }
+
+    #[tokio::test]
+    async fn commands_do_not_enter_a_claimed_database() {
+        let scripts = Scripts::new();
+        let entered = scripts.try_enter().unwrap();
+        assert_eq!(scripts.entered.load(Ordering::SeqCst), 1);
+        drop(entered);
+        assert_eq!(scripts.entered.load(Ordering::SeqCst), 0);
+
+        let claimed = scripts.claim().await.unwrap();
+        assert!(scripts.try_enter().is_none());
+        assert_eq!(scripts.entered.load(Ordering::SeqCst), 0);
+        drop(claimed);
+        assert!(scripts.try_enter().is_some());
+    }
+
+    #[tokio::test]
+    async fn a_waiting_command_enters_once_the_claim_is_released() {
+        let scripts = std::sync::Arc::new(Scripts::new());
+        let claimed = scripts.claim().await.unwrap();
+        let waiting = {
+            let scripts = scripts.clone();
+            tokio::spawn(async move { scripts.enter().await.map(|_| ()) })
+        };
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(!waiting.is_finished());
+        drop(claimed);
+        waiting.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn commands_reply_busy_after_the_time_limit() {
+        let scripts = Scripts::new();
+        scripts.set_time_limit(20);
+        let _claimed = scripts.claim().await.unwrap();
+        let error = scripts.enter().await.err().unwrap();
+        assert!(error.to_string().starts_with("BUSY "));
+        assert!(scripts.claim().await.is_err());
+    }
+
+    #[test]
+    fn the_timeout_stops_only_scripts_which_did_not_write() {
+        let scripts = Scripts::new();
+        scripts.set_time_limit(10);
+        let started = Instant::now() - Duration::from_millis(20);
+
+        let running = scripts.start();
+        let error = scripts.check(started).unwrap_err();
+        assert!(error.to_string().contains("lua-time-limit (10 ms)"));
+        assert!(scripts.check(Instant::now()).is_ok());
+        running.wrote();
+        assert!(scripts.check(started).is_ok());
+        drop(running);
+
+        let _running = scripts.start();
+        scripts.set_time_limit(0);
+        assert!(scripts.check(started).is_ok());
+    }
+
+    #[test]
+    fn kill() {
+        let scripts = Scripts::new();
+        let error = scripts.kill().unwrap_err();
+        assert!(error.to_string().starts_with("NOTBUSY "));
+
+        let running = scripts.start();
+        scripts.kill().unwrap();
+        let error = scripts.check(Instant::now()).unwrap_err();
+        assert!(error.to_string().contains("SCRIPT KILL"));
+        drop(running);
+
+        let running = scripts.start();
+        assert!(scripts.check(Instant::now()).is_ok());
+        running.wrote();
+        let error = scripts.kill().unwrap_err();
+        assert!(error.to_string().starts_with("UNKILLABLE "));
+    }
+}
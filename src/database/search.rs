@@ -0,0 +1,742 @@
+use crate::util::common_utils::f64_to_bytes;
+use crate::util::json_path::JsonPath;
+use crate::util::search_query::{tokenize, QueryNode};
+use crate::vojo::value::Value;
+use anyhow::{anyhow, ensure};
+use bincode::enc::Encoder;
+use bincode::error::EncodeError;
+use bincode::{Decode, Encode};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// Words which are neither indexed nor searched.
+const STOPWORDS: [&str; 33] = [
+    "a", "is", "the", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into",
+    "it", "no", "not", "of", "on", "or", "such", "that", "their", "then", "there", "these", "they",
+    "this", "to", "was", "will", "with",
+];
+/// How many indexed terms a `prefix*` query expands to at most.
+const MAX_PREFIX_EXPANSIONS: usize = 200;
+
+fn is_stopword(term: &str) -> bool {
+    STOPWORDS.contains(&term)
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Encode, Decode)]
+pub enum IndexSource {
+    Hash,
+    Json,
+}
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub enum FieldType {
+    Text {
+        weight: f64,
+    },
+    Tag {
+        separator: char,
+        case_sensitive: bool,
+    },
+    Numeric,
+}
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct SchemaField {
+    /// The hash field, or the JSONPath of the value in a JSON document.
+    pub path: String,
+    /// The name used by queries, the path unless the schema gave an alias.
+    pub name: String,
+    pub field_type: FieldType,
+    pub sortable: bool,
+}
+/// What FT.CREATE was called with, the only part of an index which is persisted.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub source: IndexSource,
+    pub prefixes: Vec<Vec<u8>>,
+    pub db_index: usize,
+    pub fields: Vec<SchemaField>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum FieldValue {
+    Text(String),
+    Tags(Vec<String>),
+    Numeric(f64),
+}
+impl FieldValue {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            FieldValue::Text(text) => text.as_bytes().to_vec(),
+            FieldValue::Tags(tags) => tags.join(",").into_bytes(),
+            FieldValue::Numeric(number) => f64_to_bytes(*number),
+        }
+    }
+}
+/// The schema fields of an indexed key, in the order of the schema.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Document {
+    pub values: Vec<Option<FieldValue>>,
+}
+
+/// The keys matched by a query, with their scores.
+pub type Matches = HashMap<Vec<u8>, f64>;
+
+#[derive(Debug, Clone)]
+pub struct SearchIndex {
+    pub definition: IndexDefinition,
+    pub documents: HashMap<Vec<u8>, Document>,
+    json_paths: Vec<Option<JsonPath>>,
+    /// (field position, term) -> key -> term frequency
+    terms: BTreeMap<(usize, String), HashMap<Vec<u8>, u32>>,
+    /// (field position, tag) -> keys
+    tags: HashMap<(usize, String), HashSet<Vec<u8>>>,
+}
+impl SearchIndex {
+    pub fn new(definition: IndexDefinition) -> Result<Self, anyhow::Error> {
+        let json_paths = definition
+            .fields
+            .iter()
+            .map(|field| match definition.source {
+                IndexSource::Hash => Ok(None),
+                IndexSource::Json => JsonPath::parse(&field.path).map(Some),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SearchIndex {
+            definition,
+            documents: HashMap::new(),
+            json_paths,
+            terms: BTreeMap::new(),
+            tags: HashMap::new(),
+        })
+    }
+    pub fn covers(&self, db_index: usize, key: &[u8]) -> bool {
+        db_index == self.definition.db_index
+            && (self.definition.prefixes.is_empty()
+                || self
+                    .definition
+                    .prefixes
+                    .iter()
+                    .any(|prefix| key.starts_with(prefix)))
+    }
+    pub fn field_position(&self, name: &str) -> Option<usize> {
+        self.definition
+            .fields
+            .iter()
+            .position(|field| field.name == name)
+    }
+    /// Indexes the new value of a key, a missing value or one of another type drops it.
+    pub fn update(&mut self, key: &[u8], value: Option<&Value>) {
+        self.remove_document(key);
+        if let Some(document) = value.and_then(|value| self.extract(value)) {
+            self.add_document(key, document);
+        }
+    }
+    fn extract(&self, value: &Value) -> Option<Document> {
+        let raw_values: Vec<Vec<String>> = match (self.definition.source, value) {
+            (IndexSource::Hash, Value::Hash(hash)) => self
+                .definition
+                .fields
+                .iter()
+                .map(|field| {
+                    hash.data
+                        .get(field.path.as_bytes())
+                        .map(|value| String::from_utf8_lossy(value).to_string())
+                        .into_iter()
+                        .collect()
+                })
+                .collect(),
+            (IndexSource::Json, Value::Json(json)) => self
+                .json_paths
+                .iter()
+                .map(|path| {
+                    let mut raw = vec![];
+                    if let Some(path) = path {
+                        for value in json.get(path) {
+                            flatten_json(value, &mut raw);
+                        }
+                    }
+                    raw
+                })
+                .collect(),
+            _ => return None,
+        };
+        let values = self
+            .definition
+            .fields
+            .iter()
+            .zip(raw_values)
+            .map(|(field, raw)| {
+                if raw.is_empty() {
+                    return None;
+                }
+                match &field.field_type {
+                    FieldType::Text { .. } => Some(FieldValue::Text(raw.join(" "))),
+                    FieldType::Tag {
+                        separator,
+                        case_sensitive,
+                    } => Some(FieldValue::Tags(
+                        raw.iter()
+                            .flat_map(|text| text.split(*separator))
+                            .map(|tag| normalize_tag(tag, *case_sensitive))
+                            .filter(|tag| !tag.is_empty())
+                            .collect(),
+                    )),
+                    FieldType::Numeric => raw[0].trim().parse().ok().map(FieldValue::Numeric),
+                }
+            })
+            .collect();
+        Some(Document { values })
+    }
+    fn add_document(&mut self, key: &[u8], document: Document) {
+        for (position, value) in document.values.iter().enumerate() {
+            match value {
+                Some(FieldValue::Text(text)) => {
+                    for term in tokenize(text) {
+                        if !is_stopword(&term) {
+                            *self
+                                .terms
+                                .entry((position, term))
+                                .or_default()
+                                .entry(key.to_vec())
+                                .or_insert(0) += 1;
+                        }
+                    }
+                }
+                Some(FieldValue::Tags(tags)) => {
+                    for tag in tags {
+                        self.tags
+                            .entry((position, tag.clone()))
+                            .or_default()
+                            .insert(key.to_vec());
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.documents.insert(key.to_vec(), document);
+    }
+    fn remove_document(&mut self, key: &[u8]) {
+        let Some(document) = self.documents.remove(key) else {
+            return;
+        };
+        for (position, value) in document.values.into_iter().enumerate() {
+            match value {
+                Some(FieldValue::Text(text)) => {
+                    for term in tokenize(&text) {
+                        let entry = (position, term);
+                        if let Some(postings) = self.terms.get_mut(&entry) {
+                            postings.remove(key);
+                            if postings.is_empty() {
+                                self.terms.remove(&entry);
+                            }
+                        }
+                    }
+                }
+                Some(FieldValue::Tags(tags)) => {
+                    for tag in tags {
+                        let entry = (position, tag);
+                        if let Some(keys) = self.tags.get_mut(&entry) {
+                            keys.remove(key);
+                            if keys.is_empty() {
+                                self.tags.remove(&entry);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn execute(&self, node: &QueryNode) -> Result<Matches, anyhow::Error> {
+        match node {
+            QueryNode::All => Ok(self.all()),
+            QueryNode::Term {
+                field,
+                term,
+                prefix,
+            } => self.term(field, term, *prefix),
+            QueryNode::Phrase { field, terms } => self.phrase(field, terms),
+            QueryNode::Numeric {
+                field,
+                min,
+                min_exclusive,
+                max,
+                max_exclusive,
+            } => {
+                let position =
+                    self.typed_field(field, |field_type| matches!(field_type, FieldType::Numeric))?;
+                Ok(self
+                    .documents
+                    .iter()
+                    .filter(|(_, document)| match document.values[position] {
+                        Some(FieldValue::Numeric(value)) => {
+                            (if *min_exclusive {
+                                value > *min
+                            } else {
+                                value >= *min
+                            }) && (if *max_exclusive {
+                                value < *max
+                            } else {
+                                value <= *max
+                            })
+                        }
+                        _ => false,
+                    })
+                    .map(|(key, _)| (key.clone(), 1.0))
+                    .collect())
+            }
+            QueryNode::Tag { field, tags } => {
+                let position = self.typed_field(field, |field_type| {
+                    matches!(field_type, FieldType::Tag { .. })
+                })?;
+                let case_sensitive = matches!(
+                    self.definition.fields[position].field_type,
+                    FieldType::Tag {
+                        case_sensitive: true,
+                        ..
+                    }
+                );
+                let mut matches = Matches::new();
+                for tag in tags {
+                    if let Some(keys) = self
+                        .tags
+                        .get(&(position, normalize_tag(tag, case_sensitive)))
+                    {
+                        matches.extend(keys.iter().map(|key| (key.clone(), 1.0)));
+                    }
+                }
+                Ok(matches)
+            }
+            QueryNode::And(nodes) => {
+                let mut result: Option<Matches> = None;
+                let mut excluded = vec![];
+                for node in nodes {
+                    match node {
+                        // Stopwords do not restrict an intersection
+                        QueryNode::Term {
+                            term,
+                            prefix: false,
+                            ..
+                        } if is_stopword(term) => {}
+                        QueryNode::Not(inner) => excluded.push(self.execute(inner)?),
+                        _ => {
+                            let matches = self.execute(node)?;
+                            result = Some(match result {
+                                None => matches,
+                                Some(result) => result
+                                    .into_iter()
+                                    .filter_map(|(key, score)| {
+                                        matches.get(&key).map(|other| (key, score + other))
+                                    })
+                                    .collect(),
+                            });
+                        }
+                    }
+                }
+                let mut result = result.unwrap_or_else(|| self.all());
+                for matches in excluded {
+                    result.retain(|key, _| !matches.contains_key(key));
+                }
+                Ok(result)
+            }
+            QueryNode::Or(nodes) => {
+                let mut result = Matches::new();
+                for node in nodes {
+                    for (key, score) in self.execute(node)? {
+                        *result.entry(key).or_insert(0.0) += score;
+                    }
+                }
+                Ok(result)
+            }
+            QueryNode::Not(inner) => {
+                let excluded = self.execute(inner)?;
+                let mut result = self.all();
+                result.retain(|key, _| !excluded.contains_key(key));
+                Ok(result)
+            }
+        }
+    }
+    fn all(&self) -> Matches {
+        self.documents
+            .keys()
+            .map(|key| (key.clone(), 1.0))
+            .collect()
+    }
+    fn typed_field(
+        &self,
+        name: &str,
+        expected: impl Fn(&FieldType) -> bool,
+    ) -> Result<usize, anyhow::Error> {
+        let position = self
+            .field_position(name)
+            .ok_or(anyhow!("Unknown field `{}`", name))?;
+        ensure!(
+            expected(&self.definition.fields[position].field_type),
+            "Field `{}` does not support this kind of query",
+            name
+        );
+        Ok(position)
+    }
+    /// The positions and weights of the TEXT fields a term is looked up in.
+    fn text_fields(&self, field: &Option<String>) -> Result<Vec<(usize, f64)>, anyhow::Error> {
+        let weight = |position: usize| match self.definition.fields[position].field_type {
+            FieldType::Text { weight } => Some((position, weight)),
+            _ => None,
+        };
+        match field {
+            Some(name) => {
+                let position = self.typed_field(name, |field_type| {
+                    matches!(field_type, FieldType::Text { .. })
+                })?;
+                Ok(weight(position).into_iter().collect())
+            }
+            None => Ok((0..self.definition.fields.len())
+                .filter_map(weight)
+                .collect()),
+        }
+    }
+    /// TF-IDF of the term, weighted by the field it was found in.
+    fn term(
+        &self,
+        field: &Option<String>,
+        term: &str,
+        prefix: bool,
+    ) -> Result<Matches, anyhow::Error> {
+        let mut matches = Matches::new();
+        let documents = self.documents.len() as f64;
+        for (position, weight) in self.text_fields(field)? {
+            let postings: Vec<&HashMap<Vec<u8>, u32>> = if prefix {
+                self.terms
+                    .range((position, term.to_string())..)
+                    .take_while(|((other, indexed), _)| {
+                        *other == position && indexed.starts_with(term)
+                    })
+                    .take(MAX_PREFIX_EXPANSIONS)
+                    .map(|(_, postings)| postings)
+                    .collect()
+            } else {
+                self.terms
+                    .get(&(position, term.to_string()))
+                    .into_iter()
+                    .collect()
+            };
+            for postings in postings {
+                let idf = (1.0 + documents / postings.len() as f64).ln();
+                for (key, frequency) in postings {
+                    *matches.entry(key.clone()).or_insert(0.0) += *frequency as f64 * idf * weight;
+                }
+            }
+        }
+        Ok(matches)
+    }
+    /// Keys with all the terms, next to each other and in order in one of the fields.
+    fn phrase(&self, field: &Option<String>, terms: &[String]) -> Result<Matches, anyhow::Error> {
+        let terms: Vec<&String> = terms.iter().filter(|term| !is_stopword(term)).collect();
+        let mut result: Option<Matches> = None;
+        for term in &terms {
+            let matches = self.term(field, term, false)?;
+            result = Some(match result {
+                None => matches,
+                Some(result) => result
+                    .into_iter()
+                    .filter_map(|(key, score)| matches.get(&key).map(|other| (key, score + other)))
+                    .collect(),
+            });
+        }
+        let fields = self.text_fields(field)?;
+        let mut result = result.unwrap_or_default();
+        result.retain(|key, _| {
+            fields.iter().any(|(position, _)| {
+                let Some(Some(FieldValue::Text(text))) = self
+                    .documents
+                    .get(key)
+                    .map(|document| &document.values[*position])
+                else {
+                    return false;
+                };
+                let words: Vec<String> = tokenize(text)
+                    .into_iter()
+                    .filter(|word| !is_stopword(word))
+                    .collect();
+                words.windows(terms.len()).any(|window| {
+                    window
+                        .iter()
+                        .zip(terms.iter())
+                        .all(|(word, term)| word == *term)
+                })
+            })
+        });
+        Ok(result)
+    }
+}
+fn normalize_tag(tag: &str, case_sensitive: bool) -> String {
+    let tag = tag.trim();
+    if case_sensitive {
+        tag.to_string()
+    } else {
+        tag.to_lowercase()
+    }
+}
+/// The scalars of a JSON value as text, arrays are indexed as all of their elements.
+fn flatten_json(value: &serde_json::Value, raw: &mut Vec<String>) {
+    match value {
+        serde_json::Value::String(text) => raw.push(text.clone()),
+        serde_json::Value::Number(number) => raw.push(number.to_string()),
+        serde_json::Value::Bool(flag) => raw.push(flag.to_string()),
+        serde_json::Value::Array(values) => {
+            for value in values {
+                flatten_json(value, raw);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The indexes of all databases, only their definitions are written to the
+/// snapshot and the documents are indexed again when it is loaded.
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndexes {
+    pub indexes: BTreeMap<String, SearchIndex>,
+}
+impl PartialEq for SearchIndexes {
+    fn eq(&self, other: &Self) -> bool {
+        self.indexes.len() == other.indexes.len()
+            && self
+                .indexes
+                .values()
+                .zip(other.indexes.values())
+                .all(|(index, other)| index.definition == other.definition)
+    }
+}
+impl Encode for SearchIndexes {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        let definitions: Vec<&IndexDefinition> = self
+            .indexes
+            .values()
+            .map(|index| &index.definition)
+            .collect();
+        definitions.encode(encoder)
+    }
+}
+impl SearchIndexes {
+    /// Creates an index and indexes the keys of its database it covers.
+    pub fn create(
+        &mut self,
+        definition: IndexDefinition,
+        data: &[HashMap<Vec<u8>, Value>],
+    ) -> Result<(), anyhow::Error> {
+        ensure!(
+            !self.indexes.contains_key(&definition.name),
+            "Index already exists"
+        );
+        let mut index = SearchIndex::new(definition)?;
+        if let Some(keys) = data.get(index.definition.db_index) {
+            for (key, value) in keys {
+                if index.covers(index.definition.db_index, key) {
+                    index.update(key, Some(value));
+                }
+            }
+        }
+        self.indexes.insert(index.definition.name.clone(), index);
+        Ok(())
+    }
+    pub fn get(&self, name: &str) -> Result<&SearchIndex, anyhow::Error> {
+        self.indexes.get(name).ok_or(anyhow!("Unknown index name"))
+    }
+    pub fn drop_index(&mut self, name: &str) -> Result<SearchIndex, anyhow::Error> {
+        self.indexes
+            .remove(name)
+            .ok_or(anyhow!("Unknown index name"))
+    }
+    pub fn update(&mut self, db_index: usize, key: &[u8], value: Option<&Value>) {
+        for index in self.indexes.values_mut() {
+            if index.covers(db_index, key) {
+                index.update(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::search_query::parse_query;
+    use crate::vojo::json::ValueJson;
+    use crate::vojo::value::ValueHash;
+    use serde_json::json;
+
+    fn field(name: &str, field_type: FieldType) -> SchemaField {
+        SchemaField {
+            path: name.to_owned(),
+            name: name.to_owned(),
+            field_type,
+            sortable: false,
+        }
+    }
+    fn hash(fields: &[(&str, &str)]) -> Value {
+        Value::Hash(ValueHash {
+            data: fields
+                .iter()
+                .map(|(field, value)| (field.as_bytes().to_vec(), value.as_bytes().to_vec()))
+                .collect(),
+        })
+    }
+    fn hash_index() -> SearchIndex {
+        let mut index = SearchIndex::new(IndexDefinition {
+            name: "idx".to_owned(),
+            source: IndexSource::Hash,
+            prefixes: vec![b"doc:".to_vec()],
+            db_index: 0,
+            fields: vec![
+                field("title", FieldType::Text { weight: 5.0 }),
+                field("body", FieldType::Text { weight: 1.0 }),
+                field(
+                    "tags",
+                    FieldType::Tag {
+                        separator: ',',
+                        case_sensitive: false,
+                    },
+                ),
+                field("price", FieldType::Numeric),
+            ],
+        })
+        .unwrap();
+        let documents = [
+            (
+                "doc:1",
+                "Red shoes",
+                "the best running shoes",
+                "Sale,New",
+                "50",
+            ),
+            ("doc:2", "Blue hat", "a warm hat for running", "new", "20"),
+            ("doc:3", "Shoe rack", "holds red shoes", "", "x"),
+        ];
+        for (key, title, body, tags, price) in documents {
+            let value = hash(&[
+                ("title", title),
+                ("body", body),
+                ("tags", tags),
+                ("price", price),
+            ]);
+            index.update(key.as_bytes(), Some(&value));
+        }
+        index
+    }
+    fn search(index: &SearchIndex, query: &str) -> Vec<String> {
+        let mut keys: Vec<String> = index
+            .execute(&parse_query(query).unwrap())
+            .unwrap()
+            .into_keys()
+            .map(|key| String::from_utf8(key).unwrap())
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn text_queries() {
+        let index = hash_index();
+        assert_eq!(search(&index, "shoes"), vec!["doc:1", "doc:3"]);
+        assert_eq!(search(&index, "running shoes"), vec!["doc:1"]);
+        assert_eq!(search(&index, "hat | rack"), vec!["doc:2", "doc:3"]);
+        assert_eq!(search(&index, "running -hat"), vec!["doc:1"]);
+        assert_eq!(search(&index, "sho*"), vec!["doc:1", "doc:3"]);
+        assert_eq!(search(&index, "@title:shoes"), vec!["doc:1"]);
+        assert_eq!(search(&index, "the shoes"), vec!["doc:1", "doc:3"]);
+        assert_eq!(search(&index, "\"red shoes\""), vec!["doc:1", "doc:3"]);
+        assert_eq!(search(&index, "\"shoes red\""), Vec::<String>::new());
+        assert_eq!(search(&index, "*").len(), 3);
+    }
+
+    #[test]
+    fn title_matches_score_higher() {
+        let index = hash_index();
+        let matches = index.execute(&parse_query("red").unwrap()).unwrap();
+        assert!(matches[b"doc:1".as_slice()] > matches[b"doc:3".as_slice()]);
+    }
+
+    #[test]
+    fn numeric_and_tag_queries() {
+        let index = hash_index();
+        assert_eq!(search(&index, "@price:[20 50]"), vec!["doc:1", "doc:2"]);
+        assert_eq!(search(&index, "@price:[(20 +inf]"), vec!["doc:1"]);
+        assert_eq!(search(&index, "@tags:{NEW}"), vec!["doc:1", "doc:2"]);
+        assert_eq!(search(&index, "@tags:{sale | missing}"), vec!["doc:1"]);
+        let wrong_type = parse_query("@title:[0 1]").unwrap();
+        assert!(index.execute(&wrong_type).is_err());
+        let unknown = parse_query("@color:{red}").unwrap();
+        assert!(index.execute(&unknown).is_err());
+    }
+
+    #[test]
+    fn updates_replace_the_indexed_values() {
+        let mut index = hash_index();
+        index.update(b"doc:1", Some(&hash(&[("title", "Green boots")])));
+        assert_eq!(search(&index, "shoes"), vec!["doc:3"]);
+        assert_eq!(search(&index, "boots"), vec!["doc:1"]);
+        assert_eq!(search(&index, "@tags:{sale}"), Vec::<String>::new());
+        index.update(b"doc:1", None);
+        assert_eq!(search(&index, "boots"), Vec::<String>::new());
+        assert!(!index.terms.keys().any(|(_, term)| term == "boots"));
+        assert!(index.covers(0, b"doc:9"));
+        assert!(!index.covers(0, b"other:1"));
+        assert!(!index.covers(1, b"doc:9"));
+    }
+
+    #[test]
+    fn json_documents() {
+        let mut index = SearchIndex::new(IndexDefinition {
+            name: "json".to_owned(),
+            source: IndexSource::Json,
+            prefixes: vec![],
+            db_index: 0,
+            fields: vec![
+                SchemaField {
+                    path: "$.name".to_owned(),
+                    name: "name".to_owned(),
+                    field_type: FieldType::Text { weight: 1.0 },
+                    sortable: false,
+                },
+                SchemaField {
+                    path: "$.colors[*]".to_owned(),
+                    name: "colors".to_owned(),
+                    field_type: FieldType::Tag {
+                        separator: ',',
+                        case_sensitive: true,
+                    },
+                    sortable: false,
+                },
+            ],
+        })
+        .unwrap();
+        let value = Value::Json(ValueJson::new(
+            json!({"name": "Chair", "colors": ["Red", "Blue"]}),
+        ));
+        index.update(b"chair", Some(&value));
+        // A hash is not indexed by a JSON index
+        index.update(b"hash", Some(&hash(&[("name", "chair")])));
+        assert_eq!(search(&index, "chair"), vec!["chair"]);
+        assert_eq!(search(&index, "@colors:{Blue}"), vec!["chair"]);
+        assert_eq!(search(&index, "@colors:{blue}"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn only_the_definitions_are_persisted() {
+        let mut indexes = SearchIndexes::default();
+        let data = vec![HashMap::from([(
+            b"doc:1".to_vec(),
+            hash(&[("title", "hello")]),
+        )])];
+        let definition = hash_index().definition;
+        indexes.create(definition.clone(), &data).unwrap();
+        assert!(indexes.create(definition.clone(), &data).is_err());
+        assert_eq!(indexes.get("idx").unwrap().documents.len(), 1);
+        let bytes = bincode::encode_to_vec(&indexes, bincode::config::standard()).unwrap();
+        let (definitions, _): (Vec<IndexDefinition>, usize) =
+            bincode::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+        assert_eq!(definitions, vec![definition]);
+        assert!(indexes.drop_index("idx").is_ok());
+        assert!(indexes.get("idx").is_err());
+    }
+}
@@ -0,0 +1,540 @@
+use super::pubsub::PubSub;
+use super::watch::WatchedKeys;
+use crate::parser::response::Response;
+use anyhow::{anyhow, ensure};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// The channel RESP2 clients subscribe to when they are the target of a REDIRECT.
+pub const INVALIDATE_CHANNEL: &[u8] = b"__redis__:invalidate";
+const DEFAULT_MAX_KEYS: usize = 1_000_000;
+
+tokio::task_local! {
+    /// The connection whose command is running, NOLOOP clients are not told
+    /// about their own changes.
+    pub static CURRENT_CLIENT: u64;
+}
+
+/// The options given to CLIENT TRACKING ON.
+#[derive(Clone, Debug, Default)]
+pub struct TrackingOptions {
+    pub redirect: Option<u64>,
+    pub bcast: bool,
+    pub prefixes: Vec<Vec<u8>>,
+    pub optin: bool,
+    pub optout: bool,
+    pub noloop: bool,
+}
+
+struct Client {
+    sender: UnboundedSender<Response>,
+    protocol: u8,
+    tracking: Option<TrackingOptions>,
+    /// Set by CLIENT CACHING for the next command only.
+    caching: Option<bool>,
+}
+struct Table {
+    clients: HashMap<u64, Client>,
+    /// The clients which may have cached each key, in the default tracking mode.
+    keys: HashMap<Vec<u8>, HashSet<u64>>,
+    max_keys: usize,
+}
+impl Table {
+    /// Sends the invalidation of `keys` to the client, or to the client it redirects to.
+    /// RESP2 targets only get the message when subscribed to the invalidation channel.
+    fn send(&self, pubsub: &PubSub, id: u64, keys: Vec<Vec<u8>>) {
+        let Some(client) = self.clients.get(&id) else {
+            return;
+        };
+        let Some(options) = &client.tracking else {
+            return;
+        };
+        let keys = Response::Array(keys.into_iter().map(Response::Data).collect());
+        match options.redirect {
+            Some(redirect) => match self.clients.get(&redirect) {
+                Some(target) if target.protocol == 3 => {
+                    let _ = target.sender.send(invalidate_push(keys));
+                }
+                Some(_) => {
+                    pubsub.send_to(INVALIDATE_CHANNEL, redirect, keys);
+                }
+                None if client.protocol == 3 => {
+                    let _ = client.sender.send(Response::Push(vec![Response::Data(
+                        b"tracking-redir-broken".to_vec(),
+                    )]));
+                }
+                None => {}
+            },
+            // RESP2 connections can not receive pushes, they need a redirect
+            None if client.protocol == 3 => {
+                let _ = client.sender.send(invalidate_push(keys));
+            }
+            None => {}
+        }
+    }
+    /// Forgets keys until the table fits `max_keys`, their clients are told to drop them.
+    fn evict(&mut self, pubsub: &PubSub) {
+        while self.max_keys > 0 && self.keys.len() > self.max_keys {
+            let Some(key) = self.keys.keys().next().cloned() else {
+                return;
+            };
+            for id in self.keys.remove(&key).unwrap_or_default() {
+                self.send(pubsub, id, vec![key.clone()]);
+            }
+        }
+    }
+}
+
+fn invalidate_push(keys: Response) -> Response {
+    Response::Push(vec![Response::Data(b"invalidate".to_vec()), keys])
+}
+
+/// The server side of client side caching: remembers which keys the tracking
+/// clients read, and sends them an invalidation message when the keys are modified.
+///
+/// In the default mode only the keys read by a client are remembered, in BCAST mode
+/// a client is told about every key matching its prefixes and nothing is remembered.
+/// NOLOOP clients are not told about the keys they modified themselves.
+/// The table holds at most `tracking-table-max-keys` keys, the clients of the keys
+/// evicted to make room are sent an invalidation right away.
+pub struct Tracking {
+    table: Mutex<Table>,
+    pubsub: Arc<PubSub>,
+}
+impl Tracking {
+    pub fn new(pubsub: Arc<PubSub>) -> Self {
+        Tracking {
+            table: Mutex::new(Table {
+                clients: HashMap::new(),
+                keys: HashMap::new(),
+                max_keys: DEFAULT_MAX_KEYS,
+            }),
+            pubsub,
+        }
+    }
+    /// Registers a connection, its messages are pushed to `sender`.
+    pub fn connect(&self, id: u64, sender: UnboundedSender<Response>) {
+        if let Ok(mut table) = self.table.lock() {
+            table.clients.insert(
+                id,
+                Client {
+                    sender,
+                    protocol: 2,
+                    tracking: None,
+                    caching: None,
+                },
+            );
+        }
+    }
+    /// The keys of a closed connection are left in the table, they are
+    /// skipped when invalidated.
+    pub fn disconnect(&self, id: u64) {
+        if let Ok(mut table) = self.table.lock() {
+            table.clients.remove(&id);
+        }
+    }
+    pub fn set_protocol(&self, id: u64, protocol: u8) {
+        if let Ok(mut table) = self.table.lock() {
+            if let Some(client) = table.clients.get_mut(&id) {
+                client.protocol = protocol;
+            }
+        }
+    }
+    pub fn enable(&self, id: u64, options: TrackingOptions) -> Result<(), anyhow::Error> {
+        let mut table = self.table.lock().map_err(|e| anyhow!("{}", e))?;
+        if let Some(redirect) = options.redirect {
+            ensure!(
+                table.clients.contains_key(&redirect),
+                "The client ID you want redirect to does not exist"
+            );
+        }
+        let client = table
+            .clients
+            .get_mut(&id)
+            .ok_or(anyhow!("can not find client-{}", id))?;
+        let options = match &client.tracking {
+            Some(current) => {
+                ensure!(
+                    current.bcast == options.bcast,
+                    "You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode."
+                );
+                ensure!(
+                    current.optin == options.optin && current.optout == options.optout,
+                    "You can't switch OPTIN/OPTOUT mode before disabling tracking for this client, and then re-enabling it with a different mode."
+                );
+                let mut prefixes = current.prefixes.clone();
+                for prefix in options.prefixes {
+                    if !prefixes.contains(&prefix) {
+                        prefixes.push(prefix);
+                    }
+                }
+                TrackingOptions {
+                    prefixes,
+                    ..options
+                }
+            }
+            None => options,
+        };
+        client.tracking = Some(options);
+        Ok(())
+    }
+    pub fn disable(&self, id: u64) {
+        if let Ok(mut table) = self.table.lock() {
+            if let Some(client) = table.clients.get_mut(&id) {
+                client.tracking = None;
+                client.caching = None;
+            }
+        }
+    }
+    /// CLIENT CACHING, which decides whether the keys of the next command are tracked.
+    pub fn set_caching(&self, id: u64, yes: bool) -> Result<(), anyhow::Error> {
+        let mut table = self.table.lock().map_err(|e| anyhow!("{}", e))?;
+        let client = table
+            .clients
+            .get_mut(&id)
+            .ok_or(anyhow!("can not find client-{}", id))?;
+        let (optin, optout) = match &client.tracking {
+            Some(options) => (options.optin, options.optout),
+            None => (false, false),
+        };
+        ensure!(
+            optin || optout,
+            "CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled"
+        );
+        ensure!(
+            !yes || optin,
+            "CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode."
+        );
+        ensure!(
+            yes || optout,
+            "CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode."
+        );
+        client.caching = Some(yes);
+        Ok(())
+    }
+    /// The client the invalidations are redirected to, 0 without a redirect
+    /// and -1 when tracking is off.
+    pub fn redirect(&self, id: u64) -> i64 {
+        self.table
+            .lock()
+            .ok()
+            .and_then(|table| {
+                let options = table.clients.get(&id)?.tracking.as_ref()?;
+                Some(options.redirect.map_or(0, |redirect| redirect as i64))
+            })
+            .unwrap_or(-1)
+    }
+    /// Called before every command but CLIENT CACHING with the keys the command
+    /// reads, `None` when it is not a read-only command.
+    pub fn before_command(&self, id: u64, keys: Option<Vec<Vec<u8>>>) {
+        let Ok(mut table) = self.table.lock() else {
+            return;
+        };
+        let Some(client) = table.clients.get_mut(&id) else {
+            return;
+        };
+        let caching = client.caching.take();
+        let tracked = match (&client.tracking, keys) {
+            (Some(options), Some(keys)) if !options.bcast => {
+                let tracked = if options.optin {
+                    caching == Some(true)
+                } else if options.optout {
+                    caching != Some(false)
+                } else {
+                    true
+                };
+                tracked.then_some(keys)
+            }
+            _ => None,
+        };
+        for key in tracked.into_iter().flatten() {
+            table.keys.entry(key).or_default().insert(id);
+        }
+        table.evict(&self.pubsub);
+    }
+    /// Tells the clients which may have cached the key that it was modified.
+    pub fn invalidate(&self, key: &[u8]) {
+        let Ok(mut table) = self.table.lock() else {
+            return;
+        };
+        let current = CURRENT_CLIENT.try_with(|id| *id).ok();
+        let is_loop = |table: &Table, id: u64| {
+            Some(id) == current
+                && table.clients.get(&id).is_some_and(|client| {
+                    client
+                        .tracking
+                        .as_ref()
+                        .is_some_and(|options| options.noloop)
+                })
+        };
+        for id in table.keys.remove(key).unwrap_or_default() {
+            if !is_loop(&table, id) {
+                table.send(&self.pubsub, id, vec![key.to_vec()]);
+            }
+        }
+        let broadcast: Vec<u64> = table
+            .clients
+            .iter()
+            .filter(|(_, client)| {
+                client.tracking.as_ref().is_some_and(|options| {
+                    options.bcast
+                        && (options.prefixes.is_empty()
+                            || options
+                                .prefixes
+                                .iter()
+                                .any(|prefix| key.starts_with(prefix)))
+                })
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in broadcast {
+            if !is_loop(&table, id) {
+                table.send(&self.pubsub, id, vec![key.to_vec()]);
+            }
+        }
+    }
+    /// Every key changed at once, the tracking clients are sent an
+    /// invalidation with a null list of keys.
+    pub fn invalidate_all(&self) {
+        let Ok(mut table) = self.table.lock() else {
+            return;
+        };
+        table.keys.clear();
+        for client in table.clients.values() {
+            let Some(options) = &client.tracking else {
+                continue;
+            };
+            let target = match options.redirect {
+                Some(redirect) => table.clients.get(&redirect),
+                None => Some(client),
+            };
+            match target {
+                Some(target) if target.protocol == 3 => {
+                    let _ = target.sender.send(invalidate_push(Response::Nil));
+                }
+                Some(_) => {
+                    if let Some(redirect) = options.redirect {
+                        self.pubsub
+                            .send_to(INVALIDATE_CHANNEL, redirect, Response::Nil);
+                    }
+                }
+                None => {}
+            }
+        }
+    }
+    pub fn max_keys(&self) -> usize {
+        self.table.lock().map(|table| table.max_keys).unwrap_or(0)
+    }
+    pub fn set_max_keys(&self, max_keys: usize) {
+        if let Ok(mut table) = self.table.lock() {
+            table.max_keys = max_keys;
+            table.evict(&self.pubsub);
+        }
+    }
+}
+
+/// Reports the keys modified in the database to the tracking table and to the
+/// transactions watching them. It is runtime state, nothing is written to the snapshot.
+#[derive(Clone, Default)]
+pub struct KeyTracker {
+    tracking: Option<Arc<Tracking>>,
+    watched_keys: Option<Arc<WatchedKeys>>,
+}
+impl std::fmt::Debug for KeyTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyTracker").finish()
+    }
+}
+impl PartialEq for KeyTracker {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+impl KeyTracker {
+    pub fn attach(&mut self, tracking: Arc<Tracking>, watched_keys: Arc<WatchedKeys>) {
+        self.tracking = Some(tracking);
+        self.watched_keys = Some(watched_keys);
+    }
+    pub fn modified(&self, db_index: usize, key: &[u8]) {
+        if let Some(tracking) = &self.tracking {
+            tracking.invalidate(key);
+        }
+        if let Some(watched_keys) = &self.watched_keys {
+            watched_keys.touch(db_index, key);
+        }
+    }
+    /// The databases were emptied, the tracking clients get a single invalidation
+    /// whatever the number of databases.
+    pub fn flushed(&self, db_indexes: impl IntoIterator<Item = usize>) {
+        if let Some(tracking) = &self.tracking {
+            tracking.invalidate_all();
+        }
+        if let Some(watched_keys) = &self.watched_keys {
+            for db_index in db_indexes {
+                watched_keys.touch_all(db_index);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    /// A RESP3 connection with the tracking options, which receives the pushes.
+    fn connect(
+        tracking: &Tracking,
+        id: u64,
+        options: TrackingOptions,
+    ) -> UnboundedReceiver<Response> {
+        let (sender, receiver) = unbounded_channel();
+        tracking.connect(id, sender);
+        tracking.set_protocol(id, 3);
+        tracking.enable(id, options).unwrap();
+        receiver
+    }
+    fn invalidated(receiver: &mut UnboundedReceiver<Response>) -> Vec<Response> {
+        let mut messages = vec![];
+        while let Ok(message) = receiver.try_recv() {
+            messages.push(message);
+        }
+        messages
+    }
+    fn invalidation(key: &[u8]) -> Response {
+        invalidate_push(Response::Array(vec![Response::Data(key.to_vec())]))
+    }
+    fn read(tracking: &Tracking, id: u64, key: &[u8]) {
+        tracking.before_command(id, Some(vec![key.to_vec()]));
+    }
+
+    #[test]
+    fn the_keys_read_are_invalidated_once() {
+        let tracking = Tracking::new(Arc::new(PubSub::new()));
+        let mut receiver = connect(&tracking, 1, TrackingOptions::default());
+        read(&tracking, 1, b"k");
+        tracking.invalidate(b"other");
+        tracking.invalidate(b"k");
+        tracking.invalidate(b"k");
+        assert_eq!(invalidated(&mut receiver), vec![invalidation(b"k")]);
+
+        // Writes are not tracked
+        tracking.before_command(1, None);
+        tracking.invalidate(b"k");
+        assert!(invalidated(&mut receiver).is_empty());
+    }
+
+    #[test]
+    fn optin_and_optout() {
+        let tracking = Tracking::new(Arc::new(PubSub::new()));
+        let optin = TrackingOptions {
+            optin: true,
+            ..Default::default()
+        };
+        let mut receiver = connect(&tracking, 1, optin);
+        read(&tracking, 1, b"a");
+        tracking.set_caching(1, true).unwrap();
+        read(&tracking, 1, b"b");
+        // CLIENT CACHING only applies to the next command
+        read(&tracking, 1, b"c");
+        assert!(tracking.set_caching(1, false).is_err());
+        for key in [b"a", b"b", b"c"] {
+            tracking.invalidate(key);
+        }
+        assert_eq!(invalidated(&mut receiver), vec![invalidation(b"b")]);
+
+        let optout = TrackingOptions {
+            optout: true,
+            ..Default::default()
+        };
+        let mut receiver = connect(&tracking, 2, optout);
+        read(&tracking, 2, b"a");
+        tracking.set_caching(2, false).unwrap();
+        read(&tracking, 2, b"b");
+        read(&tracking, 2, b"c");
+        assert!(tracking.set_caching(2, true).is_err());
+        for key in [b"a", b"b", b"c"] {
+            tracking.invalidate(key);
+        }
+        assert_eq!(
+            invalidated(&mut receiver),
+            vec![invalidation(b"a"), invalidation(b"c")]
+        );
+    }
+
+    #[test]
+    fn bcast_sends_the_keys_matching_the_prefixes() {
+        let tracking = Tracking::new(Arc::new(PubSub::new()));
+        let bcast = TrackingOptions {
+            bcast: true,
+            prefixes: vec![b"user:".to_vec()],
+            ..Default::default()
+        };
+        let mut receiver = connect(&tracking, 1, bcast);
+        tracking.invalidate(b"user:1");
+        tracking.invalidate(b"order:1");
+        tracking.invalidate(b"user:1");
+        assert_eq!(
+            invalidated(&mut receiver),
+            vec![invalidation(b"user:1"), invalidation(b"user:1")]
+        );
+    }
+
+    #[test]
+    fn noloop_clients_are_not_told_about_their_own_changes() {
+        let tracking = Tracking::new(Arc::new(PubSub::new()));
+        let noloop = TrackingOptions {
+            bcast: true,
+            noloop: true,
+            ..Default::default()
+        };
+        let mut receiver = connect(&tracking, 1, noloop);
+        let mut looping = connect(&tracking, 2, TrackingOptions::default());
+        read(&tracking, 2, b"k");
+        CURRENT_CLIENT.sync_scope(1, || tracking.invalidate(b"mine"));
+        CURRENT_CLIENT.sync_scope(2, || tracking.invalidate(b"k"));
+        CURRENT_CLIENT.sync_scope(2, || tracking.invalidate(b"theirs"));
+        assert_eq!(
+            invalidated(&mut receiver),
+            vec![invalidation(b"k"), invalidation(b"theirs")]
+        );
+        assert_eq!(invalidated(&mut looping), vec![invalidation(b"k")]);
+    }
+
+    #[test]
+    fn the_table_evicts_keys_beyond_max_keys() {
+        let tracking = Tracking::new(Arc::new(PubSub::new()));
+        let mut receiver = connect(&tracking, 1, TrackingOptions::default());
+        tracking.set_max_keys(2);
+        read(&tracking, 1, b"a");
+        read(&tracking, 1, b"b");
+        assert!(invalidated(&mut receiver).is_empty());
+        read(&tracking, 1, b"c");
+        let evicted = invalidated(&mut receiver);
+        assert_eq!(evicted.len(), 1);
+        tracking.set_max_keys(1);
+        assert_eq!(invalidated(&mut receiver).len(), 1);
+        // Only the key left in the table is still invalidated
+        for key in [b"a", b"b", b"c"] {
+            tracking.invalidate(key);
+        }
+        assert_eq!(invalidated(&mut receiver).len(), 1);
+    }
+
+    #[test]
+    fn flushing_sends_one_invalidation_per_client() {
+        let tracking = Arc::new(Tracking::new(Arc::new(PubSub::new())));
+        let mut tracker = KeyTracker::default();
+        tracker.attach(tracking.clone(), Arc::new(WatchedKeys::default()));
+        let mut receiver = connect(&tracking, 1, TrackingOptions::default());
+        read(&tracking, 1, b"k");
+        tracker.flushed(0..16);
+        assert_eq!(
+            invalidated(&mut receiver),
+            vec![invalidate_push(Response::Nil)]
+        );
+        tracking.invalidate(b"k");
+        assert!(invalidated(&mut receiver).is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::sync::Mutex;
+
+type Watchers = HashMap<(usize, Vec<u8>), Vec<Arc<AtomicBool>>>;
+
+/// The keys WATCHed by the clients before a transaction.
+///
+/// Every watching client owns one flag registered under all the keys it watches,
+/// the flag is set when one of them is modified and EXEC then fails.
+#[derive(Default)]
+pub struct WatchedKeys {
+    watchers: Mutex<Watchers>,
+}
+impl WatchedKeys {
+    pub fn new() -> Self {
+        WatchedKeys {
+            watchers: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Fails the transactions watching the key.
+    pub fn touch(&self, db_index: usize, key: &[u8]) {
+        if let Ok(watchers) = self.watchers.lock() {
+            if let Some(flags) = watchers.get(&(db_index, key.to_vec())) {
+                for flag in flags {
+                    flag.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+    /// Fails the transactions watching any key of the database.
+    pub fn touch_all(&self, db_index: usize) {
+        if let Ok(watchers) = self.watchers.lock() {
+            for ((index, _), flags) in watchers.iter() {
+                if *index == db_index {
+                    for flag in flags {
+                        flag.store(true, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    }
+    fn register(&self, db_index: usize, key: &[u8], flag: &Arc<AtomicBool>) {
+        if let Ok(mut watchers) = self.watchers.lock() {
+            watchers
+                .entry((db_index, key.to_vec()))
+                .or_default()
+                .push(flag.clone());
+        }
+    }
+    fn unregister(&self, keys: &[(usize, Vec<u8>)], flag: &Arc<AtomicBool>) {
+        if let Ok(mut watchers) = self.watchers.lock() {
+            for key in keys {
+                if let Some(flags) = watchers.get_mut(key) {
+                    flags.retain(|item| !Arc::ptr_eq(item, flag));
+                    if flags.is_empty() {
+                        watchers.remove(key);
+                    }
+                }
+            }
+        }
+    }
+}
+/// The keys watched by one connection, they are unwatched when the guard is dropped.
+pub struct WatchGuard {
+    watched_keys: Arc<WatchedKeys>,
+    keys: Vec<(usize, Vec<u8>)>,
+    dirty: Arc<AtomicBool>,
+}
+impl WatchGuard {
+    pub fn new(watched_keys: Arc<WatchedKeys>) -> Self {
+        WatchGuard {
+            watched_keys,
+            keys: vec![],
+            dirty: Arc::new(AtomicBool::new(false)),
+        }
+    }
+    pub fn watch(&mut self, db_index: usize, key: Vec<u8>) {
+        let key = (db_index, key);
+        if !self.keys.contains(&key) {
+            self.watched_keys.register(key.0, &key.1, &self.dirty);
+            self.keys.push(key);
+        }
+    }
+    /// Whether a watched key was modified since it was watched.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Relaxed)
+    }
+}
+impl Drop for WatchGuard {
+    fn drop(&mut self) {
+        self.watched_keys.unregister(&self.keys, &self.dirty);
+    }
+}
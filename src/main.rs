@@ -1,17 +1,15 @@
 mod command;
 mod database;
+mod module;
 mod parser;
 mod util;
 mod vojo;
-use crate::database::lib::Database;
-
 use crate::database::lib::DatabaseHolder;
 use crate::parser::handler::Handler;
 
 use clap::Parser;
-use database::common::load_rdb;
-use std::sync::Arc;
-use std::sync::Mutex;
+use database::common::load_or_new;
+use database::persistence::{RdbConfig, DEFAULT_DBFILENAME};
 use tokio::net::TcpListener;
 use tokio::task;
 
@@ -27,9 +25,21 @@ struct Cli {
     /// The port
     #[arg(default_value_t = 6379)]
     port: u32,
-    /// The rdb path
-    #[arg(short = 'r', long = "rdb_path", value_name = "rdb path")]
+    /// The rdb path, it sets both the dir and the dbfilename and can not be given with them.
+    /// The server does not start when the file does not exist
+    #[arg(
+        short = 'r',
+        long = "rdb_path",
+        value_name = "rdb path",
+        conflicts_with_all = ["dir", "dbfilename"]
+    )]
     rdb_path: Option<String>,
+    /// The directory the rdb file is loaded from, when it exists, and saved to
+    #[arg(long = "dir", value_name = "dir", default_value = ".")]
+    dir: String,
+    /// The name of the rdb file
+    #[arg(long = "dbfilename", value_name = "dbfilename", default_value = DEFAULT_DBFILENAME)]
+    dbfilename: String,
 }
 
 #[tokio::main]
@@ -45,15 +55,15 @@ async fn main_with_error() -> Result<(), anyhow::Error> {
     let port = cli.port;
     let addr = format!(r#"0.0.0.0:{port}"#);
 
-    let database = if let Some(file_path) = cli.rdb_path {
-        let database = load_rdb(file_path).await?;
-        database
-    } else {
-        Database::new()
-    };
-    let database_holder = DatabaseHolder {
-        database_lock: Arc::new(Mutex::new(database)),
+    #[cfg(feature = "native-modules")]
+    module::native::load_native_modules()?;
+    let rdb_config = match &cli.rdb_path {
+        Some(rdb_path) => RdbConfig::from_rdb_path(rdb_path)?,
+        None => RdbConfig::new(&cli.dir, &cli.dbfilename)?,
     };
+    // The snapshot is loaded when it exists, it must exist when given with --rdb_path
+    let database = load_or_new(&rdb_config.path(), cli.rdb_path.is_some()).await?;
+    let database_holder = DatabaseHolder::new(database, rdb_config);
 
     let listener = TcpListener::bind(&addr)
         .await
@@ -69,10 +79,7 @@ async fn main_with_error() -> Result<(), anyhow::Error> {
         let remote_addr = socket.peer_addr()?.to_string();
 
         let cloned_database = database_holder.clone();
-        let handler = Handler {
-            connect: socket,
-            database_holder: cloned_database,
-        };
+        let handler = Handler::new(socket, cloned_database);
         task::spawn(async move {
             if let Err(e) = handle_connection(handler, remote_addr.clone()).await {
                 info!("The error is {}", e);
@@ -105,3 +112,25 @@ async fn handle_connection(
         handler.run().await?;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_rdb_path_can_not_be_given_with_the_dir_or_the_dbfilename() {
+        let cli = Cli::try_parse_from(["rcache", "--rdb_path", "dump.rdb"]).unwrap();
+        assert_eq!(cli.rdb_path.as_deref(), Some("dump.rdb"));
+        let cli = Cli::try_parse_from(["rcache", "--dir", "/tmp", "--dbfilename", "dump.rdb"]);
+        let cli = cli.unwrap();
+        assert_eq!(
+            (cli.dir.as_str(), cli.dbfilename.as_str()),
+            ("/tmp", "dump.rdb")
+        );
+        assert_eq!(cli.rdb_path, None);
+        for conflicting in ["--dir", "--dbfilename"] {
+            let cli = Cli::try_parse_from(["rcache", "-r", "dump.rdb", conflicting, "x"]);
+            assert!(cli.is_err(), "{}", conflicting);
+        }
+    }
+}
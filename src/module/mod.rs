@@ -0,0 +1,4 @@
+#[cfg(feature = "native-modules")]
+pub mod native;
+pub mod registry;
+pub mod wasm;
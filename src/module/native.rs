@@ -0,0 +1,324 @@
+use crate::database::lib::DatabaseHolder;
+use crate::parser::response::Response;
+use crate::util::command_table::register_command;
+use crate::vojo::module_value::ModuleData;
+use crate::vojo::parsered_command::ParsedCommand;
+use anyhow::ensure;
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+/// The modules compiled into the server with the `native-modules` feature.
+/// A module is added by implementing [`Module`] and listing it here.
+fn native_modules() -> Vec<Box<dyn Module>> {
+    vec![
+        #[cfg(test)]
+        Box::new(tests::CounterModule),
+    ]
+}
+
+static NATIVE_MODULES: LazyLock<Vec<Box<dyn Module>>> = LazyLock::new(native_modules);
+/// The commands of the native modules by their upper case name.
+static NATIVE_COMMANDS: LazyLock<BTreeMap<String, CommandHandler>> = LazyLock::new(|| {
+    NATIVE_MODULES
+        .iter()
+        .flat_map(|module| module.commands())
+        .map(|command| (command.name.to_uppercase(), command.handler))
+        .collect()
+});
+
+/// A command runs like the built-in ones, it locks the database itself.
+pub type CommandHandler =
+    fn(ParsedCommand, &mut DatabaseHolder, usize) -> Result<Response, anyhow::Error>;
+/// Reads back a value written to the snapshot by [`ModuleData::encode`].
+pub type DecodeHook = fn(&[u8]) -> Result<Box<dyn ModuleData>, anyhow::Error>;
+
+pub struct NativeCommand {
+    pub name: &'static str,
+    /// The number of arguments including the command name, negative for at least that many.
+    pub arity: i32,
+    /// The command may modify the dataset, read-only scripts can not call it.
+    pub write: bool,
+    pub handler: CommandHandler,
+}
+/// A value type, stored as [`crate::vojo::value::Value::Module`] with its name.
+pub struct ModuleType {
+    pub name: &'static str,
+    pub decode: DecodeHook,
+}
+
+/// A module compiled into the server. Its commands are added to the command
+/// table at startup and its types can be read from the snapshot.
+pub trait Module: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn commands(&self) -> Vec<NativeCommand>;
+    fn types(&self) -> Vec<ModuleType> {
+        vec![]
+    }
+}
+
+/// Registers the commands of the native modules, called once at startup.
+pub fn load_native_modules() -> Result<(), anyhow::Error> {
+    let mut type_names = vec![];
+    for module in NATIVE_MODULES.iter() {
+        for command in module.commands() {
+            register_command(&command.name.to_uppercase(), command.arity, command.write)?;
+        }
+        for module_type in module.types() {
+            ensure!(
+                !type_names.contains(&module_type.name),
+                "the type {} of module '{}' already exists",
+                module_type.name,
+                module.name()
+            );
+            type_names.push(module_type.name);
+        }
+        info!("Native module '{}' loaded", module.name());
+    }
+    Ok(())
+}
+pub fn native_command(command_name: &str) -> Option<CommandHandler> {
+    NATIVE_COMMANDS.get(command_name).copied()
+}
+pub fn decode_hook(type_name: &str) -> Option<DecodeHook> {
+    NATIVE_MODULES
+        .iter()
+        .flat_map(|module| module.types())
+        .find(|module_type| module_type.name == type_name)
+        .map(|module_type| module_type.decode)
+}
+/// The native modules as MODULE LIST shows them.
+pub fn native_module_commands() -> Vec<(&'static str, Vec<String>)> {
+    NATIVE_MODULES
+        .iter()
+        .map(|module| {
+            let commands = module
+                .commands()
+                .iter()
+                .map(|command| command.name.to_lowercase())
+                .collect();
+            (module.name(), commands)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::module_command::module_command;
+    use crate::database::lib::Database;
+    use crate::database::notify::NOTIFY_MODULE;
+    use crate::database::persistence::RdbConfig;
+    use crate::module::registry::Modules;
+    use crate::module::wasm::tests::load;
+    use crate::vojo::module_value::ModuleValue;
+    use crate::vojo::value::{Value, ValueString};
+    use anyhow::anyhow;
+    use bincode::config;
+
+    /// A module with a counter type, which every test module is loaded with.
+    pub struct CounterModule;
+    impl Module for CounterModule {
+        fn name(&self) -> &'static str {
+            "counter"
+        }
+        fn commands(&self) -> Vec<NativeCommand> {
+            vec![NativeCommand {
+                name: "counter.incrby",
+                arity: 3,
+                write: true,
+                handler: incr_by,
+            }]
+        }
+        fn types(&self) -> Vec<ModuleType> {
+            vec![ModuleType {
+                name: "counter",
+                decode: Counter::decode,
+            }]
+        }
+    }
+    #[derive(Debug, Clone, PartialEq)]
+    struct Counter(i64);
+    impl Counter {
+        fn decode(bytes: &[u8]) -> Result<Box<dyn ModuleData>, anyhow::Error> {
+            let bytes = bytes.try_into().map_err(|_| anyhow!("not a counter"))?;
+            Ok(Box::new(Counter(i64::from_be_bytes(bytes))))
+        }
+    }
+    impl ModuleData for Counter {
+        fn encode(&self) -> Vec<u8> {
+            self.0.to_be_bytes().to_vec()
+        }
+        fn clone_data(&self) -> Box<dyn ModuleData> {
+            Box::new(self.clone())
+        }
+    }
+    /// A type no module registers.
+    #[derive(Debug, Clone)]
+    struct Unregistered;
+    impl ModuleData for Unregistered {
+        fn encode(&self) -> Vec<u8> {
+            vec![]
+        }
+        fn clone_data(&self) -> Box<dyn ModuleData> {
+            Box::new(self.clone())
+        }
+    }
+    /// COUNTER.INCRBY key increment
+    fn incr_by(
+        parser: ParsedCommand,
+        database_lock: &mut DatabaseHolder,
+        db_index: usize,
+    ) -> Result<Response, anyhow::Error> {
+        let key = parser.get_vec(1)?;
+        let increment = parser.get_i64(2)?;
+        let mut db = database_lock
+            .database_lock
+            .lock()
+            .map_err(|e| anyhow!("{}", e))?;
+        match db.get_module_value_mut::<Counter>(db_index, &key)? {
+            Some(counter) => {
+                counter.0 += increment;
+                db.modified(db_index, &key);
+            }
+            None => {
+                let counter = ModuleValue::new("counter", Counter(increment));
+                db.insert(db_index, key.clone(), Value::Module(counter))?;
+            }
+        }
+        db.notify(NOTIFY_MODULE, "counter.incrby", db_index, &key);
+        let counter = db.get_module_value::<Counter>(db_index, &key)?;
+        Ok(Response::Integer(counter.map_or(0, |counter| counter.0)))
+    }
+
+    static LOADED: LazyLock<()> = LazyLock::new(|| load_native_modules().unwrap());
+
+    fn database_holder() -> DatabaseHolder {
+        let rdb_config = RdbConfig::new(std::env::temp_dir(), "rcache-native.rdb").unwrap();
+        DatabaseHolder::new(Database::new(), rdb_config)
+    }
+    fn command(args: &[&str]) -> ParsedCommand {
+        ParsedCommand::from_args(args.iter().map(|arg| arg.as_bytes().to_vec()).collect())
+    }
+
+    #[test]
+    fn the_commands_are_registered_once() {
+        LazyLock::force(&LOADED);
+        assert!(native_command("COUNTER.INCRBY").is_some());
+        assert!(native_command("counter.incrby").is_none());
+        assert!(decode_hook("counter").is_some());
+        assert!(decode_hook("json").is_none());
+        let modules = native_module_commands();
+        assert_eq!(
+            modules,
+            vec![("counter", vec!["counter.incrby".to_string()])]
+        );
+
+        let collision = load_native_modules().unwrap_err();
+        assert_eq!(
+            collision.to_string(),
+            "command 'counter.incrby' already exists"
+        );
+        assert!(register_command("COUNTER.INCRBY", 3, true).is_err());
+        // A loaded module can not take the name of a native command either
+        let module = load(
+            "native1",
+            r#"(data (i32.const 0) "native1") (data (i32.const 32) "counter.incrby") (data (i32.const 64) "incrby")
+  (func (export "incrby") (param i32 i32 i32))
+  (func (export "rcache_init")
+    (call $set_name (i32.const 0) (i32.const 7))
+    (call $register (i32.const 32) (i32.const 14) (i32.const 64) (i32.const 6) (i32.const 3) (i32.const 1)))"#,
+        );
+        let loaded = module.and_then(|module| Modules::new().load(module));
+        assert!(loaded.unwrap_err().to_string().contains("already exists"));
+    }
+
+    #[test]
+    fn native_commands_are_dispatched_with_their_arity() {
+        LazyLock::force(&LOADED);
+        let mut database_holder = database_holder();
+        let incr_by = |database_holder: &mut DatabaseHolder, args: &[&str]| {
+            module_command("COUNTER.INCRBY", command(args), database_holder, 0).unwrap()
+        };
+        let reply = incr_by(&mut database_holder, &["counter.incrby", "hits", "2"]);
+        assert_eq!(reply.unwrap(), Response::Integer(2));
+        let reply = incr_by(&mut database_holder, &["counter.incrby", "hits", "3"]);
+        assert_eq!(reply.unwrap(), Response::Integer(5));
+        assert!(incr_by(&mut database_holder, &["counter.incrby", "hits"]).is_err());
+        let reply = module_command(
+            "COUNTER.GET",
+            command(&["counter.get"]),
+            &mut database_holder,
+            0,
+        );
+        assert!(reply.is_none());
+    }
+
+    #[test]
+    fn module_values_are_read_back_by_their_type() {
+        let mut database = Database::new();
+        let counter = ModuleValue::new("counter", Counter(7));
+        database
+            .insert(0, b"hits".to_vec(), Value::Module(counter))
+            .unwrap();
+        database
+            .insert(
+                0,
+                b"json".to_vec(),
+                Value::Module(ModuleValue::new("json", Unregistered)),
+            )
+            .unwrap();
+        database
+            .insert(
+                0,
+                b"string".to_vec(),
+                Value::String(ValueString {
+                    data: b"7".to_vec(),
+                }),
+            )
+            .unwrap();
+
+        assert_eq!(
+            database.get_module_value::<Counter>(0, b"hits").unwrap(),
+            Some(&Counter(7))
+        );
+        assert_eq!(
+            database.get_module_value::<Counter>(0, b"missing").unwrap(),
+            None
+        );
+        assert!(database.get_module_value::<Counter>(0, b"string").is_err());
+        assert!(database.get_module_value::<Counter>(0, b"json").is_err());
+        database
+            .get_module_value_mut::<Counter>(0, b"hits")
+            .unwrap()
+            .unwrap()
+            .0 += 1;
+        assert!(database
+            .get_module_value_mut::<Counter>(0, b"json")
+            .is_err());
+        assert!(database
+            .get_module_value_mut::<Counter>(0, b"string")
+            .is_err());
+
+        // Only the registered types are read back from the snapshot
+        let encoded = bincode::encode_to_vec(&database, config::standard()).unwrap();
+        let decoded: Result<(Database, usize), _> =
+            bincode::decode_from_slice(&encoded, config::standard());
+        assert!(decoded
+            .unwrap_err()
+            .to_string()
+            .contains("no module registers the type json"));
+        database.remove(0, b"json").unwrap();
+        let encoded = bincode::encode_to_vec(&database, config::standard()).unwrap();
+        let (decoded, _): (Database, usize) =
+            bincode::decode_from_slice(&encoded, config::standard()).unwrap();
+        assert_eq!(
+            decoded.get_module_value::<Counter>(0, b"hits").unwrap(),
+            Some(&Counter(8))
+        );
+        assert_eq!(
+            decoded.get(0, b"string".to_vec()).unwrap(),
+            database.get(0, b"string".to_vec()).unwrap()
+        );
+        assert!(Counter::decode(b"short").is_err());
+    }
+}
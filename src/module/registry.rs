@@ -0,0 +1,235 @@
+use crate::database::lib::Database;
+#[cfg(feature = "native-modules")]
+use crate::module::native::native_module_commands;
+use crate::module::wasm::WasmModule;
+use crate::parser::response::Response;
+use crate::util::command_table::{check_arity, register_command, unregister_command};
+use crate::vojo::parsered_command::ParsedCommand;
+use anyhow::{anyhow, ensure};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+/// The modules loaded with MODULE LOADWASM, by name.
+///
+/// The commands of a module are added to the command table when it is loaded
+/// and removed when it is unloaded. A module command runs with the database
+/// lock held, which is always taken before this one.
+#[derive(Default)]
+pub struct Modules {
+    modules: Mutex<BTreeMap<String, WasmModule>>,
+}
+impl Modules {
+    pub fn new() -> Self {
+        Modules {
+            modules: Mutex::new(BTreeMap::new()),
+        }
+    }
+    /// Registers the commands of the module, nothing is registered when one of
+    /// them can not be.
+    pub fn load(&self, module: WasmModule) -> Result<String, anyhow::Error> {
+        let mut modules = self.modules.lock().map_err(|e| anyhow!("{}", e))?;
+        ensure!(
+            !modules.contains_key(&module.name),
+            "Error loading the extension. Module '{}' already exists",
+            module.name
+        );
+        for (i, command) in module.commands.iter().enumerate() {
+            if let Err(e) = register_command(&command.name, command.arity, command.write) {
+                for registered in &module.commands[..i] {
+                    unregister_command(&registered.name);
+                }
+                return Err(e);
+            }
+        }
+        let name = module.name.clone();
+        modules.insert(name.clone(), module);
+        Ok(name)
+    }
+    pub fn unload(&self, name: &str) -> Result<(), anyhow::Error> {
+        let mut modules = self.modules.lock().map_err(|e| anyhow!("{}", e))?;
+        let module = modules.remove(name).ok_or(anyhow!(
+            "Error unloading module: no such module with that name"
+        ))?;
+        for command in &module.commands {
+            unregister_command(&command.name);
+        }
+        Ok(())
+    }
+    /// MODULE LIST, the name, path and commands of each module. The native
+    /// modules come first and have no path.
+    pub fn list(&self) -> Response {
+        let data = |text: &str| Response::Data(text.as_bytes().to_vec());
+        let describe = |name: &str, path: Option<&str>, commands: Vec<String>| {
+            Response::Array(vec![
+                data("name"),
+                data(name),
+                data("path"),
+                path.map_or(Response::Nil, data),
+                data("commands"),
+                Response::Array(commands.iter().map(|command| data(command)).collect()),
+            ])
+        };
+        let mut list = vec![];
+        #[cfg(feature = "native-modules")]
+        for (name, commands) in native_module_commands() {
+            list.push(describe(name, None, commands));
+        }
+        if let Ok(modules) = self.modules.lock() {
+            for module in modules.values() {
+                let commands = module
+                    .commands
+                    .iter()
+                    .map(|command| command.name.to_lowercase())
+                    .collect();
+                list.push(describe(&module.name, Some(&module.path), commands));
+            }
+        }
+        Response::Array(list)
+    }
+    /// Whether a module registered the command.
+    pub fn handles(&self, command_name: &str) -> bool {
+        self.modules.lock().is_ok_and(|modules| {
+            modules.values().any(|module| {
+                module
+                    .commands
+                    .iter()
+                    .any(|command| command.name == command_name)
+            })
+        })
+    }
+    /// Runs the command when a module registered it, `None` otherwise.
+    pub fn call(
+        &self,
+        command_name: &str,
+        database: &mut Database,
+        db_index: usize,
+        parser: &ParsedCommand,
+    ) -> Option<Result<Response, anyhow::Error>> {
+        let mut modules = match self.modules.lock() {
+            Ok(modules) => modules,
+            Err(e) => return Some(Err(anyhow!("{}", e))),
+        };
+        modules.values_mut().find_map(|module| {
+            let command = module
+                .commands
+                .iter()
+                .find(|command| command.name == command_name)?
+                .clone();
+            if let Err(e) = check_arity(command_name, parser) {
+                return Some(Err(e));
+            }
+            Some(module.call(database, db_index, &command, parser))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::wasm::tests::load;
+
+    /// A module named `name` whose commands reply the number of their arguments.
+    fn module(name: &str, commands: &[&str]) -> WasmModule {
+        let mut items = format!(
+            r#"(data (i32.const 0) "{}") (data (i32.const 32) "argc")
+  (func (export "argc") (param i32 i32) (param $argc i32)
+    (call $reply_integer (i64.extend_i32_s (local.get $argc))))"#,
+            name
+        );
+        let mut init = format!("(call $set_name (i32.const 0) (i32.const {}))", name.len());
+        for (i, command) in commands.iter().enumerate() {
+            let offset = 64 * (i + 1);
+            items += &format!(r#"(data (i32.const {}) "{}")"#, offset, command);
+            init += &format!(
+                "(call $register (i32.const {}) (i32.const {}) (i32.const 32) (i32.const 4) (i32.const -1) (i32.const 0))",
+                offset,
+                command.len()
+            );
+        }
+        load(
+            name,
+            &format!(r#"{} (func (export "rcache_init") {})"#, items, init),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn loaded_commands_can_be_called_until_unloaded() {
+        let modules = Modules::new();
+        modules
+            .load(module("registry1", &["registry1.a", "registry1.b"]))
+            .unwrap();
+        assert!(modules.handles("REGISTRY1.B"));
+        assert!(!modules.handles("registry1.b"));
+        let parser = ParsedCommand::from_args(vec![b"registry1.a".to_vec(), b"x".to_vec()]);
+        assert!(check_arity("REGISTRY1.A", &parser).is_ok());
+        let mut database = Database::new();
+        let reply = modules.call("REGISTRY1.A", &mut database, 0, &parser);
+        assert_eq!(reply.unwrap().unwrap(), Response::Integer(2));
+        assert!(modules.call("GET", &mut database, 0, &parser).is_none());
+
+        modules.unload("registry1").unwrap();
+        assert!(!modules.handles("REGISTRY1.A"));
+        assert!(check_arity("REGISTRY1.A", &parser).is_err());
+        assert!(modules
+            .call("REGISTRY1.A", &mut database, 0, &parser)
+            .is_none());
+        let error = modules.unload("registry1").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Error unloading module: no such module with that name"
+        );
+    }
+
+    #[test]
+    fn names_can_not_be_taken_twice() {
+        let modules = Modules::new();
+        modules.load(module("registry2", &["registry2.a"])).unwrap();
+        let error = modules
+            .load(module("registry2", &["registry2.b"]))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Error loading the extension. Module 'registry2' already exists"
+        );
+        assert!(!modules.handles("REGISTRY2.B"));
+
+        // Nothing is registered when a command exists already, built-in or not
+        for taken in ["get", "registry2.a"] {
+            let error = modules
+                .load(module("registry3", &["registry3.a", taken]))
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("command '{}' already exists", taken)
+            );
+            let parser = ParsedCommand::from_args(vec![b"registry3.a".to_vec()]);
+            assert!(check_arity("REGISTRY3.A", &parser).is_err());
+        }
+        modules.load(module("registry3", &["registry3.a"])).unwrap();
+    }
+
+    #[test]
+    fn list() {
+        let modules = Modules::new();
+        modules
+            .load(module("registry4", &["registry4.a", "registry4.b"]))
+            .unwrap();
+        let data = |text: &str| Response::Data(text.as_bytes().to_vec());
+        let path = std::env::temp_dir().join("rcache-registry4.wasm");
+        let Response::Array(list) = modules.list() else {
+            panic!("MODULE LIST replies an array");
+        };
+        assert_eq!(
+            list.last(),
+            Some(&Response::Array(vec![
+                data("name"),
+                data("registry4"),
+                data("path"),
+                data(path.to_str().unwrap()),
+                data("commands"),
+                Response::Array(vec![data("registry4.a"), data("registry4.b")]),
+            ]))
+        );
+    }
+}
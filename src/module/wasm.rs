@@ -0,0 +1,715 @@
+use crate::database::lib::Database;
+use crate::database::notify::{NOTIFY_GENERIC, NOTIFY_STRING};
+use crate::parser::response::Response;
+use crate::vojo::parsered_command::ParsedCommand;
+use crate::vojo::value::{Value, ValueString};
+use anyhow::{anyhow, ensure};
+use std::path::Path;
+use wasmi::{
+    Caller, Config, Engine, Extern, Instance, Linker, Module, Store, StoreLimits,
+    StoreLimitsBuilder, TypedFunc,
+};
+
+/// The fuel a command gets when MODULE LOADWASM is not given FUEL, about one
+/// unit per instruction.
+pub const DEFAULT_FUEL: u64 = 10_000_000;
+/// The largest linear memory of a module when MODULE LOADWASM is not given MAXMEMORY.
+pub const DEFAULT_MAX_MEMORY: usize = 64 * 1024 * 1024;
+/// The module the host functions are imported from.
+const HOST_MODULE: &str = "rcache";
+
+/// A command registered by a module, served by one of its exported functions.
+#[derive(Clone, Debug)]
+pub struct WasmCommand {
+    /// Upper case, like the names of the built-in commands.
+    pub name: String,
+    pub export: String,
+    pub arity: i32,
+    pub write: bool,
+}
+
+/// What the host functions work on. The database is swapped in while a
+/// command runs and swapped back out when it returns.
+struct HostState {
+    database: Database,
+    db_index: usize,
+    /// The key functions can only be called by a command, not by `rcache_init`.
+    in_command: bool,
+    name: Option<String>,
+    commands: Vec<WasmCommand>,
+    /// The arrays being replied, each with the number of items it still expects.
+    /// The first one holds the reply itself.
+    replies: Vec<(usize, Vec<Response>)>,
+    limits: StoreLimits,
+}
+impl HostState {
+    fn reply(&mut self, response: Response) -> Result<(), wasmi::Error> {
+        let Some((expected, items)) = self.replies.last_mut() else {
+            return Err(wasmi::Error::new("the command already replied"));
+        };
+        if items.len() == *expected {
+            return Err(wasmi::Error::new("the command already replied"));
+        }
+        items.push(response);
+        // Completed arrays become an item of the array they are nested in
+        while self.replies.len() > 1 {
+            let (expected, items) = &self.replies[self.replies.len() - 1];
+            if items.len() < *expected {
+                break;
+            }
+            let (_, items) = self.replies.pop().unwrap_or_default();
+            if let Some((_, parent)) = self.replies.last_mut() {
+                parent.push(Response::Array(items));
+            }
+        }
+        Ok(())
+    }
+    fn database(&mut self) -> Result<(&mut Database, usize), wasmi::Error> {
+        if !self.in_command {
+            return Err(wasmi::Error::new("the keys can only be used by a command"));
+        }
+        Ok((&mut self.database, self.db_index))
+    }
+}
+
+/// A WebAssembly module loaded with MODULE LOADWASM.
+///
+/// The module exports its `memory`, `rcache_alloc(len) -> ptr` for the host to
+/// pass the arguments, and `rcache_init()` which calls the host function
+/// `register_command` for each of its commands. A command is an exported function
+/// `(args_ptr, args_len, argc)`, the arguments are each written as a little endian
+/// `u32` length followed by the bytes, and it answers with the `reply_*` functions.
+///
+/// Each command runs with the database lock held and gets a fixed amount of fuel,
+/// the memory of the module can not grow past its limit.
+pub struct WasmModule {
+    pub name: String,
+    pub path: String,
+    pub commands: Vec<WasmCommand>,
+    fuel: u64,
+    store: Store<HostState>,
+    instance: Instance,
+    alloc: TypedFunc<i32, i32>,
+}
+impl WasmModule {
+    /// Compiles and instantiates the module, then runs its `rcache_init`.
+    pub fn load(path: &str, fuel: u64, max_memory: usize) -> Result<Self, anyhow::Error> {
+        let wasm = std::fs::read(path)
+            .map_err(|e| anyhow!("Error loading the extension '{}': {}", path, e))?;
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &wasm[..]).map_err(|e| anyhow!("{}", e))?;
+        let state = HostState {
+            database: Database::new(),
+            db_index: 0,
+            in_command: false,
+            name: None,
+            commands: vec![],
+            replies: vec![],
+            limits: StoreLimitsBuilder::new()
+                .memory_size(max_memory)
+                .instances(1)
+                .build(),
+        };
+        let mut store = Store::new(&engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(fuel).map_err(|e| anyhow!("{}", e))?;
+        let linker = host_functions(&engine)?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| anyhow!("{}", e))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "rcache_alloc")
+            .map_err(|_| anyhow!("the module does not export rcache_alloc"))?;
+        let init = instance
+            .get_typed_func::<(), ()>(&store, "rcache_init")
+            .map_err(|_| anyhow!("the module does not export rcache_init"))?;
+        init.call(&mut store, ())
+            .map_err(|e| anyhow!("rcache_init failed: {}", e))?;
+
+        let state = store.data_mut();
+        let name = state.name.take().unwrap_or_else(|| {
+            Path::new(path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+        let commands = std::mem::take(&mut state.commands);
+        ensure!(
+            !commands.is_empty(),
+            "the module did not register any command"
+        );
+        for command in &commands {
+            ensure!(
+                instance
+                    .get_typed_func::<(i32, i32, i32), ()>(&store, &command.export)
+                    .is_ok(),
+                "the module does not export {}(i32, i32, i32) for {}",
+                command.export,
+                command.name.to_lowercase()
+            );
+        }
+        Ok(WasmModule {
+            name,
+            path: path.to_string(),
+            commands,
+            fuel,
+            store,
+            instance,
+            alloc,
+        })
+    }
+    /// Runs a command of the module on the database.
+    pub fn call(
+        &mut self,
+        database: &mut Database,
+        db_index: usize,
+        command: &WasmCommand,
+        parser: &ParsedCommand,
+    ) -> Result<Response, anyhow::Error> {
+        let mut args = vec![];
+        for i in 0..parser.argv.len() {
+            let arg = parser.get_slice(i)?;
+            args.extend((arg.len() as u32).to_le_bytes());
+            args.extend(arg);
+        }
+        let function = self
+            .instance
+            .get_typed_func::<(i32, i32, i32), ()>(&self.store, &command.export)
+            .map_err(|e| anyhow!("{}", e))?;
+        self.store
+            .set_fuel(self.fuel)
+            .map_err(|e| anyhow!("{}", e))?;
+        let state = self.store.data_mut();
+        std::mem::swap(&mut state.database, database);
+        state.db_index = db_index;
+        state.in_command = true;
+        state.replies = vec![(1, vec![])];
+        let result = self.run(function, &args, parser.argv.len());
+        let state = self.store.data_mut();
+        std::mem::swap(&mut state.database, database);
+        state.in_command = false;
+        let replies = std::mem::take(&mut state.replies);
+        result?;
+        ensure!(
+            replies.len() == 1,
+            "the command {} did not complete its array reply",
+            command.name.to_lowercase()
+        );
+        replies
+            .into_iter()
+            .next()
+            .and_then(|(_, mut items)| items.pop())
+            .ok_or(anyhow!(
+                "the command {} did not reply",
+                command.name.to_lowercase()
+            ))
+    }
+    fn run(
+        &mut self,
+        function: TypedFunc<(i32, i32, i32), ()>,
+        args: &[u8],
+        argc: usize,
+    ) -> Result<(), anyhow::Error> {
+        let error = |e: wasmi::Error| match is_out_of_fuel(&e) {
+            true => anyhow!("the module command ran out of fuel"),
+            false => anyhow!("the module command failed: {}", e),
+        };
+        let ptr = self
+            .alloc
+            .call(&mut self.store, args.len() as i32)
+            .map_err(error)?;
+        let memory = self
+            .instance
+            .get_memory(&self.store, "memory")
+            .ok_or(anyhow!("the module does not export its memory"))?;
+        memory
+            .write(&mut self.store, ptr as u32 as usize, args)
+            .map_err(|e| anyhow!("{}", e))?;
+        function
+            .call(&mut self.store, (ptr, args.len() as i32, argc as i32))
+            .map_err(error)
+    }
+}
+fn is_out_of_fuel(error: &wasmi::Error) -> bool {
+    error.as_trap_code() == Some(wasmi::core::TrapCode::OutOfFuel)
+}
+/// Copies `len` bytes at `ptr` out of the memory of the module.
+fn read(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<Vec<u8>, wasmi::Error> {
+    let memory = caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or(wasmi::Error::new("the module does not export its memory"))?;
+    let start = ptr as u32 as usize;
+    memory
+        .data(caller)
+        .get(start..start + len as u32 as usize)
+        .map(<[u8]>::to_vec)
+        .ok_or(wasmi::Error::new("out of bounds memory access"))
+}
+fn read_string(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<String, wasmi::Error> {
+    String::from_utf8(read(caller, ptr, len)?)
+        .map_err(|_| wasmi::Error::new("the string is not valid UTF-8"))
+}
+/// The functions of the `rcache` import module.
+fn host_functions(engine: &Engine) -> Result<Linker<HostState>, anyhow::Error> {
+    let mut linker = Linker::new(engine);
+    let failed = |e: wasmi::errors::LinkerError| anyhow!("{}", e);
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "set_name",
+            |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+                let name = read_string(&caller, ptr, len)?;
+                caller.data_mut().name = Some(name);
+                Ok(())
+            },
+        )
+        .map_err(failed)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "register_command",
+            |mut caller: Caller<'_, HostState>,
+             name_ptr: i32,
+             name_len: i32,
+             export_ptr: i32,
+             export_len: i32,
+             arity: i32,
+             write: i32| {
+                let command = WasmCommand {
+                    name: read_string(&caller, name_ptr, name_len)?.to_uppercase(),
+                    export: read_string(&caller, export_ptr, export_len)?,
+                    arity,
+                    write: write != 0,
+                };
+                caller.data_mut().commands.push(command);
+                Ok(())
+            },
+        )
+        .map_err(failed)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "log",
+            |caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+                info!("{}", read_string(&caller, ptr, len)?);
+                Ok(())
+            },
+        )
+        .map_err(failed)?;
+    // Copies the string stored at the key into the buffer, up to its capacity,
+    // and returns its full length, or -1 when the key does not exist
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "get",
+            |mut caller: Caller<'_, HostState>,
+             key_ptr: i32,
+             key_len: i32,
+             buf_ptr: i32,
+             buf_cap: i32|
+             -> Result<i64, wasmi::Error> {
+                let key = read(&caller, key_ptr, key_len)?;
+                let (database, db_index) = caller.data_mut().database()?;
+                let value = database
+                    .get_string(db_index, &key)
+                    .map_err(|e| wasmi::Error::new(e.to_string()))?
+                    .map(|value| value.data.clone());
+                let Some(value) = value else {
+                    return Ok(-1);
+                };
+                let copied = value.len().min(buf_cap.max(0) as usize);
+                let memory = caller
+                    .get_export("memory")
+                    .and_then(Extern::into_memory)
+                    .ok_or(wasmi::Error::new("the module does not export its memory"))?;
+                memory
+                    .write(&mut caller, buf_ptr as u32 as usize, &value[..copied])
+                    .map_err(|e| wasmi::Error::new(e.to_string()))?;
+                Ok(value.len() as i64)
+            },
+        )
+        .map_err(failed)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "set",
+            |mut caller: Caller<'_, HostState>,
+             key_ptr: i32,
+             key_len: i32,
+             value_ptr: i32,
+             value_len: i32| {
+                let key = read(&caller, key_ptr, key_len)?;
+                let data = read(&caller, value_ptr, value_len)?;
+                let (database, db_index) = caller.data_mut().database()?;
+                database
+                    .insert(db_index, key.clone(), Value::String(ValueString { data }))
+                    .map_err(|e| wasmi::Error::new(e.to_string()))?;
+                database.notify(NOTIFY_STRING, "set", db_index, &key);
+                Ok(())
+            },
+        )
+        .map_err(failed)?;
+    // Returns 1 when the key existed
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "del",
+            |mut caller: Caller<'_, HostState>,
+             key_ptr: i32,
+             key_len: i32|
+             -> Result<i32, wasmi::Error> {
+                let key = read(&caller, key_ptr, key_len)?;
+                let (database, db_index) = caller.data_mut().database()?;
+                let removed = database
+                    .remove(db_index, &key)
+                    .map_err(|e| wasmi::Error::new(e.to_string()))?;
+                if removed.is_some() {
+                    database.notify(NOTIFY_GENERIC, "del", db_index, &key);
+                }
+                Ok(removed.is_some() as i32)
+            },
+        )
+        .map_err(failed)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "reply_integer",
+            |mut caller: Caller<'_, HostState>, value: i64| {
+                caller.data_mut().reply(Response::Integer(value))
+            },
+        )
+        .map_err(failed)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "reply_bulk",
+            |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+                let data = read(&caller, ptr, len)?;
+                caller.data_mut().reply(Response::Data(data))
+            },
+        )
+        .map_err(failed)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "reply_simple",
+            |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+                let status = read_string(&caller, ptr, len)?;
+                caller.data_mut().reply(Response::Status(status))
+            },
+        )
+        .map_err(failed)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "reply_error",
+            |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| {
+                let error = read_string(&caller, ptr, len)?;
+                caller.data_mut().reply(Response::Error(error))
+            },
+        )
+        .map_err(failed)?;
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "reply_null",
+            |mut caller: Caller<'_, HostState>| caller.data_mut().reply(Response::Nil),
+        )
+        .map_err(failed)?;
+    // The next `len` replies are the items of an array
+    linker
+        .func_wrap(
+            HOST_MODULE,
+            "reply_array",
+            |mut caller: Caller<'_, HostState>, len: i32| {
+                let state = caller.data_mut();
+                match len {
+                    0 => state.reply(Response::Array(vec![])),
+                    len if len > 0 => {
+                        state.replies.push((len as usize, vec![]));
+                        Ok(())
+                    }
+                    _ => Err(wasmi::Error::new(
+                        "the length of an array can not be negative",
+                    )),
+                }
+            },
+        )
+        .map_err(failed)?;
+    Ok(linker)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const IMPORTS: &str = r#"
+  (import "rcache" "set_name" (func $set_name (param i32 i32)))
+  (import "rcache" "register_command" (func $register (param i32 i32 i32 i32 i32 i32)))
+  (import "rcache" "get" (func $get (param i32 i32 i32 i32) (result i64)))
+  (import "rcache" "set" (func $set (param i32 i32 i32 i32)))
+  (import "rcache" "del" (func $del (param i32 i32) (result i32)))
+  (import "rcache" "reply_integer" (func $reply_integer (param i64)))
+  (import "rcache" "reply_bulk" (func $reply_bulk (param i32 i32)))
+  (import "rcache" "reply_simple" (func $reply_simple (param i32 i32)))
+  (import "rcache" "reply_null" (func $reply_null))
+  (import "rcache" "reply_array" (func $reply_array (param i32)))
+  (memory (export "memory") 1)
+  (func (export "rcache_alloc") (param i32) (result i32) (i32.const 4096))
+  ;; the pointer to the argument after the one at p
+  (func $next (param $p i32) (result i32)
+    (i32.add (i32.add (local.get $p) (i32.const 4)) (i32.load (local.get $p))))
+"#;
+
+    /// A module with the host imports, compiled from `items` and loaded from a
+    /// file named after `file`.
+    pub(crate) fn load(file: &str, items: &str) -> Result<WasmModule, anyhow::Error> {
+        let wasm = wat::parse_str(format!("(module {} {})", IMPORTS, items)).unwrap();
+        let path = std::env::temp_dir().join(format!("rcache-{}.wasm", file));
+        std::fs::write(&path, wasm).unwrap();
+        let module = WasmModule::load(path.to_str().unwrap(), 100_000, 2 * 65536);
+        std::fs::remove_file(path).unwrap();
+        module
+    }
+    fn call(
+        module: &mut WasmModule,
+        database: &mut Database,
+        args: &[&str],
+    ) -> Result<Response, anyhow::Error> {
+        let name = args[0].to_uppercase();
+        let command = module
+            .commands
+            .iter()
+            .find(|command| command.name == name)
+            .unwrap()
+            .clone();
+        let parser =
+            ParsedCommand::from_args(args.iter().map(|arg| arg.as_bytes().to_vec()).collect());
+        module.call(database, 0, &command, &parser)
+    }
+
+    const COMMANDS: &str = r#"
+  (data (i32.const 0) "test")
+  (data (i32.const 16) "test.echo")
+  (data (i32.const 32) "echo")
+  (data (i32.const 48) "test.copy")
+  (data (i32.const 64) "copy")
+  (data (i32.const 80) "test.del")
+  (data (i32.const 96) "del")
+  (data (i32.const 112) "test.arr")
+  (data (i32.const 128) "arr")
+  (data (i32.const 144) "x")
+  (func (export "rcache_init")
+    (call $set_name (i32.const 0) (i32.const 4))
+    (call $register (i32.const 16) (i32.const 9) (i32.const 32) (i32.const 4) (i32.const 2) (i32.const 0))
+    (call $register (i32.const 48) (i32.const 9) (i32.const 64) (i32.const 4) (i32.const 3) (i32.const 1))
+    (call $register (i32.const 80) (i32.const 8) (i32.const 96) (i32.const 3) (i32.const 2) (i32.const 1))
+    (call $register (i32.const 112) (i32.const 8) (i32.const 128) (i32.const 3) (i32.const 1) (i32.const 0)))
+  (func (export "echo") (param $p i32) (param $len i32) (param $argc i32)
+    (local $a i32)
+    (local.set $a (call $next (local.get $p)))
+    (call $reply_bulk (i32.add (local.get $a) (i32.const 4)) (i32.load (local.get $a))))
+  (func (export "copy") (param $p i32) (param $len i32) (param $argc i32)
+    (local $a i32) (local $b i32) (local $n i64)
+    (local.set $a (call $next (local.get $p)))
+    (local.set $b (call $next (local.get $a)))
+    (local.set $n (call $get (i32.add (local.get $a) (i32.const 4)) (i32.load (local.get $a)) (i32.const 8192) (i32.const 1024)))
+    (if (i64.lt_s (local.get $n) (i64.const 0))
+      (then (call $reply_null) (return)))
+    (call $set (i32.add (local.get $b) (i32.const 4)) (i32.load (local.get $b)) (i32.const 8192) (i32.wrap_i64 (local.get $n)))
+    (call $reply_integer (local.get $n)))
+  (func (export "del") (param $p i32) (param $len i32) (param $argc i32)
+    (local $a i32)
+    (local.set $a (call $next (local.get $p)))
+    (call $reply_integer (i64.extend_i32_s (call $del (i32.add (local.get $a) (i32.const 4)) (i32.load (local.get $a))))))
+  (func (export "arr") (param i32 i32 i32)
+    (call $reply_array (i32.const 2))
+    (call $reply_integer (i64.const 1))
+    (call $reply_array (i32.const 1))
+    (call $reply_simple (i32.const 144) (i32.const 1)))
+"#;
+
+    #[test]
+    fn init_registers_the_commands() {
+        let module = load("init", COMMANDS).unwrap();
+        assert_eq!(module.name, "test");
+        let commands: Vec<_> = module
+            .commands
+            .iter()
+            .map(|c| (c.name.as_str(), c.export.as_str(), c.arity, c.write))
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                ("TEST.ECHO", "echo", 2, false),
+                ("TEST.COPY", "copy", 3, true),
+                ("TEST.DEL", "del", 2, true),
+                ("TEST.ARR", "arr", 1, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn commands_read_and_write_the_keys() {
+        let mut module = load("keys", COMMANDS).unwrap();
+        let mut database = Database::new();
+        let data = |text: &str| Response::Data(text.as_bytes().to_vec());
+        assert_eq!(
+            call(&mut module, &mut database, &["test.echo", "hello"]).unwrap(),
+            data("hello")
+        );
+        assert_eq!(
+            call(&mut module, &mut database, &["test.copy", "a", "b"]).unwrap(),
+            Response::Nil
+        );
+
+        database
+            .insert(
+                0,
+                b"a".to_vec(),
+                Value::String(ValueString {
+                    data: b"value".to_vec(),
+                }),
+            )
+            .unwrap();
+        assert_eq!(
+            call(&mut module, &mut database, &["test.copy", "a", "b"]).unwrap(),
+            Response::Integer(5)
+        );
+        assert_eq!(
+            database.get_string(0, b"b").unwrap().unwrap().data,
+            b"value"
+        );
+        assert_eq!(
+            call(&mut module, &mut database, &["test.del", "b"]).unwrap(),
+            Response::Integer(1)
+        );
+        assert_eq!(
+            call(&mut module, &mut database, &["test.del", "b"]).unwrap(),
+            Response::Integer(0)
+        );
+    }
+
+    #[test]
+    fn nested_array_replies() {
+        let mut module = load("arrays", COMMANDS).unwrap();
+        assert_eq!(
+            call(&mut module, &mut Database::new(), &["test.arr"]).unwrap(),
+            Response::Array(vec![
+                Response::Integer(1),
+                Response::Array(vec![Response::Status(String::from("x"))]),
+            ])
+        );
+    }
+
+    #[test]
+    fn replies_must_be_complete() {
+        let mut module = load(
+            "replies",
+            r#"
+  (data (i32.const 0) "none") (data (i32.const 16) "twice") (data (i32.const 32) "half")
+  (func (export "rcache_init")
+    (call $register (i32.const 0) (i32.const 4) (i32.const 0) (i32.const 4) (i32.const 1) (i32.const 0))
+    (call $register (i32.const 16) (i32.const 5) (i32.const 16) (i32.const 5) (i32.const 1) (i32.const 0))
+    (call $register (i32.const 32) (i32.const 4) (i32.const 32) (i32.const 4) (i32.const 1) (i32.const 0)))
+  (func (export "none") (param i32 i32 i32))
+  (func (export "twice") (param i32 i32 i32)
+    (call $reply_null) (call $reply_null))
+  (func (export "half") (param i32 i32 i32)
+    (call $reply_array (i32.const 2)) (call $reply_null))
+"#,
+        )
+        .unwrap();
+        assert_eq!(module.name, "rcache-replies");
+        let mut database = Database::new();
+        database
+            .insert(
+                0,
+                b"a".to_vec(),
+                Value::String(ValueString { data: vec![] }),
+            )
+            .unwrap();
+        let mut error = |name: &str| {
+            call(&mut module, &mut database, &[name])
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(error("none"), "the command none did not reply");
+        assert!(error("twice").contains("the command already replied"));
+        assert_eq!(
+            error("half"),
+            "the command half did not complete its array reply"
+        );
+        // The database is given back when a command fails
+        assert!(database.get_string(0, b"a").unwrap().is_some());
+    }
+
+    #[test]
+    fn fuel_and_memory_are_limited() {
+        let mut module = load(
+            "limits",
+            r#"
+  (data (i32.const 0) "loop") (data (i32.const 16) "grow")
+  (func (export "rcache_init")
+    (call $register (i32.const 0) (i32.const 4) (i32.const 0) (i32.const 4) (i32.const 1) (i32.const 0))
+    (call $register (i32.const 16) (i32.const 4) (i32.const 16) (i32.const 4) (i32.const 2) (i32.const 0)))
+  (func (export "loop") (param i32 i32 i32)
+    (loop $l (br $l)))
+  (func (export "grow") (param $p i32) (param $len i32) (param $argc i32)
+    (local $a i32)
+    (local.set $a (call $next (local.get $p)))
+    (call $reply_integer (i64.extend_i32_s (memory.grow (i32.load8_u (i32.add (local.get $a) (i32.const 4)))))))
+"#,
+        )
+        .unwrap();
+        let mut database = Database::new();
+        let error = call(&mut module, &mut database, &["loop"]).unwrap_err();
+        assert_eq!(error.to_string(), "the module command ran out of fuel");
+        // The fuel is refilled for each command, the memory can grow by one page
+        // ("\x01") but not by two more ("\x02")
+        assert_eq!(
+            call(&mut module, &mut database, &["grow", "\x01"]).unwrap(),
+            Response::Integer(1)
+        );
+        assert_eq!(
+            call(&mut module, &mut database, &["grow", "\x02"]).unwrap(),
+            Response::Integer(-1)
+        );
+    }
+
+    #[test]
+    fn load_errors() {
+        let error = |file: &str, items: &str| load(file, items).err().unwrap().to_string();
+        assert_eq!(
+            error("no-init", ""),
+            "the module does not export rcache_init"
+        );
+        assert_eq!(
+            error("no-commands", r#"(func (export "rcache_init"))"#),
+            "the module did not register any command"
+        );
+        assert_eq!(
+            error(
+                "no-export",
+                r#"(data (i32.const 0) "cmd")
+  (func (export "rcache_init")
+    (call $register (i32.const 0) (i32.const 3) (i32.const 0) (i32.const 3) (i32.const 1) (i32.const 0)))"#
+            ),
+            "the module does not export cmd(i32, i32, i32) for cmd"
+        );
+        assert!(error(
+            "keys-in-init",
+            r#"(func (export "rcache_init") (drop (call $del (i32.const 0) (i32.const 1))))"#
+        )
+        .contains("the keys can only be used by a command"));
+        assert!(WasmModule::load("/nonexistent/module.wasm", 1, 1)
+            .err()
+            .unwrap()
+            .to_string()
+            .starts_with("Error loading the extension '/nonexistent/module.wasm'"));
+    }
+}
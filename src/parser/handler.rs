@@ -1,24 +1,115 @@
+use crate::command::bitmap_command::{
+    bitcount, bitfield, bitfield_ro, bitop, bitpos, getbit, setbit,
+};
+use crate::command::bloom_command::{bf_add, bf_exists, bf_madd, bf_mexists, bf_reserve};
+use crate::command::client_command::{client, hello};
+use crate::command::config_command::config;
+use crate::command::count_min_sketch_command::{
+    cms_incrby, cms_initbydim, cms_initbyprob, cms_merge, cms_query,
+};
+use crate::command::cuckoo_command::{cf_add, cf_del, cf_exists, cf_reserve};
+use crate::command::function_command::function;
+use crate::command::geo_command::{geoadd, geodist, geohash, geopos, geosearch, geosearchstore};
 use crate::command::hash_command::hset;
+use crate::command::hyperloglog_command::{pfadd, pfcount, pfmerge};
+use crate::command::json_command::{
+    json_arrappend, json_arrpop, json_del, json_get, json_mget, json_numincrby, json_objkeys,
+    json_set, json_strappend, json_type,
+};
 use crate::command::list_command::{lpop, lpush, lrange, rpop, rpush};
+use crate::command::module_command::{module, module_command};
+use crate::command::pubsub_command::{
+    publish, pubsub, spublish, subscribe, subscribed_ping, unsubscribe, SUBSCRIBER_COMMANDS,
+};
+use crate::command::script_command::{parse_eval, parse_fcall, run_script, script};
+use crate::command::search_command::{ft_aggregate, ft_create, ft_dropindex, ft_search};
+use crate::command::server_command::{flushall, flushdb, info};
 use crate::command::set_command::sadd;
-use crate::command::sorted_set_command::zadd;
-use crate::command::string_command::{get, incr, set};
-use crate::database::lib::DatabaseHolder;
+use crate::command::sorted_set_command::{
+    bzmpop, bzpopmax, bzpopmin, zadd, zcard, zdiff, zdiffstore, zincrby, zinter, zintercard,
+    zinterstore, zmpop, zpopmax, zpopmin, zrandmember, zrem, zremrangebylex, zremrangebyscore,
+    zunion, zunionstore,
+};
+use crate::command::stream_command::{
+    xack, xadd, xautoclaim, xclaim, xdel, xgroup, xinfo, xlen, xpending, xrange, xread, xreadgroup,
+    xrevrange, xtrim,
+};
+use crate::command::string_command::{get, getrange, incr, set, setrange};
+use crate::command::throttle_command::cl_throttle;
+use crate::command::timeseries_command::{
+    ts_add, ts_create, ts_createrule, ts_madd, ts_mrange, ts_range, ts_revrange,
+};
+use crate::command::topk_command::{topk_add, topk_incrby, topk_list, topk_query, topk_reserve};
+use crate::command::vector_set_command::{vadd, vcard, vdim, vemb, vgetattr, vrem, vsetattr, vsim};
+use crate::database::lib::{Database, DatabaseHolder};
+use crate::database::pubsub::Subscription;
+use crate::database::pubsub::SubscriptionKind::{Channel, Pattern, Shard};
+use crate::database::tracking::CURRENT_CLIENT;
+use crate::database::watch::WatchGuard;
 use crate::parser::ping::ping;
 use crate::parser::request::Request;
 use crate::parser::response::Response;
+use crate::util::command_table::{check_arity, read_only_keys};
+use crate::vojo::parsered_command::ParsedCommand;
+use anyhow::ensure;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio::task;
 
 pub struct Handler {
     pub connect: TcpStream,
     pub database_holder: DatabaseHolder,
+    pub subscription: Subscription,
+    /// 2 or 3, the version of the protocol chosen with HELLO.
+    pub protocol: u8,
+    transaction: Option<Transaction>,
+    watch: Option<WatchGuard>,
+}
+/// The commands queued since MULTI.
+#[derive(Default)]
+struct Transaction {
+    commands: Vec<(String, ParsedCommand)>,
+    /// A command was refused, EXEC fails.
+    aborted: bool,
 }
 
 impl Handler {
+    pub fn new(connect: TcpStream, database_holder: DatabaseHolder) -> Self {
+        let subscription = Subscription::new(database_holder.pubsub.clone());
+        // The connection is known by the ID of its subscription
+        database_holder
+            .tracking
+            .connect(subscription.id, subscription.sender());
+        Handler {
+            connect,
+            database_holder,
+            subscription,
+            protocol: 2,
+            transaction: None,
+            watch: None,
+        }
+    }
     pub async fn run(&mut self) -> Result<(), anyhow::Error> {
+        let client_id = self.subscription.id;
+        CURRENT_CLIENT.scope(client_id, self.serve()).await
+    }
+    /// Reads and runs one command, or writes a pushed message.
+    async fn serve(&mut self) -> Result<(), anyhow::Error> {
         let mut buf = vec![0u8; 1024];
-        let parsed_command = match self.connect.read(&mut buf).await {
+        // Messages published to the subscribed channels are written between commands
+        let read = tokio::select! {
+            read = self.connect.read(&mut buf) => read,
+            Some(message) = self.subscription.receiver.recv() => {
+                let message = out_of_band(message, self.protocol);
+                self.connect.write_all(&message.as_bytes()).await?;
+                return Ok(());
+            }
+        };
+        let parsed_command = match read {
             Ok(0) => {
                 info!("Connection closed by client");
                 return Err(anyhow!(""));
@@ -32,10 +123,83 @@ impl Handler {
                 return Err(anyhow!(""));
             }
         };
+        let command_name = parsed_command.get_str(0)?.to_uppercase();
+        // RESP3 connections can run any command while subscribed
+        if self.subscription.is_active()
+            && self.protocol == 2
+            && !SUBSCRIBER_COMMANDS.contains(&command_name.as_str())
+        {
+            let error = Response::Error(format!(
+                "Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING are allowed in this context",
+                command_name.to_lowercase()
+            ));
+            self.connect.write_all(&error.as_bytes()).await?;
+            return Ok(());
+        }
+        let subscription = &mut self.subscription;
+        let replies = match command_name.as_str() {
+            _ if self.transaction.is_some() => None,
+            "SUBSCRIBE" => Some(subscribe(&parsed_command, subscription, Channel)),
+            "PSUBSCRIBE" => Some(subscribe(&parsed_command, subscription, Pattern)),
+            "SSUBSCRIBE" => Some(subscribe(&parsed_command, subscription, Shard)),
+            "UNSUBSCRIBE" => Some(unsubscribe(&parsed_command, subscription, Channel)),
+            "PUNSUBSCRIBE" => Some(unsubscribe(&parsed_command, subscription, Pattern)),
+            "SUNSUBSCRIBE" => Some(unsubscribe(&parsed_command, subscription, Shard)),
+            _ => None,
+        };
+        if let Some(replies) = replies {
+            let replies = replies.unwrap_or_else(|e| vec![Response::Error(e.to_string())]);
+            for reply in replies {
+                let reply = out_of_band(reply, self.protocol);
+                self.connect.write_all(&reply.as_bytes()).await?;
+            }
+            return Ok(());
+        }
+        let scripts = self.database_holder.scripts.clone();
+        let data = match command_name.as_str() {
+            "MULTI" => self.multi(&parsed_command),
+            // Like a script, the transaction holds the database lock until it ends
+            "EXEC" => match scripts.claim().await {
+                Ok(claimed) => task::block_in_place(|| {
+                    claimed.wait_for_entered();
+                    self.exec(&parsed_command)
+                }),
+                Err(e) => Response::Error(e.to_string()),
+            },
+            "DISCARD" => self.discard(&parsed_command),
+            "WATCH" => self.watch(&parsed_command),
+            _ if self.transaction.is_some() => self.queue(command_name, parsed_command),
+            _ if waits_for_scripts(&command_name, &parsed_command) => match scripts.enter().await {
+                Ok(_entered) => self.dispatch(&command_name, parsed_command).await,
+                Err(e) => Response::Error(e.to_string()),
+            },
+            _ => self.dispatch(&command_name, parsed_command).await,
+        };
+        self.connect.write_all(&data.as_bytes()).await?;
+        Ok(())
+    }
+    /// Runs one command, either read from the socket or queued in a transaction.
+    async fn dispatch(&mut self, command_name: &str, parsed_command: ParsedCommand) -> Response {
         let db_index = 0;
+        let client_id = self.subscription.id;
+        let read_keys = read_only_keys(command_name, &parsed_command);
+        // CLIENT CACHING applies to the command after it
+        let caching = command_name == "CLIENT"
+            && parsed_command
+                .get_str(1)
+                .is_ok_and(|subcommand| subcommand.eq_ignore_ascii_case("CACHING"));
+        // The keys are tracked before they are read, a write from another client
+        // once they are read always sends an invalidation
+        if !caching {
+            self.database_holder
+                .tracking
+                .before_command(client_id, read_keys);
+        }
         let database_holder = &mut self.database_holder;
-        let command_name = parsed_command.get_str(0)?.to_uppercase();
-        let result = match command_name.as_str() {
+        let result = match command_name {
+            "PING" if self.subscription.is_active() && self.protocol == 2 => {
+                subscribed_ping(parsed_command)
+            }
             "PING" => ping(parsed_command),
             "SET" => set(parsed_command, database_holder, db_index),
             "GET" => get(parsed_command, database_holder, db_index),
@@ -46,13 +210,176 @@ impl Handler {
             "SADD" => sadd(parsed_command, database_holder, db_index),
             "HSET" => hset(parsed_command, database_holder, db_index),
             "ZADD" => zadd(parsed_command, database_holder, db_index),
+            "ZINCRBY" => zincrby(parsed_command, database_holder, db_index),
+            "ZREM" => zrem(parsed_command, database_holder, db_index),
+            "ZREMRANGEBYSCORE" => zremrangebyscore(parsed_command, database_holder, db_index),
+            "ZREMRANGEBYLEX" => zremrangebylex(parsed_command, database_holder, db_index),
+            "ZPOPMIN" => zpopmin(parsed_command, database_holder, db_index),
+            "ZPOPMAX" => zpopmax(parsed_command, database_holder, db_index),
+            "ZMPOP" => zmpop(parsed_command, database_holder, db_index),
+            "ZRANDMEMBER" => zrandmember(parsed_command, database_holder, db_index),
+            "ZCARD" => zcard(parsed_command, database_holder, db_index),
+            "ZUNION" => zunion(parsed_command, database_holder, db_index),
+            "ZINTER" => zinter(parsed_command, database_holder, db_index),
+            "ZDIFF" => zdiff(parsed_command, database_holder, db_index),
+            "ZUNIONSTORE" => zunionstore(parsed_command, database_holder, db_index),
+            "ZINTERSTORE" => zinterstore(parsed_command, database_holder, db_index),
+            "ZDIFFSTORE" => zdiffstore(parsed_command, database_holder, db_index),
+            "ZINTERCARD" => zintercard(parsed_command, database_holder, db_index),
+            "BZPOPMIN" => {
+                until_closed(
+                    &self.connect,
+                    bzpopmin(parsed_command, database_holder, db_index),
+                )
+                .await
+            }
+            "BZPOPMAX" => {
+                until_closed(
+                    &self.connect,
+                    bzpopmax(parsed_command, database_holder, db_index),
+                )
+                .await
+            }
+            "BZMPOP" => {
+                until_closed(
+                    &self.connect,
+                    bzmpop(parsed_command, database_holder, db_index),
+                )
+                .await
+            }
             "LRANGE" => lrange(parsed_command, database_holder, db_index),
             "INCR" => incr(parsed_command, database_holder, db_index),
-
-            _ => {
-                info!("{}", command_name);
-                Ok(Response::Nil)
+            "SETRANGE" => setrange(parsed_command, database_holder, db_index),
+            "GETRANGE" => getrange(parsed_command, database_holder, db_index),
+            "SETBIT" => setbit(parsed_command, database_holder, db_index),
+            "GETBIT" => getbit(parsed_command, database_holder, db_index),
+            "BITCOUNT" => bitcount(parsed_command, database_holder, db_index),
+            "BITPOS" => bitpos(parsed_command, database_holder, db_index),
+            "BITOP" => bitop(parsed_command, database_holder, db_index),
+            "BITFIELD" => bitfield(parsed_command, database_holder, db_index),
+            "BITFIELD_RO" => bitfield_ro(parsed_command, database_holder, db_index),
+            "PFADD" => pfadd(parsed_command, database_holder, db_index),
+            "PFCOUNT" => pfcount(parsed_command, database_holder, db_index),
+            "PFMERGE" => pfmerge(parsed_command, database_holder, db_index),
+            "GEOADD" => geoadd(parsed_command, database_holder, db_index),
+            "GEOPOS" => geopos(parsed_command, database_holder, db_index),
+            "GEODIST" => geodist(parsed_command, database_holder, db_index),
+            "GEOHASH" => geohash(parsed_command, database_holder, db_index),
+            "GEOSEARCH" => geosearch(parsed_command, database_holder, db_index),
+            "GEOSEARCHSTORE" => geosearchstore(parsed_command, database_holder, db_index),
+            "XADD" => xadd(parsed_command, database_holder, db_index),
+            "XRANGE" => xrange(parsed_command, database_holder, db_index),
+            "XREVRANGE" => xrevrange(parsed_command, database_holder, db_index),
+            "XLEN" => xlen(parsed_command, database_holder, db_index),
+            "XDEL" => xdel(parsed_command, database_holder, db_index),
+            "XTRIM" => xtrim(parsed_command, database_holder, db_index),
+            "XREAD" => {
+                until_closed(
+                    &self.connect,
+                    xread(parsed_command, database_holder, db_index),
+                )
+                .await
             }
+            "XREADGROUP" => {
+                until_closed(
+                    &self.connect,
+                    xreadgroup(parsed_command, database_holder, db_index),
+                )
+                .await
+            }
+            "XGROUP" => xgroup(parsed_command, database_holder, db_index),
+            "XACK" => xack(parsed_command, database_holder, db_index),
+            "XPENDING" => xpending(parsed_command, database_holder, db_index),
+            "XCLAIM" => xclaim(parsed_command, database_holder, db_index),
+            "XAUTOCLAIM" => xautoclaim(parsed_command, database_holder, db_index),
+            "XINFO" => xinfo(parsed_command, database_holder, db_index),
+            "JSON.SET" => json_set(parsed_command, database_holder, db_index),
+            "JSON.GET" => json_get(parsed_command, database_holder, db_index),
+            "JSON.MGET" => json_mget(parsed_command, database_holder, db_index),
+            "JSON.DEL" | "JSON.FORGET" => json_del(parsed_command, database_holder, db_index),
+            "JSON.NUMINCRBY" => json_numincrby(parsed_command, database_holder, db_index),
+            "JSON.STRAPPEND" => json_strappend(parsed_command, database_holder, db_index),
+            "JSON.ARRAPPEND" => json_arrappend(parsed_command, database_holder, db_index),
+            "JSON.ARRPOP" => json_arrpop(parsed_command, database_holder, db_index),
+            "JSON.OBJKEYS" => json_objkeys(parsed_command, database_holder, db_index),
+            "JSON.TYPE" => json_type(parsed_command, database_holder, db_index),
+            "BF.RESERVE" => bf_reserve(parsed_command, database_holder, db_index),
+            "BF.ADD" => bf_add(parsed_command, database_holder, db_index),
+            "BF.MADD" => bf_madd(parsed_command, database_holder, db_index),
+            "BF.EXISTS" => bf_exists(parsed_command, database_holder, db_index),
+            "BF.MEXISTS" => bf_mexists(parsed_command, database_holder, db_index),
+            "CF.RESERVE" => cf_reserve(parsed_command, database_holder, db_index),
+            "CF.ADD" => cf_add(parsed_command, database_holder, db_index),
+            "CF.EXISTS" => cf_exists(parsed_command, database_holder, db_index),
+            "CF.DEL" => cf_del(parsed_command, database_holder, db_index),
+            "CMS.INITBYDIM" => cms_initbydim(parsed_command, database_holder, db_index),
+            "CMS.INITBYPROB" => cms_initbyprob(parsed_command, database_holder, db_index),
+            "CMS.INCRBY" => cms_incrby(parsed_command, database_holder, db_index),
+            "CMS.QUERY" => cms_query(parsed_command, database_holder, db_index),
+            "CMS.MERGE" => cms_merge(parsed_command, database_holder, db_index),
+            "TOPK.RESERVE" => topk_reserve(parsed_command, database_holder, db_index),
+            "TOPK.ADD" => topk_add(parsed_command, database_holder, db_index),
+            "TOPK.INCRBY" => topk_incrby(parsed_command, database_holder, db_index),
+            "TOPK.LIST" => topk_list(parsed_command, database_holder, db_index),
+            "TOPK.QUERY" => topk_query(parsed_command, database_holder, db_index),
+            "TS.CREATE" => ts_create(parsed_command, database_holder, db_index),
+            "TS.ADD" => ts_add(parsed_command, database_holder, db_index),
+            "TS.MADD" => ts_madd(parsed_command, database_holder, db_index),
+            "TS.RANGE" => ts_range(parsed_command, database_holder, db_index),
+            "TS.REVRANGE" => ts_revrange(parsed_command, database_holder, db_index),
+            "TS.MRANGE" => ts_mrange(parsed_command, database_holder, db_index),
+            "TS.CREATERULE" => ts_createrule(parsed_command, database_holder, db_index),
+            "VADD" => vadd(parsed_command, database_holder, db_index),
+            "VSIM" => vsim(parsed_command, database_holder, db_index),
+            "VREM" => vrem(parsed_command, database_holder, db_index),
+            "VCARD" => vcard(parsed_command, database_holder, db_index),
+            "VDIM" => vdim(parsed_command, database_holder, db_index),
+            "VEMB" => vemb(parsed_command, database_holder, db_index),
+            "VSETATTR" => vsetattr(parsed_command, database_holder, db_index),
+            "VGETATTR" => vgetattr(parsed_command, database_holder, db_index),
+            "FT.CREATE" => ft_create(parsed_command, database_holder, db_index),
+            "FT.SEARCH" => ft_search(parsed_command, database_holder, db_index),
+            "FT.AGGREGATE" => ft_aggregate(parsed_command, database_holder, db_index),
+            "FT.DROPINDEX" => ft_dropindex(parsed_command, database_holder, db_index),
+            "CL.THROTTLE" => cl_throttle(parsed_command, database_holder, db_index),
+            "PUBLISH" => publish(parsed_command, database_holder, db_index),
+            "PUBSUB" => pubsub(parsed_command, database_holder, db_index),
+            "SPUBLISH" => spublish(parsed_command, database_holder, db_index),
+            "CONFIG" => config(parsed_command, database_holder, db_index),
+            "CLIENT" => client(parsed_command, database_holder, client_id),
+            "HELLO" => hello(
+                &parsed_command,
+                database_holder,
+                client_id,
+                &mut self.protocol,
+            ),
+            "EVAL" | "EVALSHA" | "EVAL_RO" | "EVALSHA_RO" | "FCALL" | "FCALL_RO" => {
+                self.eval(command_name, &parsed_command).await
+            }
+            "SCRIPT" => script(parsed_command, database_holder, db_index),
+            "FUNCTION" if is_function_load(&parsed_command) => {
+                self.claimed(|handler| {
+                    function(parsed_command, &mut handler.database_holder, db_index)
+                })
+                .await
+            }
+            "FUNCTION" => function(parsed_command, database_holder, db_index),
+            "UNWATCH" => {
+                self.watch = None;
+                Ok(Response::Status(String::from("OK")))
+            }
+            "FLUSHDB" => flushdb(parsed_command, database_holder, db_index),
+            "FLUSHALL" => flushall(parsed_command, database_holder, db_index),
+            "MODULE" => module(parsed_command, database_holder, db_index),
+            "INFO" => info(parsed_command, database_holder, db_index),
+
+            _ => match module_command(command_name, parsed_command, database_holder, db_index) {
+                Some(result) => result,
+                None => {
+                    info!("{}", command_name);
+                    Ok(Response::Nil)
+                }
+            },
         };
         let data = match result {
             Ok(r) => r,
@@ -61,7 +388,962 @@ impl Handler {
                 Response::Error(r.to_string())
             }
         };
-        self.connect.write_all(&data.as_bytes()).await?;
-        Ok(())
+        data
+    }
+    fn multi(&mut self, parsed_command: &ParsedCommand) -> Response {
+        if let Err(e) = check_arity("MULTI", parsed_command) {
+            return Response::Error(e.to_string());
+        }
+        if self.transaction.is_some() {
+            return Response::Error(String::from("MULTI calls can not be nested"));
+        }
+        self.transaction = Some(Transaction::default());
+        Response::Status(String::from("OK"))
+    }
+    /// Queues a command of the transaction. A command that can not run, e.g. with
+    /// the wrong number of arguments, makes EXEC discard the transaction.
+    fn queue(&mut self, command_name: String, parsed_command: ParsedCommand) -> Response {
+        let Some(transaction) = self.transaction.as_mut() else {
+            return Response::Error(String::from("EXEC without MULTI"));
+        };
+        let checked = check_arity(&command_name, &parsed_command).and_then(|_| {
+            ensure!(
+                !SUBSCRIBER_COMMANDS.contains(&command_name.as_str()) || command_name == "PING",
+                "Command not allowed inside a transaction"
+            );
+            Ok(())
+        });
+        match checked {
+            Ok(()) => {
+                transaction.commands.push((command_name, parsed_command));
+                Response::Status(String::from("QUEUED"))
+            }
+            Err(e) => {
+                transaction.aborted = true;
+                Response::Error(e.to_string())
+            }
+        }
+    }
+    /// Runs the queued commands while holding the database lock, so that no other
+    /// client sees the dataset between two of them. Fails when a watched key changed.
+    fn exec(&mut self, parsed_command: &ParsedCommand) -> Response {
+        if let Err(e) = check_arity("EXEC", parsed_command) {
+            return Response::Error(e.to_string());
+        }
+        let Some(transaction) = self.transaction.take() else {
+            return Response::Error(String::from("EXEC without MULTI"));
+        };
+        let watch = self.watch.take();
+        if transaction.aborted {
+            return Response::Error(String::from(
+                "EXECABORT Transaction discarded because of previous errors.",
+            ));
+        }
+        let result = self.atomically(|handler| {
+            if watch.is_some_and(|watch| watch.is_dirty()) {
+                return Response::Nil;
+            }
+            let responses = transaction
+                .commands
+                .into_iter()
+                .map(|(command_name, parsed_command)| {
+                    handler.dispatch_now(&command_name, parsed_command)
+                })
+                .collect();
+            Response::Array(responses)
+        });
+        result.unwrap_or_else(|e| Response::Error(e.to_string()))
+    }
+    /// EVAL, FCALL and their variants, the commands of the script go through `dispatch`.
+    async fn eval(
+        &mut self,
+        command_name: &str,
+        parsed_command: &ParsedCommand,
+    ) -> Result<Response, anyhow::Error> {
+        let scripts = self.database_holder.scripts.clone();
+        let run = |handler: &mut Self| {
+            let script = match command_name {
+                "FCALL" | "FCALL_RO" => {
+                    let db = handler
+                        .database_holder
+                        .database_lock
+                        .lock()
+                        .map_err(|e| anyhow!("{}", e))?;
+                    parse_fcall(command_name, parsed_command, &db.functions)?
+                }
+                _ => parse_eval(command_name, parsed_command, &scripts)?,
+            };
+            handler.atomically(|handler| {
+                run_script(&scripts, script, |command_name, parsed_command| {
+                    handler.dispatch_now(command_name, parsed_command)
+                })
+            })?
+        };
+        self.claimed(run).await
+    }
+    /// Runs Lua code, a script or the library given to FUNCTION LOAD, with the
+    /// database claimed.
+    async fn claimed<T>(
+        &mut self,
+        run: impl FnOnce(&mut Self) -> Result<T, anyhow::Error>,
+    ) -> Result<T, anyhow::Error> {
+        // A script run by a transaction is already off the worker threads
+        if self.database_holder.non_blocking {
+            return run(self);
+        }
+        let scripts = self.database_holder.scripts.clone();
+        let claimed = scripts.claim().await?;
+        // The worker thread is handed over, so that the other connections
+        // are still served and can SCRIPT KILL a long script
+        task::block_in_place(|| {
+            claimed.wait_for_entered();
+            run(self)
+        })
+    }
+    /// Runs `f` with the database moved into a holder of its own while the shared
+    /// lock stays held, no other client sees the dataset before `f` returns.
+    fn atomically<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> Result<T, anyhow::Error> {
+        let database_lock = self.database_holder.database_lock.clone();
+        let mut guard = database_lock.lock().map_err(|e| anyhow!("{}", e))?;
+        let private_lock = Arc::new(Mutex::new(std::mem::take(&mut *guard)));
+        // Dropped last, also when `f` panics
+        let _restore = RestoreDatabase {
+            guard,
+            private_lock: private_lock.clone(),
+        };
+        let private_holder = DatabaseHolder {
+            database_lock: private_lock,
+            non_blocking: true,
+            ..self.database_holder.clone()
+        };
+        let holder = std::mem::replace(&mut self.database_holder, private_holder);
+        let result = f(self);
+        self.database_holder = holder;
+        Ok(result)
+    }
+    /// Runs a command of a transaction or a script, where the blocking commands
+    /// do not wait.
+    fn dispatch_now(&mut self, command_name: &str, parsed_command: ParsedCommand) -> Response {
+        poll_once(self.dispatch(command_name, parsed_command)).unwrap_or(Response::Error(
+            String::from("command can not block in a transaction"),
+        ))
+    }
+    fn discard(&mut self, parsed_command: &ParsedCommand) -> Response {
+        if let Err(e) = check_arity("DISCARD", parsed_command) {
+            return Response::Error(e.to_string());
+        }
+        if self.transaction.take().is_none() {
+            return Response::Error(String::from("DISCARD without MULTI"));
+        }
+        self.watch = None;
+        Response::Status(String::from("OK"))
+    }
+    fn watch(&mut self, parsed_command: &ParsedCommand) -> Response {
+        if let Err(e) = check_arity("WATCH", parsed_command) {
+            return Response::Error(e.to_string());
+        }
+        if self.transaction.is_some() {
+            return Response::Error(String::from("WATCH inside MULTI is not allowed"));
+        }
+        let watched_keys = self.database_holder.watched_keys.clone();
+        let watch = self
+            .watch
+            .get_or_insert_with(|| WatchGuard::new(watched_keys));
+        for i in 1..parsed_command.argv.len() {
+            if let Ok(key) = parsed_command.get_vec(i) {
+                watch.watch(0, key);
+            }
+        }
+        Response::Status(String::from("OK"))
+    }
+}
+/// Moves the dataset of a transaction or a script back behind the shared lock
+/// when dropped, so that it is not lost when a command panics.
+struct RestoreDatabase<'a> {
+    guard: MutexGuard<'a, Database>,
+    private_lock: Arc<Mutex<Database>>,
+}
+impl Drop for RestoreDatabase<'_> {
+    fn drop(&mut self) {
+        let mut database = self.private_lock.lock().unwrap_or_else(|e| e.into_inner());
+        *self.guard = std::mem::take(&mut *database);
+    }
+}
+impl Drop for Handler {
+    fn drop(&mut self) {
+        self.database_holder
+            .tracking
+            .disconnect(self.subscription.id);
+    }
+}
+/// The commands which take the database lock wait while a script or a transaction
+/// holds it. Scripts, FUNCTION LOAD and blocking commands wait on their own, and
+/// SCRIPT does not take the lock so that SCRIPT KILL is served while a script runs.
+fn waits_for_scripts(command_name: &str, parsed_command: &ParsedCommand) -> bool {
+    !(command_name == "FUNCTION" && is_function_load(parsed_command)
+        || matches!(
+            command_name,
+            "EVAL"
+                | "EVALSHA"
+                | "EVAL_RO"
+                | "EVALSHA_RO"
+                | "FCALL"
+                | "FCALL_RO"
+                | "SCRIPT"
+                | "BZPOPMIN"
+                | "BZPOPMAX"
+                | "BZMPOP"
+                | "XREAD"
+                | "XREADGROUP"
+        ))
+}
+/// FUNCTION LOAD runs the code of the library like a script.
+fn is_function_load(parsed_command: &ParsedCommand) -> bool {
+    parsed_command
+        .get_str(1)
+        .is_ok_and(|subcommand| subcommand.eq_ignore_ascii_case("LOAD"))
+}
+/// Pub/Sub messages and subscription replies are pushes in RESP3.
+fn out_of_band(message: Response, protocol: u8) -> Response {
+    match message {
+        Response::Array(items) if protocol == 3 => Response::Push(items),
+        message => message,
+    }
+}
+/// Polls a command once. In a transaction the blocking commands do not wait,
+/// so the commands are always ready.
+fn poll_once<F: Future>(future: F) -> Option<F::Output> {
+    let mut context = Context::from_waker(Waker::noop());
+    match pin!(future).poll(&mut context) {
+        Poll::Ready(output) => Some(output),
+        Poll::Pending => None,
+    }
+}
+/// Runs a blocking command, giving up when the client closes the connection
+/// so that it does not consume data it will never receive.
+async fn until_closed<F>(connect: &TcpStream, command: F) -> Result<Response, anyhow::Error>
+where
+    F: Future<Output = Result<Response, anyhow::Error>>,
+{
+    let closed = async {
+        let mut buf = [0u8; 1];
+        match connect.peek(&mut buf).await {
+            Ok(0) | Err(_) => {}
+            // The client pipelined more commands, they are read after this one.
+            Ok(_) => std::future::pending::<()>().await,
+        }
+    };
+    tokio::select! {
+        res = command => res,
+        _ = closed => Err(anyhow!("Connection closed by client")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::response::Response;
+    use crate::parser::test_client::{data, serve, status, Client};
+    use crate::util::common_utils::mstime;
+    use std::time::Duration;
+
+    // A single worker, which the library must not keep while it runs
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn function_load_does_not_hold_the_database_lock() {
+        let (database_holder, addr) = serve().await;
+        database_holder.scripts.set_time_limit(0);
+        let mut loading = Client::connect(addr).await;
+        let mut other = Client::connect(addr).await;
+        let mut killer = Client::connect(addr).await;
+        loading
+            .send(&["FUNCTION", "LOAD", "#!lua name=lib\nwhile true do end"])
+            .await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // The other clients wait for the library, SCRIPT KILL is still served
+        other.send(&["SET", "k", "v"]).await;
+        assert_eq!(other.try_read(Duration::from_millis(100)).await, None);
+        let mut killed = false;
+        for _ in 0..100 {
+            if killer.cmd(&["SCRIPT", "KILL"]).await == status("OK") {
+                killed = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(killed);
+        let Response::Error(error) = loading.read().await else {
+            panic!("the library was loaded");
+        };
+        assert!(error.contains("Script killed by user"));
+        assert_eq!(other.read().await, status("OK"));
+
+        let library = "#!lua name=lib\nredis.register_function('f', function() return 'done' end)";
+        assert_eq!(
+            loading.cmd(&["FUNCTION", "LOAD", library]).await,
+            data("lib")
+        );
+        assert_eq!(loading.cmd(&["FCALL", "f", "0"]).await, data("done"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn transactions_run_the_queued_commands() {
+        let (_, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        assert_eq!(
+            client.cmd(&["EXEC"]).await,
+            Response::Error("EXEC without MULTI".to_string())
+        );
+        assert_eq!(client.cmd(&["MULTI"]).await, status("OK"));
+        assert_eq!(
+            client.cmd(&["MULTI"]).await,
+            Response::Error("MULTI calls can not be nested".to_string())
+        );
+        assert_eq!(client.cmd(&["SET", "k", "v"]).await, status("QUEUED"));
+        assert_eq!(client.cmd(&["GET", "k"]).await, status("QUEUED"));
+        assert_eq!(
+            client.cmd(&["EXEC"]).await,
+            Response::Array(vec![status("OK"), data("v")])
+        );
+
+        assert_eq!(client.cmd(&["MULTI"]).await, status("OK"));
+        assert_eq!(client.cmd(&["SET", "k", "w"]).await, status("QUEUED"));
+        assert_eq!(client.cmd(&["DISCARD"]).await, status("OK"));
+        assert_eq!(client.cmd(&["GET", "k"]).await, data("v"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_refused_command_aborts_the_transaction() {
+        let (_, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        assert_eq!(client.cmd(&["MULTI"]).await, status("OK"));
+        assert_eq!(client.cmd(&["SET", "k", "v"]).await, status("QUEUED"));
+        assert!(client.cmd(&["GET"]).await.is_error());
+        assert!(client.cmd(&["SUBSCRIBE", "channel"]).await.is_error());
+        assert_eq!(
+            client.cmd(&["EXEC"]).await,
+            Response::Error(
+                "EXECABORT Transaction discarded because of previous errors.".to_string()
+            )
+        );
+        assert_eq!(client.cmd(&["GET", "k"]).await, Response::Nil);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_modified_watched_key_aborts_the_transaction() {
+        let (_, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        let mut other = Client::connect(addr).await;
+        assert_eq!(client.cmd(&["WATCH", "k"]).await, status("OK"));
+        assert_eq!(other.cmd(&["SET", "k", "other"]).await, status("OK"));
+        assert_eq!(client.cmd(&["MULTI"]).await, status("OK"));
+        assert!(client.cmd(&["WATCH", "k"]).await.is_error());
+        assert_eq!(client.cmd(&["SET", "k", "mine"]).await, status("QUEUED"));
+        assert_eq!(client.cmd(&["EXEC"]).await, Response::Nil);
+        assert_eq!(client.cmd(&["GET", "k"]).await, data("other"));
+
+        // EXEC and UNWATCH forget the watched keys
+        assert_eq!(client.cmd(&["MULTI"]).await, status("OK"));
+        assert_eq!(client.cmd(&["SET", "k", "mine"]).await, status("QUEUED"));
+        assert_eq!(
+            client.cmd(&["EXEC"]).await,
+            Response::Array(vec![status("OK")])
+        );
+        assert_eq!(client.cmd(&["WATCH", "k"]).await, status("OK"));
+        assert_eq!(client.cmd(&["UNWATCH"]).await, status("OK"));
+        assert_eq!(other.cmd(&["SET", "k", "other"]).await, status("OK"));
+        assert_eq!(client.cmd(&["MULTI"]).await, status("OK"));
+        assert_eq!(client.cmd(&["EXEC"]).await, Response::Array(vec![]));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn commands_which_change_nothing_do_not_abort_the_transaction() {
+        let (_, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        let mut other = Client::connect(addr).await;
+        assert_eq!(
+            other.cmd(&["ZADD", "zset", "1", "a"]).await,
+            Response::Integer(1)
+        );
+        assert_eq!(other.cmd(&["SET", "string", "v"]).await, status("OK"));
+        let Response::Data(_) = other.cmd(&["XADD", "stream", "*", "f", "v"]).await else {
+            panic!("XADD replies the ID");
+        };
+        let created = other
+            .cmd(&["XGROUP", "CREATE", "stream", "group", "0"])
+            .await;
+        assert_eq!(created, status("OK"));
+
+        assert_eq!(
+            client.cmd(&["WATCH", "zset", "string", "stream"]).await,
+            status("OK")
+        );
+        assert_eq!(
+            other.cmd(&["ZREM", "zset", "missing"]).await,
+            Response::Integer(0)
+        );
+        assert!(other.cmd(&["ZREM", "string", "a"]).await.is_error());
+        let acked = other.cmd(&["XACK", "stream", "group", "0-1"]).await;
+        assert_eq!(acked, Response::Integer(0));
+        assert_eq!(client.cmd(&["MULTI"]).await, status("OK"));
+        assert_eq!(client.cmd(&["EXEC"]).await, Response::Array(vec![]));
+
+        assert_eq!(client.cmd(&["WATCH", "zset"]).await, status("OK"));
+        assert_eq!(
+            other.cmd(&["ZREM", "zset", "a"]).await,
+            Response::Integer(1)
+        );
+        assert_eq!(client.cmd(&["MULTI"]).await, status("OK"));
+        assert_eq!(client.cmd(&["EXEC"]).await, Response::Nil);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn expiry_and_flushall_abort_the_transaction() {
+        let (database_holder, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        let mut other = Client::connect(addr).await;
+        assert_eq!(client.cmd(&["SET", "k", "v"]).await, status("OK"));
+        assert_eq!(client.cmd(&["WATCH", "k"]).await, status("OK"));
+        {
+            let mut db = database_holder.database_lock.lock().unwrap();
+            db.expire_at(0, b"k".to_vec(), mstime() as i64 + 50)
+                .unwrap();
+        }
+        let expire_loop = {
+            let database_holder = database_holder.clone();
+            tokio::spawn(async move { database_holder.expire_loop().await })
+        };
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        expire_loop.abort();
+        assert_eq!(client.cmd(&["MULTI"]).await, status("OK"));
+        assert_eq!(client.cmd(&["EXEC"]).await, Response::Nil);
+
+        assert_eq!(client.cmd(&["WATCH", "missing"]).await, status("OK"));
+        assert_eq!(other.cmd(&["FLUSHALL"]).await, status("OK"));
+        assert_eq!(client.cmd(&["MULTI"]).await, status("OK"));
+        assert_eq!(client.cmd(&["EXEC"]).await, Response::Nil);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn other_clients_do_not_see_a_transaction_half_done() {
+        let (_, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        let mut reader = Client::connect(addr).await;
+        let reads = tokio::spawn(async move {
+            let mut reads = vec![];
+            for _ in 0..200 {
+                reads.push(reader.cmd(&["GET", "counter"]).await);
+            }
+            reads
+        });
+        for _ in 0..20 {
+            assert_eq!(client.cmd(&["MULTI"]).await, status("OK"));
+            for _ in 0..10 {
+                assert_eq!(client.cmd(&["INCR", "counter"]).await, status("QUEUED"));
+            }
+            let Response::Array(replies) = client.cmd(&["EXEC"]).await else {
+                panic!("the transaction was not run");
+            };
+            assert_eq!(replies.len(), 10);
+        }
+        for read in reads.await.unwrap() {
+            let Response::Data(counter) = read else {
+                assert_eq!(read, Response::Nil);
+                continue;
+            };
+            let counter: i64 = String::from_utf8(counter).unwrap().parse().unwrap();
+            assert_eq!(counter % 10, 0);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn read_only_scripts_can_not_drop_an_index() {
+        let (_, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        let created = client
+            .cmd(&[
+                "FT.CREATE",
+                "idx",
+                "PREFIX",
+                "1",
+                "doc:",
+                "SCHEMA",
+                "title",
+                "TEXT",
+            ])
+            .await;
+        assert_eq!(created, status("OK"));
+        assert_eq!(
+            client.cmd(&["HSET", "doc:1", "title", "hello"]).await,
+            Response::Integer(1)
+        );
+        for script in [
+            "return redis.call('FT.DROPINDEX', 'idx', 'DD')",
+            "return redis.call('FT.CREATE', 'other', 'SCHEMA', 'title', 'TEXT')",
+        ] {
+            let Response::Error(error) = client.cmd(&["EVAL_RO", script, "0"]).await else {
+                panic!("a read-only script ran {}", script);
+            };
+            assert!(error.contains("Write commands are not allowed from read-only scripts"));
+        }
+        assert_eq!(
+            client.cmd(&["HSET", "doc:1", "title", "hello"]).await,
+            Response::Integer(0)
+        );
+        let Response::Array(found) = client.cmd(&["FT.SEARCH", "idx", "hello"]).await else {
+            panic!("the index was dropped");
+        };
+        assert_eq!(found[0], Response::Integer(1));
+    }
+
+    /// The event and key of the next keyevent notification.
+    async fn next_event(subscriber: &mut Client) -> (String, String) {
+        let Response::Array(message) = subscriber.read().await else {
+            panic!("not a message");
+        };
+        let text = |response: &Response| match response {
+            Response::Data(data) => String::from_utf8(data.clone()).unwrap(),
+            _ => panic!("not a bulk string"),
+        };
+        let channel = text(&message[2]);
+        let event = channel.strip_prefix("__keyevent@0__:").unwrap().to_string();
+        (event, text(&message[3]))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn module_types_dropped_documents_and_expiry_are_notified() {
+        let (database_holder, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        let mut subscriber = Client::connect(addr).await;
+        let flags = client
+            .cmd(&["CONFIG", "SET", "notify-keyspace-events", "EA"])
+            .await;
+        assert_eq!(flags, status("OK"));
+        subscriber.send(&["PSUBSCRIBE", "__keyevent@0__:*"]).await;
+        subscriber.read().await;
+
+        let commands: [(&[&str], &[&str]); 13] = [
+            (&["JSON.SET", "json", "$", r#"{"a":1}"#], &["json.set"]),
+            (&["JSON.NUMINCRBY", "json", "$.a", "1"], &["json.numincrby"]),
+            (&["TS.ADD", "ts", "1", "1"], &["ts.add"]),
+            (&["BF.ADD", "bf", "item"], &["bf.add"]),
+            (&["CF.ADD", "cf", "item"], &["cf.add"]),
+            (&["CMS.INITBYDIM", "cms", "10", "2"], &["cms.initbydim"]),
+            (&["CMS.INCRBY", "cms", "item", "1"], &["cms.incrby"]),
+            (&["TOPK.RESERVE", "topk", "2"], &["topk.reserve"]),
+            (&["TOPK.ADD", "topk", "item"], &["topk.add"]),
+            (&["VADD", "vset", "VALUES", "2", "1", "0", "e"], &["vadd"]),
+            (&["VSETATTR", "vset", "e", r#"{"a":1}"#], &["vsetattr"]),
+            // Removing the last element deletes the vector set
+            (&["VREM", "vset", "e"], &["vrem", "del"]),
+            (
+                &["CL.THROTTLE", "throttle", "1", "1", "60"],
+                &["cl.throttle"],
+            ),
+        ];
+        for (command, events) in commands {
+            assert!(!client.cmd(command).await.is_error(), "{:?}", command);
+            for event in events {
+                let expected = (event.to_string(), command[1].to_string());
+                assert_eq!(next_event(&mut subscriber).await, expected);
+            }
+        }
+
+        let created = client
+            .cmd(&[
+                "FT.CREATE",
+                "idx",
+                "PREFIX",
+                "1",
+                "doc:",
+                "SCHEMA",
+                "title",
+                "TEXT",
+            ])
+            .await;
+        assert_eq!(created, status("OK"));
+        assert_eq!(
+            client.cmd(&["HSET", "doc:1", "title", "a"]).await,
+            Response::Integer(1)
+        );
+        assert_eq!(next_event(&mut subscriber).await.0, "hset");
+        assert_eq!(
+            client.cmd(&["FT.DROPINDEX", "idx", "DD"]).await,
+            status("OK")
+        );
+        let deleted = next_event(&mut subscriber).await;
+        assert_eq!(deleted, ("del".to_string(), "doc:1".to_string()));
+
+        {
+            let mut db = database_holder.database_lock.lock().unwrap();
+            db.expire_at(0, b"json".to_vec(), mstime() as i64).unwrap();
+        }
+        let expire_loop = {
+            let database_holder = database_holder.clone();
+            tokio::spawn(async move { database_holder.expire_loop().await })
+        };
+        let expired = next_event(&mut subscriber).await;
+        expire_loop.abort();
+        assert_eq!(expired, ("expired".to_string(), "json".to_string()));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tracking_clients_are_sent_one_invalidation_per_flushall() {
+        let (_, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        let mut other = Client::connect(addr).await;
+        let Response::Map(_) = client.cmd(&["HELLO", "3"]).await else {
+            panic!("HELLO replies a map");
+        };
+        let enabled = client.cmd(&["CLIENT", "TRACKING", "ON", "NOLOOP"]).await;
+        assert_eq!(enabled, status("OK"));
+        let invalidate = |key: Response| Response::Push(vec![data("invalidate"), key]);
+
+        // The client is not told about its own writes
+        assert_eq!(client.cmd(&["GET", "k"]).await, Response::Nil);
+        assert_eq!(client.cmd(&["SET", "k", "mine"]).await, status("OK"));
+        assert_eq!(client.cmd(&["GET", "k"]).await, data("mine"));
+        assert_eq!(other.cmd(&["SET", "k", "other"]).await, status("OK"));
+        let keys = Response::Array(vec![data("k")]);
+        assert_eq!(client.read().await, invalidate(keys));
+
+        assert_eq!(client.cmd(&["GET", "k"]).await, data("other"));
+        assert_eq!(other.cmd(&["FLUSHALL"]).await, status("OK"));
+        assert_eq!(client.read().await, invalidate(Response::Nil));
+        let more = client.try_read(Duration::from_millis(100)).await;
+        assert_eq!(more, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn the_index_follows_an_updated_hash_field() {
+        let (_, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        let created = client
+            .cmd(&[
+                "FT.CREATE",
+                "idx",
+                "PREFIX",
+                "1",
+                "doc:",
+                "SCHEMA",
+                "title",
+                "TEXT",
+            ])
+            .await;
+        assert_eq!(created, status("OK"));
+        assert_eq!(
+            client.cmd(&["HSET", "doc:1", "title", "red"]).await,
+            Response::Integer(1)
+        );
+        assert_eq!(
+            client.cmd(&["HSET", "doc:1", "title", "blue"]).await,
+            Response::Integer(0)
+        );
+        let Response::Array(red) = client.cmd(&["FT.SEARCH", "idx", "red"]).await else {
+            panic!("FT.SEARCH replies an array");
+        };
+        assert_eq!(red[0], Response::Integer(0));
+        let Response::Array(blue) = client.cmd(&["FT.SEARCH", "idx", "blue"]).await else {
+            panic!("FT.SEARCH replies an array");
+        };
+        assert_eq!(blue[0], Response::Integer(1));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn infinite_scores_are_range_bounds_like_any_other() {
+        let (_, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        let added = client
+            .cmd(&["ZADD", "z", "-inf", "a", "1", "b", "+inf", "c"])
+            .await;
+        assert_eq!(added, Response::Integer(3));
+        let removed = client.cmd(&["ZREMRANGEBYSCORE", "z", "+inf", "+inf"]).await;
+        assert_eq!(removed, Response::Integer(1));
+        let removed = client.cmd(&["ZREMRANGEBYSCORE", "z", "(-inf", "inf"]).await;
+        assert_eq!(removed, Response::Integer(1));
+        assert_eq!(client.cmd(&["ZCARD", "z"]).await, Response::Integer(1));
+        let removed = client.cmd(&["ZREMRANGEBYSCORE", "z", "-inf", "-inf"]).await;
+        assert_eq!(removed, Response::Integer(1));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sorted_set_operations_combine_the_inputs() {
+        let (_, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        let added = client.cmd(&["ZADD", "first", "1", "a", "2", "b"]).await;
+        assert_eq!(added, Response::Integer(2));
+        let added = client.cmd(&["ZADD", "second", "3", "b", "4", "c"]).await;
+        assert_eq!(added, Response::Integer(2));
+        // The members of a plain set have a score of 1
+        assert_eq!(
+            client.cmd(&["SADD", "set", "c"]).await,
+            Response::Integer(1)
+        );
+        let members = |members: &[&str]| Response::Array(members.iter().map(|m| data(m)).collect());
+
+        let union = client
+            .cmd(&[
+                "ZUNION",
+                "2",
+                "first",
+                "second",
+                "WEIGHTS",
+                "1",
+                "2",
+                "WITHSCORES",
+            ])
+            .await;
+        assert_eq!(union, members(&["a", "1", "b", "8", "c", "8"]));
+        let union = client
+            .cmd(&[
+                "ZUNION",
+                "2",
+                "second",
+                "set",
+                "AGGREGATE",
+                "MIN",
+                "WITHSCORES",
+            ])
+            .await;
+        assert_eq!(union, members(&["c", "1", "b", "3"]));
+        let inter = client
+            .cmd(&[
+                "ZINTER",
+                "2",
+                "first",
+                "second",
+                "AGGREGATE",
+                "MAX",
+                "WITHSCORES",
+            ])
+            .await;
+        assert_eq!(inter, members(&["b", "3"]));
+        let diff = client.cmd(&["ZDIFF", "2", "first", "second"]).await;
+        assert_eq!(diff, members(&["a"]));
+        assert!(client
+            .cmd(&["ZDIFF", "1", "first", "WEIGHTS", "1"])
+            .await
+            .is_error());
+        assert!(client
+            .cmd(&["ZUNION", "3", "first", "second"])
+            .await
+            .is_error());
+
+        let stored = client
+            .cmd(&["ZUNIONSTORE", "out", "3", "first", "second", "set"])
+            .await;
+        assert_eq!(stored, Response::Integer(3));
+        assert_eq!(client.cmd(&["ZCARD", "out"]).await, Response::Integer(3));
+        let stored = client
+            .cmd(&["ZINTERSTORE", "out", "2", "first", "set"])
+            .await;
+        assert_eq!(stored, Response::Integer(0));
+        assert_eq!(client.cmd(&["ZCARD", "out"]).await, Response::Integer(0));
+        let stored = client
+            .cmd(&["ZDIFFSTORE", "out", "2", "second", "set"])
+            .await;
+        assert_eq!(stored, Response::Integer(1));
+
+        let card = client.cmd(&["ZINTERCARD", "2", "out", "second"]).await;
+        assert_eq!(card, Response::Integer(1));
+        let card = client
+            .cmd(&["ZINTERCARD", "1", "first", "LIMIT", "1"])
+            .await;
+        assert_eq!(card, Response::Integer(1));
+        let limit = client
+            .cmd(&["ZINTERCARD", "1", "first", "LIMIT", "-1"])
+            .await;
+        assert!(limit.is_error());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn subscribers_receive_the_channel_and_pattern_messages() {
+        let (_, addr) = serve().await;
+        let mut subscriber = Client::connect(addr).await;
+        let mut publisher = Client::connect(addr).await;
+        let reply = subscriber.cmd(&["SUBSCRIBE", "news.tech"]).await;
+        let subscribed = |kind: &str, name: &str, count| {
+            Response::Array(vec![data(kind), data(name), Response::Integer(count)])
+        };
+        assert_eq!(reply, subscribed("subscribe", "news.tech", 1));
+        let reply = subscriber.cmd(&["PSUBSCRIBE", "news.*"]).await;
+        assert_eq!(reply, subscribed("psubscribe", "news.*", 2));
+
+        let numsub = publisher
+            .cmd(&["PUBSUB", "NUMSUB", "news.tech", "sport"])
+            .await;
+        let expected = vec![
+            data("news.tech"),
+            Response::Integer(1),
+            data("sport"),
+            Response::Integer(0),
+        ];
+        assert_eq!(numsub, Response::Array(expected));
+        assert_eq!(
+            publisher.cmd(&["PUBSUB", "NUMPAT"]).await,
+            Response::Integer(1)
+        );
+        let channels = publisher.cmd(&["PUBSUB", "CHANNELS", "news*"]).await;
+        assert_eq!(channels, Response::Array(vec![data("news.tech")]));
+
+        let receivers = publisher.cmd(&["PUBLISH", "news.tech", "hello"]).await;
+        assert_eq!(receivers, Response::Integer(2));
+        let message = vec![data("message"), data("news.tech"), data("hello")];
+        assert_eq!(subscriber.read().await, Response::Array(message));
+        let message = vec![
+            data("pmessage"),
+            data("news.*"),
+            data("news.tech"),
+            data("hello"),
+        ];
+        assert_eq!(subscriber.read().await, Response::Array(message));
+        assert_eq!(
+            publisher.cmd(&["PUBLISH", "sport", "goal"]).await,
+            Response::Integer(0)
+        );
+
+        let reply = subscriber.cmd(&["PUNSUBSCRIBE"]).await;
+        assert_eq!(reply, subscribed("punsubscribe", "news.*", 1));
+        assert_eq!(
+            publisher.cmd(&["PUBSUB", "NUMPAT"]).await,
+            Response::Integer(0)
+        );
+        let receivers = publisher.cmd(&["PUBLISH", "news.sport", "goal"]).await;
+        assert_eq!(receivers, Response::Integer(0));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn only_the_subscriber_commands_are_run_in_subscriber_mode() {
+        let (_, addr) = serve().await;
+        let mut subscriber = Client::connect(addr).await;
+        subscriber.cmd(&["SUBSCRIBE", "news"]).await;
+        let Response::Error(error) = subscriber.cmd(&["GET", "key"]).await else {
+            panic!("GET was run in subscriber mode");
+        };
+        assert!(error.starts_with("Can't execute 'get'"));
+        let pong = Response::Array(vec![data("pong"), data("")]);
+        assert_eq!(subscriber.cmd(&["PING"]).await, pong);
+
+        let reply = subscriber.cmd(&["UNSUBSCRIBE", "news"]).await;
+        let unsubscribed = vec![data("unsubscribe"), data("news"), Response::Integer(0)];
+        assert_eq!(reply, Response::Array(unsubscribed));
+        assert_eq!(subscriber.cmd(&["GET", "key"]).await, Response::Nil);
+        assert_eq!(subscriber.cmd(&["PING"]).await, status("PONG"));
+
+        // RESP3 connections can run any command while subscribed
+        let mut subscriber = Client::connect(addr).await;
+        let Response::Map(_) = subscriber.cmd(&["HELLO", "3"]).await else {
+            panic!("HELLO 3 did not reply a map");
+        };
+        let reply = subscriber.cmd(&["SUBSCRIBE", "news"]).await;
+        let subscribed = vec![data("subscribe"), data("news"), Response::Integer(1)];
+        assert_eq!(reply, Response::Push(subscribed));
+        assert_eq!(subscriber.cmd(&["GET", "key"]).await, Response::Nil);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sharded_subscribers_receive_the_sharded_messages() {
+        let (_, addr) = serve().await;
+        let mut subscriber = Client::connect(addr).await;
+        let mut publisher = Client::connect(addr).await;
+        let crossslot = subscriber.cmd(&["SSUBSCRIBE", "a", "b"]).await;
+        assert!(matches!(crossslot, Response::Error(error) if error.starts_with("CROSSSLOT")));
+        subscriber
+            .send(&["SSUBSCRIBE", "{user}.a", "{user}.b"])
+            .await;
+        for (channel, count) in [("{user}.a", 1), ("{user}.b", 2)] {
+            let subscribed = vec![data("ssubscribe"), data(channel), Response::Integer(count)];
+            assert_eq!(subscriber.read().await, Response::Array(subscribed));
+        }
+        // Sharded channels are counted apart from the other subscriptions
+        let reply = subscriber.cmd(&["SUBSCRIBE", "{user}.a"]).await;
+        let subscribed = vec![data("subscribe"), data("{user}.a"), Response::Integer(1)];
+        assert_eq!(reply, Response::Array(subscribed));
+
+        let numsub = publisher
+            .cmd(&["PUBSUB", "SHARDNUMSUB", "{user}.b", "c"])
+            .await;
+        let expected = vec![
+            data("{user}.b"),
+            Response::Integer(1),
+            data("c"),
+            Response::Integer(0),
+        ];
+        assert_eq!(numsub, Response::Array(expected));
+        let channels = publisher.cmd(&["PUBSUB", "SHARDCHANNELS", "*.b"]).await;
+        assert_eq!(channels, Response::Array(vec![data("{user}.b")]));
+
+        let receivers = publisher.cmd(&["SPUBLISH", "{user}.b", "hello"]).await;
+        assert_eq!(receivers, Response::Integer(1));
+        let message = vec![data("smessage"), data("{user}.b"), data("hello")];
+        assert_eq!(subscriber.read().await, Response::Array(message));
+        assert_eq!(
+            publisher.cmd(&["PUBLISH", "{user}.b", "hello"]).await,
+            Response::Integer(0)
+        );
+
+        let reply = subscriber.cmd(&["SUNSUBSCRIBE", "{user}.b"]).await;
+        let unsubscribed = vec![data("sunsubscribe"), data("{user}.b"), Response::Integer(1)];
+        assert_eq!(reply, Response::Array(unsubscribed));
+        subscriber.send(&["SUNSUBSCRIBE"]).await;
+        let unsubscribed = vec![data("sunsubscribe"), data("{user}.a"), Response::Integer(0)];
+        assert_eq!(subscriber.read().await, Response::Array(unsubscribed));
+        let receivers = publisher.cmd(&["SPUBLISH", "{user}.a", "hello"]).await;
+        assert_eq!(receivers, Response::Integer(0));
+        // Still subscribed to the unsharded channel
+        assert!(subscriber.cmd(&["GET", "key"]).await.is_error());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn config_sets_the_snapshot_location() {
+        let (database_holder, addr) = serve().await;
+        let mut client = Client::connect(addr).await;
+        let temp_dir = std::env::temp_dir().canonicalize().unwrap();
+        let temp_dir = temp_dir.to_str().unwrap();
+        let location = |dir: &str, dbfilename: &str| {
+            Response::Array(vec![
+                data("dir"),
+                data(dir),
+                data("dbfilename"),
+                data(dbfilename),
+            ])
+        };
+        let reply = client.cmd(&["CONFIG", "GET", "d*"]).await;
+        assert_eq!(reply, location(temp_dir, "rcache-test.rdb"));
+
+        let current_dir = std::env::current_dir().unwrap();
+        let current_dir = current_dir.to_str().unwrap();
+        let set = client
+            .cmd(&["CONFIG", "SET", "dir", ".", "dbfilename", "dump.rdb"])
+            .await;
+        assert_eq!(set, status("OK"));
+        let reply = client.cmd(&["CONFIG", "GET", "dir", "dbfilename"]).await;
+        assert_eq!(reply, location(current_dir, "dump.rdb"));
+        assert_eq!(
+            database_holder.rdb_config.path(),
+            std::env::current_dir().unwrap().join("dump.rdb")
+        );
+
+        // Nothing is changed when a value is refused
+        let set = client
+            .cmd(&[
+                "CONFIG",
+                "SET",
+                "dir",
+                temp_dir,
+                "dbfilename",
+                "src/dump.rdb",
+            ])
+            .await;
+        let Response::Error(error) = set else {
+            panic!("a path was set as the dbfilename");
+        };
+        assert!(error.contains("dbfilename can't be a path, just a filename"));
+        let set = client
+            .cmd(&["CONFIG", "SET", "dir", "./no-such-directory"])
+            .await;
+        assert!(set.is_error());
+        let set = client.cmd(&["CONFIG", "SET", "dir", "Cargo.toml"]).await;
+        assert!(set.is_error());
+        let reply = client.cmd(&["CONFIG", "GET", "dir", "dbfilename"]).await;
+        assert_eq!(reply, location(current_dir, "dump.rdb"));
     }
 }
@@ -2,3 +2,5 @@ pub mod handler;
 pub mod ping;
 pub mod request;
 pub mod response;
+#[cfg(test)]
+pub mod test_client;
@@ -13,6 +13,10 @@ pub enum Response {
     Status(String),
     /// An array of responses that may mix different types
     Array(Vec<Response>),
+    /// RESP3 out of band data, e.g. an invalidation message
+    Push(Vec<Response>),
+    /// A RESP3 map
+    Map(Vec<(Response, Response)>),
 }
 
 impl Response {
@@ -43,6 +47,23 @@ impl Response {
                 &(a.iter().map(|el| el.as_bytes()).collect::<Vec<_>>()[..].concat())[..],
             ]
             .concat(),
+            Response::Push(a) => [
+                &b">"[..],
+                &a.len().to_string().into_bytes()[..],
+                b"\r\n",
+                &(a.iter().map(|el| el.as_bytes()).collect::<Vec<_>>()[..].concat())[..],
+            ]
+            .concat(),
+            Response::Map(m) => [
+                &b"%"[..],
+                &m.len().to_string().into_bytes()[..],
+                b"\r\n",
+                &(m.iter()
+                    .flat_map(|(key, value)| [key.as_bytes(), value.as_bytes()])
+                    .collect::<Vec<_>>()[..]
+                    .concat())[..],
+            ]
+            .concat(),
         }
     }
 
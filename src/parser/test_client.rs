@@ -0,0 +1,116 @@
+use crate::database::lib::{Database, DatabaseHolder};
+use crate::database::persistence::RdbConfig;
+use crate::parser::handler::Handler;
+use crate::parser::response::Response;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How long a reply is waited for before the test fails.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves the connections to a new empty database, like `main` does.
+pub async fn serve() -> (DatabaseHolder, SocketAddr) {
+    let rdb_config = RdbConfig::new(std::env::temp_dir(), "rcache-test.rdb").unwrap();
+    let database_holder = DatabaseHolder::new(Database::new(), rdb_config);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let cloned_holder = database_holder.clone();
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut handler = Handler::new(socket, cloned_holder.clone());
+            tokio::spawn(async move { while handler.run().await.is_ok() {} });
+        }
+    });
+    (database_holder, addr)
+}
+
+/// A connection sending one command at a time and reading the RESP replies.
+pub struct Client {
+    reader: BufReader<TcpStream>,
+}
+impl Client {
+    pub async fn connect(addr: SocketAddr) -> Self {
+        Client {
+            reader: BufReader::new(TcpStream::connect(addr).await.unwrap()),
+        }
+    }
+    pub async fn send(&mut self, args: &[&str]) {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend(format!("${}\r\n{}\r\n", arg.len(), arg).into_bytes());
+        }
+        self.reader.get_mut().write_all(&request).await.unwrap();
+    }
+    /// The next reply or pushed message.
+    pub async fn read(&mut self) -> Response {
+        tokio::time::timeout(REPLY_TIMEOUT, read_response(&mut self.reader))
+            .await
+            .expect("no reply")
+    }
+    /// The next message, `None` when nothing arrives within `wait`.
+    pub async fn try_read(&mut self, wait: Duration) -> Option<Response> {
+        tokio::time::timeout(wait, read_response(&mut self.reader))
+            .await
+            .ok()
+    }
+    pub async fn cmd(&mut self, args: &[&str]) -> Response {
+        self.send(args).await;
+        self.read().await
+    }
+}
+
+pub fn data(text: &str) -> Response {
+    Response::Data(text.as_bytes().to_vec())
+}
+pub fn status(text: &str) -> Response {
+    Response::Status(text.to_string())
+}
+
+fn read_response(
+    reader: &mut BufReader<TcpStream>,
+) -> Pin<Box<dyn Future<Output = Response> + Send + '_>> {
+    Box::pin(async move {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        assert!(line.ends_with("\r\n"), "connection closed");
+        let (kind, rest) = line.trim_end().split_at(1);
+        let len = || rest.parse::<i64>().unwrap();
+        match kind {
+            "+" => Response::Status(rest.to_string()),
+            "-" => Response::Error(rest.to_string()),
+            ":" => Response::Integer(len()),
+            "_" => Response::Nil,
+            "$" | "*" if len() < 0 => Response::Nil,
+            "$" => {
+                let mut bytes = vec![0; len() as usize + 2];
+                reader.read_exact(&mut bytes).await.unwrap();
+                bytes.truncate(len() as usize);
+                Response::Data(bytes)
+            }
+            "*" | ">" => {
+                let mut items = vec![];
+                for _ in 0..len() {
+                    items.push(read_response(reader).await);
+                }
+                match kind {
+                    "*" => Response::Array(items),
+                    _ => Response::Push(items),
+                }
+            }
+            "%" => {
+                let mut map = vec![];
+                for _ in 0..len() {
+                    let key = read_response(reader).await;
+                    map.push((key, read_response(reader).await));
+                }
+                Response::Map(map)
+            }
+            _ => panic!("unexpected reply {}", line),
+        }
+    })
+}
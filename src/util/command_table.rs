@@ -0,0 +1,352 @@
+use crate::vojo::parsered_command::ParsedCommand;
+use anyhow::{anyhow, ensure};
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+/// The number of arguments of every command, including the command name. A negative
+/// arity means at least that many, like in the Redis command table. The commands
+/// check the rest of their syntax when they run.
+const ARITIES: [(&str, i32); 141] = [
+    ("PING", -1),
+    ("SET", -3),
+    ("GET", 2),
+    ("INCR", 2),
+    ("SETRANGE", 4),
+    ("GETRANGE", 4),
+    ("LPUSH", -3),
+    ("RPUSH", -3),
+    ("LPOP", -2),
+    ("RPOP", -2),
+    ("LRANGE", 4),
+    ("SADD", -3),
+    ("HSET", -4),
+    ("ZADD", -4),
+    ("ZINCRBY", 4),
+    ("ZREM", -3),
+    ("ZREMRANGEBYSCORE", 4),
+    ("ZREMRANGEBYLEX", 4),
+    ("ZPOPMIN", -2),
+    ("ZPOPMAX", -2),
+    ("ZMPOP", -4),
+    ("ZRANDMEMBER", -2),
+    ("ZCARD", 2),
+    ("ZUNION", -3),
+    ("ZINTER", -3),
+    ("ZDIFF", -3),
+    ("ZUNIONSTORE", -4),
+    ("ZINTERSTORE", -4),
+    ("ZDIFFSTORE", -4),
+    ("ZINTERCARD", -3),
+    ("BZPOPMIN", -3),
+    ("BZPOPMAX", -3),
+    ("BZMPOP", -5),
+    ("SETBIT", 4),
+    ("GETBIT", 3),
+    ("BITCOUNT", -2),
+    ("BITPOS", -3),
+    ("BITOP", -4),
+    ("BITFIELD", -2),
+    ("BITFIELD_RO", -2),
+    ("PFADD", -2),
+    ("PFCOUNT", -2),
+    ("PFMERGE", -2),
+    ("GEOADD", -5),
+    ("GEOPOS", -2),
+    ("GEODIST", -4),
+    ("GEOHASH", -2),
+    ("GEOSEARCH", -6),
+    ("GEOSEARCHSTORE", -7),
+    ("XADD", -5),
+    ("XRANGE", -4),
+    ("XREVRANGE", -4),
+    ("XLEN", 2),
+    ("XDEL", -3),
+    ("XTRIM", -4),
+    ("XREAD", -4),
+    ("XREADGROUP", -7),
+    ("XGROUP", -2),
+    ("XACK", -4),
+    ("XPENDING", -3),
+    ("XCLAIM", -6),
+    ("XAUTOCLAIM", -6),
+    ("XINFO", -3),
+    ("JSON.SET", -4),
+    ("JSON.GET", -2),
+    ("JSON.MGET", -3),
+    ("JSON.DEL", -2),
+    ("JSON.NUMINCRBY", 4),
+    ("JSON.STRAPPEND", -3),
+    ("JSON.ARRAPPEND", -4),
+    ("JSON.ARRPOP", -2),
+    ("JSON.OBJKEYS", -2),
+    ("JSON.TYPE", -2),
+    ("BF.RESERVE", -4),
+    ("BF.ADD", 3),
+    ("BF.MADD", -3),
+    ("BF.EXISTS", 3),
+    ("BF.MEXISTS", -3),
+    ("CF.RESERVE", -3),
+    ("CF.ADD", 3),
+    ("CF.EXISTS", 3),
+    ("CF.DEL", 3),
+    ("CMS.INITBYDIM", 4),
+    ("CMS.INITBYPROB", 4),
+    ("CMS.INCRBY", -4),
+    ("CMS.QUERY", -3),
+    ("CMS.MERGE", -4),
+    ("TOPK.RESERVE", -3),
+    ("TOPK.ADD", -3),
+    ("TOPK.INCRBY", -4),
+    ("TOPK.LIST", -2),
+    ("TOPK.QUERY", -3),
+    ("TS.CREATE", -2),
+    ("TS.ADD", -4),
+    ("TS.MADD", -4),
+    ("TS.RANGE", -4),
+    ("TS.REVRANGE", -4),
+    ("TS.MRANGE", -5),
+    ("TS.CREATERULE", -6),
+    ("VADD", -4),
+    ("VSIM", -4),
+    ("VREM", 3),
+    ("VCARD", 2),
+    ("VDIM", 2),
+    ("VEMB", -3),
+    ("VSETATTR", 4),
+    ("VGETATTR", 3),
+    ("FT.CREATE", -5),
+    ("FT.SEARCH", -3),
+    ("FT.AGGREGATE", -3),
+    ("FT.DROPINDEX", -2),
+    ("CL.THROTTLE", -5),
+    ("PUBLISH", 3),
+    ("PUBSUB", -2),
+    ("SPUBLISH", 3),
+    ("SUBSCRIBE", -2),
+    ("PSUBSCRIBE", -2),
+    ("SSUBSCRIBE", -2),
+    ("UNSUBSCRIBE", -1),
+    ("PUNSUBSCRIBE", -1),
+    ("SUNSUBSCRIBE", -1),
+    ("CONFIG", -2),
+    ("CLIENT", -2),
+    ("HELLO", -1),
+    ("FLUSHDB", -1),
+    ("FLUSHALL", -1),
+    ("MULTI", 1),
+    ("EXEC", 1),
+    ("DISCARD", 1),
+    ("WATCH", -2),
+    ("UNWATCH", 1),
+    ("EVAL", -3),
+    ("EVALSHA", -3),
+    ("EVAL_RO", -3),
+    ("EVALSHA_RO", -3),
+    ("SCRIPT", -2),
+    ("FUNCTION", -2),
+    ("FCALL", -3),
+    ("FCALL_RO", -3),
+    ("MODULE", -2),
+    ("INFO", -1),
+];
+/// The commands registered by the loaded modules, with their arity and whether
+/// they may modify the dataset.
+static MODULE_COMMANDS: RwLock<BTreeMap<String, (i32, bool)>> = RwLock::new(BTreeMap::new());
+
+/// The commands which may modify the dataset, refused by the read-only scripts.
+const WRITE_COMMANDS: [&str; 69] = [
+    "SET",
+    "INCR",
+    "SETRANGE",
+    "LPUSH",
+    "RPUSH",
+    "LPOP",
+    "RPOP",
+    "SADD",
+    "HSET",
+    "ZADD",
+    "ZINCRBY",
+    "ZREM",
+    "ZREMRANGEBYSCORE",
+    "ZREMRANGEBYLEX",
+    "ZPOPMIN",
+    "ZPOPMAX",
+    "ZMPOP",
+    "ZUNIONSTORE",
+    "ZINTERSTORE",
+    "ZDIFFSTORE",
+    "BZPOPMIN",
+    "BZPOPMAX",
+    "BZMPOP",
+    "SETBIT",
+    "BITOP",
+    "BITFIELD",
+    "PFADD",
+    "PFMERGE",
+    "GEOADD",
+    "GEOSEARCHSTORE",
+    "XADD",
+    "XDEL",
+    "XTRIM",
+    "XREADGROUP",
+    "XGROUP",
+    "XACK",
+    "XCLAIM",
+    "XAUTOCLAIM",
+    "JSON.SET",
+    "JSON.DEL",
+    "JSON.NUMINCRBY",
+    "JSON.STRAPPEND",
+    "JSON.ARRAPPEND",
+    "JSON.ARRPOP",
+    "BF.RESERVE",
+    "BF.ADD",
+    "BF.MADD",
+    "CF.RESERVE",
+    "CF.ADD",
+    "CF.DEL",
+    "CMS.INITBYDIM",
+    "CMS.INITBYPROB",
+    "CMS.INCRBY",
+    "CMS.MERGE",
+    "TOPK.RESERVE",
+    "TOPK.ADD",
+    "TOPK.INCRBY",
+    "TS.CREATE",
+    "TS.ADD",
+    "TS.MADD",
+    "TS.CREATERULE",
+    "VADD",
+    "VREM",
+    "VSETATTR",
+    "FT.CREATE",
+    "FT.DROPINDEX",
+    "CL.THROTTLE",
+    "FLUSHDB",
+    "FLUSHALL",
+];
+/// The commands a script can not call, they act on the connection or run scripts.
+const NOSCRIPT_COMMANDS: [&str; 23] = [
+    "MULTI",
+    "EXEC",
+    "DISCARD",
+    "WATCH",
+    "UNWATCH",
+    "SUBSCRIBE",
+    "PSUBSCRIBE",
+    "SSUBSCRIBE",
+    "UNSUBSCRIBE",
+    "PUNSUBSCRIBE",
+    "SUNSUBSCRIBE",
+    "CLIENT",
+    "HELLO",
+    "EVAL",
+    "EVALSHA",
+    "EVAL_RO",
+    "EVALSHA_RO",
+    "SCRIPT",
+    "CONFIG",
+    "FUNCTION",
+    "FCALL",
+    "FCALL_RO",
+    "MODULE",
+];
+
+/// Adds a command of a module to the table, the name of a command can not be reused.
+pub fn register_command(command_name: &str, arity: i32, write: bool) -> Result<(), anyhow::Error> {
+    let mut commands = MODULE_COMMANDS.write().map_err(|e| anyhow!("{}", e))?;
+    ensure!(
+        !ARITIES.iter().any(|(name, _)| *name == command_name)
+            && !commands.contains_key(command_name),
+        "command '{}' already exists",
+        command_name.to_lowercase()
+    );
+    ensure!(arity != 0, "the arity of a command can not be 0");
+    commands.insert(command_name.to_string(), (arity, write));
+    Ok(())
+}
+pub fn unregister_command(command_name: &str) {
+    if let Ok(mut commands) = MODULE_COMMANDS.write() {
+        commands.remove(command_name);
+    }
+}
+fn arity(command_name: &str) -> Option<i32> {
+    match ARITIES.iter().find(|(name, _)| *name == command_name) {
+        Some((_, arity)) => Some(*arity),
+        None => MODULE_COMMANDS
+            .read()
+            .ok()?
+            .get(command_name)
+            .map(|(arity, _)| *arity),
+    }
+}
+/// Checks the number of arguments of a command before it is queued in a transaction.
+pub fn check_arity(command_name: &str, parser: &ParsedCommand) -> Result<(), anyhow::Error> {
+    let Some(arity) = arity(command_name) else {
+        return Err(anyhow!(
+            "unknown command '{}', with args beginning with: {}",
+            command_name.to_lowercase(),
+            (1..parser.argv.len())
+                .filter_map(|i| parser.get_str(i).ok())
+                .map(|arg| format!("'{}' ", arg))
+                .collect::<String>()
+        ));
+    };
+    let len = parser.argv.len() as i32;
+    ensure!(
+        if arity >= 0 {
+            len == arity
+        } else {
+            len >= -arity
+        },
+        "wrong number of arguments for '{}' command",
+        command_name.to_lowercase()
+    );
+    Ok(())
+}
+pub fn is_write_command(command_name: &str) -> bool {
+    WRITE_COMMANDS.contains(&command_name)
+        || MODULE_COMMANDS
+            .read()
+            .is_ok_and(|commands| commands.get(command_name).is_some_and(|(_, write)| *write))
+}
+pub fn is_noscript_command(command_name: &str) -> bool {
+    NOSCRIPT_COMMANDS.contains(&command_name)
+}
+/// The keys read by a read-only command, `None` for the commands which modify
+/// the dataset or do not take keys. Client side caching only tracks these keys.
+pub fn read_only_keys(command_name: &str, parser: &ParsedCommand) -> Option<Vec<Vec<u8>>> {
+    let len = parser.argv.len();
+    let positions: Vec<usize> = match command_name {
+        "GET" | "GETRANGE" | "GETBIT" | "BITCOUNT" | "BITPOS" | "BITFIELD_RO" | "LRANGE"
+        | "ZCARD" | "ZRANDMEMBER" | "GEOPOS" | "GEODIST" | "GEOHASH" | "GEOSEARCH" | "XRANGE"
+        | "XREVRANGE" | "XLEN" | "XPENDING" | "JSON.GET" | "JSON.OBJKEYS" | "JSON.TYPE"
+        | "BF.EXISTS" | "BF.MEXISTS" | "CF.EXISTS" | "CMS.QUERY" | "TOPK.LIST" | "TOPK.QUERY"
+        | "TS.RANGE" | "TS.REVRANGE" | "VSIM" | "VCARD" | "VDIM" | "VEMB" | "VGETATTR" => vec![1],
+        "PFCOUNT" => (1..len).collect(),
+        // The last argument is the path
+        "JSON.MGET" => (1..len.saturating_sub(1)).collect(),
+        "XINFO" => vec![2],
+        // The keys are counted by the argument before them
+        "ZUNION" | "ZINTER" | "ZDIFF" | "ZINTERCARD" => {
+            let numkeys = parser.get_i64(1).ok()?.max(0) as usize;
+            (2..(2 + numkeys).min(len)).collect()
+        }
+        // Half of the arguments after STREAMS are keys, the others their IDs
+        "XREAD" => {
+            let streams = (1..len).find(|i| {
+                parser
+                    .get_slice(*i)
+                    .is_ok_and(|arg| arg.eq_ignore_ascii_case(b"STREAMS"))
+            })?;
+            (streams + 1..streams + 1 + (len - streams - 1) / 2).collect()
+        }
+        _ => return None,
+    };
+    positions
+        .into_iter()
+        .filter(|pos| *pos < len)
+        .map(|pos| parser.get_vec(pos))
+        .collect::<Result<_, _>>()
+        .ok()
+}
@@ -1,11 +1,48 @@
 use time::OffsetDateTime;
 
+/// Current timestamp in microseconds
 pub fn ustime() -> i128 {
-    let now = OffsetDateTime::now_utc();
-    now.unix_timestamp_nanos()
+    OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000
 }
 
 /// Current timestamp in milliseconds
 pub fn mstime() -> i128 {
-    ustime() / 1000
+    OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000
+}
+
+/// Formats a float the way scores are returned to clients
+pub fn f64_to_bytes(value: f64) -> Vec<u8> {
+    value.to_string().into_bytes()
+}
+
+/// Turns a `start`/`end` pair that may count from the end, like the one of GETRANGE,
+/// into indexes of a sequence of length `len`. Both ends are included.
+pub fn normalize_range(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+    if start > end || start >= len {
+        return None;
+    }
+    Some((start as usize, end as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn timestamps_are_in_their_units() {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i128;
+        assert!((mstime() - millis).abs() < 1_000);
+        assert!((ustime() / 1_000 - millis).abs() < 1_000);
+    }
 }
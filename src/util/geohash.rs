@@ -0,0 +1,370 @@
+/// The geohashes stored as sorted set scores use 26 steps per coordinate,
+/// 52 interleaved bits fit exactly in the mantissa of an f64.
+pub const GEO_STEP_MAX: u8 = 26;
+/// The latitude limits of EPSG:3857, the projection used by web maps.
+pub const GEO_LAT_MIN: f64 = -85.05112878;
+pub const GEO_LAT_MAX: f64 = 85.05112878;
+pub const GEO_LONG_MIN: f64 = -180.0;
+pub const GEO_LONG_MAX: f64 = 180.0;
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+const MERCATOR_MAX: f64 = 20037726.37;
+const GEO_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoHash {
+    pub bits: u64,
+    pub step: u8,
+}
+/// The area covered by a geohash.
+#[derive(Debug, Clone, Copy)]
+pub struct GeoHashArea {
+    pub long_min: f64,
+    pub long_max: f64,
+    pub lat_min: f64,
+    pub lat_max: f64,
+}
+impl GeoHashArea {
+    pub fn center(&self) -> (f64, f64) {
+        let longitude = ((self.long_min + self.long_max) / 2.0).clamp(GEO_LONG_MIN, GEO_LONG_MAX);
+        let latitude = ((self.lat_min + self.lat_max) / 2.0).clamp(GEO_LAT_MIN, GEO_LAT_MAX);
+        (longitude, latitude)
+    }
+}
+/// The area searched by GEOSEARCH, sizes are in meters.
+#[derive(Debug, Clone, Copy)]
+pub enum GeoShape {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+pub fn validate_coordinates(longitude: f64, latitude: f64) -> bool {
+    (GEO_LONG_MIN..=GEO_LONG_MAX).contains(&longitude)
+        && (GEO_LAT_MIN..=GEO_LAT_MAX).contains(&latitude)
+}
+pub fn encode(longitude: f64, latitude: f64, step: u8) -> GeoHash {
+    encode_with_ranges(
+        longitude,
+        latitude,
+        step,
+        (GEO_LONG_MIN, GEO_LONG_MAX),
+        (GEO_LAT_MIN, GEO_LAT_MAX),
+    )
+}
+pub fn decode(hash: GeoHash) -> GeoHashArea {
+    let (lat_index, long_index) = deinterleave(hash.bits);
+    let cells = (1u64 << hash.step) as f64;
+    let lat_scale = GEO_LAT_MAX - GEO_LAT_MIN;
+    let long_scale = GEO_LONG_MAX - GEO_LONG_MIN;
+    GeoHashArea {
+        lat_min: GEO_LAT_MIN + (lat_index as f64 / cells) * lat_scale,
+        lat_max: GEO_LAT_MIN + ((lat_index as f64 + 1.0) / cells) * lat_scale,
+        long_min: GEO_LONG_MIN + (long_index as f64 / cells) * long_scale,
+        long_max: GEO_LONG_MIN + ((long_index as f64 + 1.0) / cells) * long_scale,
+    }
+}
+/// Decodes a sorted set score into the longitude and latitude of a member.
+pub fn decode_score(score: f64) -> (f64, f64) {
+    decode(GeoHash {
+        bits: score as u64,
+        step: GEO_STEP_MAX,
+    })
+    .center()
+}
+/// The usual 11 characters geohash string, which is computed on the standard
+/// latitude range of [-90, 90] rather than the one used for the scores.
+pub fn to_geohash_string(score: f64) -> Vec<u8> {
+    let (longitude, latitude) = decode_score(score);
+    let hash = encode_with_ranges(
+        longitude,
+        latitude,
+        GEO_STEP_MAX,
+        (-180.0, 180.0),
+        (-90.0, 90.0),
+    );
+    (0..11)
+        .map(|i| {
+            // The 52 bits only give 10 full characters
+            let index = if i == 10 {
+                0
+            } else {
+                (hash.bits >> (52 - (i + 1) * 5)) & 0x1f
+            };
+            GEO_ALPHABET[index as usize]
+        })
+        .collect()
+}
+/// Great-circle distance in meters with the haversine formula.
+pub fn distance(long1: f64, lat1: f64, long2: f64, lat2: f64) -> f64 {
+    let v = ((long2.to_radians() - long1.to_radians()) / 2.0).sin();
+    if v == 0.0 {
+        return lat_distance(lat1, lat2);
+    }
+    let lat1 = lat1.to_radians();
+    let lat2 = lat2.to_radians();
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+/// Returns the distance to the center when the point is inside the shape.
+pub fn distance_if_in_shape(
+    shape: &GeoShape,
+    center: (f64, f64),
+    point: (f64, f64),
+) -> Option<f64> {
+    match shape {
+        GeoShape::Radius(radius) => {
+            let distance = distance(center.0, center.1, point.0, point.1);
+            (distance <= *radius).then_some(distance)
+        }
+        GeoShape::Box { width, height } => {
+            if lat_distance(point.1, center.1) > height / 2.0 {
+                return None;
+            }
+            if distance(point.0, point.1, center.0, point.1) > width / 2.0 {
+                return None;
+            }
+            Some(distance(center.0, center.1, point.0, point.1))
+        }
+    }
+}
+/// The score ranges, as `[min, max)` pairs, of the geohash boxes covering the shape:
+/// the box of the center and its neighbours, at a step large enough to contain the shape.
+pub fn covering_score_ranges(shape: &GeoShape, longitude: f64, latitude: f64) -> Vec<(f64, f64)> {
+    let (long_min, lat_min, long_max, lat_max) = bounding_box(shape, longitude, latitude);
+    let radius = match shape {
+        GeoShape::Radius(radius) => *radius,
+        GeoShape::Box { width, height } => (width / 2.0).hypot(height / 2.0),
+    };
+    let mut step = estimate_steps_by_radius(radius, latitude);
+    let mut hash = encode(longitude, latitude, step);
+    // Near the edge of the center box a neighbour may be too small to reach the
+    // border of the shape, one step less makes the boxes twice as large.
+    let too_small = |hash: GeoHash| {
+        decode(neighbour(hash, 0, 1)).lat_max < lat_max
+            || decode(neighbour(hash, 0, -1)).lat_min > lat_min
+            || decode(neighbour(hash, 1, 0)).long_max < long_max
+            || decode(neighbour(hash, -1, 0)).long_min > long_min
+    };
+    if step > 1 && too_small(hash) {
+        step -= 1;
+        hash = encode(longitude, latitude, step);
+    }
+    let area = decode(hash);
+    let mut ranges = vec![];
+    for lat_move in -1..=1 {
+        for long_move in -1..=1 {
+            // Skip the neighbours that are entirely outside of the bounding box
+            if step >= 2
+                && ((lat_move == -1 && area.lat_min < lat_min)
+                    || (lat_move == 1 && area.lat_max > lat_max)
+                    || (long_move == -1 && area.long_min < long_min)
+                    || (long_move == 1 && area.long_max > long_max))
+            {
+                continue;
+            }
+            let box_hash = neighbour(hash, long_move, lat_move);
+            let shift = 2 * (GEO_STEP_MAX - step) as u32;
+            let range = (
+                (box_hash.bits << shift) as f64,
+                ((box_hash.bits + 1) << shift) as f64,
+            );
+            if !ranges.contains(&range) {
+                ranges.push(range);
+            }
+        }
+    }
+    ranges
+}
+
+fn encode_with_ranges(
+    longitude: f64,
+    latitude: f64,
+    step: u8,
+    long_range: (f64, f64),
+    lat_range: (f64, f64),
+) -> GeoHash {
+    let cells = (1u64 << step) as f64;
+    let lat_offset = (latitude - lat_range.0) / (lat_range.1 - lat_range.0);
+    let long_offset = (longitude - long_range.0) / (long_range.1 - long_range.0);
+    let max_index = (1u64 << step) - 1;
+    let lat_index = ((lat_offset * cells) as u64).min(max_index);
+    let long_index = ((long_offset * cells) as u64).min(max_index);
+    GeoHash {
+        bits: interleave(lat_index as u32, long_index as u32),
+        step,
+    }
+}
+/// Moves a geohash by whole boxes, wrapping around at the edges of the map.
+fn neighbour(hash: GeoHash, long_move: i64, lat_move: i64) -> GeoHash {
+    let (lat_index, long_index) = deinterleave(hash.bits);
+    let cells = 1i64 << hash.step;
+    let lat_index = (lat_index as i64 + lat_move).rem_euclid(cells);
+    let long_index = (long_index as i64 + long_move).rem_euclid(cells);
+    GeoHash {
+        bits: interleave(lat_index as u32, long_index as u32),
+        step: hash.step,
+    }
+}
+fn bounding_box(shape: &GeoShape, longitude: f64, latitude: f64) -> (f64, f64, f64, f64) {
+    let (width, height) = match shape {
+        GeoShape::Radius(radius) => (*radius, *radius),
+        GeoShape::Box { width, height } => (width / 2.0, height / 2.0),
+    };
+    let lat_delta = (height / EARTH_RADIUS_IN_METERS).to_degrees();
+    let long_delta_top =
+        (width / EARTH_RADIUS_IN_METERS / (latitude + lat_delta).to_radians().cos()).to_degrees();
+    let long_delta_bottom =
+        (width / EARTH_RADIUS_IN_METERS / (latitude - lat_delta).to_radians().cos()).to_degrees();
+    // The longitude delta is larger on the side closer to a pole
+    let long_delta = if latitude < 0.0 {
+        long_delta_bottom
+    } else {
+        long_delta_top
+    };
+    (
+        longitude - long_delta,
+        latitude - lat_delta,
+        longitude + long_delta,
+        latitude + lat_delta,
+    )
+}
+fn estimate_steps_by_radius(radius: f64, latitude: f64) -> u8 {
+    if radius == 0.0 {
+        return GEO_STEP_MAX;
+    }
+    let mut radius = radius;
+    let mut step: i32 = 1;
+    while radius < MERCATOR_MAX {
+        radius *= 2.0;
+        step += 1;
+    }
+    // Make sure the radius is included in most of the base cases
+    step -= 2;
+    // Boxes get narrower towards the poles
+    if !(-66.0..=66.0).contains(&latitude) {
+        step -= 1;
+        if !(-80.0..=80.0).contains(&latitude) {
+            step -= 1;
+        }
+    }
+    step.clamp(1, GEO_STEP_MAX as i32) as u8
+}
+fn lat_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS_IN_METERS * (lat2.to_radians() - lat1.to_radians()).abs()
+}
+/// Interleaves the bits of the latitude index (even bits) and the longitude index (odd bits).
+fn interleave(lat_index: u32, long_index: u32) -> u64 {
+    spread(lat_index) | (spread(long_index) << 1)
+}
+fn deinterleave(bits: u64) -> (u32, u32) {
+    (squash(bits), squash(bits >> 1))
+}
+fn spread(value: u32) -> u64 {
+    let mut x = value as u64;
+    x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+    x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+fn squash(bits: u64) -> u32 {
+    let mut x = bits & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x >> 4)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x >> 8)) & 0x0000_ffff_0000_ffff;
+    ((x | (x >> 16)) & 0x0000_0000_ffff_ffff) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PALERMO: (f64, f64) = (13.361389, 38.115556);
+    const CATANIA: (f64, f64) = (15.087269, 37.502669);
+
+    fn score(point: (f64, f64)) -> f64 {
+        encode(point.0, point.1, GEO_STEP_MAX).bits as f64
+    }
+
+    #[test]
+    fn encode_matches_the_redis_scores() {
+        assert_eq!(score(PALERMO), 3479099956230698.0);
+        assert_eq!(score(CATANIA), 3479447370796909.0);
+    }
+
+    #[test]
+    fn decode_returns_the_center_of_the_box() {
+        let (longitude, latitude) = decode_score(score(PALERMO));
+        assert!((longitude - 13.361_389_338_970_184).abs() < 1e-12);
+        assert!((latitude - 38.115_556_395_496_3).abs() < 1e-12);
+    }
+
+    #[test]
+    fn encode_and_decode_round_trip() {
+        for point in [PALERMO, CATANIA, (-180.0, -85.0), (180.0, 85.0), (0.0, 0.0)] {
+            for step in [1, 10, GEO_STEP_MAX] {
+                let area = decode(encode(point.0, point.1, step));
+                assert!(area.long_min <= point.0 && point.0 <= area.long_max);
+                assert!(area.lat_min <= point.1 && point.1 <= area.lat_max);
+            }
+        }
+    }
+
+    #[test]
+    fn geohash_strings() {
+        assert_eq!(to_geohash_string(score(PALERMO)), b"sqc8b49rny0");
+        assert_eq!(to_geohash_string(score(CATANIA)), b"sqdtr74hyu0");
+    }
+
+    #[test]
+    fn haversine_distance() {
+        // GEODIST measures between the positions decoded from the scores
+        let palermo = decode_score(score(PALERMO));
+        let catania = decode_score(score(CATANIA));
+        let distance = distance(palermo.0, palermo.1, catania.0, catania.1);
+        assert!((distance - 166_274.151_6).abs() < 0.001, "{}", distance);
+        assert_eq!(super::distance(1.0, 2.0, 1.0, 2.0), 0.0);
+    }
+
+    #[test]
+    fn shapes() {
+        let radius = GeoShape::Radius(200_000.0);
+        assert!(distance_if_in_shape(&radius, PALERMO, CATANIA).is_some());
+        let radius = GeoShape::Radius(100_000.0);
+        assert!(distance_if_in_shape(&radius, PALERMO, CATANIA).is_none());
+        let narrow = GeoShape::Box {
+            width: 100_000.0,
+            height: 400_000.0,
+        };
+        assert!(distance_if_in_shape(&narrow, PALERMO, CATANIA).is_none());
+        let wide = GeoShape::Box {
+            width: 400_000.0,
+            height: 400_000.0,
+        };
+        assert!(distance_if_in_shape(&wide, PALERMO, CATANIA).is_some());
+    }
+
+    #[test]
+    fn the_covering_ranges_contain_the_points_in_the_shape() {
+        let shape = GeoShape::Radius(200_000.0);
+        let ranges = covering_score_ranges(&shape, PALERMO.0, PALERMO.1);
+        for point in [PALERMO, CATANIA] {
+            let score = score(point);
+            assert!(ranges
+                .iter()
+                .any(|(min, max)| *min <= score && score < *max));
+        }
+    }
+
+    #[test]
+    fn neighbours_wrap_around() {
+        let hash = encode(179.9, 0.0, 4);
+        let (_, long_index) = deinterleave(neighbour(hash, 1, 0).bits);
+        assert_eq!(long_index, 0);
+        assert_eq!(
+            deinterleave(interleave(0x3ff_ffff, 0x155_5555)),
+            (0x3ff_ffff, 0x155_5555)
+        );
+    }
+}
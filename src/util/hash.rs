@@ -0,0 +1,60 @@
+/// MurmurHash64A by Austin Appleby, the hash Redis uses for HyperLogLog. Unlike the hashers
+/// of the standard library its output is stable, so it can be used for persisted values.
+pub fn murmurhash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes([
+            chunk[0], chunk[1], chunk[2], chunk[3], chunk[4], chunk[5], chunk[6], chunk[7],
+        ]);
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// The number of slots keys are partitioned in.
+pub const KEY_SLOTS: u16 = 16384;
+
+/// CRC16-CCITT (XModem), the checksum Redis Cluster maps keys to slots with.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+/// The slot of a key. When the key contains a non-empty `{tag}` only the tag is
+/// hashed, so related keys can be kept in the same slot.
+pub fn key_hash_slot(key: &[u8]) -> u16 {
+    let tag = key.iter().position(|c| *c == b'{').and_then(|start| {
+        key[start + 1..]
+            .iter()
+            .position(|c| *c == b'}')
+            .filter(|len| *len > 0)
+            .map(|len| &key[start + 1..start + 1 + len])
+    });
+    crc16(tag.unwrap_or(key)) % KEY_SLOTS
+}
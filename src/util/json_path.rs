@@ -0,0 +1,463 @@
+use anyhow::{anyhow, ensure};
+use serde_json::Value;
+
+/// A step of a concrete location in a document.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PathElement {
+    Key(String),
+    Index(usize),
+}
+#[derive(Debug, Clone)]
+enum Selector {
+    Key(String),
+    Index(i64),
+    Wildcard,
+    Slice(Option<i64>, Option<i64>, i64),
+    Union(Vec<Selector>),
+}
+#[derive(Debug, Clone)]
+enum Segment {
+    Child(Selector),
+    /// `..`, the selector is applied to the node and all of its descendants
+    Descendant(Selector),
+}
+/// A JSONPath expression, or a legacy path which does not start with `$`.
+///
+/// JSONPath expressions can match any number of values and the commands reply
+/// with an array of results, while legacy paths reply with the first match only.
+#[derive(Debug, Clone)]
+pub struct JsonPath {
+    pub legacy: bool,
+    segments: Vec<Segment>,
+}
+impl JsonPath {
+    pub fn root() -> Self {
+        JsonPath {
+            legacy: true,
+            segments: vec![],
+        }
+    }
+    pub fn parse(text: &str) -> Result<JsonPath, anyhow::Error> {
+        let legacy = !text.starts_with('$');
+        let chars: Vec<char> = text.chars().collect();
+        let mut pos = if legacy { 0 } else { 1 };
+        let mut segments = vec![];
+        // `.` is the root, and the leading dot of a legacy path may be left out
+        if legacy && chars.first().is_some_and(|c| *c != '.' && *c != '[') {
+            let (name, next) = parse_name(&chars, pos)?;
+            segments.push(Segment::Child(name));
+            pos = next;
+        } else if legacy && chars == ['.'] {
+            pos = 1;
+        }
+        while pos < chars.len() {
+            match chars[pos] {
+                '.' if chars.get(pos + 1) == Some(&'.') => {
+                    pos += 2;
+                    let (selector, next) = if chars.get(pos) == Some(&'[') {
+                        parse_bracket(&chars, pos)?
+                    } else {
+                        parse_name(&chars, pos)?
+                    };
+                    segments.push(Segment::Descendant(selector));
+                    pos = next;
+                }
+                '.' => {
+                    let (selector, next) = parse_name(&chars, pos + 1)?;
+                    segments.push(Segment::Child(selector));
+                    pos = next;
+                }
+                '[' => {
+                    let (selector, next) = parse_bracket(&chars, pos)?;
+                    segments.push(Segment::Child(selector));
+                    pos = next;
+                }
+                _ => return Err(syntax_error(pos)),
+            }
+        }
+        Ok(JsonPath { legacy, segments })
+    }
+    pub fn is_root(&self) -> bool {
+        self.segments.is_empty()
+    }
+    /// The locations of the values matched in `root`, in document order.
+    pub fn select(&self, root: &Value) -> Vec<Vec<PathElement>> {
+        let mut locations = vec![vec![]];
+        for segment in self.segments.iter() {
+            let mut next = vec![];
+            for location in locations {
+                let Some(node) = get(root, &location) else {
+                    continue;
+                };
+                match segment {
+                    Segment::Child(selector) => apply(selector, node, &location, &mut next),
+                    Segment::Descendant(selector) => {
+                        descend(selector, node, location, &mut next);
+                    }
+                }
+            }
+            locations = next;
+        }
+        locations
+    }
+    /// The location where a value would be added by `JSON.SET`: the parent, which
+    /// must be matched by the path without its last step, and the new key.
+    pub fn split_last_key(&self) -> Option<(JsonPath, String)> {
+        match self.segments.last()? {
+            Segment::Child(Selector::Key(key)) => {
+                let parent = JsonPath {
+                    legacy: self.legacy,
+                    segments: self.segments[..self.segments.len() - 1].to_vec(),
+                };
+                Some((parent, key.clone()))
+            }
+            _ => None,
+        }
+    }
+}
+pub fn get<'a>(root: &'a Value, location: &[PathElement]) -> Option<&'a Value> {
+    let mut node = root;
+    for element in location {
+        node = match element {
+            PathElement::Key(key) => node.as_object()?.get(key)?,
+            PathElement::Index(index) => node.as_array()?.get(*index)?,
+        };
+    }
+    Some(node)
+}
+pub fn get_mut<'a>(root: &'a mut Value, location: &[PathElement]) -> Option<&'a mut Value> {
+    let mut node = root;
+    for element in location {
+        node = match element {
+            PathElement::Key(key) => node.as_object_mut()?.get_mut(key)?,
+            PathElement::Index(index) => node.as_array_mut()?.get_mut(*index)?,
+        };
+    }
+    Some(node)
+}
+/// Removes the value at `location`, which must not be the root.
+pub fn remove(root: &mut Value, location: &[PathElement]) -> bool {
+    let Some((last, parent)) = location.split_last() else {
+        return false;
+    };
+    match (get_mut(root, parent), last) {
+        (Some(Value::Object(map)), PathElement::Key(key)) => map.shift_remove(key).is_some(),
+        (Some(Value::Array(array)), PathElement::Index(index)) if *index < array.len() => {
+            array.remove(*index);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn apply(
+    selector: &Selector,
+    node: &Value,
+    location: &[PathElement],
+    out: &mut Vec<Vec<PathElement>>,
+) {
+    let mut push = |element: PathElement| {
+        let mut child = location.to_vec();
+        child.push(element);
+        out.push(child);
+    };
+    match (selector, node) {
+        (Selector::Key(key), Value::Object(map)) if map.contains_key(key) => {
+            push(PathElement::Key(key.clone()));
+        }
+        (Selector::Index(index), Value::Array(array)) => {
+            let len = array.len() as i64;
+            let index = if *index < 0 { len + index } else { *index };
+            if (0..len).contains(&index) {
+                push(PathElement::Index(index as usize));
+            }
+        }
+        (Selector::Wildcard, Value::Object(map)) => {
+            for key in map.keys() {
+                push(PathElement::Key(key.clone()));
+            }
+        }
+        (Selector::Wildcard, Value::Array(array)) => {
+            for index in 0..array.len() {
+                push(PathElement::Index(index));
+            }
+        }
+        (Selector::Slice(start, end, step), Value::Array(array)) => {
+            let len = array.len() as i64;
+            let clamp = |value: i64| {
+                let value = if value < 0 { len + value } else { value };
+                value.clamp(0, len)
+            };
+            let start = clamp(start.unwrap_or(0));
+            let end = clamp(end.unwrap_or(len));
+            let mut index = start;
+            while index < end {
+                push(PathElement::Index(index as usize));
+                index += step;
+            }
+        }
+        (Selector::Union(selectors), _) => {
+            for selector in selectors {
+                apply(selector, node, location, out);
+            }
+        }
+        _ => {}
+    }
+}
+fn descend(
+    selector: &Selector,
+    node: &Value,
+    location: Vec<PathElement>,
+    out: &mut Vec<Vec<PathElement>>,
+) {
+    apply(selector, node, &location, out);
+    let children: Vec<(PathElement, &Value)> = match node {
+        Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| (PathElement::Key(key.clone()), value))
+            .collect(),
+        Value::Array(array) => array
+            .iter()
+            .enumerate()
+            .map(|(index, value)| (PathElement::Index(index), value))
+            .collect(),
+        _ => vec![],
+    };
+    for (element, child) in children {
+        let mut child_location = location.clone();
+        child_location.push(element);
+        descend(selector, child, child_location, out);
+    }
+}
+/// Parses a dotted name, or `*`, starting at `pos`.
+fn parse_name(chars: &[char], pos: usize) -> Result<(Selector, usize), anyhow::Error> {
+    if chars.get(pos) == Some(&'*') {
+        return Ok((Selector::Wildcard, pos + 1));
+    }
+    let mut end = pos;
+    while end < chars.len() && chars[end] != '.' && chars[end] != '[' {
+        end += 1;
+    }
+    ensure!(end > pos, syntax_error(pos));
+    Ok((Selector::Key(chars[pos..end].iter().collect()), end))
+}
+/// Parses `[...]` starting at `pos`: quoted names, indexes, slices and `*`,
+/// separated by commas.
+fn parse_bracket(chars: &[char], pos: usize) -> Result<(Selector, usize), anyhow::Error> {
+    let mut selectors = vec![];
+    let mut pos = pos + 1;
+    loop {
+        skip_spaces(chars, &mut pos);
+        let selector = match chars.get(pos) {
+            Some('\'') | Some('"') => {
+                let quote = chars[pos];
+                let mut name = String::new();
+                pos += 1;
+                loop {
+                    match chars.get(pos) {
+                        Some('\\') => {
+                            name.push(*chars.get(pos + 1).ok_or_else(|| syntax_error(pos))?);
+                            pos += 2;
+                        }
+                        Some(c) if *c == quote => {
+                            pos += 1;
+                            break;
+                        }
+                        Some(c) => {
+                            name.push(*c);
+                            pos += 1;
+                        }
+                        None => return Err(syntax_error(pos)),
+                    }
+                }
+                Selector::Key(name)
+            }
+            Some('*') => {
+                pos += 1;
+                Selector::Wildcard
+            }
+            Some('?') => return Err(anyhow!("filter expressions are not supported")),
+            Some(_) => {
+                let start = pos;
+                while pos < chars.len() && !matches!(chars[pos], ',' | ']') {
+                    pos += 1;
+                }
+                let text: String = chars[start..pos].iter().collect();
+                parse_index_or_slice(text.trim()).ok_or_else(|| syntax_error(start))?
+            }
+            None => return Err(syntax_error(pos)),
+        };
+        selectors.push(selector);
+        skip_spaces(chars, &mut pos);
+        match chars.get(pos) {
+            Some(',') => pos += 1,
+            Some(']') => break,
+            _ => return Err(syntax_error(pos)),
+        }
+    }
+    let selector = if selectors.len() == 1 {
+        selectors.remove(0)
+    } else {
+        Selector::Union(selectors)
+    };
+    Ok((selector, pos + 1))
+}
+fn parse_index_or_slice(text: &str) -> Option<Selector> {
+    if !text.contains(':') {
+        return text.parse::<i64>().ok().map(Selector::Index);
+    }
+    let parts: Vec<&str> = text.split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    let bound = |part: &str| -> Option<Option<i64>> {
+        if part.trim().is_empty() {
+            Some(None)
+        } else {
+            part.trim().parse::<i64>().ok().map(Some)
+        }
+    };
+    let start = bound(parts[0])?;
+    let end = bound(parts[1])?;
+    let step = match parts.get(2) {
+        Some(part) => bound(part)?.unwrap_or(1),
+        None => 1,
+    };
+    (step > 0).then_some(Selector::Slice(start, end, step))
+}
+fn skip_spaces(chars: &[char], pos: &mut usize) {
+    while chars.get(*pos) == Some(&' ') {
+        *pos += 1;
+    }
+}
+fn syntax_error(pos: usize) -> anyhow::Error {
+    anyhow!("JSON Path error: path error at position {}", pos + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn document() -> Value {
+        json!({
+            "store": {
+                "book": [
+                    {"title": "a", "price": 8},
+                    {"title": "b", "price": 12},
+                    {"title": "c", "price": 9}
+                ],
+                "bicycle": {"price": 20}
+            },
+            "a.b": 1
+        })
+    }
+    fn select(path: &str) -> Vec<Value> {
+        let root = document();
+        let path = JsonPath::parse(path).unwrap();
+        path.select(&root)
+            .iter()
+            .map(|location| get(&root, location).unwrap().clone())
+            .collect()
+    }
+
+    #[test]
+    fn legacy_paths() {
+        assert!(JsonPath::parse(".").unwrap().is_root());
+        assert!(JsonPath::parse(".").unwrap().legacy);
+        assert_eq!(select("store.bicycle.price"), vec![json!(20)]);
+        assert_eq!(select(".store.book[1].title"), vec![json!("b")]);
+        assert!(!JsonPath::parse("$.store").unwrap().legacy);
+        assert!(JsonPath::parse("$").unwrap().is_root());
+    }
+
+    #[test]
+    fn children_and_indexes() {
+        assert_eq!(select("$.store.book[0].price"), vec![json!(8)]);
+        assert_eq!(select("$.store.book[-1].title"), vec![json!("c")]);
+        assert!(select("$.store.book[3]").is_empty());
+        assert!(select("$.missing.price").is_empty());
+        assert_eq!(select("$['a.b']"), vec![json!(1)]);
+        assert_eq!(select("$[\"store\"]['bicycle'].price"), vec![json!(20)]);
+    }
+
+    #[test]
+    fn wildcards_slices_and_unions() {
+        assert_eq!(
+            select("$.store.book[*].title"),
+            vec![json!("a"), json!("b"), json!("c")]
+        );
+        assert_eq!(select("$.store.book.*.price").len(), 3);
+        assert_eq!(
+            select("$.store.book[0:2].title"),
+            vec![json!("a"), json!("b")]
+        );
+        assert_eq!(
+            select("$.store.book[::2].title"),
+            vec![json!("a"), json!("c")]
+        );
+        assert_eq!(
+            select("$.store.book[-2:].title"),
+            vec![json!("b"), json!("c")]
+        );
+        assert_eq!(
+            select("$.store.book[2, 0].title"),
+            vec![json!("c"), json!("a")]
+        );
+    }
+
+    #[test]
+    fn descendants_in_document_order() {
+        assert_eq!(
+            select("$..price"),
+            vec![json!(8), json!(12), json!(9), json!(20)]
+        );
+        assert_eq!(select("$..book[1].title"), vec![json!("b")]);
+        assert_eq!(select("$..[0].title"), vec![json!("a")]);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for path in [
+            "$.",
+            "$[",
+            "$['a'",
+            "$[0:1:0]",
+            "$[a]",
+            "$x",
+            "$.a[0:1:2:3]",
+        ] {
+            assert!(JsonPath::parse(path).is_err(), "{}", path);
+        }
+        assert_eq!(
+            JsonPath::parse("$.a[?(@.b)]").unwrap_err().to_string(),
+            "filter expressions are not supported"
+        );
+    }
+
+    #[test]
+    fn split_and_remove() {
+        let path = JsonPath::parse("$.store.bicycle.color").unwrap();
+        let (parent, key) = path.split_last_key().unwrap();
+        assert_eq!(key, "color");
+        assert_eq!(parent.select(&document()).len(), 1);
+        assert!(JsonPath::parse("$.a[0]")
+            .unwrap()
+            .split_last_key()
+            .is_none());
+
+        let mut root = document();
+        let book = [
+            PathElement::Key("store".to_owned()),
+            PathElement::Key("book".to_owned()),
+        ];
+        let first = [&book[..], &[PathElement::Index(0)]].concat();
+        assert!(remove(&mut root, &first));
+        assert_eq!(get(&root, &book).unwrap().as_array().unwrap().len(), 2);
+        assert!(!remove(&mut root, &[]));
+        assert!(!remove(
+            &mut root,
+            &[PathElement::Key("missing".to_owned())]
+        ));
+    }
+}
@@ -1 +1,8 @@
+pub mod command_table;
 pub mod common_utils;
+pub mod geohash;
+pub mod glob;
+pub mod hash;
+pub mod json_path;
+pub mod search_query;
+pub mod vector_filter;
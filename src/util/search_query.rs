@@ -0,0 +1,426 @@
+use anyhow::{anyhow, ensure};
+
+/// A parsed `FT.SEARCH` query.
+///
+/// Words next to each other must all match, `|` gives the union of its sides
+/// and binds tighter than the intersection, `-` excludes. `@field:` restricts
+/// what follows to a field, which is how numeric ranges `[min max]` and tags
+/// `{a | b}` are queried.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryNode {
+    All,
+    Term {
+        field: Option<String>,
+        term: String,
+        prefix: bool,
+    },
+    Phrase {
+        field: Option<String>,
+        terms: Vec<String>,
+    },
+    Numeric {
+        field: String,
+        min: f64,
+        min_exclusive: bool,
+        max: f64,
+        max_exclusive: bool,
+    },
+    Tag {
+        field: String,
+        tags: Vec<String>,
+    },
+    And(Vec<QueryNode>),
+    Or(Vec<QueryNode>),
+    Not(Box<QueryNode>),
+}
+pub fn parse_query(text: &str) -> Result<QueryNode, anyhow::Error> {
+    if text.trim() == "*" {
+        return Ok(QueryNode::All);
+    }
+    let mut parser = QueryParser {
+        chars: text.chars().collect(),
+        pos: 0,
+    };
+    let node = parser.intersection(&None, None)?;
+    parser.skip_spaces();
+    ensure!(
+        parser.pos == parser.chars.len(),
+        "Syntax error at offset {} near {}",
+        parser.pos,
+        parser.chars[parser.pos..].iter().collect::<String>()
+    );
+    Ok(node)
+}
+/// Splits text in lowercase words, the way both documents and queries are indexed.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+struct QueryParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+impl QueryParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+    fn expect(&mut self, expected: char) -> Result<(), anyhow::Error> {
+        self.skip_spaces();
+        ensure!(
+            self.peek() == Some(expected),
+            "Syntax error at offset {}, expected {}",
+            self.pos,
+            expected
+        );
+        self.pos += 1;
+        Ok(())
+    }
+    /// Nodes next to each other, until `end` or the end of the query.
+    fn intersection(
+        &mut self,
+        field: &Option<String>,
+        end: Option<char>,
+    ) -> Result<QueryNode, anyhow::Error> {
+        let mut nodes = vec![];
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                None => break,
+                Some(c) if Some(c) == end => break,
+                _ => {}
+            }
+            if let Some(node) = self.union(field)? {
+                nodes.push(node);
+            }
+        }
+        ensure!(!nodes.is_empty() || end.is_some(), "Empty query");
+        Ok(match nodes.len() {
+            0 => QueryNode::Or(vec![]),
+            1 => nodes.remove(0),
+            _ => QueryNode::And(nodes),
+        })
+    }
+    fn union(&mut self, field: &Option<String>) -> Result<Option<QueryNode>, anyhow::Error> {
+        let mut nodes: Vec<QueryNode> = self.unary(field)?.into_iter().collect();
+        loop {
+            self.skip_spaces();
+            if self.peek() != Some('|') {
+                break;
+            }
+            self.pos += 1;
+            self.skip_spaces();
+            nodes.extend(self.unary(field)?);
+        }
+        Ok(match nodes.len() {
+            0 => None,
+            1 => Some(nodes.remove(0)),
+            _ => Some(QueryNode::Or(nodes)),
+        })
+    }
+    fn unary(&mut self, field: &Option<String>) -> Result<Option<QueryNode>, anyhow::Error> {
+        if self.peek() == Some('-') {
+            self.pos += 1;
+            return Ok(self
+                .unary(field)?
+                .map(|node| QueryNode::Not(Box::new(node))));
+        }
+        self.atom(field)
+    }
+    /// A term, a phrase, a group or a field restriction. `None` for a phrase without words.
+    fn atom(&mut self, field: &Option<String>) -> Result<Option<QueryNode>, anyhow::Error> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let node = self.intersection(field, Some(')'))?;
+                self.expect(')')?;
+                Ok(Some(node))
+            }
+            Some('"') => {
+                self.pos += 1;
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c != '"') {
+                    self.pos += 1;
+                }
+                let text: String = self.chars[start..self.pos].iter().collect();
+                self.expect('"')?;
+                let terms = tokenize(&text);
+                Ok(match terms.len() {
+                    0 => None,
+                    _ => Some(QueryNode::Phrase {
+                        field: field.clone(),
+                        terms,
+                    }),
+                })
+            }
+            Some('@') => {
+                self.pos += 1;
+                let name = self.word();
+                ensure!(!name.is_empty(), "Syntax error at offset {}", self.pos);
+                self.expect(':')?;
+                self.skip_spaces();
+                self.field_atom(name)
+            }
+            Some(c) if is_word_char(c) || c == '\\' => {
+                let word = self.word().to_lowercase();
+                let prefix = self.peek() == Some('*');
+                if prefix {
+                    self.pos += 1;
+                }
+                Ok(Some(QueryNode::Term {
+                    field: field.clone(),
+                    term: word,
+                    prefix,
+                }))
+            }
+            _ => Err(anyhow!("Syntax error at offset {}", self.pos)),
+        }
+    }
+    fn field_atom(&mut self, field: String) -> Result<Option<QueryNode>, anyhow::Error> {
+        match self.peek() {
+            Some('[') => {
+                self.pos += 1;
+                let (min, min_exclusive) = self.numeric_bound()?;
+                let (max, max_exclusive) = self.numeric_bound()?;
+                self.expect(']')?;
+                Ok(Some(QueryNode::Numeric {
+                    field,
+                    min,
+                    min_exclusive,
+                    max,
+                    max_exclusive,
+                }))
+            }
+            Some('{') => {
+                self.pos += 1;
+                let mut tags = vec![];
+                let mut tag = String::new();
+                loop {
+                    match self.peek() {
+                        Some('\\') => {
+                            self.pos += 1;
+                            if let Some(c) = self.peek() {
+                                tag.push(c);
+                                self.pos += 1;
+                            }
+                        }
+                        Some('|') | Some('}') => {
+                            let closing = self.peek() == Some('}');
+                            self.pos += 1;
+                            let trimmed = tag.trim();
+                            ensure!(!trimmed.is_empty(), "Syntax error at offset {}", self.pos);
+                            tags.push(trimmed.to_string());
+                            tag.clear();
+                            if closing {
+                                break;
+                            }
+                        }
+                        Some(c) => {
+                            tag.push(c);
+                            self.pos += 1;
+                        }
+                        None => return Err(anyhow!("Syntax error, missing }}")),
+                    }
+                }
+                Ok(Some(QueryNode::Tag { field, tags }))
+            }
+            Some('-') => {
+                self.pos += 1;
+                Ok(self
+                    .field_atom(field)?
+                    .map(|node| QueryNode::Not(Box::new(node))))
+            }
+            _ => {
+                let field = Some(field);
+                let mut nodes: Vec<QueryNode> = self.atom(&field)?.into_iter().collect();
+                // `@field:a|b` is a union restricted to the field
+                while self.peek() == Some('|') {
+                    self.pos += 1;
+                    nodes.extend(self.atom(&field)?);
+                }
+                Ok(match nodes.len() {
+                    0 => None,
+                    1 => Some(nodes.remove(0)),
+                    _ => Some(QueryNode::Or(nodes)),
+                })
+            }
+        }
+    }
+    fn numeric_bound(&mut self) -> Result<(f64, bool), anyhow::Error> {
+        self.skip_spaces();
+        let exclusive = self.peek() == Some('(');
+        if exclusive {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        while self.peek().is_some_and(|c| !c.is_whitespace() && c != ']') {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        let value = match text.to_lowercase().as_str() {
+            "inf" | "+inf" => f64::INFINITY,
+            "-inf" => f64::NEG_INFINITY,
+            _ => text
+                .parse::<f64>()
+                .map_err(|_| anyhow!("Syntax error, invalid numeric range value {}", text))?,
+        };
+        Ok((value, exclusive))
+    }
+    /// A field name or a term, `\` escapes the next character.
+    fn word(&mut self) -> String {
+        let mut word = String::new();
+        while let Some(c) = self.peek() {
+            if c == '\\' {
+                self.pos += 1;
+                if let Some(escaped) = self.peek() {
+                    word.push(escaped);
+                    self.pos += 1;
+                }
+            } else if is_word_char(c) {
+                word.push(c);
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        word
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(field: Option<&str>, term: &str) -> QueryNode {
+        QueryNode::Term {
+            field: field.map(str::to_owned),
+            term: term.to_owned(),
+            prefix: false,
+        }
+    }
+
+    #[test]
+    fn terms_intersect_and_unions_bind_tighter() {
+        assert_eq!(parse_query(" * ").unwrap(), QueryNode::All);
+        assert_eq!(parse_query("Hello").unwrap(), term(None, "hello"));
+        assert_eq!(
+            parse_query("a b|c").unwrap(),
+            QueryNode::And(vec![
+                term(None, "a"),
+                QueryNode::Or(vec![term(None, "b"), term(None, "c")])
+            ])
+        );
+        assert_eq!(
+            parse_query("(a b) | c").unwrap(),
+            QueryNode::Or(vec![
+                QueryNode::And(vec![term(None, "a"), term(None, "b")]),
+                term(None, "c")
+            ])
+        );
+        assert_eq!(
+            parse_query("a -b").unwrap(),
+            QueryNode::And(vec![
+                term(None, "a"),
+                QueryNode::Not(Box::new(term(None, "b")))
+            ])
+        );
+    }
+
+    #[test]
+    fn prefixes_phrases_and_escapes() {
+        assert_eq!(
+            parse_query("hel*").unwrap(),
+            QueryNode::Term {
+                field: None,
+                term: "hel".to_owned(),
+                prefix: true
+            }
+        );
+        assert_eq!(
+            parse_query("\"Hello, World\"").unwrap(),
+            QueryNode::Phrase {
+                field: None,
+                terms: vec!["hello".to_owned(), "world".to_owned()]
+            }
+        );
+        assert_eq!(parse_query("a\\-b").unwrap(), term(None, "a-b"));
+        assert_eq!(parse_query("a \"\"").unwrap(), term(None, "a"));
+    }
+
+    #[test]
+    fn field_restrictions() {
+        assert_eq!(
+            parse_query("@title:hello").unwrap(),
+            term(Some("title"), "hello")
+        );
+        assert_eq!(
+            parse_query("@title:(a b)").unwrap(),
+            QueryNode::And(vec![term(Some("title"), "a"), term(Some("title"), "b")])
+        );
+        assert_eq!(
+            parse_query("@title:a|b").unwrap(),
+            QueryNode::Or(vec![term(Some("title"), "a"), term(Some("title"), "b")])
+        );
+        assert_eq!(
+            parse_query("@price:[(10 +inf]").unwrap(),
+            QueryNode::Numeric {
+                field: "price".to_owned(),
+                min: 10.0,
+                min_exclusive: true,
+                max: f64::INFINITY,
+                max_exclusive: false
+            }
+        );
+        assert_eq!(
+            parse_query("@tags:{ red | big\\ blue }").unwrap(),
+            QueryNode::Tag {
+                field: "tags".to_owned(),
+                tags: vec!["red".to_owned(), "big blue".to_owned()]
+            }
+        );
+        assert_eq!(
+            parse_query("@tags:-{red}").unwrap(),
+            QueryNode::Not(Box::new(QueryNode::Tag {
+                field: "tags".to_owned(),
+                tags: vec!["red".to_owned()]
+            }))
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for query in [
+            "",
+            "(a",
+            "a)",
+            "\"a",
+            "@:a",
+            "@a b",
+            "@price:[1 x]",
+            "@price:[1 2",
+            "@tags:{a",
+            "@tags:{a||b}",
+            "a | !",
+        ] {
+            assert!(parse_query(query).is_err(), "{:?}", query);
+        }
+    }
+
+    #[test]
+    fn tokenize_lowercases_words() {
+        assert_eq!(
+            tokenize("Hello, wide_World! 42"),
+            vec!["hello", "wide_world", "42"]
+        );
+    }
+}
@@ -0,0 +1,455 @@
+use anyhow::{anyhow, ensure};
+use serde_json::Value;
+
+/// A `VSIM ... FILTER` expression, evaluated against the JSON attributes of the
+/// elements, like `.year >= 1980 and .genre in ["action", "drama"]`.
+///
+/// `.field` selects a top-level attribute, and an element without one of the
+/// selected attributes does not match.
+#[derive(Debug, Clone)]
+pub struct FilterExpression {
+    root: Expr,
+}
+#[derive(Debug, Clone)]
+enum Expr {
+    Literal(FilterValue),
+    Selector(String),
+    Array(Vec<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    In,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Power,
+}
+#[derive(Debug, Clone, PartialEq)]
+enum FilterValue {
+    Number(f64),
+    Str(String),
+    Bool(bool),
+    Array(Vec<FilterValue>),
+}
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Str(String),
+    Word(String),
+    Selector(String),
+    Symbol(&'static str),
+}
+const SYMBOLS: [&str; 21] = [
+    "**", "&&", "||", "==", "!=", ">=", "<=", ">", "<", "!", "+", "-", "*", "/", "%", "(", ")",
+    "[", "]", ",", "=",
+];
+
+impl FilterExpression {
+    pub fn parse(text: &str) -> Result<FilterExpression, anyhow::Error> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let root = parser.or()?;
+        ensure!(
+            parser.pos == parser.tokens.len(),
+            "syntax error in FILTER expression"
+        );
+        Ok(FilterExpression { root })
+    }
+    /// Whether the attributes, a JSON object, match the expression.
+    pub fn matches(&self, attributes: &Value) -> bool {
+        evaluate(&self.root, attributes).is_some_and(|value| truthy(&value))
+    }
+}
+fn syntax_error() -> anyhow::Error {
+    anyhow!("syntax error in FILTER expression")
+}
+fn tokenize(text: &str) -> Result<Vec<Token>, anyhow::Error> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = vec![];
+    let mut pos = 0;
+    while pos < chars.len() {
+        let c = chars[pos];
+        if c.is_whitespace() {
+            pos += 1;
+        } else if c == '.'
+            && chars
+                .get(pos + 1)
+                .is_some_and(|c| c.is_alphabetic() || *c == '_')
+        {
+            let start = pos + 1;
+            pos = start;
+            while pos < chars.len() && (chars[pos].is_alphanumeric() || chars[pos] == '_') {
+                pos += 1;
+            }
+            tokens.push(Token::Selector(chars[start..pos].iter().collect()));
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = pos;
+            while pos < chars.len()
+                && (chars[pos].is_ascii_digit()
+                    || chars[pos] == '.'
+                    || chars[pos] == 'e'
+                    || chars[pos] == 'E'
+                    || ((chars[pos] == '-' || chars[pos] == '+')
+                        && matches!(chars[pos - 1], 'e' | 'E')))
+            {
+                pos += 1;
+            }
+            let number: String = chars[start..pos].iter().collect();
+            tokens.push(Token::Number(number.parse().map_err(|_| syntax_error())?));
+        } else if c == '"' || c == '\'' {
+            let mut value = String::new();
+            pos += 1;
+            loop {
+                match chars.get(pos) {
+                    Some('\\') => {
+                        value.push(*chars.get(pos + 1).ok_or_else(syntax_error)?);
+                        pos += 2;
+                    }
+                    Some(quote) if *quote == c => {
+                        pos += 1;
+                        break;
+                    }
+                    Some(other) => {
+                        value.push(*other);
+                        pos += 1;
+                    }
+                    None => return Err(syntax_error()),
+                }
+            }
+            tokens.push(Token::Str(value));
+        } else if c.is_alphabetic() {
+            let start = pos;
+            while pos < chars.len() && chars[pos].is_alphanumeric() {
+                pos += 1;
+            }
+            tokens.push(Token::Word(
+                chars[start..pos].iter().collect::<String>().to_lowercase(),
+            ));
+        } else {
+            let rest: String = chars[pos..chars.len().min(pos + 2)].iter().collect();
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(**symbol))
+                .ok_or_else(syntax_error)?;
+            tokens.push(Token::Symbol(symbol));
+            pos += symbol.len();
+        }
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser, from the lowest precedence to the highest:
+/// `or`, `and`, `== != in`, `> >= < <=`, `+ -`, `* / %`, unary `! not -`, `**`.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+    fn binary_op(&self, ops: &[(&str, BinaryOp)]) -> Option<BinaryOp> {
+        let name = match self.peek()? {
+            Token::Symbol(symbol) => *symbol,
+            Token::Word(word) => word.as_str(),
+            _ => return None,
+        };
+        ops.iter()
+            .find(|(text, _)| *text == name)
+            .map(|(_, op)| *op)
+    }
+    fn left_associative(
+        &mut self,
+        ops: &[(&str, BinaryOp)],
+        next: fn(&mut Parser) -> Result<Expr, anyhow::Error>,
+    ) -> Result<Expr, anyhow::Error> {
+        let mut left = next(self)?;
+        while let Some(op) = self.binary_op(ops) {
+            self.pos += 1;
+            let right = next(self)?;
+            left = Expr::Binary(Box::new(left), op, Box::new(right));
+        }
+        Ok(left)
+    }
+    fn or(&mut self) -> Result<Expr, anyhow::Error> {
+        self.left_associative(&[("or", BinaryOp::Or), ("||", BinaryOp::Or)], Parser::and)
+    }
+    fn and(&mut self) -> Result<Expr, anyhow::Error> {
+        self.left_associative(
+            &[("and", BinaryOp::And), ("&&", BinaryOp::And)],
+            Parser::equality,
+        )
+    }
+    fn equality(&mut self) -> Result<Expr, anyhow::Error> {
+        self.left_associative(
+            &[
+                ("==", BinaryOp::Equal),
+                ("=", BinaryOp::Equal),
+                ("!=", BinaryOp::NotEqual),
+                ("in", BinaryOp::In),
+            ],
+            Parser::comparison,
+        )
+    }
+    fn comparison(&mut self) -> Result<Expr, anyhow::Error> {
+        self.left_associative(
+            &[
+                (">", BinaryOp::Greater),
+                (">=", BinaryOp::GreaterEqual),
+                ("<", BinaryOp::Less),
+                ("<=", BinaryOp::LessEqual),
+            ],
+            Parser::additive,
+        )
+    }
+    fn additive(&mut self) -> Result<Expr, anyhow::Error> {
+        self.left_associative(
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+            Parser::multiplicative,
+        )
+    }
+    fn multiplicative(&mut self) -> Result<Expr, anyhow::Error> {
+        self.left_associative(
+            &[
+                ("*", BinaryOp::Multiply),
+                ("/", BinaryOp::Divide),
+                ("%", BinaryOp::Modulo),
+            ],
+            Parser::unary,
+        )
+    }
+    fn unary(&mut self) -> Result<Expr, anyhow::Error> {
+        match self.peek() {
+            Some(Token::Symbol("!")) => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Word(word)) if word == "not" => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Symbol("-")) => {
+                self.pos += 1;
+                Ok(Expr::Negate(Box::new(self.unary()?)))
+            }
+            _ => self.power(),
+        }
+    }
+    fn power(&mut self) -> Result<Expr, anyhow::Error> {
+        let base = self.primary()?;
+        if self.peek() == Some(&Token::Symbol("**")) {
+            self.pos += 1;
+            let exponent = self.unary()?;
+            return Ok(Expr::Binary(
+                Box::new(base),
+                BinaryOp::Power,
+                Box::new(exponent),
+            ));
+        }
+        Ok(base)
+    }
+    fn primary(&mut self) -> Result<Expr, anyhow::Error> {
+        let token = self.peek().cloned().ok_or_else(syntax_error)?;
+        self.pos += 1;
+        match token {
+            Token::Number(number) => Ok(Expr::Literal(FilterValue::Number(number))),
+            Token::Str(value) => Ok(Expr::Literal(FilterValue::Str(value))),
+            Token::Selector(name) => Ok(Expr::Selector(name)),
+            Token::Word(word) if word == "true" => Ok(Expr::Literal(FilterValue::Bool(true))),
+            Token::Word(word) if word == "false" => Ok(Expr::Literal(FilterValue::Bool(false))),
+            Token::Symbol("(") => {
+                let expr = self.or()?;
+                ensure!(self.peek() == Some(&Token::Symbol(")")), syntax_error());
+                self.pos += 1;
+                Ok(expr)
+            }
+            Token::Symbol("[") => {
+                let mut items = vec![];
+                if self.peek() == Some(&Token::Symbol("]")) {
+                    self.pos += 1;
+                    return Ok(Expr::Array(items));
+                }
+                loop {
+                    items.push(self.or()?);
+                    match self.peek() {
+                        Some(Token::Symbol(",")) => self.pos += 1,
+                        Some(Token::Symbol("]")) => {
+                            self.pos += 1;
+                            return Ok(Expr::Array(items));
+                        }
+                        _ => return Err(syntax_error()),
+                    }
+                }
+            }
+            _ => Err(syntax_error()),
+        }
+    }
+}
+
+fn from_json(value: &Value) -> Option<FilterValue> {
+    match value {
+        Value::Number(number) => number.as_f64().map(FilterValue::Number),
+        Value::String(value) => Some(FilterValue::Str(value.clone())),
+        Value::Bool(value) => Some(FilterValue::Bool(*value)),
+        Value::Array(items) => items
+            .iter()
+            .map(from_json)
+            .collect::<Option<Vec<FilterValue>>>()
+            .map(FilterValue::Array),
+        _ => None,
+    }
+}
+fn truthy(value: &FilterValue) -> bool {
+    match value {
+        FilterValue::Number(number) => *number != 0.0,
+        FilterValue::Str(value) => !value.is_empty(),
+        FilterValue::Bool(value) => *value,
+        FilterValue::Array(items) => !items.is_empty(),
+    }
+}
+fn number(value: &FilterValue) -> Option<f64> {
+    match value {
+        FilterValue::Number(number) => Some(*number),
+        FilterValue::Bool(value) => Some(*value as i64 as f64),
+        _ => None,
+    }
+}
+fn equal(left: &FilterValue, right: &FilterValue) -> bool {
+    match (left, right) {
+        (FilterValue::Str(left), FilterValue::Str(right)) => left == right,
+        (FilterValue::Array(left), FilterValue::Array(right)) => left == right,
+        _ => matches!((number(left), number(right)), (Some(l), Some(r)) if l == r),
+    }
+}
+/// Evaluates an expression, `None` when a selected attribute is missing or the
+/// operands do not have the types of the operator.
+fn evaluate(expr: &Expr, attributes: &Value) -> Option<FilterValue> {
+    match expr {
+        Expr::Literal(value) => Some(value.clone()),
+        Expr::Selector(name) => from_json(attributes.get(name)?),
+        Expr::Array(items) => items
+            .iter()
+            .map(|item| evaluate(item, attributes))
+            .collect::<Option<Vec<FilterValue>>>()
+            .map(FilterValue::Array),
+        Expr::Not(inner) => Some(FilterValue::Bool(!truthy(&evaluate(inner, attributes)?))),
+        Expr::Negate(inner) => Some(FilterValue::Number(-number(&evaluate(inner, attributes)?)?)),
+        Expr::Binary(left, BinaryOp::And, right) => Some(FilterValue::Bool(
+            truthy(&evaluate(left, attributes)?) && truthy(&evaluate(right, attributes)?),
+        )),
+        Expr::Binary(left, BinaryOp::Or, right) => {
+            let left = evaluate(left, attributes).is_some_and(|value| truthy(&value));
+            Some(FilterValue::Bool(
+                left || evaluate(right, attributes).is_some_and(|value| truthy(&value)),
+            ))
+        }
+        Expr::Binary(left, op, right) => {
+            let left = evaluate(left, attributes)?;
+            let right = evaluate(right, attributes)?;
+            binary(&left, *op, &right)
+        }
+    }
+}
+fn binary(left: &FilterValue, op: BinaryOp, right: &FilterValue) -> Option<FilterValue> {
+    let result = match op {
+        BinaryOp::Equal => FilterValue::Bool(equal(left, right)),
+        BinaryOp::NotEqual => FilterValue::Bool(!equal(left, right)),
+        BinaryOp::In => FilterValue::Bool(match (left, right) {
+            (_, FilterValue::Array(items)) => items.iter().any(|item| equal(left, item)),
+            (FilterValue::Str(left), FilterValue::Str(right)) => right.contains(left.as_str()),
+            _ => return None,
+        }),
+        BinaryOp::Greater | BinaryOp::GreaterEqual | BinaryOp::Less | BinaryOp::LessEqual => {
+            let ordering = match (left, right) {
+                (FilterValue::Str(left), FilterValue::Str(right)) => left.partial_cmp(right),
+                _ => number(left)?.partial_cmp(&number(right)?),
+            }?;
+            FilterValue::Bool(match op {
+                BinaryOp::Greater => ordering.is_gt(),
+                BinaryOp::GreaterEqual => ordering.is_ge(),
+                BinaryOp::Less => ordering.is_lt(),
+                _ => ordering.is_le(),
+            })
+        }
+        _ => {
+            let (left, right) = (number(left)?, number(right)?);
+            FilterValue::Number(match op {
+                BinaryOp::Add => left + right,
+                BinaryOp::Subtract => left - right,
+                BinaryOp::Multiply => left * right,
+                BinaryOp::Divide => left / right,
+                BinaryOp::Modulo => left % right,
+                _ => left.powf(right),
+            })
+        }
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matches(expression: &str, attributes: Value) -> bool {
+        FilterExpression::parse(expression)
+            .unwrap()
+            .matches(&attributes)
+    }
+
+    #[test]
+    fn comparisons_and_logic() {
+        let movie = json!({"year": 1984, "genre": "action", "rating": 4.5, "seen": true});
+        assert!(matches(
+            ".year >= 1980 and .genre == 'action'",
+            movie.clone()
+        ));
+        assert!(matches(".year < 1980 || .rating > 4", movie.clone()));
+        assert!(!matches("not .seen", movie.clone()));
+        assert!(matches("!(.year == 1985)", movie.clone()));
+        assert!(matches(".genre in [\"drama\", \"action\"]", movie.clone()));
+        assert!(matches("'act' in .genre", movie.clone()));
+        assert!(matches(".seen == 1", movie));
+    }
+
+    #[test]
+    fn arithmetic_precedence() {
+        let value = json!({"x": 3});
+        assert!(matches(".x * 2 + 1 == 7", value.clone()));
+        assert!(matches("2 ** 3 ** 2 == 512", value.clone()));
+        assert!(matches("-.x ** 2 == -9", value.clone()));
+        assert!(matches(".x % 2 == 1 and 1e1 / 4 == 2.5", value));
+    }
+
+    #[test]
+    fn missing_attributes_do_not_match() {
+        assert!(!matches(".year > 1980", json!({})));
+        assert!(!matches(".year != 1980", json!({"name": "x"})));
+        assert!(matches(".year > 1980 or true", json!({})));
+        assert!(!matches(".tags == 1", json!({"tags": null})));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for expression in ["", ".year >", "(.a", "[1, 2", ".a @ 1", "'open", ".a .b"] {
+            assert!(
+                FilterExpression::parse(expression).is_err(),
+                "{}",
+                expression
+            );
+        }
+    }
+}
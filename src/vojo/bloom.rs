@@ -0,0 +1,173 @@
+use crate::util::hash::murmurhash64a;
+use anyhow::{anyhow, ensure};
+use bincode::{Decode, Encode};
+
+pub const BLOOM_DEFAULT_ERROR_RATE: f64 = 0.01;
+pub const BLOOM_DEFAULT_CAPACITY: u64 = 100;
+pub const BLOOM_DEFAULT_EXPANSION: u32 = 2;
+/// Every new layer gets a tighter error rate, so that the compound error rate
+/// of all the layers stays below twice the requested one.
+const BLOOM_TIGHTENING_RATIO: f64 = 0.5;
+const BLOOM_HASH_SEED: u64 = 0xc6a4_a793_5bd1_e995;
+/// The bits of a layer are allocated at once, a layer can not use more than 512MB.
+const BLOOM_MAX_BITS: u64 = 1 << 32;
+
+/// A scalable Bloom filter.
+///
+/// When the last layer holds as many items as its capacity, a new layer
+/// `expansion` times larger is added, unless the filter is non scaling.
+/// An item is in the filter if any layer may contain it.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct ValueBloomFilter {
+    pub layers: Vec<BloomLayer>,
+    pub error_rate: f64,
+    /// 0 for a non scaling filter
+    pub expansion: u32,
+}
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct BloomLayer {
+    pub bits: Vec<u8>,
+    pub bit_count: u64,
+    pub hash_count: u32,
+    pub capacity: u64,
+    pub items: u64,
+}
+impl BloomLayer {
+    /// `None` when the layer would need more than [`BLOOM_MAX_BITS`].
+    fn new(capacity: u64, error_rate: f64) -> Option<Self> {
+        let ln2 = std::f64::consts::LN_2;
+        let bits_per_item = -error_rate.ln() / (ln2 * ln2);
+        let bit_count = ((capacity as f64 * bits_per_item).ceil() as u64).max(8);
+        if bit_count > BLOOM_MAX_BITS {
+            return None;
+        }
+        let hash_count = ((bits_per_item * ln2).ceil() as u32).max(1);
+        Some(BloomLayer {
+            bits: vec![0; bit_count.div_ceil(8) as usize],
+            bit_count,
+            hash_count,
+            capacity,
+            items: 0,
+        })
+    }
+    /// The bit positions of an item, by double hashing.
+    fn positions(&self, hashes: (u64, u64)) -> impl Iterator<Item = usize> + '_ {
+        (0..self.hash_count as u64).map(move |i| {
+            (hashes.0.wrapping_add(i.wrapping_mul(hashes.1)) % self.bit_count) as usize
+        })
+    }
+    fn contains(&self, hashes: (u64, u64)) -> bool {
+        self.positions(hashes)
+            .all(|position| self.bits[position / 8] & (1 << (position % 8)) != 0)
+    }
+    fn insert(&mut self, hashes: (u64, u64)) {
+        let positions: Vec<usize> = self.positions(hashes).collect();
+        for position in positions {
+            self.bits[position / 8] |= 1 << (position % 8);
+        }
+        self.items += 1;
+    }
+}
+impl ValueBloomFilter {
+    /// Creates a filter, an `expansion` of 0 makes it non scaling.
+    pub fn new(error_rate: f64, capacity: u64, expansion: u32) -> Result<Self, anyhow::Error> {
+        let layer = BloomLayer::new(capacity, error_rate).ok_or(anyhow!("Bad capacity"))?;
+        Ok(ValueBloomFilter {
+            layers: vec![layer],
+            error_rate,
+            expansion,
+        })
+    }
+    /// Adds an item, returns false when it may already be in the filter.
+    pub fn add(&mut self, item: &[u8]) -> Result<bool, anyhow::Error> {
+        let hashes = bloom_hashes(item);
+        if self.layers.iter().any(|layer| layer.contains(hashes)) {
+            return Ok(false);
+        }
+        let last = self
+            .layers
+            .last()
+            .filter(|layer| layer.items < layer.capacity);
+        if last.is_none() {
+            ensure!(self.expansion > 0, "non scaling filter is full");
+            let (capacity, error_rate) = self
+                .layers
+                .last()
+                .map(|layer| (layer.capacity, self.layer_error_rate(self.layers.len())))
+                .unwrap_or((BLOOM_DEFAULT_CAPACITY, self.error_rate));
+            let layer = BloomLayer::new(capacity.saturating_mul(self.expansion as u64), error_rate)
+                .ok_or(anyhow!("Maximum expansion reached"))?;
+            self.layers.push(layer);
+        }
+        if let Some(layer) = self.layers.last_mut() {
+            layer.insert(hashes);
+        }
+        Ok(true)
+    }
+    pub fn exists(&self, item: &[u8]) -> bool {
+        let hashes = bloom_hashes(item);
+        self.layers.iter().any(|layer| layer.contains(hashes))
+    }
+    fn layer_error_rate(&self, layer: usize) -> f64 {
+        self.error_rate * BLOOM_TIGHTENING_RATIO.powi(layer as i32)
+    }
+}
+fn bloom_hashes(item: &[u8]) -> (u64, u64) {
+    let first = murmurhash64a(item, BLOOM_HASH_SEED);
+    (first, murmurhash64a(item, first))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(i: u64) -> Vec<u8> {
+        format!("item:{}", i).into_bytes()
+    }
+
+    #[test]
+    fn no_false_negatives_and_a_bounded_error_rate() {
+        let mut filter = ValueBloomFilter::new(0.01, 1000, 0).unwrap();
+        for i in 0..1000 {
+            filter.add(&item(i)).unwrap();
+        }
+        assert!((0..1000).all(|i| filter.exists(&item(i))));
+        let false_positives = (1000..11_000).filter(|i| filter.exists(&item(*i))).count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn add_reports_items_that_may_exist() {
+        let mut filter = ValueBloomFilter::new(0.01, 100, 2).unwrap();
+        assert!(filter.add(b"a").unwrap());
+        assert!(!filter.add(b"a").unwrap());
+        assert_eq!(filter.layers[0].items, 1);
+    }
+
+    #[test]
+    fn a_full_filter_scales_or_fails() {
+        let mut scaling = ValueBloomFilter::new(0.01, 10, 2).unwrap();
+        let mut non_scaling = ValueBloomFilter::new(0.01, 10, 0).unwrap();
+        let mut full = false;
+        for i in 0..100 {
+            scaling.add(&item(i)).unwrap();
+            full |= non_scaling.add(&item(i)).is_err();
+        }
+        assert!(full);
+        assert!(scaling.layers.len() >= 3);
+        assert_eq!(scaling.layers[1].capacity, 20);
+        assert!(scaling.layers[1].hash_count > scaling.layers[0].hash_count);
+        assert!((0..100).all(|i| scaling.exists(&item(i))));
+    }
+
+    #[test]
+    fn layers_are_bounded() {
+        assert!(ValueBloomFilter::new(0.01, u64::MAX, 2).is_err());
+        assert!(ValueBloomFilter::new(1e-300, 1 << 30, 2).is_err());
+        let mut filter = ValueBloomFilter::new(0.01, 1, u32::MAX).unwrap();
+        assert!(filter.add(b"a").unwrap());
+        let error = filter.add(b"b").unwrap_err();
+        assert_eq!(error.to_string(), "Maximum expansion reached");
+        assert_eq!(filter.layers.len(), 1);
+    }
+}
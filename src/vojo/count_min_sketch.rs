@@ -0,0 +1,155 @@
+use crate::util::hash::murmurhash64a;
+use anyhow::{anyhow, ensure};
+use bincode::{Decode, Encode};
+
+/// The counters are allocated at once, a sketch can not use more than 512MB.
+const CMS_MAX_COUNTERS: usize = 1 << 27;
+
+/// A Count-Min Sketch, which estimates how many times items were counted.
+///
+/// Every item is counted once per row, in a column chosen by hashing it with the
+/// row as seed. The estimate is the smallest of its counters, so it can be too
+/// high but never too low. The counters are small integers, which bincode stores
+/// in a byte or two each.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct ValueCountMinSketch {
+    pub width: u32,
+    pub depth: u32,
+    /// `depth` rows of `width` counters
+    pub counters: Vec<u32>,
+    pub count: u64,
+}
+impl ValueCountMinSketch {
+    pub fn new(width: u32, depth: u32) -> Result<Self, anyhow::Error> {
+        let counter_count = (width as usize)
+            .checked_mul(depth as usize)
+            .filter(|count| *count <= CMS_MAX_COUNTERS)
+            .ok_or(anyhow!("CMS: invalid init arguments"))?;
+        Ok(ValueCountMinSketch {
+            width,
+            depth,
+            counters: vec![0; counter_count],
+            count: 0,
+        })
+    }
+    /// The dimensions for an estimate which is at most `error` times the total
+    /// count too high, with a chance of `probability` to be wrong.
+    pub fn dimensions_for(error: f64, probability: f64) -> (u32, u32) {
+        let width = (2.0 / error).ceil() as u32;
+        let depth = (probability.ln() / 0.5f64.ln()).ceil() as u32;
+        (width.max(1), depth.max(1))
+    }
+    fn positions<'a>(&'a self, item: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        (0..self.depth as usize).map(move |row| {
+            let column = murmurhash64a(item, row as u64) % self.width as u64;
+            row * self.width as usize + column as usize
+        })
+    }
+    /// Counts an item `increment` times, returns its new estimate.
+    pub fn incr_by(&mut self, item: &[u8], increment: u32) -> Result<u32, anyhow::Error> {
+        let positions: Vec<usize> = self.positions(item).collect();
+        ensure!(
+            positions
+                .iter()
+                .all(|position| self.counters[*position].checked_add(increment).is_some()),
+            "CMS: INCRBY overflow"
+        );
+        for position in positions.iter() {
+            self.counters[*position] += increment;
+        }
+        self.count += increment as u64;
+        Ok(self.query(item))
+    }
+    pub fn query(&self, item: &[u8]) -> u32 {
+        self.positions(item)
+            .map(|position| self.counters[position])
+            .min()
+            .unwrap_or(0)
+    }
+    /// Replaces the counters by the weighted sum of the ones of `sources`, which
+    /// must all have the same dimensions.
+    pub fn merge(&mut self, sources: &[(&ValueCountMinSketch, i64)]) -> Result<(), anyhow::Error> {
+        ensure!(
+            sources
+                .iter()
+                .all(|(source, _)| source.width == self.width && source.depth == self.depth),
+            "CMS: width/depth is not equal"
+        );
+        let mut counters = vec![0u32; self.counters.len()];
+        let mut count = 0u64;
+        for (index, counter) in counters.iter_mut().enumerate() {
+            let sum = sources.iter().try_fold(0i64, |sum, (source, weight)| {
+                (source.counters[index] as i64)
+                    .checked_mul(*weight)
+                    .and_then(|value| sum.checked_add(value))
+            });
+            *counter = sum
+                .and_then(|sum| u32::try_from(sum).ok())
+                .ok_or(anyhow!("CMS: MERGE overflow"))?;
+        }
+        for (source, weight) in sources.iter() {
+            let value = (source.count as i64).saturating_mul(*weight);
+            count = count.saturating_add_signed(value);
+        }
+        self.counters = counters;
+        self.count = count;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_are_never_too_low() {
+        let mut sketch = ValueCountMinSketch::new(100, 5).unwrap();
+        for i in 0..1000u32 {
+            sketch
+                .incr_by(format!("{}", i % 50).as_bytes(), i % 3)
+                .unwrap();
+        }
+        for item in 0..50u32 {
+            let expected: u32 = (0..1000).filter(|i| i % 50 == item).map(|i| i % 3).sum();
+            assert!(sketch.query(format!("{}", item).as_bytes()) >= expected);
+        }
+        assert_eq!(sketch.count, (0..1000u64).map(|i| i % 3).sum::<u64>());
+    }
+
+    #[test]
+    fn dimensions_for_the_error_and_probability() {
+        assert_eq!(ValueCountMinSketch::dimensions_for(0.001, 0.01), (2000, 7));
+        assert_eq!(ValueCountMinSketch::dimensions_for(0.5, 0.9), (4, 1));
+    }
+
+    #[test]
+    fn increments_do_not_overflow() {
+        let mut sketch = ValueCountMinSketch::new(10, 2).unwrap();
+        assert_eq!(sketch.incr_by(b"a", u32::MAX).unwrap(), u32::MAX);
+        assert!(sketch.incr_by(b"a", 1).is_err());
+        assert_eq!(sketch.query(b"a"), u32::MAX);
+        assert_eq!(sketch.count, u32::MAX as u64);
+    }
+
+    #[test]
+    fn merge_weights_the_sources() {
+        let mut first = ValueCountMinSketch::new(10, 2).unwrap();
+        let mut second = ValueCountMinSketch::new(10, 2).unwrap();
+        first.incr_by(b"a", 3).unwrap();
+        second.incr_by(b"a", 2).unwrap();
+        let mut target = ValueCountMinSketch::new(10, 2).unwrap();
+        target.merge(&[(&first, 1), (&second, 3)]).unwrap();
+        assert_eq!(target.query(b"a"), 9);
+        assert_eq!(target.count, 9);
+        assert!(target.merge(&[(&first, -1)]).is_err());
+        let other = ValueCountMinSketch::new(5, 2).unwrap();
+        assert!(target.merge(&[(&other, 1)]).is_err());
+        assert_eq!(target.query(b"a"), 9);
+    }
+
+    #[test]
+    fn the_size_is_bounded() {
+        assert!(ValueCountMinSketch::new(u32::MAX, u32::MAX).is_err());
+        assert!(ValueCountMinSketch::new(1 << 20, 1 << 8).is_err());
+    }
+}
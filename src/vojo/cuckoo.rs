@@ -0,0 +1,263 @@
+use crate::util::hash::murmurhash64a;
+use anyhow::{anyhow, ensure};
+use bincode::{Decode, Encode};
+use rand::Rng;
+
+pub const CUCKOO_DEFAULT_CAPACITY: u64 = 1024;
+pub const CUCKOO_DEFAULT_BUCKET_SIZE: u32 = 2;
+pub const CUCKOO_DEFAULT_MAX_ITERATIONS: u32 = 20;
+pub const CUCKOO_DEFAULT_EXPANSION: u32 = 1;
+const CUCKOO_HASH_SEED: u64 = 0;
+/// Fingerprints are a byte, 0 marks an empty slot.
+const CUCKOO_EMPTY: u8 = 0;
+/// The slots of a layer are allocated at once, a layer can not use more than 512MB.
+const CUCKOO_MAX_SLOTS: u64 = 1 << 29;
+
+/// A Cuckoo filter, which unlike a Bloom filter supports deleting items.
+///
+/// Every item has a fingerprint stored in one of two buckets. When both are full,
+/// fingerprints are moved to their other bucket to make room, and when that fails
+/// a new layer is added.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct ValueCuckooFilter {
+    pub layers: Vec<CuckooLayer>,
+    pub bucket_size: u32,
+    pub max_iterations: u32,
+    pub expansion: u32,
+}
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct CuckooLayer {
+    /// `bucket_count * bucket_size` fingerprints
+    pub slots: Vec<u8>,
+    /// A power of two, so that the alternate bucket of the alternate bucket is the first one
+    pub bucket_count: u64,
+    pub items: u64,
+}
+impl CuckooLayer {
+    /// `None` when the layer would need more than [`CUCKOO_MAX_SLOTS`].
+    fn new(capacity: u64, bucket_size: u32) -> Option<Self> {
+        let bucket_count = capacity
+            .div_ceil(bucket_size as u64)
+            .max(1)
+            .checked_next_power_of_two()?;
+        let slot_count = bucket_count.checked_mul(bucket_size as u64)?;
+        if slot_count > CUCKOO_MAX_SLOTS {
+            return None;
+        }
+        Some(CuckooLayer {
+            slots: vec![CUCKOO_EMPTY; slot_count as usize],
+            bucket_count,
+            items: 0,
+        })
+    }
+    fn buckets(&self, hash: u64, fingerprint: u8) -> (u64, u64) {
+        let first = hash & (self.bucket_count - 1);
+        (first, self.alternate(first, fingerprint))
+    }
+    fn alternate(&self, bucket: u64, fingerprint: u8) -> u64 {
+        (bucket ^ (fingerprint as u64).wrapping_mul(0x5bd1_e995)) & (self.bucket_count - 1)
+    }
+    fn bucket(&self, bucket: u64, bucket_size: u32) -> std::ops::Range<usize> {
+        let start = (bucket * bucket_size as u64) as usize;
+        start..start + bucket_size as usize
+    }
+    fn find(&self, bucket: u64, bucket_size: u32, fingerprint: u8) -> Option<usize> {
+        self.bucket(bucket, bucket_size)
+            .find(|slot| self.slots[*slot] == fingerprint)
+    }
+    fn contains(&self, hash: u64, fingerprint: u8, bucket_size: u32) -> bool {
+        let (first, second) = self.buckets(hash, fingerprint);
+        self.find(first, bucket_size, fingerprint).is_some()
+            || self.find(second, bucket_size, fingerprint).is_some()
+    }
+    fn insert(
+        &mut self,
+        hash: u64,
+        fingerprint: u8,
+        bucket_size: u32,
+        max_iterations: u32,
+    ) -> bool {
+        let (first, second) = self.buckets(hash, fingerprint);
+        for bucket in [first, second] {
+            if let Some(slot) = self.find(bucket, bucket_size, CUCKOO_EMPTY) {
+                self.slots[slot] = fingerprint;
+                self.items += 1;
+                return true;
+            }
+        }
+        // Evict fingerprints to their alternate bucket, remembering the swaps so they
+        // can be undone: a fingerprint left without a slot could not be moved to a
+        // new layer, which may have another number of buckets.
+        let mut rng = rand::thread_rng();
+        let mut bucket = if rng.gen_bool(0.5) { first } else { second };
+        let mut fingerprint = fingerprint;
+        let mut swaps = vec![];
+        for _ in 0..max_iterations {
+            let slot =
+                self.bucket(bucket, bucket_size).start + rng.gen_range(0..bucket_size as usize);
+            std::mem::swap(&mut fingerprint, &mut self.slots[slot]);
+            swaps.push(slot);
+            bucket = self.alternate(bucket, fingerprint);
+            if let Some(slot) = self.find(bucket, bucket_size, CUCKOO_EMPTY) {
+                self.slots[slot] = fingerprint;
+                self.items += 1;
+                return true;
+            }
+        }
+        for slot in swaps.into_iter().rev() {
+            std::mem::swap(&mut fingerprint, &mut self.slots[slot]);
+        }
+        false
+    }
+    fn remove(&mut self, hash: u64, fingerprint: u8, bucket_size: u32) -> bool {
+        let (first, second) = self.buckets(hash, fingerprint);
+        for bucket in [first, second] {
+            if let Some(slot) = self.find(bucket, bucket_size, fingerprint) {
+                self.slots[slot] = CUCKOO_EMPTY;
+                self.items -= 1;
+                return true;
+            }
+        }
+        false
+    }
+}
+impl ValueCuckooFilter {
+    pub fn new(
+        capacity: u64,
+        bucket_size: u32,
+        max_iterations: u32,
+        expansion: u32,
+    ) -> Result<Self, anyhow::Error> {
+        let layer = CuckooLayer::new(capacity, bucket_size).ok_or(anyhow!("Bad capacity"))?;
+        Ok(ValueCuckooFilter {
+            layers: vec![layer],
+            bucket_size,
+            max_iterations,
+            expansion,
+        })
+    }
+    /// Adds an item, which may be added several times.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), anyhow::Error> {
+        let (hash, fingerprint) = cuckoo_hash(item);
+        let (bucket_size, max_iterations) = (self.bucket_size, self.max_iterations);
+        for layer in self.layers.iter_mut().rev() {
+            if layer.insert(hash, fingerprint, bucket_size, max_iterations) {
+                return Ok(());
+            }
+        }
+        ensure!(self.expansion > 0, "Filter is full");
+        let capacity = self
+            .layers
+            .last()
+            .map(|layer| layer.bucket_count * bucket_size as u64)
+            .unwrap_or(CUCKOO_DEFAULT_CAPACITY);
+        let mut layer =
+            CuckooLayer::new(capacity.saturating_mul(self.expansion as u64), bucket_size)
+                .ok_or(anyhow!("Maximum expansion reached"))?;
+        layer.insert(hash, fingerprint, bucket_size, max_iterations);
+        self.layers.push(layer);
+        Ok(())
+    }
+    pub fn exists(&self, item: &[u8]) -> bool {
+        let (hash, fingerprint) = cuckoo_hash(item);
+        self.layers
+            .iter()
+            .any(|layer| layer.contains(hash, fingerprint, self.bucket_size))
+    }
+    /// Deletes one occurrence of an item, returns false when it was not found.
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        let (hash, fingerprint) = cuckoo_hash(item);
+        let bucket_size = self.bucket_size;
+        self.layers
+            .iter_mut()
+            .rev()
+            .any(|layer| layer.remove(hash, fingerprint, bucket_size))
+    }
+}
+fn cuckoo_hash(item: &[u8]) -> (u64, u8) {
+    let hash = murmurhash64a(item, CUCKOO_HASH_SEED);
+    // The fingerprint comes from the high bits, the buckets from the low ones
+    let fingerprint = ((hash >> 56) % 255 + 1) as u8;
+    (hash, fingerprint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(i: u64) -> Vec<u8> {
+        format!("item:{}", i).into_bytes()
+    }
+    fn filter(capacity: u64, expansion: u32) -> ValueCuckooFilter {
+        ValueCuckooFilter::new(
+            capacity,
+            CUCKOO_DEFAULT_BUCKET_SIZE,
+            CUCKOO_DEFAULT_MAX_ITERATIONS,
+            expansion,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn add_exists_and_delete() {
+        let mut filter = filter(1024, 1);
+        filter.add(b"a").unwrap();
+        filter.add(b"a").unwrap();
+        assert!(filter.exists(b"a"));
+        assert!(filter.delete(b"a"));
+        assert!(filter.exists(b"a"));
+        assert!(filter.delete(b"a"));
+        assert!(!filter.exists(b"a"));
+        assert!(!filter.delete(b"a"));
+        assert_eq!(filter.layers[0].items, 0);
+    }
+
+    #[test]
+    fn the_alternate_bucket_is_symmetric() {
+        let layer = CuckooLayer::new(1024, 2).unwrap();
+        assert!(layer.bucket_count.is_power_of_two());
+        for i in 0..1000 {
+            let (hash, fingerprint) = cuckoo_hash(&item(i));
+            assert_ne!(fingerprint, CUCKOO_EMPTY);
+            let (first, second) = layer.buckets(hash, fingerprint);
+            assert_eq!(layer.alternate(second, fingerprint), first);
+        }
+    }
+
+    #[test]
+    fn a_failed_insert_loses_no_item() {
+        let mut non_scaling = filter(64, 0);
+        let mut added = vec![];
+        for i in 0..1000 {
+            if non_scaling.add(&item(i)).is_err() {
+                break;
+            }
+            added.push(i);
+        }
+        assert!(added.len() < 1000);
+        assert!(added.iter().all(|i| non_scaling.exists(&item(*i))));
+        assert_eq!(non_scaling.layers[0].items, added.len() as u64);
+    }
+
+    #[test]
+    fn a_full_filter_scales() {
+        let mut filter = filter(64, 2);
+        for i in 0..1000 {
+            filter.add(&item(i)).unwrap();
+        }
+        assert!(filter.layers.len() > 1);
+        assert!(filter.layers[1].bucket_count > filter.layers[0].bucket_count);
+        assert!((0..1000).all(|i| filter.exists(&item(i))));
+    }
+
+    #[test]
+    fn layers_are_bounded() {
+        assert!(ValueCuckooFilter::new(u64::MAX, 2, 20, 1).is_err());
+        assert!(ValueCuckooFilter::new(CUCKOO_MAX_SLOTS + 1, 1, 20, 1).is_err());
+        let mut filter = ValueCuckooFilter::new(1, 1, 0, u32::MAX).unwrap();
+        filter.add(b"a").unwrap();
+        let error = filter.add(b"b").unwrap_err();
+        assert_eq!(error.to_string(), "Maximum expansion reached");
+        assert_eq!(filter.layers.len(), 1);
+    }
+}
@@ -0,0 +1,274 @@
+use crate::util::hash::murmurhash64a;
+use bincode::{Decode, Encode};
+
+/// 2^14 registers give a standard error of 1.04 / sqrt(16384) = 0.81%.
+const HLL_P: u32 = 14;
+const HLL_REGISTERS: usize = 1 << HLL_P;
+const HLL_BITS: usize = 6;
+const HLL_REGISTER_MAX: u8 = (1 << HLL_BITS) - 1;
+/// The number of hash bits left once the register index is taken out.
+const HLL_Q: usize = 64 - HLL_P as usize;
+/// The packed registers, plus one byte so that the last register can be read as a pair of bytes.
+const HLL_DENSE_SIZE: usize = (HLL_REGISTERS * HLL_BITS).div_ceil(8) + 1;
+/// Past this many non-zero registers the sparse form takes about as much memory
+/// as a quarter of the dense one and gets slow to update, so it is converted.
+const HLL_SPARSE_MAX_REGISTERS: usize = 1000;
+const HLL_ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+/// A HyperLogLog estimating the number of unique elements added to it.
+///
+/// Small cardinalities use the sparse form, which only keeps the registers that
+/// are not zero, ordered by index. It is converted to the dense form, where
+/// every register takes 6 bits, once it holds too many registers.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct ValueHyperLogLog {
+    pub registers: HllRegisters,
+}
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub enum HllRegisters {
+    Sparse(Vec<(u16, u8)>),
+    Dense(Vec<u8>),
+}
+impl Default for ValueHyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl ValueHyperLogLog {
+    pub fn new() -> Self {
+        ValueHyperLogLog {
+            registers: HllRegisters::Sparse(vec![]),
+        }
+    }
+    /// Adds an element and returns whether a register was updated.
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let (index, count) = hll_pattern_len(element);
+        self.set_register_max(index, count)
+    }
+    /// Merges the registers of another HyperLogLog, keeping the maximum of each one.
+    pub fn merge(&mut self, other: &ValueHyperLogLog) -> bool {
+        let mut changed = false;
+        other.for_each_register(|index, count| {
+            changed |= self.set_register_max(index, count);
+        });
+        changed
+    }
+    pub fn count(&self) -> u64 {
+        let mut histogram = [0u32; 64];
+        let mut non_zero = 0;
+        self.for_each_register(|_, count| {
+            histogram[count as usize] += 1;
+            non_zero += 1;
+        });
+        histogram[0] += (HLL_REGISTERS - non_zero) as u32;
+        hll_estimate(&histogram)
+    }
+    /// Calls `f` with the index and value of every register that is not zero.
+    fn for_each_register<F: FnMut(usize, u8)>(&self, mut f: F) {
+        match &self.registers {
+            HllRegisters::Sparse(registers) => {
+                for (index, count) in registers {
+                    f(*index as usize, *count);
+                }
+            }
+            HllRegisters::Dense(registers) => {
+                for index in 0..HLL_REGISTERS {
+                    let count = dense_get(registers, index);
+                    if count > 0 {
+                        f(index, count);
+                    }
+                }
+            }
+        }
+    }
+    fn set_register_max(&mut self, index: usize, count: u8) -> bool {
+        match &mut self.registers {
+            HllRegisters::Sparse(registers) => {
+                match registers.binary_search_by_key(&(index as u16), |(index, _)| *index) {
+                    Ok(position) => {
+                        if registers[position].1 >= count {
+                            return false;
+                        }
+                        registers[position].1 = count;
+                    }
+                    Err(position) => {
+                        registers.insert(position, (index as u16, count));
+                        if registers.len() > HLL_SPARSE_MAX_REGISTERS {
+                            self.promote_to_dense();
+                        }
+                    }
+                }
+                true
+            }
+            HllRegisters::Dense(registers) => {
+                if dense_get(registers, index) >= count {
+                    return false;
+                }
+                dense_set(registers, index, count);
+                true
+            }
+        }
+    }
+    fn promote_to_dense(&mut self) {
+        if let HllRegisters::Sparse(sparse) = &self.registers {
+            let mut dense = vec![0u8; HLL_DENSE_SIZE];
+            for (index, count) in sparse {
+                dense_set(&mut dense, *index as usize, *count);
+            }
+            self.registers = HllRegisters::Dense(dense);
+        }
+    }
+}
+fn dense_get(registers: &[u8], index: usize) -> u8 {
+    let byte = index * HLL_BITS / 8;
+    let first_bit = index * HLL_BITS % 8;
+    let value =
+        (registers[byte] as u16 >> first_bit) | ((registers[byte + 1] as u16) << (8 - first_bit));
+    (value as u8) & HLL_REGISTER_MAX
+}
+fn dense_set(registers: &mut [u8], index: usize, count: u8) {
+    let byte = index * HLL_BITS / 8;
+    let first_bit = index * HLL_BITS % 8;
+    let value = (count & HLL_REGISTER_MAX) as u16;
+    let mask = (HLL_REGISTER_MAX as u16) << first_bit;
+    registers[byte] = (registers[byte] & !(mask as u8)) | ((value << first_bit) as u8);
+    registers[byte + 1] =
+        (registers[byte + 1] & !((mask >> 8) as u8)) | ((value << first_bit) >> 8) as u8;
+}
+/// Hashes the element and returns the register it goes to, and the position of the
+/// first set bit in the rest of the hash.
+fn hll_pattern_len(element: &[u8]) -> (usize, u8) {
+    let hash = murmurhash64a(element, 0xadc8_3b19);
+    let index = (hash & (HLL_REGISTERS as u64 - 1)) as usize;
+    // The guard bit makes sure the count is at most HLL_Q + 1.
+    let rest = (hash >> HLL_P) | (1u64 << HLL_Q);
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+/// The cardinality estimator from Otmar Ertl, "New cardinality estimation algorithms
+/// for HyperLogLog sketches", which needs no bias correction.
+fn hll_estimate(histogram: &[u32; 64]) -> u64 {
+    let m = HLL_REGISTERS as f64;
+    let mut z = m * hll_tau((m - histogram[HLL_Q + 1] as f64) / m);
+    for j in (1..=HLL_Q).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * hll_sigma(histogram[0] as f64 / m);
+    (HLL_ALPHA_INF * m * m / z).round() as u64
+}
+fn hll_sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let z_prime = z;
+        z += x * y;
+        y += y;
+        if z_prime == z {
+            return z;
+        }
+    }
+}
+fn hll_tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let z_prime = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z_prime == z {
+            return z / 3.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(range: std::ops::Range<u32>) -> ValueHyperLogLog {
+        let mut hll = ValueHyperLogLog::new();
+        for i in range {
+            hll.add(format!("element:{}", i).as_bytes());
+        }
+        hll
+    }
+    fn assert_close(estimate: u64, expected: u64) {
+        let error = (estimate as f64 - expected as f64).abs() / expected as f64;
+        // Five standard errors
+        assert!(error < 0.04, "estimated {} for {}", estimate, expected);
+    }
+
+    #[test]
+    fn an_empty_hll_counts_zero() {
+        assert_eq!(ValueHyperLogLog::new().count(), 0);
+    }
+
+    #[test]
+    fn small_cardinalities() {
+        let mut hll = ValueHyperLogLog::new();
+        assert!(hll.add(b"a"));
+        assert!(!hll.add(b"a"));
+        assert!(hll.add(b"b"));
+        assert_eq!(hll.count(), 2);
+        assert!(filled(0..100).count().abs_diff(100) <= 1);
+    }
+
+    #[test]
+    fn the_estimator_stays_within_the_standard_error() {
+        for expected in [1_000, 10_000, 100_000, 1_000_000] {
+            let hll = filled(0..expected);
+            assert_close(hll.count(), expected as u64);
+        }
+    }
+
+    #[test]
+    fn sparse_registers_are_promoted() {
+        assert!(matches!(
+            filled(0..10_000).registers,
+            HllRegisters::Dense(_)
+        ));
+    }
+
+    #[test]
+    fn promotion_keeps_the_registers() {
+        let sparse = filled(0..HLL_SPARSE_MAX_REGISTERS as u32 / 2);
+        let mut dense = sparse.clone();
+        dense.promote_to_dense();
+        assert!(matches!(sparse.registers, HllRegisters::Sparse(_)));
+        assert!(matches!(dense.registers, HllRegisters::Dense(_)));
+        assert_eq!(sparse.count(), dense.count());
+        let mut sparse_registers = vec![];
+        sparse.for_each_register(|index, count| sparse_registers.push((index, count)));
+        let mut dense_registers = vec![];
+        dense.for_each_register(|index, count| dense_registers.push((index, count)));
+        assert_eq!(sparse_registers, dense_registers);
+    }
+
+    #[test]
+    fn dense_registers_do_not_overlap() {
+        let mut registers = vec![0u8; HLL_DENSE_SIZE];
+        for index in 0..HLL_REGISTERS {
+            dense_set(&mut registers, index, (index % 64) as u8);
+        }
+        for index in 0..HLL_REGISTERS {
+            assert_eq!(dense_get(&registers, index), (index % 64) as u8);
+        }
+    }
+
+    #[test]
+    fn merge_counts_the_union() {
+        let mut first = filled(0..30_000);
+        let second = filled(20_000..50_000);
+        assert!(first.merge(&second));
+        assert_close(first.count(), 50_000);
+        assert!(!first.merge(&second));
+    }
+}
@@ -0,0 +1,313 @@
+use crate::util::json_path::{get, get_mut, remove, JsonPath};
+use anyhow::anyhow;
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{impl_borrow_decode, Decode, Encode};
+use serde_json::{Number, Value};
+
+/// A JSON document, persisted as its serialized text.
+#[derive(PartialEq, Debug, Clone)]
+pub struct ValueJson {
+    pub data: Value,
+}
+impl Encode for ValueJson {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        let text =
+            serde_json::to_vec(&self.data).map_err(|e| EncodeError::OtherString(e.to_string()))?;
+        text.encode(encoder)
+    }
+}
+impl<Context> Decode<Context> for ValueJson {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let text: Vec<u8> = Decode::decode(decoder)?;
+        let data =
+            serde_json::from_slice(&text).map_err(|e| DecodeError::OtherString(e.to_string()))?;
+        Ok(ValueJson { data })
+    }
+}
+impl_borrow_decode!(ValueJson);
+
+/// The INDENT, NEWLINE and SPACE options of JSON.GET.
+#[derive(Debug, Clone, Default)]
+pub struct JsonFormat {
+    pub indent: String,
+    pub newline: String,
+    pub space: String,
+}
+
+impl ValueJson {
+    pub fn new(data: Value) -> Self {
+        ValueJson { data }
+    }
+    pub fn get(&self, path: &JsonPath) -> Vec<&Value> {
+        path.select(&self.data)
+            .iter()
+            .filter_map(|location| get(&self.data, location))
+            .collect()
+    }
+    /// Replaces the matched values, or adds the value when the path names a missing
+    /// key of an object. Returns whether anything was set.
+    pub fn set(&mut self, path: &JsonPath, value: Value, nx: bool, xx: bool) -> bool {
+        let locations = path.select(&self.data);
+        if !locations.is_empty() {
+            if nx {
+                return false;
+            }
+            for location in locations {
+                if let Some(node) = get_mut(&mut self.data, &location) {
+                    *node = value.clone();
+                }
+            }
+            return true;
+        }
+        if xx {
+            return false;
+        }
+        let Some((parent, key)) = path.split_last_key() else {
+            return false;
+        };
+        let mut set = false;
+        for location in parent.select(&self.data) {
+            if let Some(Value::Object(map)) = get_mut(&mut self.data, &location) {
+                map.insert(key.clone(), value.clone());
+                set = true;
+            }
+        }
+        set
+    }
+    /// Deletes the matched values, the root can not be deleted this way.
+    pub fn delete(&mut self, path: &JsonPath) -> usize {
+        let mut locations = path.select(&self.data);
+        // Children and the last items of arrays go first, so that the other
+        // locations stay valid.
+        locations.sort_unstable_by(|a, b| b.cmp(a));
+        locations.dedup();
+        locations
+            .iter()
+            .filter(|location| remove(&mut self.data, location))
+            .count()
+    }
+    /// Increments the matched numbers, returns their new values, `None` for the
+    /// values which are not numbers.
+    pub fn num_incr_by(
+        &mut self,
+        path: &JsonPath,
+        by: &Number,
+    ) -> Result<Vec<Option<Value>>, anyhow::Error> {
+        let mut results = vec![];
+        for location in path.select(&self.data) {
+            let Some(Value::Number(number)) = get_mut(&mut self.data, &location) else {
+                results.push(None);
+                continue;
+            };
+            let sum = match (number.as_i64(), by.as_i64()) {
+                (Some(a), Some(b)) if a.checked_add(b).is_some() => Number::from(a + b),
+                _ => {
+                    let sum = number.as_f64().unwrap_or(0.0) + by.as_f64().unwrap_or(0.0);
+                    Number::from_f64(sum).ok_or(anyhow!("result is not a number"))?
+                }
+            };
+            *number = sum.clone();
+            results.push(Some(Value::Number(sum)));
+        }
+        Ok(results)
+    }
+    pub fn str_append(&mut self, path: &JsonPath, suffix: &str) -> Vec<Option<usize>> {
+        self.map_matches(path, |value| match value {
+            Value::String(text) => {
+                text.push_str(suffix);
+                Some(text.chars().count())
+            }
+            _ => None,
+        })
+    }
+    pub fn arr_append(&mut self, path: &JsonPath, values: &[Value]) -> Vec<Option<usize>> {
+        self.map_matches(path, |value| match value {
+            Value::Array(array) => {
+                array.extend_from_slice(values);
+                Some(array.len())
+            }
+            _ => None,
+        })
+    }
+    /// Removes the item at `index` of the matched arrays, which counts from the end when
+    /// negative and is clamped to the array. The inner `None` is for an empty array.
+    pub fn arr_pop(&mut self, path: &JsonPath, index: i64) -> Vec<Option<Option<Value>>> {
+        self.map_matches(path, |value| match value {
+            Value::Array(array) => {
+                if array.is_empty() {
+                    return Some(None);
+                }
+                let len = array.len() as i64;
+                let index = if index < 0 { len + index } else { index };
+                Some(Some(array.remove(index.clamp(0, len - 1) as usize)))
+            }
+            _ => None,
+        })
+    }
+    pub fn obj_keys(&self, path: &JsonPath) -> Vec<Option<Vec<String>>> {
+        self.get(path)
+            .into_iter()
+            .map(|value| value.as_object().map(|map| map.keys().cloned().collect()))
+            .collect()
+    }
+    pub fn types(&self, path: &JsonPath) -> Vec<&'static str> {
+        self.get(path).into_iter().map(type_name).collect()
+    }
+    fn map_matches<T, F: FnMut(&mut Value) -> Option<T>>(
+        &mut self,
+        path: &JsonPath,
+        mut f: F,
+    ) -> Vec<Option<T>> {
+        path.select(&self.data)
+            .iter()
+            .map(|location| get_mut(&mut self.data, location).and_then(&mut f))
+            .collect()
+    }
+}
+pub fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+pub fn parse_json(data: &[u8]) -> Result<Value, anyhow::Error> {
+    serde_json::from_slice(data).map_err(|e| anyhow!("expected value: {}", e))
+}
+/// Serializes a value, using the separators of `format` when any is set.
+pub fn format_json(value: &Value, format: &JsonFormat) -> Vec<u8> {
+    if format.indent.is_empty() && format.newline.is_empty() && format.space.is_empty() {
+        return serde_json::to_vec(value).unwrap_or_default();
+    }
+    let mut out = String::new();
+    write_json(value, format, 0, &mut out);
+    out.into_bytes()
+}
+fn write_json(value: &Value, format: &JsonFormat, depth: usize, out: &mut String) {
+    let (open, close, items): (char, char, Vec<(Option<&String>, &Value)>) = match value {
+        Value::Object(map) if !map.is_empty() => {
+            ('{', '}', map.iter().map(|(k, v)| (Some(k), v)).collect())
+        }
+        Value::Array(array) if !array.is_empty() => {
+            ('[', ']', array.iter().map(|v| (None, v)).collect())
+        }
+        _ => {
+            out.push_str(&value.to_string());
+            return;
+        }
+    };
+    out.push(open);
+    for (i, (key, item)) in items.into_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(&format.newline);
+        out.push_str(&format.indent.repeat(depth + 1));
+        if let Some(key) = key {
+            out.push_str(&Value::String(key.clone()).to_string());
+            out.push(':');
+            out.push_str(&format.space);
+        }
+        write_json(item, format, depth + 1, out);
+    }
+    out.push_str(&format.newline);
+    out.push_str(&format.indent.repeat(depth));
+    out.push(close);
+}
+/// Checks that the number argument of JSON.NUMINCRBY is a number.
+pub fn parse_number(data: &[u8]) -> Result<Number, anyhow::Error> {
+    match parse_json(data)? {
+        Value::Number(number) => Ok(number),
+        other => Err(anyhow!("expected a number but found {}", type_name(&other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn path(text: &str) -> JsonPath {
+        JsonPath::parse(text).unwrap()
+    }
+
+    #[test]
+    fn set_honours_nx_and_xx() {
+        let mut json = ValueJson::new(json!({"a": 1}));
+        assert!(!json.set(&path("$.a"), json!(2), true, false));
+        assert!(json.set(&path("$.a"), json!(2), false, true));
+        assert!(!json.set(&path("$.b"), json!(3), false, true));
+        assert!(json.set(&path("$.b"), json!(3), true, false));
+        assert!(!json.set(&path("$.c.d"), json!(4), false, false));
+        assert_eq!(json.data, json!({"a": 2, "b": 3}));
+    }
+
+    #[test]
+    fn delete_keeps_the_other_locations_valid() {
+        let mut json = ValueJson::new(json!({"a": [1, 2, 3, 4], "b": {"a": 5}}));
+        assert_eq!(json.delete(&path("$.a[0,2]")), 2);
+        assert_eq!(json.data, json!({"a": [2, 4], "b": {"a": 5}}));
+        assert_eq!(json.delete(&path("$..a")), 2);
+        assert_eq!(json.data, json!({"b": {}}));
+        assert_eq!(json.delete(&path("$")), 0);
+    }
+
+    #[test]
+    fn num_incr_by_keeps_integers_when_it_can() {
+        let mut json = ValueJson::new(json!({"a": 1, "b": 1.5, "c": "x"}));
+        let by = parse_number(b"2").unwrap();
+        let results = json.num_incr_by(&path("$.*"), &by).unwrap();
+        assert_eq!(results, vec![Some(json!(3)), Some(json!(3.5)), None]);
+        assert_eq!(type_name(&json.data["a"]), "integer");
+        let mut json = ValueJson::new(json!({"a": i64::MAX}));
+        let results = json.num_incr_by(&path("$.a"), &by).unwrap();
+        assert_eq!(type_name(results[0].as_ref().unwrap()), "number");
+        assert!(parse_number(b"\"2\"").is_err());
+    }
+
+    #[test]
+    fn array_and_string_helpers() {
+        let mut json = ValueJson::new(json!({"a": [1, 2, 3], "b": [], "s": "ab"}));
+        assert_eq!(json.arr_append(&path("$.a"), &[json!(4)]), vec![Some(4)]);
+        assert_eq!(json.arr_pop(&path("$.a"), -1), vec![Some(Some(json!(4)))]);
+        assert_eq!(json.arr_pop(&path("$.a"), 99), vec![Some(Some(json!(3)))]);
+        assert_eq!(json.arr_pop(&path("$.b"), 0), vec![Some(None)]);
+        assert_eq!(json.arr_pop(&path("$.s"), 0), vec![None]);
+        assert_eq!(json.str_append(&path("$.s"), "cé"), vec![Some(4)]);
+        assert_eq!(
+            json.obj_keys(&path("$")),
+            vec![Some(vec!["a".to_owned(), "b".to_owned(), "s".to_owned()])]
+        );
+    }
+
+    #[test]
+    fn format_with_separators() {
+        let value = json!({"a": [1, {}], "b": "x"});
+        assert_eq!(
+            format_json(&value, &JsonFormat::default()),
+            br#"{"a":[1,{}],"b":"x"}"#
+        );
+        let format = JsonFormat {
+            indent: "  ".to_owned(),
+            newline: "\n".to_owned(),
+            space: " ".to_owned(),
+        };
+        let expected = "{\n  \"a\": [\n    1,\n    {}\n  ],\n  \"b\": \"x\"\n}";
+        assert_eq!(format_json(&value, &format), expected.as_bytes());
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let json = ValueJson::new(json!({"b": 1, "a": [true, null, 1.5]}));
+        let config = bincode::config::standard();
+        let bytes = bincode::encode_to_vec(&json, config).unwrap();
+        let (decoded, _): (ValueJson, usize) = bincode::decode_from_slice(&bytes, config).unwrap();
+        assert_eq!(decoded, json);
+    }
+}
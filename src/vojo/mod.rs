@@ -1,3 +1,13 @@
-pub mod client;
+pub mod bloom;
+pub mod count_min_sketch;
+pub mod cuckoo;
+pub mod hyperloglog;
+pub mod json;
+#[cfg(feature = "native-modules")]
+pub mod module_value;
 pub mod parsered_command;
+pub mod stream;
+pub mod timeseries;
+pub mod topk;
 pub mod value;
+pub mod vector_set;
@@ -0,0 +1,69 @@
+use crate::module::native::decode_hook;
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{impl_borrow_decode, Decode, Encode};
+use std::any::Any;
+use std::fmt::Debug;
+
+/// The data of a value type registered by a native module.
+pub trait ModuleData: Any + Debug + Send + Sync {
+    /// The bytes written to the snapshot, they are read back by the decode hook
+    /// the type was registered with.
+    fn encode(&self) -> Vec<u8>;
+    fn clone_data(&self) -> Box<dyn ModuleData>;
+}
+
+/// A value of a type registered by a native module, stored under its type name.
+#[derive(Debug)]
+pub struct ModuleValue {
+    pub type_name: String,
+    pub data: Box<dyn ModuleData>,
+}
+impl ModuleValue {
+    pub fn new(type_name: &str, data: impl ModuleData) -> Self {
+        ModuleValue {
+            type_name: type_name.to_string(),
+            data: Box::new(data),
+        }
+    }
+    pub fn downcast_ref<T: ModuleData>(&self) -> Option<&T> {
+        (&*self.data as &dyn Any).downcast_ref()
+    }
+    pub fn downcast_mut<T: ModuleData>(&mut self) -> Option<&mut T> {
+        (&mut *self.data as &mut dyn Any).downcast_mut()
+    }
+}
+impl Clone for ModuleValue {
+    fn clone(&self) -> Self {
+        ModuleValue {
+            type_name: self.type_name.clone(),
+            data: self.data.clone_data(),
+        }
+    }
+}
+/// Two values are equal when they would be written to the snapshot the same way.
+impl PartialEq for ModuleValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.type_name == other.type_name && self.data.encode() == other.data.encode()
+    }
+}
+impl Encode for ModuleValue {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.type_name.encode(encoder)?;
+        self.data.encode().encode(encoder)
+    }
+}
+impl<Context> Decode<Context> for ModuleValue {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let type_name = String::decode(decoder)?;
+        let encoded = Vec::<u8>::decode(decoder)?;
+        let decode = decode_hook(&type_name).ok_or(DecodeError::OtherString(format!(
+            "no module registers the type {}",
+            type_name
+        )))?;
+        let data = decode(&encoded).map_err(|e| DecodeError::OtherString(e.to_string()))?;
+        Ok(ModuleValue { type_name, data })
+    }
+}
+impl_borrow_decode!(ModuleValue);
@@ -2,7 +2,6 @@ use std::collections::Bound;
 use std::str::from_utf8;
 
 use anyhow::anyhow;
-use std::f64::{INFINITY, NEG_INFINITY};
 
 /// A command argument
 #[derive(Debug, Clone)]
@@ -13,6 +12,39 @@ pub struct Argument {
     pub len: usize,
 }
 
+/// A lexicographical range bound used by the sorted set *BYLEX commands
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexBound {
+    /// `-`, lower than every member
+    NegInf,
+    /// `+`, greater than every member
+    PosInf,
+    /// `[member`
+    Included(Vec<u8>),
+    /// `(member`
+    Excluded(Vec<u8>),
+}
+impl LexBound {
+    /// Whether `member` is not lower than this bound used as a minimum
+    pub fn is_below(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::NegInf => true,
+            LexBound::PosInf => false,
+            LexBound::Included(min) => member >= min.as_slice(),
+            LexBound::Excluded(min) => member > min.as_slice(),
+        }
+    }
+    /// Whether `member` is not greater than this bound used as a maximum
+    pub fn is_above(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::NegInf => false,
+            LexBound::PosInf => true,
+            LexBound::Included(max) => member <= max.as_slice(),
+            LexBound::Excluded(max) => member < max.as_slice(),
+        }
+    }
+}
+
 /// A protocol parser
 pub struct ParsedCommand {
     /// The data itself
@@ -25,7 +57,21 @@ impl ParsedCommand {
     pub fn new(data: Vec<u8>, argv: Vec<Argument>) -> ParsedCommand {
         ParsedCommand { data, argv }
     }
-    /// Gets a `Bound` from a parameter.
+    /// Creates a parser holding the arguments, e.g. for a command called from a script
+    pub fn from_args(args: Vec<Vec<u8>>) -> ParsedCommand {
+        let mut data = vec![];
+        let mut argv = vec![];
+        for arg in args {
+            argv.push(Argument {
+                pos: data.len(),
+                len: arg.len(),
+            });
+            data.extend(arg);
+        }
+        ParsedCommand { data, argv }
+    }
+    /// Gets the bound of a score range, where `-inf` and `+inf` are scores like
+    /// any other: `+inf` as a minimum only matches the infinite scores.
     ///
     /// # Examples
     ///
@@ -33,41 +79,44 @@ impl ParsedCommand {
     /// # use std::collections::Bound;
     /// # use parser::{ParsedCommand, Argument};
     /// let parser = ParsedCommand::new(b"+inf", vec![Argument { pos: 0, len: 4 }]);
-    /// assert_eq!(parser.get_f64_bound(0).unwrap(), Bound::Unbounded);
-    /// ```
-    ///
-    /// ```
-    /// # use std::collections::Bound;
-    /// # use parser::{ParsedCommand, Argument};
-    /// let parser = ParsedCommand::new(b"1.23", vec![Argument { pos: 0, len: 4 }]);
-    /// assert_eq!(parser.get_f64_bound(0).unwrap(), Bound::Included(1.23));
+    /// assert_eq!(parser.get_score_bound(0).unwrap(), Bound::Included(f64::INFINITY));
     /// ```
     ///
     /// ```
     /// # use std::collections::Bound;
     /// # use parser::{ParsedCommand, Argument};
     /// let parser = ParsedCommand::new(b"(1.23", vec![Argument { pos: 0, len: 5 }]);
-    /// assert_eq!(parser.get_f64_bound(0).unwrap(), Bound::Excluded(1.23));
+    /// assert_eq!(parser.get_score_bound(0).unwrap(), Bound::Excluded(1.23));
     /// ```
-    pub fn get_f64_bound(&self, pos: usize) -> Result<Bound<f64>, anyhow::Error> {
+    pub fn get_score_bound(&self, pos: usize) -> Result<Bound<f64>, anyhow::Error> {
         let s = self.get_str(pos)?;
-        if s == "inf" || s == "+inf" || s == "-inf" {
-            return Ok(Bound::Unbounded);
-        }
-
-        if s.starts_with('(') {
-            let f = s[1..].parse::<f64>()?;
+        if let Some(stripped) = s.strip_prefix('(') {
+            let f = stripped.parse::<f64>()?;
             if f.is_nan() {
                 return Err(anyhow!("InvalidArgument"));
             }
             return Ok(Bound::Excluded(f));
         }
-        let f = s.parse::<f64>()?;
+        Ok(Bound::Included(self.get_f64(pos)?))
+    }
 
-        if f.is_nan() {
-            Err(anyhow!("InvalidArgument"))
-        } else {
-            Ok(Bound::Included(f))
+    /// Gets a `LexBound` from a parameter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use parser::{ParsedCommand, Argument, LexBound};
+    /// let parser = ParsedCommand::new(b"[abc", vec![Argument { pos: 0, len: 4 }]);
+    /// assert_eq!(parser.get_lex_bound(0).unwrap(), LexBound::Included(b"abc".to_vec()));
+    /// ```
+    pub fn get_lex_bound(&self, pos: usize) -> Result<LexBound, anyhow::Error> {
+        let data = self.get_slice(pos)?;
+        match data.first() {
+            Some(b'-') if data.len() == 1 => Ok(LexBound::NegInf),
+            Some(b'+') if data.len() == 1 => Ok(LexBound::PosInf),
+            Some(b'[') => Ok(LexBound::Included(data[1..].to_vec())),
+            Some(b'(') => Ok(LexBound::Excluded(data[1..].to_vec())),
+            _ => Err(anyhow!("min or max not valid string range item")),
         }
     }
 
@@ -85,10 +134,10 @@ impl ParsedCommand {
     pub fn get_f64(&self, pos: usize) -> Result<f64, anyhow::Error> {
         let s = self.get_str(pos)?;
         if s == "+inf" || s == "inf" {
-            return Ok(INFINITY);
+            return Ok(f64::INFINITY);
         }
         if s == "-inf" {
-            return Ok(NEG_INFINITY);
+            return Ok(f64::NEG_INFINITY);
         }
         let f = s.parse::<f64>()?;
         if f.is_nan() {
@@ -157,8 +206,4 @@ impl ParsedCommand {
         let arg = &self.argv[pos];
         Ok(&self.data[arg.pos..arg.pos + arg.len])
     }
-
-    pub fn get_data(&self) -> Vec<u8> {
-        self.data.clone()
-    }
 }
@@ -0,0 +1,731 @@
+use anyhow::{anyhow, ensure};
+use bincode::{Decode, Encode};
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::Bound;
+
+/// The fields of an entry, stored flat as `field, value, field, value, ...`.
+pub type StreamFields = Vec<Vec<u8>>;
+
+#[derive(Encode, Decode, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+    pub fn new(ms: u64, seq: u64) -> Self {
+        StreamId { ms, seq }
+    }
+    /// Parses `<ms>-<seq>`, the sequence is `missing_seq` when only `<ms>` is given.
+    pub fn parse(data: &[u8], missing_seq: u64) -> Result<StreamId, anyhow::Error> {
+        let invalid = || anyhow!("Invalid stream ID specified as stream command argument");
+        let text = std::str::from_utf8(data).map_err(|_| invalid())?;
+        let (ms, seq) = match text.split_once('-') {
+            Some((ms, seq)) => (ms, seq.parse::<u64>().map_err(|_| invalid())?),
+            None => (text, missing_seq),
+        };
+        let ms = ms.parse::<u64>().map_err(|_| invalid())?;
+        Ok(StreamId { ms, seq })
+    }
+    pub fn to_bytes(self) -> Vec<u8> {
+        format!("{}-{}", self.ms, self.seq).into_bytes()
+    }
+    /// The smallest ID greater than this one.
+    pub fn next(&self) -> Option<StreamId> {
+        if self.seq < u64::MAX {
+            Some(StreamId::new(self.ms, self.seq + 1))
+        } else if self.ms < u64::MAX {
+            Some(StreamId::new(self.ms + 1, 0))
+        } else {
+            None
+        }
+    }
+    /// The greatest ID smaller than this one.
+    pub fn prev(&self) -> Option<StreamId> {
+        if self.seq > 0 {
+            Some(StreamId::new(self.ms, self.seq - 1))
+        } else if self.ms > 0 {
+            Some(StreamId::new(self.ms - 1, u64::MAX))
+        } else {
+            None
+        }
+    }
+}
+/// The ID given to XADD.
+#[derive(Debug, Clone, Copy)]
+pub enum StreamIdSpec {
+    /// `*`, both parts are generated
+    Auto,
+    /// `<ms>-*`, the sequence is generated
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+impl StreamIdSpec {
+    pub fn parse(data: &[u8]) -> Result<StreamIdSpec, anyhow::Error> {
+        if data == b"*" {
+            return Ok(StreamIdSpec::Auto);
+        }
+        if let Some(ms) = data.strip_suffix(b"-*") {
+            let id = StreamId::parse(ms, 0)?;
+            ensure!(
+                !ms.contains(&b'-'),
+                "Invalid stream ID specified as stream command argument"
+            );
+            return Ok(StreamIdSpec::AutoSeq(id.ms));
+        }
+        Ok(StreamIdSpec::Explicit(StreamId::parse(data, 0)?))
+    }
+}
+#[derive(Debug, Clone, Copy)]
+pub enum TrimStrategy {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+/// The MAXLEN and MINID options of XADD and XTRIM, a `limit` of 0 means no limit.
+#[derive(Debug, Clone, Copy)]
+pub struct TrimOptions {
+    pub strategy: TrimStrategy,
+    pub limit: usize,
+}
+/// The options of XCLAIM that change how the claimed entries are recorded.
+#[derive(Debug, Clone, Default)]
+pub struct ClaimOptions {
+    /// The delivery time to set, the current time when not given
+    pub delivery_time: Option<i64>,
+    pub retry_count: Option<u64>,
+    pub force: bool,
+    pub just_id: bool,
+    pub last_id: Option<StreamId>,
+}
+/// An append-only log of entries, ordered by ID, with its consumer groups.
+#[derive(PartialEq, Debug, Clone, Encode, Decode, Default)]
+pub struct ValueStream {
+    pub entries: BTreeMap<StreamId, StreamFields>,
+    pub last_id: StreamId,
+    /// The greatest ID removed by XDEL
+    pub max_deleted_id: StreamId,
+    /// The number of entries added over the lifetime of the stream
+    pub entries_added: u64,
+    pub groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+#[derive(PartialEq, Debug, Clone, Encode, Decode, Default)]
+pub struct ConsumerGroup {
+    pub last_delivered_id: StreamId,
+    /// The logical position of `last_delivered_id`, `None` when it is not known
+    pub entries_read: Option<u64>,
+    /// The entries delivered to a consumer and not acknowledged yet
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct PendingEntry {
+    pub consumer: Vec<u8>,
+    /// The last delivery time in milliseconds
+    pub delivery_time: i64,
+    pub delivery_count: u64,
+}
+#[derive(PartialEq, Debug, Clone, Encode, Decode, Default)]
+pub struct Consumer {
+    /// The last time the consumer attempted an interaction, in milliseconds
+    pub seen_time: i64,
+    /// The last time the consumer read or claimed an entry, -1 if it never did
+    pub active_time: i64,
+    pub pending: BTreeSet<StreamId>,
+}
+impl ValueStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn first_entry(&self) -> Option<(&StreamId, &StreamFields)> {
+        self.entries.first_key_value()
+    }
+    pub fn last_entry(&self) -> Option<(&StreamId, &StreamFields)> {
+        self.entries.last_key_value()
+    }
+    /// Appends an entry and returns its ID, `now` is used for the generated IDs.
+    pub fn add(
+        &mut self,
+        spec: StreamIdSpec,
+        fields: StreamFields,
+        now: u64,
+    ) -> Result<StreamId, anyhow::Error> {
+        let last = self.last_id;
+        let id = match spec {
+            StreamIdSpec::Auto => {
+                if now > last.ms {
+                    StreamId::new(now, 0)
+                } else {
+                    last.next().ok_or(anyhow!(
+                        "The stream has exhausted the last possible ID, unable to add more items"
+                    ))?
+                }
+            }
+            StreamIdSpec::AutoSeq(ms) => {
+                if ms == last.ms {
+                    ensure!(
+                        last.seq < u64::MAX,
+                        "The ID specified in XADD is equal or smaller than the target stream top item"
+                    );
+                    StreamId::new(ms, last.seq + 1)
+                } else {
+                    StreamId::new(ms, 0)
+                }
+            }
+            StreamIdSpec::Explicit(id) => id,
+        };
+        ensure!(
+            id != StreamId::MIN,
+            "The ID specified in XADD must be greater than 0-0"
+        );
+        ensure!(
+            id > last,
+            "The ID specified in XADD is equal or smaller than the target stream top item"
+        );
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        self.entries_added += 1;
+        Ok(id)
+    }
+    /// Removes the oldest entries, returns how many were removed.
+    pub fn trim(&mut self, options: &TrimOptions) -> usize {
+        let mut removed = 0;
+        while options.limit == 0 || removed < options.limit {
+            let Some(first) = self.entries.keys().next().copied() else {
+                break;
+            };
+            let evict = match options.strategy {
+                TrimStrategy::MaxLen(max_len) => self.entries.len() > max_len,
+                TrimStrategy::MinId(min_id) => first < min_id,
+            };
+            if !evict {
+                break;
+            }
+            self.entries.remove(&first);
+            removed += 1;
+        }
+        removed
+    }
+    /// The entries between `start` and `end`, both included.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        rev: bool,
+    ) -> Vec<(StreamId, StreamFields)> {
+        if start > end {
+            return vec![];
+        }
+        let range = self
+            .entries
+            .range((Bound::Included(start), Bound::Included(end)))
+            .map(|(id, fields)| (*id, fields.clone()));
+        let count = count.unwrap_or(usize::MAX);
+        if rev {
+            range.rev().take(count).collect()
+        } else {
+            range.take(count).collect()
+        }
+    }
+    pub fn delete(&mut self, ids: &[StreamId]) -> usize {
+        let mut deleted = 0;
+        for id in ids {
+            if self.entries.remove(id).is_some() {
+                deleted += 1;
+                self.max_deleted_id = self.max_deleted_id.max(*id);
+            }
+        }
+        deleted
+    }
+    /// The number of entries added up to `id`, when it can be known without counting.
+    pub fn entries_read_at(&self, id: StreamId) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        if id >= self.last_id || self.entries.is_empty() {
+            return Some(self.entries_added);
+        }
+        let first = *self.entries.keys().next()?;
+        // Before the first entry, with no deleted entry in the way, nothing is left to read
+        // but the whole stream.
+        if id < first && self.max_deleted_id < first {
+            return Some(self.entries_added - self.entries.len() as u64);
+        }
+        None
+    }
+    /// The number of entries the group still has to read, when it can be known.
+    pub fn lag(&self, group: &ConsumerGroup) -> Option<u64> {
+        if self.entries_added == 0 {
+            return Some(0);
+        }
+        let entries_read = match group.entries_read {
+            Some(entries_read) if !self.has_tombstones_after(group.last_delivered_id) => {
+                Some(entries_read)
+            }
+            _ => self.entries_read_at(group.last_delivered_id),
+        }?;
+        Some(self.entries_added.saturating_sub(entries_read))
+    }
+    pub fn group(&self, name: &[u8]) -> Option<&ConsumerGroup> {
+        self.groups.get(name)
+    }
+    /// Creates a group positioned after `id`, returns false when it already exists.
+    pub fn create_group(&mut self, name: Vec<u8>, id: StreamId, entries_read: Option<u64>) -> bool {
+        if self.groups.contains_key(&name) {
+            return false;
+        }
+        let entries_read = entries_read.or_else(|| self.entries_read_at(id));
+        self.groups.insert(
+            name,
+            ConsumerGroup {
+                last_delivered_id: id,
+                entries_read,
+                ..Default::default()
+            },
+        );
+        true
+    }
+    /// Moves the group to `id`, returns false when the group does not exist.
+    pub fn set_group_id(&mut self, name: &[u8], id: StreamId, entries_read: Option<u64>) -> bool {
+        let entries_read = entries_read.or_else(|| self.entries_read_at(id));
+        match self.groups.get_mut(name) {
+            Some(group) => {
+                group.last_delivered_id = id;
+                group.entries_read = entries_read;
+                true
+            }
+            None => false,
+        }
+    }
+    /// Creates a consumer, returns `None` when the group does not exist.
+    pub fn create_consumer(&mut self, group: &[u8], consumer: &[u8], now: i64) -> Option<bool> {
+        let group = self.groups.get_mut(group)?;
+        if group.consumers.contains_key(consumer) {
+            return Some(false);
+        }
+        group.consumer_mut(consumer, now);
+        Some(true)
+    }
+    /// Deletes a consumer and its pending entries, returns how many entries were pending.
+    pub fn delete_consumer(&mut self, group: &[u8], consumer: &[u8]) -> Option<usize> {
+        let group = self.groups.get_mut(group)?;
+        let Some(removed) = group.consumers.remove(consumer) else {
+            return Some(0);
+        };
+        for id in removed.pending.iter() {
+            group.pending.remove(id);
+        }
+        Some(removed.pending.len())
+    }
+    /// Reads entries for a consumer of a group. With no `start` the entries never
+    /// delivered to the group are read, otherwise the entries pending for the consumer
+    /// after `start`, in which case deleted entries have no fields.
+    /// Returns `None` when the group does not exist.
+    pub fn read_group(
+        &mut self,
+        group: &[u8],
+        consumer: &[u8],
+        start: Option<StreamId>,
+        count: usize,
+        no_ack: bool,
+        now: i64,
+    ) -> Option<Vec<(StreamId, Option<StreamFields>)>> {
+        let mut group_value = self.groups.remove(group)?;
+        let count = if count == 0 { usize::MAX } else { count };
+        let mut result = vec![];
+        match start {
+            None => {
+                let ids: Vec<StreamId> = self
+                    .entries
+                    .range((
+                        Bound::Excluded(group_value.last_delivered_id),
+                        Bound::Unbounded,
+                    ))
+                    .take(count)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for id in ids {
+                    group_value.entries_read = match group_value.entries_read {
+                        Some(entries_read)
+                            if !self.has_tombstones_after(group_value.last_delivered_id) =>
+                        {
+                            Some(entries_read + 1)
+                        }
+                        _ => self.entries_read_at(id),
+                    };
+                    group_value.last_delivered_id = id;
+                    if !no_ack {
+                        group_value.assign(id, consumer, now, 1);
+                    }
+                    result.push((id, self.entries.get(&id).cloned()));
+                }
+                let consumer_value = group_value.consumer_mut(consumer, now);
+                if !result.is_empty() {
+                    consumer_value.active_time = now;
+                }
+            }
+            Some(start) => {
+                let consumer_value = group_value.consumer_mut(consumer, now);
+                let ids: Vec<StreamId> = consumer_value
+                    .pending
+                    .range((Bound::Excluded(start), Bound::Unbounded))
+                    .take(count)
+                    .copied()
+                    .collect();
+                for id in ids {
+                    let fields = self.entries.get(&id).cloned();
+                    if fields.is_some() {
+                        if let Some(pending) = group_value.pending.get_mut(&id) {
+                            pending.delivery_time = now;
+                            pending.delivery_count += 1;
+                        }
+                    }
+                    result.push((id, fields));
+                }
+            }
+        }
+        self.groups.insert(group.to_vec(), group_value);
+        Some(result)
+    }
+    /// Acknowledges entries, returns how many were pending.
+    pub fn ack(&mut self, group: &[u8], ids: &[StreamId]) -> Option<usize> {
+        let group = self.groups.get_mut(group)?;
+        Some(ids.iter().filter(|id| group.unassign(id)).count())
+    }
+    /// Changes the owner of pending entries idle for at least `min_idle` ms,
+    /// returns the claimed entries, with no fields when only the IDs are wanted.
+    pub fn claim(
+        &mut self,
+        group: &[u8],
+        consumer: &[u8],
+        min_idle: i64,
+        ids: &[StreamId],
+        options: &ClaimOptions,
+        now: i64,
+    ) -> Option<Vec<(StreamId, StreamFields)>> {
+        let group = self.groups.get_mut(group)?;
+        if let Some(last_id) = options.last_id {
+            if last_id > group.last_delivered_id {
+                group.last_delivered_id = last_id;
+            }
+        }
+        let delivery_time = options.delivery_time.unwrap_or(now);
+        let mut claimed = vec![];
+        for id in ids {
+            let Some(fields) = self.entries.get(id) else {
+                // The entry was deleted, it can not be delivered anymore
+                group.unassign(id);
+                continue;
+            };
+            let delivery_count = match group.pending.get(id) {
+                Some(pending) => {
+                    if min_idle > 0 && now - pending.delivery_time < min_idle {
+                        continue;
+                    }
+                    pending.delivery_count
+                }
+                None if options.force => 0,
+                None => continue,
+            };
+            let delivery_count = match options.retry_count {
+                Some(retry_count) => retry_count,
+                None if options.just_id => delivery_count,
+                None => delivery_count + 1,
+            };
+            group.assign(*id, consumer, delivery_time, delivery_count);
+            let fields = if options.just_id {
+                vec![]
+            } else {
+                fields.clone()
+            };
+            claimed.push((*id, fields));
+        }
+        let consumer = group.consumer_mut(consumer, now);
+        if !claimed.is_empty() {
+            consumer.active_time = now;
+        }
+        Some(claimed)
+    }
+    /// Claims up to `count` pending entries idle for at least `min_idle` ms, scanning
+    /// the pending entries from `start`. Returns the ID to continue the scan from,
+    /// `0-0` when it is over, the claimed entries and the deleted entries that were
+    /// removed from the pending entries.
+    #[allow(clippy::too_many_arguments)]
+    pub fn auto_claim(
+        &mut self,
+        group: &[u8],
+        consumer: &[u8],
+        min_idle: i64,
+        start: StreamId,
+        count: usize,
+        just_id: bool,
+        now: i64,
+    ) -> Option<AutoClaimResult> {
+        let group = self.groups.get_mut(group)?;
+        let candidates: Vec<(StreamId, i64, u64)> = group
+            .pending
+            .range(start..)
+            .map(|(id, pending)| (*id, pending.delivery_time, pending.delivery_count))
+            .collect();
+        // Bounds the work done when few entries are idle enough
+        let mut attempts = count.saturating_mul(10);
+        let mut result = AutoClaimResult {
+            next: StreamId::MIN,
+            claimed: vec![],
+            deleted: vec![],
+        };
+        let mut candidates = candidates.into_iter().peekable();
+        while attempts > 0 && result.claimed.len() < count {
+            let Some((id, delivery_time, delivery_count)) = candidates.next() else {
+                break;
+            };
+            attempts -= 1;
+            if min_idle > 0 && now - delivery_time < min_idle {
+                continue;
+            }
+            let Some(fields) = self.entries.get(&id) else {
+                group.unassign(&id);
+                result.deleted.push(id);
+                continue;
+            };
+            let delivery_count = if just_id {
+                delivery_count
+            } else {
+                delivery_count + 1
+            };
+            group.assign(id, consumer, now, delivery_count);
+            let fields = if just_id { vec![] } else { fields.clone() };
+            result.claimed.push((id, fields));
+        }
+        if let Some((id, _, _)) = candidates.peek() {
+            result.next = *id;
+        }
+        let consumer = group.consumer_mut(consumer, now);
+        if !result.claimed.is_empty() {
+            consumer.active_time = now;
+        }
+        Some(result)
+    }
+    /// Whether an entry after `id` was deleted, which makes counting the entries
+    /// read from the number of entries added wrong.
+    fn has_tombstones_after(&self, id: StreamId) -> bool {
+        self.max_deleted_id != StreamId::MIN && self.max_deleted_id > id
+    }
+}
+pub struct AutoClaimResult {
+    pub next: StreamId,
+    pub claimed: Vec<(StreamId, StreamFields)>,
+    pub deleted: Vec<StreamId>,
+}
+impl ConsumerGroup {
+    /// Gets a consumer, creating it when it does not exist, and records it was seen.
+    pub fn consumer_mut(&mut self, name: &[u8], now: i64) -> &mut Consumer {
+        let consumer = self
+            .consumers
+            .entry(name.to_vec())
+            .or_insert_with(|| Consumer {
+                seen_time: now,
+                active_time: -1,
+                pending: BTreeSet::new(),
+            });
+        consumer.seen_time = now;
+        consumer
+    }
+    /// Makes `consumer` the owner of the pending entry, which may belong to another consumer.
+    fn assign(&mut self, id: StreamId, consumer: &[u8], delivery_time: i64, delivery_count: u64) {
+        self.unassign(&id);
+        self.consumers
+            .entry(consumer.to_vec())
+            .or_insert_with(|| Consumer {
+                seen_time: delivery_time,
+                active_time: -1,
+                pending: BTreeSet::new(),
+            })
+            .pending
+            .insert(id);
+        self.pending.insert(
+            id,
+            PendingEntry {
+                consumer: consumer.to_vec(),
+                delivery_time,
+                delivery_count,
+            },
+        );
+    }
+    fn unassign(&mut self, id: &StreamId) -> bool {
+        let Some(pending) = self.pending.remove(id) else {
+            return false;
+        };
+        if let Some(consumer) = self.consumers.get_mut(&pending.consumer) {
+            consumer.pending.remove(id);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> StreamFields {
+        vec![b"field".to_vec(), b"value".to_vec()]
+    }
+    fn stream_with(ids: &[(u64, u64)]) -> ValueStream {
+        let mut stream = ValueStream::new();
+        for (ms, seq) in ids {
+            stream
+                .add(
+                    StreamIdSpec::Explicit(StreamId::new(*ms, *seq)),
+                    fields(),
+                    0,
+                )
+                .unwrap();
+        }
+        stream
+    }
+
+    #[test]
+    fn parse_ids() {
+        assert_eq!(StreamId::parse(b"5-3", 0).unwrap(), StreamId::new(5, 3));
+        assert_eq!(
+            StreamId::parse(b"5", u64::MAX).unwrap(),
+            StreamId::new(5, u64::MAX)
+        );
+        assert!(StreamId::parse(b"5-", 0).is_err());
+        assert!(StreamId::parse(b"a-1", 0).is_err());
+        assert!(matches!(
+            StreamIdSpec::parse(b"7-*").unwrap(),
+            StreamIdSpec::AutoSeq(7)
+        ));
+        assert!(StreamIdSpec::parse(b"7-1-*").is_err());
+        assert_eq!(StreamId::new(1, u64::MAX).next(), Some(StreamId::new(2, 0)));
+        assert_eq!(StreamId::new(2, 0).prev(), Some(StreamId::new(1, u64::MAX)));
+        assert_eq!(StreamId::MAX.next(), None);
+        assert_eq!(StreamId::MIN.prev(), None);
+    }
+
+    #[test]
+    fn generated_ids_always_grow() {
+        let mut stream = ValueStream::new();
+        assert_eq!(
+            stream.add(StreamIdSpec::Auto, fields(), 10).unwrap(),
+            StreamId::new(10, 0)
+        );
+        // The clock went backwards
+        assert_eq!(
+            stream.add(StreamIdSpec::Auto, fields(), 5).unwrap(),
+            StreamId::new(10, 1)
+        );
+        assert_eq!(
+            stream.add(StreamIdSpec::AutoSeq(10), fields(), 0).unwrap(),
+            StreamId::new(10, 2)
+        );
+        assert!(stream.add(StreamIdSpec::AutoSeq(9), fields(), 0).is_err());
+        let explicit = StreamIdSpec::Explicit(StreamId::new(10, 2));
+        assert!(stream.add(explicit, fields(), 0).is_err());
+        let zero = StreamIdSpec::Explicit(StreamId::MIN);
+        assert!(ValueStream::new().add(zero, fields(), 0).is_err());
+        assert_eq!(stream.entries_added, 3);
+    }
+
+    #[test]
+    fn trim_and_range() {
+        let mut stream = stream_with(&[(1, 0), (2, 0), (3, 0), (4, 0)]);
+        let ids = |entries: Vec<(StreamId, StreamFields)>| -> Vec<u64> {
+            entries.iter().map(|(id, _)| id.ms).collect()
+        };
+        let all = stream.range(StreamId::MIN, StreamId::MAX, None, false);
+        assert_eq!(ids(all), vec![1, 2, 3, 4]);
+        let reversed = stream.range(StreamId::new(2, 0), StreamId::MAX, Some(2), true);
+        assert_eq!(ids(reversed), vec![4, 3]);
+        let max_len = TrimOptions {
+            strategy: TrimStrategy::MaxLen(1),
+            limit: 2,
+        };
+        assert_eq!(stream.trim(&max_len), 2);
+        let min_id = TrimOptions {
+            strategy: TrimStrategy::MinId(StreamId::new(4, 0)),
+            limit: 0,
+        };
+        assert_eq!(stream.trim(&min_id), 1);
+        assert_eq!(stream.len(), 1);
+        assert_eq!(stream.last_id, StreamId::new(4, 0));
+    }
+
+    #[test]
+    fn groups_track_the_lag() {
+        let mut stream = stream_with(&[(1, 0), (2, 0), (3, 0)]);
+        assert!(stream.create_group(b"group".to_vec(), StreamId::MIN, None));
+        assert!(!stream.create_group(b"group".to_vec(), StreamId::MIN, None));
+        let group = stream.group(b"group").unwrap();
+        assert_eq!(stream.lag(group), Some(3));
+        let read = stream
+            .read_group(b"group", b"alice", None, 2, false, 100)
+            .unwrap();
+        assert_eq!(read.len(), 2);
+        let group = stream.group(b"group").unwrap();
+        assert_eq!(group.entries_read, Some(2));
+        assert_eq!(stream.lag(group), Some(1));
+        // A deleted entry the group has not read makes the lag unknown
+        stream.delete(&[StreamId::new(3, 0)]);
+        let group = stream.group(b"group").unwrap();
+        assert_eq!(stream.lag(group), None);
+    }
+
+    #[test]
+    fn pending_entries_follow_reads_acks_and_claims() {
+        let mut stream = stream_with(&[(1, 0), (2, 0)]);
+        stream.create_group(b"group".to_vec(), StreamId::MIN, None);
+        stream.read_group(b"group", b"alice", None, 0, false, 100);
+        let history = stream
+            .read_group(b"group", b"alice", Some(StreamId::MIN), 0, false, 150)
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(stream.ack(b"group", &[StreamId::new(1, 0)]), Some(1));
+        assert_eq!(stream.ack(b"group", &[StreamId::new(1, 0)]), Some(0));
+
+        let ids = [StreamId::new(2, 0)];
+        let options = ClaimOptions::default();
+        let claimed = stream.claim(b"group", b"bob", 100, &ids, &options, 200);
+        assert!(claimed.unwrap().is_empty());
+        let claimed = stream.claim(b"group", b"bob", 100, &ids, &options, 300);
+        assert_eq!(claimed.unwrap().len(), 1);
+        let group = stream.group(b"group").unwrap();
+        let pending = &group.pending[&ids[0]];
+        assert_eq!(pending.consumer, b"bob");
+        assert_eq!(pending.delivery_count, 3);
+        assert!(group.consumers[b"alice".as_slice()].pending.is_empty());
+
+        stream.delete(&ids);
+        let result = stream
+            .auto_claim(b"group", b"alice", 0, StreamId::MIN, 10, false, 400)
+            .unwrap();
+        assert!(result.claimed.is_empty());
+        assert_eq!(result.deleted, ids.to_vec());
+        assert_eq!(result.next, StreamId::MIN);
+        assert!(stream.group(b"group").unwrap().pending.is_empty());
+    }
+
+    #[test]
+    fn deleting_a_consumer_drops_its_pending_entries() {
+        let mut stream = stream_with(&[(1, 0), (2, 0)]);
+        stream.create_group(b"group".to_vec(), StreamId::MIN, None);
+        assert_eq!(stream.create_consumer(b"group", b"alice", 0), Some(true));
+        assert_eq!(stream.create_consumer(b"group", b"alice", 0), Some(false));
+        assert_eq!(stream.create_consumer(b"missing", b"alice", 0), None);
+        stream.read_group(b"group", b"alice", None, 0, false, 0);
+        assert_eq!(stream.delete_consumer(b"group", b"alice"), Some(2));
+        assert!(stream.group(b"group").unwrap().pending.is_empty());
+    }
+}
@@ -0,0 +1,588 @@
+use anyhow::{anyhow, ensure};
+use bincode::{Decode, Encode};
+
+/// A chunk is closed once its compressed samples take this many bytes.
+pub const TS_CHUNK_SIZE: usize = 4096;
+
+/// What to do when a sample is added with the timestamp of an existing one.
+#[derive(PartialEq, Debug, Clone, Copy, Encode, Decode)]
+pub enum DuplicatePolicy {
+    Block,
+    First,
+    Last,
+    Min,
+    Max,
+    Sum,
+}
+impl DuplicatePolicy {
+    pub fn parse(name: &str) -> Result<DuplicatePolicy, anyhow::Error> {
+        match name.to_uppercase().as_str() {
+            "BLOCK" => Ok(DuplicatePolicy::Block),
+            "FIRST" => Ok(DuplicatePolicy::First),
+            "LAST" => Ok(DuplicatePolicy::Last),
+            "MIN" => Ok(DuplicatePolicy::Min),
+            "MAX" => Ok(DuplicatePolicy::Max),
+            "SUM" => Ok(DuplicatePolicy::Sum),
+            _ => Err(anyhow!("TSDB: Unknown DUPLICATE_POLICY")),
+        }
+    }
+    fn resolve(self, old: f64, new: f64) -> Result<f64, anyhow::Error> {
+        match self {
+            DuplicatePolicy::Block => Err(anyhow!(
+                "TSDB: Error at upsert, update is not supported when DUPLICATE_POLICY is set to BLOCK mode"
+            )),
+            DuplicatePolicy::First => Ok(old),
+            DuplicatePolicy::Last => Ok(new),
+            DuplicatePolicy::Min => Ok(old.min(new)),
+            DuplicatePolicy::Max => Ok(old.max(new)),
+            DuplicatePolicy::Sum => Ok(old + new),
+        }
+    }
+}
+#[derive(PartialEq, Debug, Clone, Copy, Encode, Decode)]
+pub enum AggregationType {
+    Avg,
+    Sum,
+    Min,
+    Max,
+    Count,
+    First,
+    Last,
+    Range,
+}
+impl AggregationType {
+    pub fn parse(name: &str) -> Result<AggregationType, anyhow::Error> {
+        match name.to_uppercase().as_str() {
+            "AVG" => Ok(AggregationType::Avg),
+            "SUM" => Ok(AggregationType::Sum),
+            "MIN" => Ok(AggregationType::Min),
+            "MAX" => Ok(AggregationType::Max),
+            "COUNT" => Ok(AggregationType::Count),
+            "FIRST" => Ok(AggregationType::First),
+            "LAST" => Ok(AggregationType::Last),
+            "RANGE" => Ok(AggregationType::Range),
+            _ => Err(anyhow!("TSDB: Unknown aggregation type")),
+        }
+    }
+}
+/// The samples of one bucket being aggregated.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct Aggregator {
+    pub aggregation: AggregationType,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    first: f64,
+    last: f64,
+}
+impl Aggregator {
+    pub fn new(aggregation: AggregationType) -> Self {
+        Aggregator {
+            aggregation,
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            first: 0.0,
+            last: 0.0,
+        }
+    }
+    pub fn add(&mut self, value: f64) {
+        if self.count == 0 {
+            self.first = value;
+        }
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.last = value;
+    }
+    pub fn value(&self) -> f64 {
+        match self.aggregation {
+            AggregationType::Avg => self.sum / self.count as f64,
+            AggregationType::Sum => self.sum,
+            AggregationType::Min => self.min,
+            AggregationType::Max => self.max,
+            AggregationType::Count => self.count as f64,
+            AggregationType::First => self.first,
+            AggregationType::Last => self.last,
+            AggregationType::Range => self.max - self.min,
+        }
+    }
+}
+/// Groups samples in buckets of `bucket_duration` milliseconds, starting at
+/// multiples of it shifted by `align`, each bucket gives one sample timestamped
+/// with its start.
+pub fn aggregate(
+    samples: &[(i64, f64)],
+    aggregation: AggregationType,
+    bucket_duration: i64,
+    align: i64,
+) -> Vec<(i64, f64)> {
+    let mut result = vec![];
+    let mut current: Option<(i64, Aggregator)> = None;
+    for (timestamp, value) in samples.iter() {
+        let bucket = bucket_start(*timestamp, bucket_duration, align);
+        match current.as_mut() {
+            Some((start, aggregator)) if *start == bucket => aggregator.add(*value),
+            _ => {
+                if let Some((start, aggregator)) = current.take() {
+                    result.push((start, aggregator.value()));
+                }
+                let mut aggregator = Aggregator::new(aggregation);
+                aggregator.add(*value);
+                current = Some((bucket, aggregator));
+            }
+        }
+    }
+    if let Some((start, aggregator)) = current {
+        result.push((start, aggregator.value()));
+    }
+    result
+}
+fn bucket_start(timestamp: i64, bucket_duration: i64, align: i64) -> i64 {
+    timestamp - (timestamp - align).rem_euclid(bucket_duration)
+}
+
+/// A compaction rule, the samples added to the source are aggregated and written
+/// to `dest_key` whenever a bucket is complete.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct CompactionRule {
+    pub dest_key: Vec<u8>,
+    pub bucket_duration: i64,
+    pub align: i64,
+    /// The start of the open bucket and its samples
+    pub current: Option<(i64, Aggregator)>,
+    pub aggregation: AggregationType,
+}
+/// A sample produced by a compaction rule, to be added to `key`.
+pub struct CompactedSample {
+    pub key: Vec<u8>,
+    pub timestamp: i64,
+    pub value: f64,
+}
+
+/// A time series, with its samples in compressed chunks ordered by time.
+///
+/// `retention` is in milliseconds and 0 keeps the samples forever, otherwise
+/// the chunks older than the retention window, relative to the newest sample,
+/// are dropped.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct ValueTimeSeries {
+    pub retention: i64,
+    pub labels: Vec<(String, String)>,
+    pub duplicate_policy: DuplicatePolicy,
+    pub chunks: Vec<Chunk>,
+    pub rules: Vec<CompactionRule>,
+    /// The series this one is the destination of a rule of
+    pub source_key: Option<Vec<u8>>,
+}
+impl ValueTimeSeries {
+    pub fn new(
+        retention: i64,
+        labels: Vec<(String, String)>,
+        duplicate_policy: DuplicatePolicy,
+    ) -> Self {
+        ValueTimeSeries {
+            retention,
+            labels,
+            duplicate_policy,
+            chunks: vec![],
+            rules: vec![],
+            source_key: None,
+        }
+    }
+    pub fn last_timestamp(&self) -> Option<i64> {
+        self.chunks.last().map(|chunk| chunk.last_timestamp)
+    }
+    fn retention_start(&self) -> Option<i64> {
+        match (self.retention, self.last_timestamp()) {
+            (retention, Some(last)) if retention > 0 => Some(last.saturating_sub(retention)),
+            _ => None,
+        }
+    }
+    /// Adds a sample, `policy` overrides the duplicate policy of the series.
+    /// Returns the samples of the buckets completed by the compaction rules,
+    /// only samples newer than the last one are fed to the rules.
+    pub fn add(
+        &mut self,
+        timestamp: i64,
+        value: f64,
+        policy: Option<DuplicatePolicy>,
+    ) -> Result<Vec<CompactedSample>, anyhow::Error> {
+        ensure!(timestamp >= 0, "TSDB: invalid timestamp");
+        if let Some(start) = self.retention_start() {
+            ensure!(
+                timestamp >= start,
+                "TSDB: Timestamp is older than retention"
+            );
+        }
+        let is_newest = self.last_timestamp().is_none_or(|last| timestamp > last);
+        if is_newest {
+            match self.chunks.last_mut() {
+                Some(chunk) if chunk.data.len() < TS_CHUNK_SIZE => chunk.push(timestamp, value),
+                _ => self.chunks.push(Chunk::new(timestamp, value)),
+            }
+        } else {
+            self.upsert(timestamp, value, policy.unwrap_or(self.duplicate_policy))?;
+        }
+        if let Some(start) = self.retention_start() {
+            self.chunks.retain(|chunk| chunk.last_timestamp >= start);
+        }
+        if !is_newest {
+            return Ok(vec![]);
+        }
+        let mut compacted = vec![];
+        for rule in self.rules.iter_mut() {
+            let bucket = bucket_start(timestamp, rule.bucket_duration, rule.align);
+            match rule.current.as_mut() {
+                Some((start, aggregator)) if *start == bucket => aggregator.add(value),
+                _ => {
+                    if let Some((start, aggregator)) = rule.current.take() {
+                        compacted.push(CompactedSample {
+                            key: rule.dest_key.clone(),
+                            timestamp: start,
+                            value: aggregator.value(),
+                        });
+                    }
+                    let mut aggregator = Aggregator::new(rule.aggregation);
+                    aggregator.add(value);
+                    rule.current = Some((bucket, aggregator));
+                }
+            }
+        }
+        Ok(compacted)
+    }
+    /// Adds a sample older than the newest one, by decoding the chunk it belongs to.
+    fn upsert(
+        &mut self,
+        timestamp: i64,
+        value: f64,
+        policy: DuplicatePolicy,
+    ) -> Result<(), anyhow::Error> {
+        let index = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.last_timestamp >= timestamp)
+            .unwrap_or(self.chunks.len() - 1);
+        let mut samples = self.chunks[index].samples();
+        match samples.binary_search_by_key(&timestamp, |(timestamp, _)| *timestamp) {
+            Ok(position) => samples[position].1 = policy.resolve(samples[position].1, value)?,
+            Err(position) => samples.insert(position, (timestamp, value)),
+        }
+        self.chunks[index] = Chunk::from_samples(&samples);
+        Ok(())
+    }
+    /// The samples between `from` and `to` included, oldest first.
+    pub fn range(&self, from: i64, to: i64) -> Vec<(i64, f64)> {
+        let from = from.max(self.retention_start().unwrap_or(i64::MIN));
+        let mut result = vec![];
+        for chunk in self.chunks.iter() {
+            if chunk.last_timestamp < from || chunk.first_timestamp > to {
+                continue;
+            }
+            result.extend(
+                chunk
+                    .samples()
+                    .into_iter()
+                    .filter(|(timestamp, _)| *timestamp >= from && *timestamp <= to),
+            );
+        }
+        result
+    }
+    pub fn label(&self, name: &str) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(label, _)| label == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Samples compressed the Gorilla way: timestamps are stored as the difference
+/// between consecutive deltas, and values as the XOR with the previous one,
+/// which both are mostly made of zero bits for regular metrics.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct Chunk {
+    pub data: Vec<u8>,
+    pub bit_len: u64,
+    pub count: u32,
+    pub first_timestamp: i64,
+    pub last_timestamp: i64,
+    last_delta: i64,
+    last_value: u64,
+    leading: u8,
+    trailing: u8,
+}
+impl Chunk {
+    fn new(timestamp: i64, value: f64) -> Self {
+        let mut chunk = Chunk {
+            data: vec![],
+            bit_len: 0,
+            count: 1,
+            first_timestamp: timestamp,
+            last_timestamp: timestamp,
+            last_delta: 0,
+            last_value: value.to_bits(),
+            leading: u8::MAX,
+            trailing: 0,
+        };
+        chunk.write_bits(timestamp as u64, 64);
+        chunk.write_bits(value.to_bits(), 64);
+        chunk
+    }
+    fn from_samples(samples: &[(i64, f64)]) -> Self {
+        let mut chunk = Chunk::new(samples[0].0, samples[0].1);
+        for (timestamp, value) in samples[1..].iter() {
+            chunk.push(*timestamp, *value);
+        }
+        chunk
+    }
+    /// Appends a sample newer than the last one.
+    fn push(&mut self, timestamp: i64, value: f64) {
+        let delta = timestamp - self.last_timestamp;
+        let delta_of_delta = zigzag(delta - self.last_delta);
+        match delta_of_delta {
+            0 => self.write_bits(0b0, 1),
+            1..=0x7f => {
+                self.write_bits(0b10, 2);
+                self.write_bits(delta_of_delta, 7);
+            }
+            0x80..=0x1ff => {
+                self.write_bits(0b110, 3);
+                self.write_bits(delta_of_delta, 9);
+            }
+            0x200..=0xfff => {
+                self.write_bits(0b1110, 4);
+                self.write_bits(delta_of_delta, 12);
+            }
+            _ => {
+                self.write_bits(0b1111, 4);
+                self.write_bits(delta_of_delta, 64);
+            }
+        }
+        let bits = value.to_bits();
+        let xor = bits ^ self.last_value;
+        if xor == 0 {
+            self.write_bits(0b0, 1);
+        } else {
+            let leading = (xor.leading_zeros() as u8).min(31);
+            let trailing = xor.trailing_zeros() as u8;
+            if self.leading != u8::MAX && leading >= self.leading && trailing >= self.trailing {
+                // The meaningful bits fit in the window of the previous value
+                self.write_bits(0b10, 2);
+                let length = 64 - self.leading - self.trailing;
+                self.write_bits(xor >> self.trailing, length);
+            } else {
+                let length = 64 - leading - trailing;
+                self.write_bits(0b11, 2);
+                self.write_bits(leading as u64, 5);
+                self.write_bits((length - 1) as u64, 6);
+                self.write_bits(xor >> trailing, length);
+                self.leading = leading;
+                self.trailing = trailing;
+            }
+        }
+        self.last_timestamp = timestamp;
+        self.last_delta = delta;
+        self.last_value = bits;
+        self.count += 1;
+    }
+    pub fn samples(&self) -> Vec<(i64, f64)> {
+        let mut reader = BitReader {
+            data: &self.data,
+            pos: 0,
+        };
+        let mut timestamp = reader.read(64) as i64;
+        let mut value = reader.read(64);
+        let mut result = vec![(timestamp, f64::from_bits(value))];
+        let (mut delta, mut leading, mut trailing) = (0i64, 0u8, 0u8);
+        for _ in 1..self.count {
+            let delta_of_delta = if reader.read(1) == 0 {
+                0
+            } else if reader.read(1) == 0 {
+                reader.read(7)
+            } else if reader.read(1) == 0 {
+                reader.read(9)
+            } else if reader.read(1) == 0 {
+                reader.read(12)
+            } else {
+                reader.read(64)
+            };
+            delta += unzigzag(delta_of_delta);
+            timestamp += delta;
+            if reader.read(1) == 1 {
+                if reader.read(1) == 1 {
+                    leading = reader.read(5) as u8;
+                    let length = reader.read(6) as u8 + 1;
+                    trailing = 64 - leading - length;
+                }
+                let length = 64 - leading - trailing;
+                value ^= reader.read(length) << trailing;
+            }
+            result.push((timestamp, f64::from_bits(value)));
+        }
+        result
+    }
+    fn write_bits(&mut self, value: u64, count: u8) {
+        for i in (0..count).rev() {
+            if self.bit_len.is_multiple_of(8) {
+                self.data.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                let last = self.data.len() - 1;
+                self.data[last] |= 0x80 >> (self.bit_len % 8);
+            }
+            self.bit_len += 1;
+        }
+    }
+}
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: u64,
+}
+impl BitReader<'_> {
+    fn read(&mut self, count: u8) -> u64 {
+        let mut value = 0u64;
+        for _ in 0..count {
+            let bit = (self.data[(self.pos / 8) as usize] >> (7 - self.pos % 8)) & 1;
+            value = (value << 1) | bit as u64;
+            self.pos += 1;
+        }
+        value
+    }
+}
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+fn unzigzag(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn series() -> ValueTimeSeries {
+        ValueTimeSeries::new(0, vec![], DuplicatePolicy::Block)
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let mut samples = vec![];
+        let mut timestamp = 1_700_000_000_000i64;
+        for i in 0..5000i64 {
+            // Regular, jittered and very large gaps between the samples
+            timestamp += match i % 7 {
+                0 => 1000,
+                1 => 1000 + i % 300,
+                2 => 5000,
+                3 => 1 << 40,
+                _ => 1,
+            };
+            let value = match i % 5 {
+                0 => 42.0,
+                1 => i as f64 * -0.1,
+                2 => f64::INFINITY,
+                3 => f64::MIN_POSITIVE,
+                _ => (i as f64).sqrt(),
+            };
+            samples.push((timestamp, value));
+        }
+        let chunk = Chunk::from_samples(&samples);
+        assert_eq!(chunk.samples(), samples);
+
+        let mut series = series();
+        for (timestamp, value) in samples.iter() {
+            series.add(*timestamp, *value, None).unwrap();
+        }
+        assert!(series.chunks.len() > 1);
+        assert_eq!(series.range(0, i64::MAX), samples);
+    }
+
+    #[test]
+    fn duplicate_policies() {
+        let cases = [
+            (DuplicatePolicy::First, 1.0),
+            (DuplicatePolicy::Last, 5.0),
+            (DuplicatePolicy::Min, 1.0),
+            (DuplicatePolicy::Max, 5.0),
+            (DuplicatePolicy::Sum, 6.0),
+        ];
+        for (policy, expected) in cases {
+            let mut series = series();
+            series.add(10, 1.0, None).unwrap();
+            series.add(20, 2.0, None).unwrap();
+            assert!(series.add(10, 5.0, None).is_err());
+            series.add(10, 5.0, Some(policy)).unwrap();
+            assert_eq!(series.range(0, 100), vec![(10, expected), (20, 2.0)]);
+        }
+        let mut series = series();
+        series.add(20, 2.0, None).unwrap();
+        series.add(10, 1.0, None).unwrap();
+        assert_eq!(series.range(0, 100), vec![(10, 1.0), (20, 2.0)]);
+        assert_eq!(series.last_timestamp(), Some(20));
+    }
+
+    #[test]
+    fn retention_drops_old_samples() {
+        let mut series = ValueTimeSeries::new(100, vec![], DuplicatePolicy::Last);
+        series.add(0, 1.0, None).unwrap();
+        series.add(150, 2.0, None).unwrap();
+        assert!(series.add(10, 3.0, None).is_err());
+        assert_eq!(series.range(0, 1000), vec![(150, 2.0)]);
+        assert!(series.add(-1, 3.0, None).is_err());
+    }
+
+    #[test]
+    fn aggregation_buckets() {
+        let samples = [(0, 1.0), (5, 3.0), (10, 2.0), (25, 4.0), (29, -1.0)];
+        let cases = [
+            (AggregationType::Avg, [2.0, 2.0, 1.5]),
+            (AggregationType::Sum, [4.0, 2.0, 3.0]),
+            (AggregationType::Min, [1.0, 2.0, -1.0]),
+            (AggregationType::Max, [3.0, 2.0, 4.0]),
+            (AggregationType::Count, [2.0, 1.0, 2.0]),
+            (AggregationType::First, [1.0, 2.0, 4.0]),
+            (AggregationType::Last, [3.0, 2.0, -1.0]),
+            (AggregationType::Range, [2.0, 0.0, 5.0]),
+        ];
+        for (aggregation, expected) in cases {
+            let result = aggregate(&samples, aggregation, 10, 0);
+            let expected: Vec<(i64, f64)> = [0, 10, 20].into_iter().zip(expected).collect();
+            assert_eq!(result, expected, "{:?}", aggregation);
+        }
+        let aligned = aggregate(&samples, AggregationType::Count, 10, 5);
+        assert_eq!(aligned, vec![(-5, 1.0), (5, 2.0), (25, 2.0)]);
+    }
+
+    #[test]
+    fn rules_emit_completed_buckets() {
+        let mut series = series();
+        series.rules.push(CompactionRule {
+            dest_key: b"dest".to_vec(),
+            bucket_duration: 10,
+            align: 0,
+            current: None,
+            aggregation: AggregationType::Sum,
+        });
+        assert!(series.add(1, 1.0, None).unwrap().is_empty());
+        assert!(series.add(2, 2.0, None).unwrap().is_empty());
+        let compacted = series.add(12, 5.0, None).unwrap();
+        assert_eq!(compacted.len(), 1);
+        assert_eq!(compacted[0].key, b"dest");
+        assert_eq!((compacted[0].timestamp, compacted[0].value), (0, 3.0));
+        // Late samples are not fed to the rules
+        series.duplicate_policy = DuplicatePolicy::Last;
+        assert!(series.add(3, 1.0, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn zigzag_round_trip() {
+        for value in [0, 1, -1, 63, -64, i64::MAX, i64::MIN] {
+            assert_eq!(unzigzag(zigzag(value)), value);
+        }
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+    }
+}
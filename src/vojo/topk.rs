@@ -0,0 +1,169 @@
+use crate::util::hash::murmurhash64a;
+use anyhow::anyhow;
+use bincode::{Decode, Encode};
+use rand::Rng;
+
+pub const TOPK_DEFAULT_WIDTH: u32 = 8;
+pub const TOPK_DEFAULT_DEPTH: u32 = 7;
+pub const TOPK_DEFAULT_DECAY: f64 = 0.9;
+const TOPK_FINGERPRINT_SEED: u64 = 1919;
+/// The buckets are allocated at once, a list can not use more than 512MB of them.
+const TOPK_MAX_BUCKETS: usize = 1 << 26;
+
+/// The `k` most frequent items, tracked with the HeavyKeeper algorithm.
+///
+/// Each row of buckets keeps, for the columns the items hash to, the fingerprint
+/// of the item counted there and its count. A different item decays the count
+/// with a probability of `decay ^ count`, and takes the bucket over when it
+/// reaches 0, so that only frequent items keep high counts.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct ValueTopK {
+    pub k: u32,
+    pub width: u32,
+    pub depth: u32,
+    pub decay: f64,
+    /// `depth` rows of `width` buckets
+    pub buckets: Vec<TopKBucket>,
+    /// At most `k` items, in no particular order
+    pub heap: Vec<TopKItem>,
+}
+#[derive(PartialEq, Debug, Clone, Default, Encode, Decode)]
+pub struct TopKBucket {
+    pub fingerprint: u32,
+    pub count: u32,
+}
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct TopKItem {
+    pub item: Vec<u8>,
+    pub count: u32,
+}
+impl ValueTopK {
+    pub fn new(k: u32, width: u32, depth: u32, decay: f64) -> Result<Self, anyhow::Error> {
+        let bucket_count = (width as usize)
+            .checked_mul(depth as usize)
+            .filter(|count| *count <= TOPK_MAX_BUCKETS)
+            .ok_or(anyhow!("TopK: invalid init arguments"))?;
+        Ok(ValueTopK {
+            k,
+            width,
+            depth,
+            decay,
+            buckets: vec![TopKBucket::default(); bucket_count],
+            heap: vec![],
+        })
+    }
+    /// Counts an item `increment` times, returns the item it expelled from the
+    /// top list, if any.
+    pub fn incr_by(&mut self, item: &[u8], increment: u32) -> Option<Vec<u8>> {
+        let fingerprint = murmurhash64a(item, TOPK_FINGERPRINT_SEED) as u32;
+        let mut rng = rand::thread_rng();
+        let mut max_count = 0;
+        for row in 0..self.depth as usize {
+            let column = murmurhash64a(item, row as u64) % self.width as u64;
+            let bucket = &mut self.buckets[row * self.width as usize + column as usize];
+            if bucket.count == 0 || bucket.fingerprint == fingerprint {
+                bucket.fingerprint = fingerprint;
+                bucket.count = bucket.count.saturating_add(increment);
+                max_count = max_count.max(bucket.count);
+                continue;
+            }
+            for remaining in (1..=increment).rev() {
+                if rng.gen::<f64>() < self.decay.powf(bucket.count as f64) {
+                    bucket.count -= 1;
+                    if bucket.count == 0 {
+                        bucket.fingerprint = fingerprint;
+                        bucket.count = remaining;
+                        max_count = max_count.max(remaining);
+                        break;
+                    }
+                }
+            }
+        }
+        if let Some(entry) = self.heap.iter_mut().find(|entry| entry.item == item) {
+            entry.count = entry.count.max(max_count);
+            return None;
+        }
+        if max_count == 0 {
+            return None;
+        }
+        let entry = TopKItem {
+            item: item.to_vec(),
+            count: max_count,
+        };
+        if self.heap.len() < self.k as usize {
+            self.heap.push(entry);
+            return None;
+        }
+        let (min_index, min) = self
+            .heap
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, entry)| entry.count)?;
+        if max_count < min.count {
+            return None;
+        }
+        Some(std::mem::replace(&mut self.heap[min_index], entry).item)
+    }
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.heap.iter().any(|entry| entry.item == item)
+    }
+    /// The top items, the most frequent first.
+    pub fn list(&self) -> Vec<&TopKItem> {
+        let mut items: Vec<&TopKItem> = self.heap.iter().collect();
+        items.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.item.cmp(&b.item)));
+        items
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn top_k() -> ValueTopK {
+        ValueTopK::new(
+            3,
+            TOPK_DEFAULT_WIDTH,
+            TOPK_DEFAULT_DEPTH,
+            TOPK_DEFAULT_DECAY,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn the_heavy_hitters_are_kept() {
+        let mut top_k = top_k();
+        for round in 0..100 {
+            top_k.incr_by(b"heavy", 5);
+            top_k.incr_by(b"medium", 2);
+            top_k.incr_by(b"light", 1);
+            top_k.incr_by(format!("noise:{}", round).as_bytes(), 1);
+        }
+        let list: Vec<&[u8]> = top_k
+            .list()
+            .iter()
+            .map(|entry| entry.item.as_slice())
+            .collect();
+        assert_eq!(list[0], b"heavy");
+        assert_eq!(list[1], b"medium");
+        assert!(top_k.contains(b"heavy"));
+        assert_eq!(top_k.heap.len(), 3);
+    }
+
+    #[test]
+    fn a_more_frequent_item_expels_the_least_frequent_one() {
+        let mut top_k = top_k();
+        assert_eq!(top_k.incr_by(b"a", 10), None);
+        assert_eq!(top_k.incr_by(b"b", 10), None);
+        assert_eq!(top_k.incr_by(b"c", 1), None);
+        assert_eq!(top_k.incr_by(b"c", 1), None);
+        assert_eq!(top_k.incr_by(b"d", 50), Some(b"c".to_vec()));
+        assert!(!top_k.contains(b"c"));
+        assert_eq!(top_k.list()[0].item, b"d");
+    }
+
+    #[test]
+    fn the_size_is_bounded() {
+        assert!(ValueTopK::new(1, u32::MAX, u32::MAX, 0.9).is_err());
+        assert!(ValueTopK::new(1, 1 << 20, 1 << 7, 0.9).is_err());
+    }
+}
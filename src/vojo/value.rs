@@ -1,8 +1,28 @@
 use crate::parser::response::Response;
 
-use bincode::{Decode, Encode};
+use crate::util::common_utils::normalize_range;
+use crate::vojo::bloom::ValueBloomFilter;
+use crate::vojo::count_min_sketch::ValueCountMinSketch;
+use crate::vojo::cuckoo::ValueCuckooFilter;
+use crate::vojo::hyperloglog::ValueHyperLogLog;
+use crate::vojo::json::ValueJson;
+#[cfg(feature = "native-modules")]
+use crate::vojo::module_value::ModuleValue;
+use crate::vojo::parsered_command::LexBound;
+use crate::vojo::stream::ValueStream;
+use crate::vojo::timeseries::ValueTimeSeries;
+use crate::vojo::topk::ValueTopK;
+use crate::vojo::vector_set::ValueVectorSet;
+use anyhow::ensure;
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{impl_borrow_decode, Decode, Encode};
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::Rng;
 use std::cmp::Ordering;
 use std::collections::BTreeSet;
+use std::collections::Bound;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
@@ -17,9 +37,18 @@ pub enum Value {
     Set(ValueSet),
     Hash(ValueHash),
     SortedSet(ValueSortedSet),
-}
-pub enum BackgroundEvent {
-    Nil,
+    HyperLogLog(ValueHyperLogLog),
+    Stream(ValueStream),
+    Json(ValueJson),
+    BloomFilter(ValueBloomFilter),
+    CuckooFilter(ValueCuckooFilter),
+    CountMinSketch(ValueCountMinSketch),
+    TopK(ValueTopK),
+    TimeSeries(ValueTimeSeries),
+    VectorSet(ValueVectorSet),
+    /// A value of a type registered by a native module.
+    #[cfg(feature = "native-modules")]
+    Module(ModuleValue),
 }
 impl Value {
     pub fn is_string(&self) -> bool {
@@ -40,6 +69,12 @@ impl Value {
             _ => Err(anyhow!("convert Error!")),
         }
     }
+    pub fn to_value_string_mut(&mut self) -> Result<&mut ValueString, anyhow::Error> {
+        match self {
+            Value::String(val) => Ok(val),
+            _ => Err(anyhow!("WrongTypeError")),
+        }
+    }
     pub fn to_value_list_mut(&mut self) -> Result<&mut ValueList, anyhow::Error> {
         match self {
             Value::List(val) => Ok(val),
@@ -100,23 +135,33 @@ impl Value {
     }
     pub fn hset(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<bool, anyhow::Error> {
         match self {
-            Value::Hash(val) => {
-                if let std::collections::hash_map::Entry::Vacant(e) = val.data.entry(key) {
-                    e.insert(value);
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
-            }
+            Value::Hash(val) => Ok(val.data.insert(key, value).is_none()),
             _ => Err(anyhow!("WrongTypeError")),
         }
     }
-    pub fn zadd(&mut self, member: Vec<u8>, score: f64) -> Result<bool, anyhow::Error> {
+    pub fn zadd(
+        &mut self,
+        member: Vec<u8>,
+        score: f64,
+        options: &ZaddOptions,
+    ) -> Result<ZaddOutcome, anyhow::Error> {
         match self {
-            Value::SortedSet(val) => {
-                val.data.insert(SortedSetData { member, score });
-                Ok(true)
-            }
+            Value::SortedSet(val) => val.zadd(member, score, options),
+            _ => Err(anyhow!("WrongTypeError")),
+        }
+    }
+    /// The members and scores used as an input of ZUNION, ZINTER and ZDIFF,
+    /// plain sets count as sorted sets where every score is 1.
+    pub fn to_score_map(&self) -> Result<HashMap<Vec<u8>, f64>, anyhow::Error> {
+        match self {
+            Value::SortedSet(val) => Ok(val.dict.clone()),
+            Value::Set(val) => Ok(val.data.iter().map(|item| (item.clone(), 1.0)).collect()),
+            _ => Err(anyhow!("WrongTypeError")),
+        }
+    }
+    pub fn to_value_sorted_set_mut(&mut self) -> Result<&mut ValueSortedSet, anyhow::Error> {
+        match self {
+            Value::SortedSet(val) => Ok(val),
             _ => Err(anyhow!("WrongTypeError")),
         }
     }
@@ -187,6 +232,125 @@ impl ValueString {
     pub fn strlen(&self) -> usize {
         self.data.len()
     }
+    /// Bits are numbered from the most significant bit of the first byte.
+    pub fn getbit(&self, offset: usize) -> u8 {
+        match self.data.get(offset / 8) {
+            Some(byte) => (byte >> (7 - offset % 8)) & 1,
+            None => 0,
+        }
+    }
+    /// Sets or clears the bit, growing the string when needed, and returns the previous bit.
+    pub fn setbit(&mut self, offset: usize, on: bool) -> u8 {
+        let old = self.getbit(offset);
+        let index = offset / 8;
+        if index >= self.data.len() {
+            self.data.resize(index + 1, 0);
+        }
+        let mask = 1 << (7 - offset % 8);
+        if on {
+            self.data[index] |= mask;
+        } else {
+            self.data[index] &= !mask;
+        }
+        old
+    }
+    /// Counts the set bits between the `start` and `end` bits, both included.
+    pub fn bitcount(&self, start: usize, end: usize) -> usize {
+        let first_byte = start / 8;
+        let last_byte = end / 8;
+        let mut count = 0;
+        for index in first_byte..=last_byte.min(self.data.len().saturating_sub(1)) {
+            let mut byte = self.data[index];
+            if index == first_byte {
+                byte &= 0xff >> (start % 8);
+            }
+            if index == last_byte {
+                byte &= 0xff << (7 - end % 8);
+            }
+            count += byte.count_ones() as usize;
+        }
+        count
+    }
+    /// Finds the first bit set to `bit` between the `start` and `end` bits, both included.
+    pub fn bitpos(&self, bit: bool, start: usize, end: usize) -> Option<usize> {
+        let skipped = if bit { 0x00 } else { 0xff };
+        let mut offset = start;
+        while offset <= end {
+            if offset.is_multiple_of(8)
+                && offset + 7 <= end
+                && self.data.get(offset / 8) == Some(&skipped)
+            {
+                offset += 8;
+                continue;
+            }
+            if (self.getbit(offset) == 1) == bit {
+                return Some(offset);
+            }
+            offset += 1;
+        }
+        None
+    }
+    /// Reads `bits` bits starting at the bit `offset` as an unsigned integer.
+    pub fn get_bits(&self, offset: usize, bits: u32) -> u64 {
+        let mut value = 0u64;
+        for index in 0..bits as usize {
+            value = (value << 1) | self.getbit(offset + index) as u64;
+        }
+        value
+    }
+    /// Writes the lowest `bits` bits of `value` starting at the bit `offset`.
+    pub fn set_bits(&mut self, offset: usize, bits: u32, value: u64) {
+        for index in 0..bits as usize {
+            let on = (value >> (bits as usize - 1 - index)) & 1 == 1;
+            self.setbit(offset + index, on);
+        }
+    }
+    /// Overwrites the string from `offset`, padding with zero bytes, and returns the new length.
+    pub fn setrange(&mut self, offset: usize, value: &[u8]) -> usize {
+        if value.is_empty() {
+            return self.data.len();
+        }
+        let end = offset + value.len();
+        if end > self.data.len() {
+            self.data.resize(end, 0);
+        }
+        self.data[offset..end].copy_from_slice(value);
+        self.data.len()
+    }
+    pub fn getrange(&self, start: i64, end: i64) -> Vec<u8> {
+        match normalize_range(start, end, self.data.len()) {
+            Some((start, end)) => self.data[start..=end].to_vec(),
+            None => vec![],
+        }
+    }
+    pub fn bitop(operation: BitOperation, inputs: &[Vec<u8>]) -> ValueString {
+        let len = inputs.iter().map(|input| input.len()).max().unwrap_or(0);
+        let byte_at = |input: &Vec<u8>, index: usize| input.get(index).copied().unwrap_or(0);
+        let data = (0..len)
+            .map(|index| {
+                let mut bytes = inputs.iter().map(|input| byte_at(input, index));
+                let first = bytes.next().unwrap_or(0);
+                match operation {
+                    BitOperation::And => bytes.fold(first, |acc, byte| acc & byte),
+                    BitOperation::Or => bytes.fold(first, |acc, byte| acc | byte),
+                    BitOperation::Xor => bytes.fold(first, |acc, byte| acc ^ byte),
+                    BitOperation::Not => !first,
+                    BitOperation::Diff => first & !bytes.fold(0, |acc, byte| acc | byte),
+                }
+            })
+            .collect();
+        ValueString { data }
+    }
+}
+/// The operations of BITOP. DIFF keeps the bits of the first key that are
+/// set in none of the other keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+    Diff,
 }
 #[derive(PartialEq, Debug, Clone, Encode, Decode)]
 pub struct ValueList {
@@ -201,9 +365,314 @@ pub struct ValueSet {
 pub struct ValueHash {
     pub data: HashMap<Vec<u8>, Vec<u8>>,
 }
-#[derive(PartialEq, Debug, Encode, Decode, Clone)]
+/// A sorted set keeps its members ordered by score in `data` and indexed by
+/// member in `dict`. Only `data` is written to the snapshot, `dict` is rebuilt
+/// when the snapshot is loaded.
+#[derive(PartialEq, Debug, Clone)]
 pub struct ValueSortedSet {
     pub data: BTreeSet<SortedSetData>,
+    pub dict: HashMap<Vec<u8>, f64>,
+}
+impl Encode for ValueSortedSet {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.data.encode(encoder)
+    }
+}
+impl<Context> Decode<Context> for ValueSortedSet {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let data: BTreeSet<SortedSetData> = Decode::decode(decoder)?;
+        let dict = data
+            .iter()
+            .map(|item| (item.member.clone(), item.score))
+            .collect();
+        Ok(ValueSortedSet { data, dict })
+    }
+}
+impl_borrow_decode!(ValueSortedSet);
+
+/// The flags accepted by ZADD.
+#[derive(Debug, Clone, Default)]
+pub struct ZaddOptions {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    pub incr: bool,
+}
+/// What ZADD did with a single member.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZaddOutcome {
+    Added(f64),
+    Updated(f64),
+    Unchanged(f64),
+    /// The member was left alone because of NX, XX, GT or LT.
+    Skipped,
+}
+impl Default for ValueSortedSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl ValueSortedSet {
+    pub fn new() -> Self {
+        ValueSortedSet {
+            data: BTreeSet::new(),
+            dict: HashMap::new(),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.dict.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.dict.is_empty()
+    }
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.dict.get(member).copied()
+    }
+    /// Sets the score of the member and returns the previous score.
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> Option<f64> {
+        let old_score = self.dict.insert(member.clone(), score);
+        if let Some(old) = old_score {
+            self.data.remove(&SortedSetData {
+                member: member.clone(),
+                score: old,
+            });
+        }
+        self.data.insert(SortedSetData { member, score });
+        old_score
+    }
+    pub fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.dict.remove(member)?;
+        self.data.remove(&SortedSetData {
+            member: member.to_vec(),
+            score,
+        });
+        Some(score)
+    }
+    pub fn zadd(
+        &mut self,
+        member: Vec<u8>,
+        score: f64,
+        options: &ZaddOptions,
+    ) -> Result<ZaddOutcome, anyhow::Error> {
+        match self.score(&member) {
+            Some(old_score) => {
+                if options.nx {
+                    return Ok(ZaddOutcome::Skipped);
+                }
+                let new_score = if options.incr {
+                    let new_score = old_score + score;
+                    ensure!(!new_score.is_nan(), "resulting score is not a number (NaN)");
+                    new_score
+                } else {
+                    score
+                };
+                if (options.gt && new_score <= old_score) || (options.lt && new_score >= old_score)
+                {
+                    return Ok(ZaddOutcome::Skipped);
+                }
+                if new_score == old_score {
+                    return Ok(ZaddOutcome::Unchanged(old_score));
+                }
+                self.insert(member, new_score);
+                Ok(ZaddOutcome::Updated(new_score))
+            }
+            None => {
+                if options.xx {
+                    return Ok(ZaddOutcome::Skipped);
+                }
+                self.insert(member, score);
+                Ok(ZaddOutcome::Added(score))
+            }
+        }
+    }
+    /// Iterates over the members whose score is in `[min, max)`, in order.
+    pub fn range_by_score(&self, min: f64, max: f64) -> impl Iterator<Item = &SortedSetData> {
+        let start = SortedSetData {
+            member: vec![],
+            score: min,
+        };
+        self.data
+            .range(start..)
+            .take_while(move |item| item.score < max)
+    }
+    /// Removes up to `count` members from the low end, or the high end when `max` is set.
+    pub fn pop(&mut self, count: usize, max: bool) -> Vec<SortedSetData> {
+        let mut popped = Vec::new();
+        while popped.len() < count {
+            let item = if max {
+                self.data.pop_last()
+            } else {
+                self.data.pop_first()
+            };
+            match item {
+                Some(item) => {
+                    self.dict.remove(&item.member);
+                    popped.push(item);
+                }
+                None => break,
+            }
+        }
+        popped
+    }
+    pub fn remove_range_by_score(&mut self, min: Bound<f64>, max: Bound<f64>) -> usize {
+        let members: Vec<Vec<u8>> = self
+            .data
+            .iter()
+            .filter(|item| score_in_range(item.score, &min, &max))
+            .map(|item| item.member.clone())
+            .collect();
+        for member in members.iter() {
+            self.remove(member);
+        }
+        members.len()
+    }
+    /// Removes the members between `min` and `max`, assuming all members share the same score.
+    pub fn remove_range_by_lex(&mut self, min: &LexBound, max: &LexBound) -> usize {
+        let members: Vec<Vec<u8>> = self
+            .data
+            .iter()
+            .filter(|item| min.is_below(&item.member) && max.is_above(&item.member))
+            .map(|item| item.member.clone())
+            .collect();
+        for member in members.iter() {
+            self.remove(member);
+        }
+        members.len()
+    }
+    /// Picks random members. A positive count returns distinct members,
+    /// a negative count may return the same member several times.
+    pub fn random_members(&self, count: i64) -> Vec<SortedSetData> {
+        let mut rng = rand::thread_rng();
+        if count >= 0 {
+            let count = (count as usize).min(self.data.len());
+            let mut picked = self.data.iter().choose_multiple(&mut rng, count);
+            picked.shuffle(&mut rng);
+            picked.into_iter().cloned().collect()
+        } else {
+            let items: Vec<&SortedSetData> = self.data.iter().collect();
+            if items.is_empty() {
+                return vec![];
+            }
+            (0..count.unsigned_abs())
+                .map(|_| items[rng.gen_range(0..items.len())].clone())
+                .collect()
+        }
+    }
+    /// Builds a sorted set from member/score pairs.
+    pub fn from_scores(scores: HashMap<Vec<u8>, f64>) -> Self {
+        let data = scores
+            .iter()
+            .map(|(member, score)| SortedSetData {
+                member: member.clone(),
+                score: *score,
+            })
+            .collect();
+        ValueSortedSet { data, dict: scores }
+    }
+    /// ZUNION: every member of every input, with weighted and aggregated scores.
+    pub fn union(
+        inputs: &[HashMap<Vec<u8>, f64>],
+        weights: &[f64],
+        aggregate: Aggregate,
+    ) -> ValueSortedSet {
+        let mut scores: HashMap<Vec<u8>, f64> = HashMap::new();
+        for (input, weight) in inputs.iter().zip(weights) {
+            for (member, score) in input {
+                let score = weighted_score(*score, *weight);
+                scores
+                    .entry(member.clone())
+                    .and_modify(|current| *current = aggregate.apply(*current, score))
+                    .or_insert(score);
+            }
+        }
+        ValueSortedSet::from_scores(scores)
+    }
+    /// ZINTER: the members found in all inputs, with weighted and aggregated scores.
+    pub fn inter(
+        inputs: &[HashMap<Vec<u8>, f64>],
+        weights: &[f64],
+        aggregate: Aggregate,
+    ) -> ValueSortedSet {
+        let mut scores = HashMap::new();
+        let Some(smallest) = inputs.iter().min_by_key(|input| input.len()) else {
+            return ValueSortedSet::new();
+        };
+        'members: for member in smallest.keys() {
+            let mut result: Option<f64> = None;
+            for (input, weight) in inputs.iter().zip(weights) {
+                let Some(score) = input.get(member) else {
+                    continue 'members;
+                };
+                let score = weighted_score(*score, *weight);
+                result = Some(match result {
+                    Some(current) => aggregate.apply(current, score),
+                    None => score,
+                });
+            }
+            if let Some(score) = result {
+                scores.insert(member.clone(), score);
+            }
+        }
+        ValueSortedSet::from_scores(scores)
+    }
+    /// ZDIFF: the members of the first input that are missing from all the others.
+    pub fn diff(inputs: &[HashMap<Vec<u8>, f64>]) -> ValueSortedSet {
+        let Some((first, others)) = inputs.split_first() else {
+            return ValueSortedSet::new();
+        };
+        let scores = first
+            .iter()
+            .filter(|(member, _)| others.iter().all(|other| !other.contains_key(*member)))
+            .map(|(member, score)| (member.clone(), *score))
+            .collect();
+        ValueSortedSet::from_scores(scores)
+    }
+}
+/// How ZUNION and ZINTER combine the scores of a member found in several inputs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+impl Aggregate {
+    fn apply(&self, current: f64, score: f64) -> f64 {
+        match self {
+            Aggregate::Sum => {
+                let sum = current + score;
+                // inf + -inf is NaN, which a score can never be
+                if sum.is_nan() {
+                    0.0
+                } else {
+                    sum
+                }
+            }
+            Aggregate::Min => current.min(score),
+            Aggregate::Max => current.max(score),
+        }
+    }
+}
+fn weighted_score(score: f64, weight: f64) -> f64 {
+    let weighted = score * weight;
+    if weighted.is_nan() {
+        0.0
+    } else {
+        weighted
+    }
+}
+fn score_in_range(score: f64, min: &Bound<f64>, max: &Bound<f64>) -> bool {
+    let above_min = match min {
+        Bound::Included(min) => score >= *min,
+        Bound::Excluded(min) => score > *min,
+        Bound::Unbounded => true,
+    };
+    let below_max = match max {
+        Bound::Included(max) => score <= *max,
+        Bound::Excluded(max) => score < *max,
+        Bound::Unbounded => true,
+    };
+    above_min && below_max
 }
 
 #[derive(Debug, Encode, Decode, Clone)]
@@ -236,3 +705,237 @@ impl Ord for SortedSetData {
         self.member.cmp(&other.member)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(flags: &str) -> ZaddOptions {
+        let mut options = ZaddOptions::default();
+        for flag in flags.split_whitespace() {
+            match flag {
+                "NX" => options.nx = true,
+                "XX" => options.xx = true,
+                "GT" => options.gt = true,
+                "LT" => options.lt = true,
+                "INCR" => options.incr = true,
+                _ => unreachable!(),
+            }
+        }
+        options
+    }
+    fn set_with_member(score: f64) -> ValueSortedSet {
+        let mut set = ValueSortedSet::new();
+        set.insert(b"a".to_vec(), score);
+        set
+    }
+
+    #[test]
+    fn zadd_on_a_missing_member() {
+        let cases = [
+            ("", ZaddOutcome::Added(5.0)),
+            ("NX", ZaddOutcome::Added(5.0)),
+            ("XX", ZaddOutcome::Skipped),
+            ("GT", ZaddOutcome::Added(5.0)),
+            ("LT", ZaddOutcome::Added(5.0)),
+            ("INCR", ZaddOutcome::Added(5.0)),
+            ("XX INCR", ZaddOutcome::Skipped),
+        ];
+        for (flags, expected) in cases {
+            let mut set = ValueSortedSet::new();
+            let outcome = set.zadd(b"a".to_vec(), 5.0, &options(flags)).unwrap();
+            assert_eq!(outcome, expected, "flags {:?}", flags);
+            let expected_score = match expected {
+                ZaddOutcome::Added(score) => Some(score),
+                _ => None,
+            };
+            assert_eq!(set.score(b"a"), expected_score, "flags {:?}", flags);
+        }
+    }
+
+    #[test]
+    fn zadd_on_an_existing_member() {
+        let cases = [
+            ("", 5.0, ZaddOutcome::Updated(5.0)),
+            ("", 2.0, ZaddOutcome::Unchanged(2.0)),
+            ("NX", 5.0, ZaddOutcome::Skipped),
+            ("XX", 5.0, ZaddOutcome::Updated(5.0)),
+            ("GT", 5.0, ZaddOutcome::Updated(5.0)),
+            ("GT", 1.0, ZaddOutcome::Skipped),
+            ("GT", 2.0, ZaddOutcome::Skipped),
+            ("LT", 1.0, ZaddOutcome::Updated(1.0)),
+            ("LT", 5.0, ZaddOutcome::Skipped),
+            ("LT", 2.0, ZaddOutcome::Skipped),
+            ("INCR", 3.0, ZaddOutcome::Updated(5.0)),
+            ("INCR", 0.0, ZaddOutcome::Unchanged(2.0)),
+            ("XX INCR", 3.0, ZaddOutcome::Updated(5.0)),
+            ("GT INCR", 3.0, ZaddOutcome::Updated(5.0)),
+            ("GT INCR", -3.0, ZaddOutcome::Skipped),
+            ("LT INCR", -3.0, ZaddOutcome::Updated(-1.0)),
+            ("LT INCR", 3.0, ZaddOutcome::Skipped),
+        ];
+        for (flags, score, expected) in cases {
+            let mut set = set_with_member(2.0);
+            let outcome = set.zadd(b"a".to_vec(), score, &options(flags)).unwrap();
+            assert_eq!(outcome, expected, "flags {:?} score {}", flags, score);
+            let expected_score = match expected {
+                ZaddOutcome::Updated(score) => score,
+                _ => 2.0,
+            };
+            assert_eq!(set.score(b"a"), Some(expected_score));
+            assert_eq!(set.data.len(), 1);
+        }
+    }
+
+    #[test]
+    fn zadd_incr_to_nan_is_an_error() {
+        let mut set = set_with_member(f64::INFINITY);
+        let result = set.zadd(b"a".to_vec(), f64::NEG_INFINITY, &options("INCR"));
+        assert!(result.is_err());
+        assert_eq!(set.score(b"a"), Some(f64::INFINITY));
+    }
+
+    #[test]
+    fn pop_from_both_ends() {
+        let mut set = ValueSortedSet::new();
+        for (member, score) in [("a", 1.0), ("b", 2.0), ("c", 3.0)] {
+            set.insert(member.as_bytes().to_vec(), score);
+        }
+        let popped = set.pop(1, true);
+        assert_eq!(popped[0].member, b"c");
+        let popped = set.pop(5, false);
+        let members: Vec<&[u8]> = popped.iter().map(|item| item.member.as_slice()).collect();
+        assert_eq!(members, vec![b"a".as_slice(), b"b".as_slice()]);
+        assert!(set.is_empty());
+        assert!(set.data.is_empty());
+    }
+
+    #[test]
+    fn remove_range_by_lex_honours_the_bounds() {
+        let mut set = ValueSortedSet::new();
+        for member in ["a", "b", "c", "d"] {
+            set.insert(member.as_bytes().to_vec(), 0.0);
+        }
+        let removed = set.remove_range_by_lex(
+            &LexBound::Excluded(b"a".to_vec()),
+            &LexBound::Included(b"c".to_vec()),
+        );
+        assert_eq!(removed, 2);
+        assert_eq!(set.score(b"a"), Some(0.0));
+        assert_eq!(set.score(b"b"), None);
+        assert_eq!(set.score(b"d"), Some(0.0));
+        assert_eq!(
+            set.remove_range_by_lex(&LexBound::NegInf, &LexBound::PosInf),
+            2
+        );
+    }
+
+    #[test]
+    fn random_members() {
+        let mut set = ValueSortedSet::new();
+        for member in ["a", "b", "c"] {
+            set.insert(member.as_bytes().to_vec(), 0.0);
+        }
+        let distinct = set.random_members(10);
+        assert_eq!(distinct.len(), 3);
+        let members: HashSet<Vec<u8>> = distinct.into_iter().map(|item| item.member).collect();
+        assert_eq!(members.len(), 3);
+        assert_eq!(set.random_members(-7).len(), 7);
+        assert!(ValueSortedSet::new().random_members(-7).is_empty());
+    }
+
+    #[test]
+    fn union_and_inter_aggregate_weighted_scores() {
+        let first = HashMap::from([(b"a".to_vec(), 1.0), (b"b".to_vec(), 2.0)]);
+        let second = HashMap::from([(b"b".to_vec(), 3.0), (b"c".to_vec(), 4.0)]);
+        let inputs = [first, second];
+        let union = ValueSortedSet::union(&inputs, &[1.0, 2.0], Aggregate::Sum);
+        assert_eq!(union.len(), 3);
+        assert_eq!(union.score(b"a"), Some(1.0));
+        assert_eq!(union.score(b"b"), Some(8.0));
+        assert_eq!(union.score(b"c"), Some(8.0));
+        let union = ValueSortedSet::union(&inputs, &[1.0, 1.0], Aggregate::Min);
+        assert_eq!(union.score(b"b"), Some(2.0));
+        let union = ValueSortedSet::union(&inputs, &[1.0, -1.0], Aggregate::Max);
+        assert_eq!(union.score(b"b"), Some(2.0));
+        assert_eq!(union.score(b"c"), Some(-4.0));
+
+        let inter = ValueSortedSet::inter(&inputs, &[1.0, 1.0], Aggregate::Max);
+        assert_eq!(inter.len(), 1);
+        assert_eq!(inter.score(b"b"), Some(3.0));
+        let inter = ValueSortedSet::inter(&inputs, &[2.0, 1.0], Aggregate::Sum);
+        assert_eq!(inter.score(b"b"), Some(7.0));
+        assert_eq!(ValueSortedSet::inter(&[], &[], Aggregate::Sum).len(), 0);
+    }
+
+    #[test]
+    fn infinite_scores_never_aggregate_to_nan() {
+        let first = HashMap::from([(b"a".to_vec(), f64::INFINITY)]);
+        let second = HashMap::from([(b"a".to_vec(), f64::NEG_INFINITY)]);
+        let inputs = [first, second];
+        let union = ValueSortedSet::union(&inputs, &[1.0, 1.0], Aggregate::Sum);
+        assert_eq!(union.score(b"a"), Some(0.0));
+        // inf * 0 is NaN as well
+        let inter = ValueSortedSet::inter(&inputs, &[0.0, 1.0], Aggregate::Max);
+        assert_eq!(inter.score(b"a"), Some(0.0));
+    }
+
+    #[test]
+    fn diff_keeps_the_members_missing_from_the_other_inputs() {
+        let first = HashMap::from([(b"a".to_vec(), 1.0), (b"b".to_vec(), 2.0)]);
+        let second = HashMap::from([(b"b".to_vec(), 3.0)]);
+        let third = HashMap::from([(b"c".to_vec(), 4.0)]);
+        let diff = ValueSortedSet::diff(&[first.clone(), second, third]);
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff.score(b"a"), Some(1.0));
+        assert_eq!(ValueSortedSet::diff(&[first]).len(), 2);
+        assert_eq!(ValueSortedSet::diff(&[]).len(), 0);
+    }
+
+    #[test]
+    fn bit_helpers() {
+        let mut value = ValueString { data: vec![] };
+        assert_eq!(value.setbit(9, true), 0);
+        assert_eq!(value.data, vec![0x00, 0x40]);
+        assert_eq!(value.setbit(9, true), 1);
+        assert_eq!(value.bitcount(0, 15), 1);
+        assert_eq!(value.bitcount(10, 15), 0);
+        assert_eq!(value.bitpos(true, 0, 15), Some(9));
+        assert_eq!(value.bitpos(false, 0, 15), Some(0));
+        assert_eq!(value.bitpos(true, 10, 15), None);
+        value.set_bits(0, 4, 0b1010);
+        assert_eq!(value.get_bits(0, 4), 0b1010);
+        assert_eq!(value.getbit(100), 0);
+    }
+
+    #[test]
+    fn bitop_pads_the_shorter_inputs() {
+        let inputs = [vec![0b1100, 0xff], vec![0b1010]];
+        let and = ValueString::bitop(BitOperation::And, &inputs);
+        assert_eq!(and.data, vec![0b1000, 0x00]);
+        let or = ValueString::bitop(BitOperation::Or, &inputs);
+        assert_eq!(or.data, vec![0b1110, 0xff]);
+        let xor = ValueString::bitop(BitOperation::Xor, &inputs);
+        assert_eq!(xor.data, vec![0b0110, 0xff]);
+        let diff = ValueString::bitop(BitOperation::Diff, &inputs);
+        assert_eq!(diff.data, vec![0b0100, 0xff]);
+        let not = ValueString::bitop(BitOperation::Not, &inputs[1..]);
+        assert_eq!(not.data, vec![!0b1010]);
+    }
+
+    #[test]
+    fn hset_overwrites_an_existing_field() {
+        let mut hash = Value::Hash(ValueHash {
+            data: HashMap::new(),
+        });
+        assert!(hash.hset(b"title".to_vec(), b"red".to_vec()).unwrap());
+        // Like HSET in Redis the field is updated, it only does not count as added
+        assert!(!hash.hset(b"title".to_vec(), b"blue".to_vec()).unwrap());
+        let Value::Hash(hash) = hash else {
+            unreachable!()
+        };
+        assert_eq!(hash.data.get(b"title".as_slice()), Some(&b"blue".to_vec()));
+        let mut string = Value::String(ValueString { data: vec![] });
+        assert!(string.hset(b"title".to_vec(), b"red".to_vec()).is_err());
+    }
+}
@@ -0,0 +1,632 @@
+use bincode::{Decode, Encode};
+use rand::Rng;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+pub const VSET_DEFAULT_M: u32 = 16;
+pub const VSET_DEFAULT_EF_CONSTRUCTION: usize = 200;
+pub const VSET_DEFAULT_EF_SEARCH: usize = 100;
+
+/// How the vectors of a set are stored.
+#[derive(PartialEq, Debug, Clone, Copy, Encode, Decode)]
+pub enum Quantization {
+    NoQuant,
+    /// A signed byte per component, scaled by the largest component
+    Q8,
+    /// A bit per component, its sign
+    Bin,
+}
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub enum QuantizedVector {
+    Float(Vec<f32>),
+    Int8 { values: Vec<i8>, scale: f32 },
+    Binary(Vec<u64>),
+}
+impl QuantizedVector {
+    /// Quantizes a normalized vector.
+    fn new(vector: &[f32], quantization: Quantization) -> Self {
+        match quantization {
+            Quantization::NoQuant => QuantizedVector::Float(vector.to_vec()),
+            Quantization::Q8 => {
+                let max = vector.iter().fold(0f32, |max, value| max.max(value.abs()));
+                let scale = if max == 0.0 { 1.0 } else { max / 127.0 };
+                QuantizedVector::Int8 {
+                    values: vector
+                        .iter()
+                        .map(|value| (value / scale).round() as i8)
+                        .collect(),
+                    scale,
+                }
+            }
+            Quantization::Bin => {
+                let mut bits = vec![0u64; vector.len().div_ceil(64)];
+                for (i, value) in vector.iter().enumerate() {
+                    if *value > 0.0 {
+                        bits[i / 64] |= 1 << (i % 64);
+                    }
+                }
+                QuantizedVector::Binary(bits)
+            }
+        }
+    }
+    fn to_vec(&self, dim: usize) -> Vec<f32> {
+        match self {
+            QuantizedVector::Float(values) => values.clone(),
+            QuantizedVector::Int8 { values, scale } => {
+                values.iter().map(|value| *value as f32 * scale).collect()
+            }
+            QuantizedVector::Binary(bits) => {
+                let unit = 1.0 / (dim as f32).sqrt();
+                (0..dim)
+                    .map(|i| {
+                        if bits[i / 64] & (1 << (i % 64)) != 0 {
+                            unit
+                        } else {
+                            -unit
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+    /// The dot product with a normalized vector, without dequantizing.
+    fn dot(&self, query: &[f32]) -> f32 {
+        match self {
+            QuantizedVector::Float(values) => values.iter().zip(query).map(|(a, b)| a * b).sum(),
+            QuantizedVector::Int8 { values, scale } => {
+                values
+                    .iter()
+                    .zip(query)
+                    .map(|(a, b)| *a as f32 * b)
+                    .sum::<f32>()
+                    * scale
+            }
+            QuantizedVector::Binary(bits) => {
+                let unit = 1.0 / (query.len() as f32).sqrt();
+                query
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| {
+                        if bits[i / 64] & (1 << (i % 64)) != 0 {
+                            value * unit
+                        } else {
+                            -value * unit
+                        }
+                    })
+                    .sum()
+            }
+        }
+    }
+}
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct VectorNode {
+    pub element: Vec<u8>,
+    pub vector: QuantizedVector,
+    /// The length of the vector before it was normalized
+    pub norm: f32,
+    pub attributes: Option<String>,
+    /// The links of the node on each of its levels
+    neighbors: Vec<Vec<u32>>,
+}
+
+/// A set of elements with a vector each, searched by cosine similarity through a
+/// HNSW graph.
+///
+/// The vectors are normalized, so that the distance between two of them is
+/// `1 - dot product`. Nodes have ids which are never reused, a link to a removed
+/// node is skipped and dropped the next time the links of the node are pruned.
+#[derive(PartialEq, Debug, Clone, Encode, Decode)]
+pub struct ValueVectorSet {
+    pub dim: u32,
+    pub quantization: Quantization,
+    /// The links per node on the levels above 0, there are twice as many on level 0
+    pub m: u32,
+    pub nodes: HashMap<u32, VectorNode>,
+    pub ids: HashMap<Vec<u8>, u32>,
+    entry: Option<u32>,
+    max_level: usize,
+    next_id: u32,
+}
+#[derive(PartialEq)]
+struct Candidate(f32, u32);
+impl Eq for Candidate {}
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+impl ValueVectorSet {
+    pub fn new(dim: u32, quantization: Quantization, m: u32) -> Self {
+        ValueVectorSet {
+            dim,
+            quantization,
+            m,
+            nodes: HashMap::new(),
+            ids: HashMap::new(),
+            entry: None,
+            max_level: 0,
+            next_id: 0,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn get(&self, element: &[u8]) -> Option<&VectorNode> {
+        self.nodes.get(self.ids.get(element)?)
+    }
+    pub fn get_mut(&mut self, element: &[u8]) -> Option<&mut VectorNode> {
+        self.nodes.get_mut(self.ids.get(element)?)
+    }
+    /// The vector of an element as it was added, up to the quantization error.
+    pub fn embedding(&self, element: &[u8]) -> Option<Vec<f32>> {
+        let node = self.get(element)?;
+        Some(
+            node.vector
+                .to_vec(self.dim as usize)
+                .into_iter()
+                .map(|value| value * node.norm)
+                .collect(),
+        )
+    }
+    /// The normalized and quantized vector of an element, to search with.
+    pub fn query_vector(&self, element: &[u8]) -> Option<Vec<f32>> {
+        Some(self.get(element)?.vector.to_vec(self.dim as usize))
+    }
+    fn max_links(&self, level: usize) -> usize {
+        if level == 0 {
+            self.m as usize * 2
+        } else {
+            self.m as usize
+        }
+    }
+    fn distance(&self, query: &[f32], id: u32) -> f32 {
+        match self.nodes.get(&id) {
+            Some(node) => 1.0 - node.vector.dot(query),
+            None => f32::INFINITY,
+        }
+    }
+    /// Adds an element, or replaces its vector. Returns false when it already existed.
+    pub fn add(
+        &mut self,
+        element: Vec<u8>,
+        vector: &[f32],
+        attributes: Option<String>,
+        ef_construction: usize,
+    ) -> bool {
+        let mut attributes = attributes;
+        let existed = match self.ids.get(&element) {
+            Some(id) => {
+                let id = *id;
+                let old = self.remove_node(id);
+                attributes = attributes.or(old.and_then(|node| node.attributes));
+                true
+            }
+            None => false,
+        };
+        let (normalized, norm) = normalize(vector);
+        let quantized = QuantizedVector::new(&normalized, self.quantization);
+        // Search with the vector as stored, like the queries do
+        let query = quantized.to_vec(self.dim as usize);
+        let id = self.next_id;
+        self.next_id += 1;
+        let level = self.random_level();
+        self.nodes.insert(
+            id,
+            VectorNode {
+                element: element.clone(),
+                vector: quantized,
+                norm,
+                attributes,
+                neighbors: vec![vec![]; level + 1],
+            },
+        );
+        self.ids.insert(element, id);
+        let Some(mut entry) = self.entry else {
+            self.entry = Some(id);
+            self.max_level = level;
+            return !existed;
+        };
+        for current in (level + 1..=self.max_level).rev() {
+            entry = self.greedy(&query, entry, current);
+        }
+        for current in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_layer(&query, &[entry], ef_construction, current);
+            let neighbors = self.select_neighbors(&candidates, self.max_links(current));
+            for neighbor in neighbors.iter() {
+                self.link(*neighbor, id, current);
+            }
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.neighbors[current] = neighbors;
+            }
+            if let Some(Candidate(_, closest)) = candidates.first() {
+                entry = *closest;
+            }
+        }
+        if level > self.max_level {
+            self.entry = Some(id);
+            self.max_level = level;
+        }
+        !existed
+    }
+    pub fn remove(&mut self, element: &[u8]) -> bool {
+        match self.ids.get(element) {
+            Some(id) => {
+                let id = *id;
+                self.remove_node(id);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Removes a node, and links its neighbors to each other so that the graph
+    /// stays connected.
+    fn remove_node(&mut self, id: u32) -> Option<VectorNode> {
+        let node = self.nodes.remove(&id)?;
+        self.ids.remove(&node.element);
+        let dim = self.dim as usize;
+        for (level, neighbors) in node.neighbors.iter().enumerate() {
+            for neighbor in neighbors.iter() {
+                let Some(vector) = self
+                    .nodes
+                    .get_mut(neighbor)
+                    .filter(|n| n.neighbors.len() > level)
+                    .map(|n| {
+                        n.neighbors[level].retain(|link| *link != id);
+                        n.vector.to_vec(dim)
+                    })
+                else {
+                    continue;
+                };
+                let mut candidates: Vec<Candidate> = neighbors
+                    .iter()
+                    .filter(|other| *other != neighbor)
+                    .map(|other| Candidate(self.distance(&vector, *other), *other))
+                    .collect();
+                candidates.sort();
+                for Candidate(_, other) in candidates {
+                    let full = self.nodes.get(neighbor).is_none_or(|n| {
+                        n.neighbors[level].len() >= self.max_links(level)
+                            || n.neighbors[level].contains(&other)
+                    });
+                    if !full && self.nodes.contains_key(&other) {
+                        if let Some(n) = self.nodes.get_mut(neighbor) {
+                            n.neighbors[level].push(other);
+                        }
+                    }
+                }
+            }
+        }
+        if self.entry == Some(id) {
+            let top = self
+                .nodes
+                .iter()
+                .max_by_key(|(id, node)| (node.neighbors.len(), std::cmp::Reverse(**id)))
+                .map(|(id, node)| (*id, node.neighbors.len() - 1));
+            self.entry = top.map(|(id, _)| id);
+            self.max_level = top.map(|(_, level)| level).unwrap_or(0);
+        }
+        Some(node)
+    }
+    fn random_level(&self) -> usize {
+        let factor = 1.0 / (self.m.max(2) as f64).ln();
+        let uniform: f64 = rand::thread_rng().gen_range(f64::EPSILON..1.0);
+        ((-uniform.ln() * factor) as usize).min(16)
+    }
+    /// Adds a link from `from` to `to`, pruning the links of `from` to the closest ones.
+    fn link(&mut self, from: u32, to: u32, level: usize) {
+        let max_links = self.max_links(level);
+        let Some(vector) = self
+            .nodes
+            .get(&from)
+            .map(|n| n.vector.to_vec(self.dim as usize))
+        else {
+            return;
+        };
+        let Some(links) = self
+            .nodes
+            .get(&from)
+            .filter(|n| n.neighbors.len() > level)
+            .map(|n| n.neighbors[level].clone())
+        else {
+            return;
+        };
+        let mut links: Vec<u32> = links
+            .into_iter()
+            .filter(|link| self.nodes.contains_key(link))
+            .collect();
+        links.push(to);
+        if links.len() > max_links {
+            let mut candidates: Vec<Candidate> = links
+                .iter()
+                .map(|link| Candidate(self.distance(&vector, *link), *link))
+                .collect();
+            candidates.sort();
+            links = self.select_neighbors(&candidates, max_links);
+        }
+        if let Some(node) = self.nodes.get_mut(&from) {
+            node.neighbors[level] = links;
+        }
+    }
+    /// The heuristic of the HNSW paper: a candidate is kept when it is closer to
+    /// the node than to the neighbors already kept, which spreads the links in all
+    /// directions. The closest of the discarded ones fill the remaining room.
+    fn select_neighbors(&self, candidates: &[Candidate], max_links: usize) -> Vec<u32> {
+        let mut selected: Vec<u32> = vec![];
+        let mut discarded = vec![];
+        let vectors: HashMap<u32, Vec<f32>> = candidates
+            .iter()
+            .filter_map(|Candidate(_, id)| {
+                Some((*id, self.nodes.get(id)?.vector.to_vec(self.dim as usize)))
+            })
+            .collect();
+        for Candidate(distance, id) in candidates.iter() {
+            if selected.len() >= max_links {
+                break;
+            }
+            let Some(vector) = vectors.get(id) else {
+                continue;
+            };
+            let closer_to_selected = selected
+                .iter()
+                .any(|other| self.distance(vector, *other) < *distance);
+            if closer_to_selected {
+                discarded.push(*id);
+            } else {
+                selected.push(*id);
+            }
+        }
+        for id in discarded {
+            if selected.len() >= max_links {
+                break;
+            }
+            selected.push(id);
+        }
+        selected
+    }
+    fn greedy(&self, query: &[f32], entry: u32, level: usize) -> u32 {
+        let mut current = entry;
+        let mut current_distance = self.distance(query, current);
+        loop {
+            let mut changed = false;
+            let links = self
+                .nodes
+                .get(&current)
+                .and_then(|node| node.neighbors.get(level))
+                .cloned()
+                .unwrap_or_default();
+            for link in links {
+                let distance = self.distance(query, link);
+                if distance < current_distance {
+                    current = link;
+                    current_distance = distance;
+                    changed = true;
+                }
+            }
+            if !changed {
+                return current;
+            }
+        }
+    }
+    /// The `ef` closest nodes found from `entries` on a level, closest first.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entries: &[u32],
+        ef: usize,
+        level: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<u32> = entries.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+        for entry in entries {
+            let distance = self.distance(query, *entry);
+            candidates.push(std::cmp::Reverse(Candidate(distance, *entry)));
+            found.push(Candidate(distance, *entry));
+        }
+        while let Some(std::cmp::Reverse(Candidate(distance, id))) = candidates.pop() {
+            if found.len() >= ef && found.peek().is_some_and(|worst| distance > worst.0) {
+                break;
+            }
+            let Some(links) = self
+                .nodes
+                .get(&id)
+                .and_then(|node| node.neighbors.get(level))
+            else {
+                continue;
+            };
+            for link in links {
+                if !visited.insert(*link) || !self.nodes.contains_key(link) {
+                    continue;
+                }
+                let distance = self.distance(query, *link);
+                if found.len() < ef || found.peek().is_some_and(|worst| distance < worst.0) {
+                    candidates.push(std::cmp::Reverse(Candidate(distance, *link)));
+                    found.push(Candidate(distance, *link));
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        found.into_sorted_vec()
+    }
+    /// The elements most similar to `query`, with their similarity between 0 and 1.
+    ///
+    /// With a filter, the search is repeated with a larger `ef` until enough of
+    /// the elements found match, or `filter_ef` elements were considered.
+    pub fn search(
+        &self,
+        query: &[f32],
+        count: usize,
+        ef: usize,
+        filter: Option<&dyn Fn(&VectorNode) -> bool>,
+        filter_ef: usize,
+    ) -> Vec<(&VectorNode, f32)> {
+        let Some(mut entry) = self.entry else {
+            return vec![];
+        };
+        let (query, _) = normalize(query);
+        for level in (1..=self.max_level).rev() {
+            entry = self.greedy(&query, entry, level);
+        }
+        let mut ef = ef.max(count);
+        loop {
+            let found = self.search_layer(&query, &[entry], ef, 0);
+            let exhausted = found.len() < ef || ef >= filter_ef || ef >= self.nodes.len();
+            let result: Vec<(&VectorNode, f32)> = found
+                .iter()
+                .filter_map(|Candidate(distance, id)| {
+                    let node = self.nodes.get(id)?;
+                    filter
+                        .is_none_or(|filter| filter(node))
+                        .then_some((node, similarity(*distance)))
+                })
+                .take(count)
+                .collect();
+            if result.len() >= count || exhausted || filter.is_none() {
+                return result;
+            }
+            ef = (ef * 2).min(filter_ef.max(count));
+        }
+    }
+    /// The exact result of `search`, by comparing the query with every element.
+    pub fn search_exact(
+        &self,
+        query: &[f32],
+        count: usize,
+        filter: Option<&dyn Fn(&VectorNode) -> bool>,
+    ) -> Vec<(&VectorNode, f32)> {
+        let (query, _) = normalize(query);
+        let mut found: Vec<(&VectorNode, f32)> = self
+            .nodes
+            .values()
+            .filter(|node| filter.is_none_or(|filter| filter(node)))
+            .map(|node| (node, similarity(1.0 - node.vector.dot(&query))))
+            .collect();
+        found.sort_by(|a, b| {
+            b.1.total_cmp(&a.1)
+                .then_with(|| a.0.element.cmp(&b.0.element))
+        });
+        found.truncate(count);
+        found
+    }
+}
+fn similarity(distance: f32) -> f32 {
+    (1.0 - distance / 2.0).clamp(0.0, 1.0)
+}
+fn normalize(vector: &[f32]) -> (Vec<f32>, f32) {
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return (vector.to_vec(), 0.0);
+    }
+    (vector.iter().map(|value| value / norm).collect(), norm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo random vectors, so that a failure can be reproduced.
+    fn vectors(count: usize, dim: usize) -> Vec<Vec<f32>> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5
+        };
+        (0..count)
+            .map(|_| (0..dim).map(|_| next()).collect())
+            .collect()
+    }
+    fn build(count: usize, quantization: Quantization) -> (ValueVectorSet, Vec<Vec<f32>>) {
+        let vectors = vectors(count, 16);
+        // Few links per node make a sparse graph with several levels
+        let mut set = ValueVectorSet::new(16, quantization, 4);
+        for (i, vector) in vectors.iter().enumerate() {
+            let element = format!("e{}", i).into_bytes();
+            assert!(set.add(element, vector, None, 32));
+        }
+        (set, vectors)
+    }
+    fn elements(found: &[(&VectorNode, f32)]) -> HashSet<Vec<u8>> {
+        found.iter().map(|(node, _)| node.element.clone()).collect()
+    }
+    /// The share of the exact top 10 found by the graph search, over some queries.
+    fn recall(set: &ValueVectorSet) -> f64 {
+        let queries = vectors(20, 16);
+        let mut hits = 0;
+        for query in queries.iter() {
+            let expected = elements(&set.search_exact(query, 10, None));
+            let found = elements(&set.search(query, 10, VSET_DEFAULT_EF_SEARCH, None, 0));
+            hits += expected.intersection(&found).count();
+        }
+        hits as f64 / (queries.len() * 10) as f64
+    }
+
+    #[test]
+    fn the_search_finds_the_nearest_neighbours() {
+        let (set, vectors) = build(400, Quantization::NoQuant);
+        assert!(recall(&set) > 0.95, "recall {}", recall(&set));
+        let found = set.search(&vectors[42], 1, VSET_DEFAULT_EF_SEARCH, None, 0);
+        assert_eq!(found[0].0.element, b"e42");
+        assert!((found[0].1 - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn the_graph_stays_connected_after_removals() {
+        let (mut set, _) = build(400, Quantization::NoQuant);
+        for i in (0..400).step_by(2) {
+            assert!(set.remove(format!("e{}", i).as_bytes()));
+        }
+        assert!(!set.remove(b"e0"));
+        assert_eq!(set.len(), 200);
+        assert!(recall(&set) > 0.9, "recall {}", recall(&set));
+        let everything = set.search(&vectors(1, 16)[0], 400, 400, None, 0);
+        assert_eq!(everything.len(), 200);
+    }
+
+    #[test]
+    fn quantized_vectors_are_close_to_the_originals() {
+        let (set, vectors) = build(300, Quantization::Q8);
+        let embedding = set.embedding(b"e7").unwrap();
+        for (a, b) in embedding.iter().zip(vectors[7].iter()) {
+            assert!((a - b).abs() < 0.01, "{} {}", a, b);
+        }
+        assert!(recall(&set) > 0.8, "recall {}", recall(&set));
+
+        let (set, _) = build(300, Quantization::Bin);
+        let node = set.get(b"e7").unwrap();
+        let query = set.query_vector(b"e7").unwrap();
+        assert!((node.vector.dot(&query) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn adding_again_replaces_the_vector_and_keeps_the_attributes() {
+        let mut set = ValueVectorSet::new(2, Quantization::NoQuant, VSET_DEFAULT_M);
+        assert!(set.add(b"a".to_vec(), &[1.0, 0.0], Some("{}".to_owned()), 10));
+        assert!(set.add(b"b".to_vec(), &[0.0, 1.0], None, 10));
+        assert!(!set.add(b"a".to_vec(), &[0.0, 2.0], None, 10));
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.get(b"a").unwrap().attributes.as_deref(), Some("{}"));
+        assert_eq!(set.embedding(b"a").unwrap(), vec![0.0, 2.0]);
+        let found = set.search(&[1.0, 0.0], 2, 10, None, 0);
+        assert_eq!(found[0].1, 0.5);
+    }
+
+    #[test]
+    fn filtered_searches_widen_until_enough_elements_match() {
+        let (set, vectors) = build(300, Quantization::NoQuant);
+        let filter = |node: &VectorNode| node.element.ends_with(b"7");
+        let found = set.search(&vectors[0], 10, 10, Some(&filter), 300);
+        assert_eq!(found.len(), 10);
+        assert!(found.iter().all(|(node, _)| filter(node)));
+        let exact = set.search_exact(&vectors[0], 10, Some(&filter));
+        assert_eq!(elements(&found), elements(&exact));
+    }
+}